@@ -0,0 +1,50 @@
+use super::PublicKey;
+use sha2::Digest;
+use sha2::Sha256;
+
+// Prepended to every address so they can't be mixed up with other base58
+// strings and so the format can change later on
+const VERSION: u8 = 0x23;
+pub const CHECKSUM_LENGTH: usize = 4;
+
+// First bytes of the double SHA-256 of `payload`, as in Base58Check
+pub fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let digest = Sha256::digest(&Sha256::digest(payload));
+
+    let mut checksum = [0; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&digest[0..CHECKSUM_LENGTH]);
+
+    checksum
+}
+
+// Base58Check encoding of the public key, which is what users copy around
+pub fn encode(public_key: &PublicKey) -> String {
+    let mut payload = vec![VERSION];
+    payload.extend_from_slice(public_key);
+
+    let checksum = checksum(&payload);
+    payload.extend_from_slice(&checksum);
+
+    bs58::encode(payload).into_string()
+}
+
+pub fn decode(address: &str) -> Result<PublicKey, String> {
+    let payload = bs58::decode(address)
+        .into_vec()
+        .map_err(|_| format!("{} is not a valid address", address))?;
+
+    if payload.len() != 1 + 32 + CHECKSUM_LENGTH || payload[0] != VERSION {
+        return Err(format!("{} is not a valid address", address));
+    }
+
+    let (data, expected) = payload.split_at(1 + 32);
+
+    if checksum(data) != expected {
+        return Err(format!("{} has an invalid checksum", address));
+    }
+
+    let mut public_key = [0; 32];
+    public_key.copy_from_slice(&data[1..]);
+
+    Ok(public_key)
+}
@@ -0,0 +1,94 @@
+use super::encoding::Encode;
+use super::name;
+use super::transaction::Output;
+use super::PublicKey;
+
+use serde::{Deserialize, Serialize};
+
+// Tokens issued on chain and moved by transactions alongside coins. Assets
+// are named like names are, see `name::is_valid`, and each one can only be
+// issued once, ever, with a supply that never changes.
+
+// What a transaction does with tokens, see `TransactionDetails::with_asset`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AssetAction {
+    // Creates `asset` with `supply` tokens, all of them held by the source
+    Issue { asset: String, supply: u64 },
+    // Moves tokens of `asset` from the source to each output
+    Transfer { asset: String, outputs: Vec<Output> },
+}
+
+impl Encode for AssetAction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            AssetAction::Issue { asset, supply } => {
+                out.push(0);
+                asset.encode(out);
+                supply.encode(out);
+            }
+            AssetAction::Transfer { asset, outputs } => {
+                out.push(1);
+                asset.encode(out);
+                outputs[..].encode(out);
+            }
+        }
+    }
+}
+
+impl AssetAction {
+    pub fn asset(&self) -> &str {
+        match self {
+            AssetAction::Issue { asset, .. } | AssetAction::Transfer { asset, .. } => asset,
+        }
+    }
+
+    // Tokens taken from the source, `None` when they add up to more than an
+    // `u64` holds
+    pub fn spent(&self) -> Option<u64> {
+        match self {
+            AssetAction::Issue { .. } => Some(0),
+            AssetAction::Transfer { outputs, .. } => outputs
+                .iter()
+                .try_fold(0u64, |total, output| total.checked_add(output.amount)),
+        }
+    }
+
+    // Tokens given to each holder, the source being given the whole supply
+    // when issuing
+    pub fn credits(&self, source: &PublicKey) -> Vec<Output> {
+        match self {
+            AssetAction::Issue { supply, .. } => vec![Output {
+                destination: *source,
+                amount: *supply,
+            }],
+            AssetAction::Transfer { outputs, .. } => outputs.clone(),
+        }
+    }
+
+    // Whether it makes sense for `source` at all, whatever it holds: a
+    // valid name, some supply, and transfers to others of some tokens
+    pub fn is_valid(&self, source: &PublicKey) -> bool {
+        name::is_valid(self.asset())
+            && match self {
+                AssetAction::Issue { supply, .. } => *supply > 0,
+                AssetAction::Transfer { outputs, .. } => {
+                    !outputs.is_empty()
+                        && outputs
+                            .iter()
+                            .all(|output| output.amount > 0 && &output.destination != source)
+                        && self.spent().is_some()
+                }
+            }
+    }
+}
+
+impl std::fmt::Display for AssetAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AssetAction::Issue { asset, supply } => write!(f, "issuing {} {}", supply, asset),
+            AssetAction::Transfer { asset, .. } => {
+                write!(f, "moving {} {}", self.spent().unwrap_or(u64::MAX), asset)
+            }
+        }
+    }
+}
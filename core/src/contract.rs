@@ -0,0 +1,394 @@
+use super::encoding::Encode;
+use super::hex;
+use super::keys;
+use super::predicate::{Predicate, Witness};
+use super::transaction::{Output, TransactionDetails};
+use super::{Hash, PublicKey, SecretKey, Signature};
+use crate::BigArray;
+use sha2::{Digest, Sha256};
+
+use serde::{Deserialize, Serialize};
+
+// Hashed with the terms into the address of a contract, so no key can
+// ever be made for it
+const CONTRACT_PREFIX: &[u8] = b"fcoin contract";
+// Signed with the state of a channel, so it can't pass for anything else
+const CHANNEL_STATE_PREFIX: &[u8] = b"fcoin channel state";
+
+// Terms on spending coins. They are paid to the address of the contract, a
+// hash of its terms, like to any address. Transactions from that address
+// then show the terms in their `Unlock`, and must follow them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Contract {
+    // `recipient` can spend with the preimage of `hash_lock` up to block
+    // `timeout`, and `refund` after it. Both sides of an atomic swap lock
+    // their coins with the same hash, so redeeming one shows the preimage
+    // that redeems the other.
+    HashTimeLock {
+        recipient: PublicKey,
+        hash_lock: Hash,
+        timeout: u64,
+        refund: PublicKey,
+    },
+    // Coins two sides pay each other with off chain, by both signing each
+    // new `ChannelState`. Either side closes it alone by moving the coins to
+    // the `Settlement` of the latest state.
+    Channel(Channel),
+    // A channel closed in `state`. Up to `timeout` either side can move it
+    // to a later state, and after it either side pays it out.
+    Settlement {
+        channel: Channel,
+        state: ChannelState,
+        timeout: u64,
+    },
+    // Spent by a transaction whose witness shows the predicate holds
+    Predicate(Predicate),
+}
+
+impl Encode for Contract {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Contract::HashTimeLock {
+                recipient,
+                hash_lock,
+                timeout,
+                refund,
+            } => {
+                out.push(0);
+                recipient.encode(out);
+                hash_lock.encode(out);
+                timeout.encode(out);
+                refund.encode(out);
+            }
+            Contract::Channel(channel) => {
+                out.push(1);
+                channel.encode(out);
+            }
+            Contract::Settlement {
+                channel,
+                state,
+                timeout,
+            } => {
+                out.push(2);
+                channel.encode(out);
+                state.encode(out);
+                timeout.encode(out);
+            }
+            Contract::Predicate(predicate) => {
+                out.push(3);
+                predicate.encode(out);
+            }
+        }
+    }
+}
+
+impl Contract {
+    pub fn address(&self) -> PublicKey {
+        let mut bytes = CONTRACT_PREFIX.to_vec();
+
+        self.encode(&mut bytes);
+
+        Sha256::digest(&bytes).into()
+    }
+
+    // Hex encoded bincode, like raw transactions, to be sent to whoever
+    // else the contract names
+    pub fn to_hex(&self) -> String {
+        hex::encode(&bincode::serialize(self).unwrap())
+    }
+
+    pub fn from_hex(encoded: &str) -> Result<Self, String> {
+        bincode::deserialize(&hex::decode(encoded.trim())?)
+            .map_err(|_| "Not a contract".to_string())
+    }
+}
+
+// The hash lock of a secret
+pub fn hash_lock(preimage: &Hash) -> Hash {
+    Sha256::digest(preimage).into()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Channel {
+    pub parties: [PublicKey; 2],
+    // Blocks either side has to show a later state once the other closes
+    pub dispute: u64,
+    // Picked at random, so states of an earlier channel between the same
+    // sides can't be used on this one
+    pub nonce: u64,
+}
+
+impl Encode for Channel {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.parties[0].encode(out);
+        self.parties[1].encode(out);
+        self.dispute.encode(out);
+        self.nonce.encode(out);
+    }
+}
+
+impl Channel {
+    pub fn address(&self) -> PublicKey {
+        Contract::Channel(self.clone()).address()
+    }
+
+    // Position of `id` in `parties`
+    pub fn party(&self, id: &PublicKey) -> Option<usize> {
+        self.parties.iter().position(|party| party == id)
+    }
+}
+
+// What each side of a channel gets when it closes. Whatever the channel has
+// beyond the balances pays the fees of closing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelState {
+    // Later states have higher sequences
+    pub sequence: u64,
+    // In the order of the parties
+    pub balances: [u64; 2],
+}
+
+impl Encode for ChannelState {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.sequence.encode(out);
+        self.balances[0].encode(out);
+        self.balances[1].encode(out);
+    }
+}
+
+impl ChannelState {
+    fn signing_bytes(&self, channel: &Channel) -> Vec<u8> {
+        let mut bytes = CHANNEL_STATE_PREFIX.to_vec();
+
+        channel.address().encode(&mut bytes);
+        self.encode(&mut bytes);
+
+        bytes
+    }
+
+    pub fn sign(&self, channel: &Channel, secret_key: &SecretKey) -> ContractSignature {
+        ContractSignature(keys::sign(secret_key, &self.signing_bytes(channel)))
+    }
+
+    pub fn is_signed_by(
+        &self,
+        channel: &Channel,
+        party: &PublicKey,
+        signature: &ContractSignature,
+    ) -> bool {
+        keys::verify(party, &self.signing_bytes(channel), &signature.0)
+    }
+
+    // Paying out the settlement, the parties with nothing left out
+    pub fn outputs(&self, channel: &Channel) -> Vec<Output> {
+        channel
+            .parties
+            .iter()
+            .zip(self.balances)
+            .filter(|(_, amount)| *amount > 0)
+            .map(|(destination, amount)| Output {
+                destination: *destination,
+                amount,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractSignature(#[serde(with = "BigArray")] pub Signature);
+
+impl Encode for ContractSignature {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+// A state both sides of a channel agreed on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedState {
+    pub state: ChannelState,
+    // In the order of the parties
+    pub signatures: [ContractSignature; 2],
+}
+
+impl Encode for SignedState {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.state.encode(out);
+        self.signatures[0].encode(out);
+        self.signatures[1].encode(out);
+    }
+}
+
+impl SignedState {
+    pub fn is_valid(&self, channel: &Channel) -> bool {
+        channel
+            .parties
+            .iter()
+            .zip(&self.signatures)
+            .all(|(party, signature)| self.state.is_signed_by(channel, party, signature))
+    }
+}
+
+// Moves the coins of a channel, or of a settlement with an earlier state, to
+// the settlement of `state`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Close {
+    pub state: SignedState,
+    // Of the new settlement, at least `dispute` blocks after the close
+    pub timeout: u64,
+}
+
+impl Encode for Close {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.state.encode(out);
+        self.timeout.encode(out);
+    }
+}
+
+impl Close {
+    pub fn settlement(&self, channel: &Channel) -> Contract {
+        Contract::Settlement {
+            channel: channel.clone(),
+            state: self.state.state.clone(),
+            timeout: self.timeout,
+        }
+    }
+
+    // Whether `details` closes `channel`, from a state before `sequence`, to
+    // the settlement of this one
+    fn closes(
+        &self,
+        channel: &Channel,
+        sequence: Option<u64>,
+        details: &TransactionDetails,
+    ) -> bool {
+        let settlement = self.settlement(channel).address();
+
+        sequence.is_none_or(|sequence| self.state.state.sequence > sequence)
+            && self.state.is_valid(channel)
+            && details.outputs().len() == 1
+            && details.destination() == &settlement
+    }
+}
+
+// What a transaction spending from a contract shows to do it, the
+// transaction being signed by whoever the terms name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unlock {
+    pub contract: Contract,
+    // Redeems a hash time lock, which is refunded without it
+    pub preimage: Option<Hash>,
+    // Closes a channel, or disputes its settlement with a later state. A
+    // settlement is paid out without it.
+    pub close: Option<Close>,
+    // Shows a predicate holds
+    pub witness: Option<Witness>,
+}
+
+impl Encode for Unlock {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.contract.encode(out);
+        self.preimage.encode(out);
+
+        // Left out when there are none, so earlier unlocks keep their
+        // encoding, and always there before a witness
+        if self.close.is_some() || self.witness.is_some() {
+            self.close.encode(out);
+        }
+
+        if let Some(witness) = &self.witness {
+            witness.encode(out);
+        }
+    }
+}
+
+impl Unlock {
+    pub fn new(contract: Contract) -> Self {
+        Unlock {
+            contract,
+            preimage: None,
+            close: None,
+            witness: None,
+        }
+    }
+
+    // Whether `details`, signed with `signature`, follows the terms
+    pub fn allows(&self, details: &TransactionDetails, signature: &Signature) -> bool {
+        let signed_by =
+            |signer: &PublicKey| keys::verify(signer, &details.signing_bytes(), signature);
+
+        match (&self.contract, &self.preimage, &self.close) {
+            (
+                Contract::HashTimeLock {
+                    recipient,
+                    hash_lock: lock,
+                    ..
+                },
+                Some(preimage),
+                None,
+            ) => &hash_lock(preimage) == lock && signed_by(recipient),
+            (Contract::HashTimeLock { refund, .. }, None, None) => signed_by(refund),
+            (Contract::Channel(channel), None, Some(close)) => {
+                close.closes(channel, None, details) && channel.parties.iter().any(signed_by)
+            }
+            (Contract::Settlement { channel, state, .. }, None, Some(close)) => {
+                close.closes(channel, Some(state.sequence), details)
+                    && channel.parties.iter().any(signed_by)
+            }
+            (Contract::Settlement { channel, state, .. }, None, None) => {
+                details.outputs() == state.outputs(channel) && channel.parties.iter().any(signed_by)
+            }
+            (Contract::Predicate(predicate), None, None) => self
+                .witness
+                .as_ref()
+                .is_some_and(|witness| predicate.holds(witness, &details.signing_bytes())),
+            _ => false,
+        }
+    }
+
+    // Whether it pays out a settlement, whose outputs the terms set
+    pub fn pays_out(&self) -> bool {
+        matches!(
+            (&self.contract, &self.close),
+            (Contract::Settlement { .. }, None)
+        )
+    }
+
+    // Last block height it can be included at
+    pub fn expires_at(&self) -> Option<u64> {
+        match (&self.contract, &self.preimage, &self.close) {
+            (Contract::HashTimeLock { timeout, .. }, Some(_), _) => Some(*timeout),
+            // Leaves the other side the whole dispute window
+            (Contract::Channel(channel), _, Some(close)) => {
+                Some(close.timeout.saturating_sub(channel.dispute))
+            }
+            (
+                Contract::Settlement {
+                    channel, timeout, ..
+                },
+                _,
+                Some(close),
+            ) => Some((*timeout).min(close.timeout.saturating_sub(channel.dispute))),
+            (Contract::Predicate(predicate), _, _) => {
+                predicate
+                    .window(self.witness.as_ref().unwrap_or(&Witness::default()))
+                    .1
+            }
+            _ => None,
+        }
+    }
+
+    // First block height it can be included at
+    pub fn valid_from(&self) -> u64 {
+        match (&self.contract, &self.preimage, &self.close) {
+            (Contract::HashTimeLock { timeout, .. }, None, _)
+            | (Contract::Settlement { timeout, .. }, _, None) => timeout + 1,
+            (Contract::Predicate(predicate), _, _) => {
+                predicate
+                    .window(self.witness.as_ref().unwrap_or(&Witness::default()))
+                    .0
+            }
+            _ => 0,
+        }
+    }
+}
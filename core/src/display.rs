@@ -0,0 +1,24 @@
+use super::{Hash, PublicKey};
+
+pub struct HashFmt(pub Hash);
+pub struct PublicKeyFmt(pub PublicKey);
+
+impl std::fmt::Display for HashFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PublicKeyFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for byte in &self.0[0..8] {
+            write!(f, "{:02x}", byte)?
+        }
+
+        Ok(())
+    }
+}
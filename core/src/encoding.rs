@@ -0,0 +1,80 @@
+// The one byte encoding used for hashing and signing, independent of how
+// things are serialized on the wire or on disk. Changing it forks the chain.
+//
+// * Integers are fixed size little-endian
+// * Keys, hashes and signatures are their raw bytes
+// * Optional values are a 0 byte when absent, a 1 byte and the value when
+//   present
+// * Lists are their length (as an u64) followed by the items
+// * Strings are their length in bytes (as an u64) followed by their UTF-8
+//   bytes
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn encoded(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        self.encode(&mut out);
+
+        out
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+}
+
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+            None => out.push(0),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u64).encode(out);
+
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
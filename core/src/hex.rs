@@ -0,0 +1,30 @@
+pub fn decode(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("Invalid hex string: {}", hex));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .map_err(|_| format!("Invalid hex digits: {}", hex))
+        })
+        .collect()
+}
+
+pub fn decode_array<const N: usize>(hex: &str) -> Result<[u8; N], String> {
+    let bytes = decode(hex)?;
+
+    if bytes.len() != N {
+        return Err(format!("Expected {} hex digits, got {}", N * 2, hex.len()));
+    }
+
+    let mut array = [0; N];
+    array.copy_from_slice(&bytes);
+
+    Ok(array)
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,78 @@
+use super::{PublicKey, Signature};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use zeroize::Zeroize;
+
+// A private key. Its bytes are wiped when it is dropped, it has no `Debug`
+// or `Display` so it can't end up in logs, and it isn't `Copy`, so the only
+// copies are explicit `clone`s.
+#[derive(Clone)]
+pub struct SecretKey([u8; 32]);
+
+impl SecretKey {
+    // Wipes `bytes`, so the key is only left in the `SecretKey`
+    pub fn from_bytes(bytes: &mut [u8; 32]) -> Self {
+        let secret_key = SecretKey(*bytes);
+
+        bytes.zeroize();
+
+        secret_key
+    }
+
+    // For the few places that need the raw key, which should not keep it
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    // To fill in place, for example with random bytes
+    pub fn as_mut_bytes(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+
+    pub fn zeroed() -> Self {
+        SecretKey([0; 32])
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// Keys are compared in constant time, like their signatures are checked
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+    }
+}
+
+impl Eq for SecretKey {}
+
+// The signing keys ed25519-dalek makes from ours wipe themselves too
+pub fn public_key(secret_key: &SecretKey) -> PublicKey {
+    SigningKey::from_bytes(secret_key.as_bytes())
+        .verifying_key()
+        .to_bytes()
+}
+
+pub fn sign(secret_key: &SecretKey, message: &[u8]) -> Signature {
+    SigningKey::from_bytes(secret_key.as_bytes())
+        .sign(message)
+        .to_bytes()
+}
+
+// Only canonical signatures are accepted (S below the group order, no
+// small order points), so nobody but the signer can produce another valid
+// signature for the same message
+pub fn verify(public_key: &PublicKey, message: &[u8], signature: &Signature) -> bool {
+    match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key
+            .verify_strict(message, &ed25519_dalek::Signature::from_bytes(signature))
+            .is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,25 @@
+#[macro_use]
+extern crate serde_big_array;
+big_array! { BigArray; }
+
+pub mod address;
+pub mod asset;
+pub mod contract;
+pub mod display;
+pub mod encoding;
+pub mod hex;
+pub mod keys;
+pub mod merkle;
+pub mod message;
+pub mod name;
+pub mod predicate;
+pub mod raw;
+pub mod transaction;
+pub mod uri;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub type Hash = [u8; 32];
+pub type PublicKey = [u8; 32];
+pub type Signature = [u8; 64];
+pub use keys::SecretKey;
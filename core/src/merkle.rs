@@ -0,0 +1,88 @@
+use sha2::Digest;
+use sha2::Sha256;
+
+use serde::{Deserialize, Serialize};
+
+type Hash = [u8; 32];
+
+// Path from a leaf up to the root of the Merkle tree. `index` is the position
+// of the leaf, its bits tell on which side each sibling goes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    index: u32,
+    siblings: Vec<Hash>,
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new().chain(left).chain(right).finalize().into()
+}
+
+// Builds the next level of the tree. Odd levels get their last node
+// duplicated, the same way Bitcoin does it.
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_pair(left, right),
+            [single] => hash_pair(single, single),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+
+    let mut level = leaves.to_vec();
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level[0]
+}
+
+pub fn merkle_proof(leaves: &[Hash], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut siblings = Vec::new();
+    let mut level = leaves.to_vec();
+    let mut position = index;
+
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+
+        siblings.push(*level.get(sibling).unwrap_or(&level[position]));
+
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof {
+        index: index as u32,
+        siblings,
+    })
+}
+
+impl MerkleProof {
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        let mut hash = *leaf;
+        let mut position = self.index;
+
+        for sibling in &self.siblings {
+            hash = if position & 1 == 0 {
+                hash_pair(&hash, sibling)
+            } else {
+                hash_pair(sibling, &hash)
+            };
+
+            position /= 2;
+        }
+
+        position == 0 && &hash == root
+    }
+}
@@ -0,0 +1,29 @@
+use super::encoding::Encode;
+use super::keys;
+use super::{PublicKey, SecretKey, Signature};
+
+// Prepended to every signed message. It is longer than a public key, the
+// first thing in the signed bytes of transactions, so a message signature
+// could only pass as a transaction one if the signer's public key happened
+// to be this text.
+const MESSAGE_PREFIX: &[u8] = b"fcoin signed message, not a transaction:\n";
+
+// The prefix followed by the length of the message, so messages can't
+// run into each other
+fn signed_bytes(message: &str) -> Vec<u8> {
+    let mut bytes = MESSAGE_PREFIX.to_vec();
+
+    (message.len() as u64).encode(&mut bytes);
+    bytes.extend_from_slice(message.as_bytes());
+
+    bytes
+}
+
+// Proves whoever holds the key of an address wrote `message`
+pub fn sign(secret_key: &SecretKey, message: &str) -> Signature {
+    keys::sign(secret_key, &signed_bytes(message))
+}
+
+pub fn verify(public_key: &PublicKey, message: &str, signature: &Signature) -> bool {
+    keys::verify(public_key, &signed_bytes(message), signature)
+}
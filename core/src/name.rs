@@ -0,0 +1,23 @@
+// Names registered on chain for public keys. A transaction registers its
+// name for its source, see `TransactionDetails::with_name`, and nobody else
+// can register it until it expires.
+
+// Names are paid to in place of addresses with it, like `name/alice`
+pub const NAME_PREFIX: &str = "name/";
+pub const MIN_LENGTH: usize = 3;
+pub const MAX_LENGTH: usize = 32;
+
+// Lowercase letters, digits and dashes, the dashes not at either end
+pub fn is_valid(name: &str) -> bool {
+    (MIN_LENGTH..=MAX_LENGTH).contains(&name.len())
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+// The name `recipient` is, when it is one
+pub fn parse(recipient: &str) -> Option<&str> {
+    recipient.strip_prefix(NAME_PREFIX)
+}
@@ -0,0 +1,251 @@
+use super::contract::{hash_lock, ContractSignature};
+use super::encoding::Encode;
+use super::keys;
+use super::{Hash, PublicKey};
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+// Spending conditions, with no loops or jumps so checking one takes time in
+// proportion to its size. Conditions are in postfix order: `All` and `Any`
+// combine the results of the conditions right before them, so a predicate
+// never nests, and neither deserializing nor checking it recurses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Predicate(pub Vec<Condition>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+    // Signed by this key
+    Signature(PublicKey),
+    // Signed by at least `required` of `keys`
+    Multisig { required: u64, keys: Vec<PublicKey> },
+    // Shows the preimage of this hash
+    Preimage(Hash),
+    // Included at this block height or later
+    ValidFrom(u64),
+    // Included at this block height or before
+    ExpiresAt(u64),
+    // The last this many results all hold
+    All(u64),
+    // One of the last this many results holds, the one the witness picks
+    Any(u64),
+}
+
+// What a spend shows to follow a predicate
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Witness {
+    // Of the transaction, by each key that signed it. The signature of the
+    // transaction itself isn't used.
+    pub signatures: Vec<(PublicKey, ContractSignature)>,
+    pub preimages: Vec<Hash>,
+    // For each `Any`, in order, which of its results holds
+    pub choices: Vec<u64>,
+}
+
+// The result of some of the conditions, with the blocks it holds at
+#[derive(Debug, Clone, Copy)]
+struct Branch {
+    holds: bool,
+    valid_from: u64,
+    expires_at: Option<u64>,
+}
+
+impl Branch {
+    fn new(holds: bool) -> Self {
+        Branch {
+            holds,
+            valid_from: 0,
+            expires_at: None,
+        }
+    }
+
+    // Holds when both do, at the blocks both do
+    fn and(self, other: Branch) -> Branch {
+        Branch {
+            holds: self.holds && other.holds,
+            valid_from: self.valid_from.max(other.valid_from),
+            expires_at: match (self.expires_at, other.expires_at) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        }
+    }
+}
+
+impl Encode for Condition {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Condition::Signature(key) => {
+                out.push(0);
+                key.encode(out);
+            }
+            Condition::Multisig { required, keys } => {
+                out.push(1);
+                required.encode(out);
+                keys[..].encode(out);
+            }
+            Condition::Preimage(hash) => {
+                out.push(2);
+                hash.encode(out);
+            }
+            Condition::ValidFrom(height) => {
+                out.push(3);
+                height.encode(out);
+            }
+            Condition::ExpiresAt(height) => {
+                out.push(4);
+                height.encode(out);
+            }
+            Condition::All(count) => {
+                out.push(5);
+                count.encode(out);
+            }
+            Condition::Any(count) => {
+                out.push(6);
+                count.encode(out);
+            }
+        }
+    }
+}
+
+impl Encode for Predicate {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0[..].encode(out);
+    }
+}
+
+impl Encode for (PublicKey, ContractSignature) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl Encode for Witness {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.signatures[..].encode(out);
+        self.preimages[..].encode(out);
+        self.choices[..].encode(out);
+    }
+}
+
+impl Predicate {
+    pub fn signature(key: PublicKey) -> Self {
+        Predicate(vec![Condition::Signature(key)])
+    }
+
+    pub fn multisig(required: u64, keys: Vec<PublicKey>) -> Self {
+        Predicate(vec![Condition::Multisig { required, keys }])
+    }
+
+    pub fn preimage(hash: Hash) -> Self {
+        Predicate(vec![Condition::Preimage(hash)])
+    }
+
+    pub fn valid_from(height: u64) -> Self {
+        Predicate(vec![Condition::ValidFrom(height)])
+    }
+
+    pub fn expires_at(height: u64) -> Self {
+        Predicate(vec![Condition::ExpiresAt(height)])
+    }
+
+    // Holds when both this and `other` do
+    pub fn and(self, other: Predicate) -> Self {
+        self.combine(other, Condition::All(2))
+    }
+
+    // Holds when either this or `other` does. Witnesses pick 0 for this one
+    // and 1 for `other`.
+    pub fn or(self, other: Predicate) -> Self {
+        self.combine(other, Condition::Any(2))
+    }
+
+    fn combine(mut self, other: Predicate, condition: Condition) -> Self {
+        self.0.extend(other.0);
+        self.0.push(condition);
+        self
+    }
+
+    // Whether `witness` shows it holds for a transaction whose signed bytes
+    // are `message`, leaving the block heights to `window`
+    pub fn holds(&self, witness: &Witness, message: &[u8]) -> bool {
+        let signed: HashSet<&PublicKey> = witness
+            .signatures
+            .iter()
+            .filter(|(key, signature)| keys::verify(key, message, &signature.0))
+            .map(|(key, _)| key)
+            .collect();
+
+        self.evaluate(witness, |key| signed.contains(key))
+            .is_some_and(|branch| branch.holds)
+    }
+
+    // First and last block heights a spend taking the branches `witness`
+    // picks can be included at
+    pub fn window(&self, witness: &Witness) -> (u64, Option<u64>) {
+        match self.evaluate(witness, |_| true) {
+            Some(branch) => (branch.valid_from, branch.expires_at),
+            None => (0, None),
+        }
+    }
+
+    // Runs the conditions, `None` when they don't make a single result
+    fn evaluate(&self, witness: &Witness, signed: impl Fn(&PublicKey) -> bool) -> Option<Branch> {
+        let mut results: Vec<Branch> = Vec::new();
+        let mut choices = witness.choices.iter();
+
+        for condition in &self.0 {
+            let branch = match condition {
+                Condition::Signature(key) => Branch::new(signed(key)),
+                Condition::Multisig { required, keys } => {
+                    let signers: HashSet<_> = keys.iter().filter(|key| signed(key)).collect();
+
+                    Branch::new(signers.len() as u64 >= *required)
+                }
+                Condition::Preimage(hash) => Branch::new(
+                    witness
+                        .preimages
+                        .iter()
+                        .any(|preimage| &hash_lock(preimage) == hash),
+                ),
+                Condition::ValidFrom(height) => Branch {
+                    valid_from: *height,
+                    ..Branch::new(true)
+                },
+                Condition::ExpiresAt(height) => Branch {
+                    expires_at: Some(*height),
+                    ..Branch::new(true)
+                },
+                Condition::All(count) => {
+                    let combined = pop(&mut results, *count)?;
+
+                    combined
+                        .iter()
+                        .fold(Branch::new(true), |all, branch| all.and(*branch))
+                }
+                Condition::Any(count) => {
+                    let combined = pop(&mut results, *count)?;
+
+                    *combined.get(*choices.next()? as usize)?
+                }
+            };
+
+            results.push(branch);
+        }
+
+        match results[..] {
+            [branch] => Some(branch),
+            _ => None,
+        }
+    }
+}
+
+// The last `count` results, at least one
+fn pop(results: &mut Vec<Branch>, count: u64) -> Option<Vec<Branch>> {
+    if count == 0 || count > results.len() as u64 {
+        return None;
+    }
+
+    Some(results.split_off(results.len() - count as usize))
+}
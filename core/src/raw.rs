@@ -0,0 +1,54 @@
+use super::hex;
+use super::keys;
+use super::transaction::{self, Transaction, TransactionDetails};
+use super::{PublicKey, SecretKey};
+
+// Raw transactions are the hex encoded bincode serialization of either the
+// unsigned `TransactionDetails` or the signed `Transaction`. Being plain
+// text they can be carried to and from an offline machine that holds the
+// keys.
+
+pub fn create_raw_transaction(
+    source: PublicKey,
+    destination: PublicKey,
+    amount: u64,
+    fee: u64,
+) -> String {
+    encode_unsigned(&TransactionDetails::new(source, destination, amount, fee))
+}
+
+pub fn sign_raw_transaction(raw: &str, secret_key: &SecretKey) -> Result<String, String> {
+    let details = decode_unsigned(raw)?;
+
+    if details.source() != &keys::public_key(secret_key) {
+        return Err("The key does not match the source of the transaction".to_string());
+    }
+
+    let signature = transaction::sign(&details, secret_key);
+
+    Ok(encode_signed(&Transaction::new(details, signature)))
+}
+
+pub fn encode_unsigned(details: &TransactionDetails) -> String {
+    hex::encode(&bincode::serialize(details).unwrap())
+}
+
+pub fn decode_unsigned(raw: &str) -> Result<TransactionDetails, String> {
+    bincode::deserialize(&hex::decode(raw.trim())?)
+        .map_err(|_| "Not an unsigned raw transaction".to_string())
+}
+
+pub fn encode_signed(transaction: &Transaction) -> String {
+    hex::encode(&bincode::serialize(transaction).unwrap())
+}
+
+pub fn decode_signed(raw: &str) -> Result<Transaction, String> {
+    let transaction: Transaction = bincode::deserialize(&hex::decode(raw.trim())?)
+        .map_err(|_| "Not a signed raw transaction".to_string())?;
+
+    if !transaction.has_valid_signature() {
+        return Err("The raw transaction has an invalid signature".to_string());
+    }
+
+    Ok(transaction)
+}
@@ -0,0 +1,476 @@
+use super::asset::AssetAction;
+use super::contract::Unlock;
+use super::display::PublicKeyFmt;
+use super::encoding::Encode;
+use super::keys;
+use super::name::NAME_PREFIX;
+use super::{Hash, PublicKey, SecretKey, Signature};
+use crate::BigArray;
+use sha2::Digest;
+use sha2::Sha256;
+
+use serde::{Deserialize, Serialize};
+
+const SIGNATURE_SIZE: u64 = 64;
+// Hashed into the burn address, so no key can ever be made for it
+const BURN_PREFIX: &[u8] = b"fcoin burn";
+// Most bytes of data a transaction can carry
+pub const MAX_DATA_SIZE: usize = 80;
+
+// A payment to a single recipient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Output {
+    pub destination: PublicKey,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionDetails {
+    source_public_key: PublicKey,
+    // The first output
+    destination_public_key: PublicKey,
+    amount: u64,
+    // Paid to whoever mines the block that includes this transaction
+    fee: u64,
+    // Last block height this transaction may be included at
+    expires_at: Option<u64>,
+    // Payments to other recipients, paid by the same signature and fee
+    more_outputs: Vec<Output>,
+    // Registers, or renews, this name for the source
+    name: Option<String>,
+    // Issues or moves tokens
+    asset: Option<Box<AssetAction>>,
+    // Anything, up to `MAX_DATA_SIZE` bytes
+    data: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    details: TransactionDetails,
+    // By a key the unlock names, when the source is a contract
+    #[serde(with = "BigArray")]
+    source_signature: Signature,
+    // For spending from the address of a contract
+    unlock: Option<Box<Unlock>>,
+}
+
+impl TransactionDetails {
+    pub fn new(source: PublicKey, destination: PublicKey, amount: u64, fee: u64) -> Self {
+        TransactionDetails {
+            source_public_key: source,
+            destination_public_key: destination,
+            amount,
+            fee,
+            expires_at: None,
+            more_outputs: Vec::new(),
+            name: None,
+            asset: None,
+            data: None,
+        }
+    }
+
+    // Only registers `name` for `source`, paying nobody. Its single output
+    // pays nothing to the source.
+    pub fn register(source: PublicKey, name: &str, fee: u64) -> Self {
+        TransactionDetails::new(source, source, 0, fee).with_name(Some(name.to_string()))
+    }
+
+    // Only does `action` with tokens, paying nobody coins, like `register`
+    pub fn with_tokens(source: PublicKey, action: AssetAction, fee: u64) -> Self {
+        TransactionDetails::new(source, source, 0, fee).with_asset(Some(action))
+    }
+
+    // Only carries `data`, a hash of a document to timestamp it say, paying
+    // nobody coins like `register`
+    pub fn anchor(source: PublicKey, data: Vec<u8>, fee: u64) -> Self {
+        TransactionDetails::new(source, source, 0, fee).with_data(Some(data))
+    }
+
+    // Pays several recipients at once. `None` without any output.
+    pub fn to_many(source: PublicKey, outputs: &[Output], fee: u64) -> Option<Self> {
+        let (first, more) = outputs.split_first()?;
+        let mut details = TransactionDetails::new(source, first.destination, first.amount, fee);
+
+        details.more_outputs = more.to_vec();
+
+        Some(details)
+    }
+
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    pub fn with_expiry(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn with_asset(mut self, asset: Option<AssetAction>) -> Self {
+        self.asset = asset.map(Box::new);
+        self
+    }
+
+    pub fn with_data(mut self, data: Option<Vec<u8>>) -> Self {
+        self.data = data;
+        self
+    }
+
+    // The fee this transaction needs to pay `rate` for every 1000 bytes
+    pub fn fee_for_rate(&self, rate: u64) -> u64 {
+        let size = self.encoded().len() as u64 + SIGNATURE_SIZE;
+
+        (size * rate).div_ceil(1000)
+    }
+
+    pub fn source(&self) -> &PublicKey {
+        &self.source_public_key
+    }
+
+    // Of the first output
+    pub fn destination(&self) -> &PublicKey {
+        &self.destination_public_key
+    }
+
+    // Of all the outputs, `u64::MAX` when they add up to more
+    pub fn amount(&self) -> u64 {
+        self.total_amount().unwrap_or(u64::MAX)
+    }
+
+    // `None` when the outputs add up to more than an `u64` holds
+    pub fn total_amount(&self) -> Option<u64> {
+        self.more_outputs
+            .iter()
+            .try_fold(self.amount, |total, output| {
+                total.checked_add(output.amount)
+            })
+    }
+
+    // Of every output, in order
+    pub fn destinations(&self) -> impl Iterator<Item = &PublicKey> {
+        std::iter::once(&self.destination_public_key)
+            .chain(self.more_outputs.iter().map(|output| &output.destination))
+    }
+
+    // What the outputs to `id` add up to
+    pub fn amount_to(&self, id: &PublicKey) -> u64 {
+        self.outputs()
+            .iter()
+            .filter(|output| &output.destination == id)
+            .map(|output| output.amount)
+            .sum()
+    }
+
+    pub fn outputs(&self) -> Vec<Output> {
+        let first = Output {
+            destination: self.destination_public_key,
+            amount: self.amount,
+        };
+
+        std::iter::once(first)
+            .chain(self.more_outputs.iter().copied())
+            .collect()
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.fee
+    }
+
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn asset(&self) -> Option<&AssetAction> {
+        self.asset.as_deref()
+    }
+
+    pub fn data(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+
+    // Whether it was made by `register`, `with_tokens` or `anchor`, and
+    // pays nobody coins
+    pub fn moves_no_coins(&self) -> bool {
+        (self.name.is_some() || self.asset.is_some() || self.data.is_some())
+            && self.destination_public_key == self.source_public_key
+            && self.amount == 0
+            && self.more_outputs.is_empty()
+    }
+
+    // The bytes covered by the source signature
+    pub(crate) fn signing_bytes(&self) -> Vec<u8> {
+        self.encoded()
+    }
+}
+
+impl Encode for Output {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.destination.encode(out);
+        self.amount.encode(out);
+    }
+}
+
+impl Encode for TransactionDetails {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.source_public_key.encode(out);
+        self.destination_public_key.encode(out);
+        self.amount.encode(out);
+        self.fee.encode(out);
+        self.expires_at.encode(out);
+
+        // Left out with a single output, so those keep the hashes and
+        // signatures they had before there could be more. Names, assets
+        // and data come after the outputs in that order, each left out when
+        // it and what follows are absent, and optional when followed.
+        let asset_or_data = self.asset.is_some() || self.data.is_some();
+
+        if !self.more_outputs.is_empty() || self.name.is_some() || asset_or_data {
+            self.more_outputs[..].encode(out);
+        }
+
+        if asset_or_data {
+            self.name.encode(out);
+        } else if let Some(name) = &self.name {
+            name.encode(out);
+        }
+
+        if self.data.is_some() {
+            self.asset.encode(out);
+        } else if let Some(asset) = &self.asset {
+            asset.encode(out);
+        }
+
+        if let Some(data) = &self.data {
+            data[..].encode(out);
+        }
+    }
+}
+
+// The signature goes first, so it can be told apart from the signed bytes
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.source_signature.encode(out);
+        self.details.encode(out);
+
+        // Left out when spending from a key, like the outputs after the
+        // first in `TransactionDetails`
+        if let Some(unlock) = &self.unlock {
+            unlock.encode(out);
+        }
+    }
+}
+
+impl Transaction {
+    pub fn new(details: TransactionDetails, signature: Signature) -> Self {
+        Transaction {
+            details,
+            source_signature: signature,
+            unlock: None,
+        }
+    }
+
+    // Spends from a contract, whose address must be the source
+    pub fn with_unlock(mut self, unlock: Unlock) -> Self {
+        self.unlock = Some(Box::new(unlock));
+        self
+    }
+
+    pub fn unlock(&self) -> Option<&Unlock> {
+        self.unlock.as_deref()
+    }
+
+    // What the signatures are of
+    pub fn details(&self) -> &TransactionDetails {
+        &self.details
+    }
+
+    // Builds a transaction from our own key, ready to be sent to a node
+    pub fn create_signed(
+        secret_key: &SecretKey,
+        destination: PublicKey,
+        amount: u64,
+        fee: u64,
+    ) -> Self {
+        let details =
+            TransactionDetails::new(keys::public_key(secret_key), destination, amount, fee);
+        let signature = sign(&details, secret_key);
+
+        Transaction::new(details, signature)
+    }
+
+    pub fn source(&self) -> &PublicKey {
+        &self.details.source_public_key
+    }
+
+    // Of the first output
+    pub fn destination(&self) -> &PublicKey {
+        &self.details.destination_public_key
+    }
+
+    // Of all the outputs, `u64::MAX` when they add up to more
+    pub fn amount(&self) -> u64 {
+        self.details.amount()
+    }
+
+    pub fn total_amount(&self) -> Option<u64> {
+        self.details.total_amount()
+    }
+
+    pub fn outputs(&self) -> Vec<Output> {
+        self.details.outputs()
+    }
+
+    pub fn destinations(&self) -> impl Iterator<Item = &PublicKey> {
+        self.details.destinations()
+    }
+
+    pub fn amount_to(&self, id: &PublicKey) -> u64 {
+        self.details.amount_to(id)
+    }
+
+    pub fn fee(&self) -> u64 {
+        self.details.fee
+    }
+
+    pub fn expires_at(&self) -> Option<u64> {
+        self.details.expires_at
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.details.name()
+    }
+
+    pub fn asset(&self) -> Option<&AssetAction> {
+        self.details.asset()
+    }
+
+    pub fn moves_no_coins(&self) -> bool {
+        self.details.moves_no_coins()
+    }
+
+    pub fn data(&self) -> Option<&[u8]> {
+        self.details.data()
+    }
+
+    // What the outputs to the burn address add up to
+    pub fn burned(&self) -> u64 {
+        self.details.amount_to(&burn_address())
+    }
+
+    // Whether it can still be included in a block at `height`
+    pub fn is_expired_at(&self, height: u64) -> bool {
+        let expires_at = self.unlock.as_deref().and_then(Unlock::expires_at);
+
+        [self.details.expires_at, expires_at]
+            .iter()
+            .flatten()
+            .any(|expires_at| height > *expires_at)
+    }
+
+    // Whether it can't be included in a block at `height` yet
+    pub fn is_locked_at(&self, height: u64) -> bool {
+        self.unlock
+            .as_ref()
+            .is_some_and(|unlock| height < unlock.valid_from())
+    }
+
+    // Whether an output pays less than `threshold`. Settlements of channels
+    // pay what their state says, however little, as nobody could pay them
+    // out otherwise, and registering names, moving tokens or anchoring
+    // data pays nobody coins.
+    pub fn pays_dust(&self, threshold: u64) -> bool {
+        let settlement = self.unlock.as_deref().is_some_and(Unlock::pays_out);
+
+        !settlement
+            && !self.moves_no_coins()
+            && self
+                .outputs()
+                .iter()
+                .any(|output| output.amount < threshold)
+    }
+
+    // Encoded size in bytes
+    pub fn size(&self) -> u64 {
+        self.encoded().len() as u64
+    }
+
+    // Fee paid for every 1000 bytes
+    pub fn fee_rate(&self) -> u64 {
+        self.details.fee * 1000 / self.size()
+    }
+
+    // Covers only what the signer chose, so changing the signature of a
+    // transaction doesn't give it another identity
+    pub fn hash(&self) -> Hash {
+        Sha256::digest(&self.details.encoded()).into()
+    }
+
+    pub fn has_valid_signature(&self) -> bool {
+        match &self.unlock {
+            None => keys::verify(
+                &self.details.source_public_key,
+                &self.details.signing_bytes(),
+                &self.source_signature,
+            ),
+            Some(unlock) => {
+                unlock.contract.address() == self.details.source_public_key
+                    && unlock.allows(&self.details, &self.source_signature)
+            }
+        }
+    }
+}
+
+// Coins paid to it are gone for good: nobody has its key, and nodes reject
+// any transaction from it
+pub fn burn_address() -> PublicKey {
+    Sha256::digest(BURN_PREFIX).into()
+}
+
+pub fn sign(details: &TransactionDetails, secret_key: &SecretKey) -> Signature {
+    keys::sign(secret_key, &details.signing_bytes())
+}
+
+impl std::fmt::Display for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "transfer ${} (fee ${}) from {} to {}",
+            self.details.amount,
+            self.details.fee,
+            PublicKeyFmt(self.details.source_public_key),
+            PublicKeyFmt(self.details.destination_public_key),
+        )?;
+
+        for output in &self.details.more_outputs {
+            write!(
+                f,
+                ", ${} to {}",
+                output.amount,
+                PublicKeyFmt(output.destination)
+            )?;
+        }
+
+        if let Some(name) = &self.details.name {
+            write!(f, ", registering {}{}", NAME_PREFIX, name)?;
+        }
+
+        if let Some(asset) = &self.details.asset {
+            write!(f, ", {}", asset)?;
+        }
+
+        if let Some(data) = &self.details.data {
+            write!(f, ", carrying {} bytes", data.len())?;
+        }
+
+        Ok(())
+    }
+}
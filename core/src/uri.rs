@@ -0,0 +1,116 @@
+use super::address;
+use super::PublicKey;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+
+const SCHEME: &str = "fcoin:";
+
+// What a receiver asks to be paid, shared as an URI (for example through a
+// QR code) so the sender doesn't have to type anything:
+//
+//     fcoin:<address>?amount=<amount>&memo=<text>&expires=<unix time>
+//
+// All parameters are optional and the memo is percent-encoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentRequest {
+    pub address: PublicKey,
+    pub amount: Option<u64>,
+    pub memo: Option<String>,
+    // Seconds since the UNIX epoch after which the request must not be paid
+    pub expires: Option<u64>,
+}
+
+impl PaymentRequest {
+    pub fn new(address: PublicKey) -> Self {
+        PaymentRequest {
+            address,
+            amount: None,
+            memo: None,
+            expires: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.expires {
+            Some(expires) => now >= expires,
+            None => false,
+        }
+    }
+
+    pub fn to_uri(&self) -> String {
+        let mut parameters = Vec::new();
+
+        if let Some(amount) = self.amount {
+            parameters.push(format!("amount={}", amount));
+        }
+
+        if let Some(memo) = &self.memo {
+            parameters.push(format!(
+                "memo={}",
+                utf8_percent_encode(memo, NON_ALPHANUMERIC)
+            ));
+        }
+
+        if let Some(expires) = self.expires {
+            parameters.push(format!("expires={}", expires));
+        }
+
+        let mut uri = format!("{}{}", SCHEME, address::encode(&self.address));
+
+        if !parameters.is_empty() {
+            uri.push('?');
+            uri.push_str(&parameters.join("&"));
+        }
+
+        uri
+    }
+
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        let rest = uri
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| format!("{} is not an {} URI", uri, SCHEME))?;
+
+        let (address, query) = match rest.find('?') {
+            Some(position) => (&rest[..position], &rest[position + 1..]),
+            None => (rest, ""),
+        };
+
+        let mut request = PaymentRequest::new(address::decode(address)?);
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (key, value) = match parameter.find('=') {
+                Some(position) => (&parameter[..position], &parameter[position + 1..]),
+                None => (parameter, ""),
+            };
+
+            let value = percent_decode_str(value)
+                .decode_utf8()
+                .map_err(|_| format!("Invalid {} in payment URI", key))?;
+
+            match key {
+                "amount" => {
+                    request.amount = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid amount in payment URI: {}", value))?,
+                    )
+                }
+                "memo" => request.memo = Some(value.into_owned()),
+                "expires" => {
+                    request.expires = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("Invalid expiry in payment URI: {}", value))?,
+                    )
+                }
+                // Unknown parameters are ignored unless they are required to
+                // understand the request
+                key if key.starts_with("req-") => {
+                    return Err(format!("Unsupported required parameter {}", key))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(request)
+    }
+}
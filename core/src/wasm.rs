@@ -0,0 +1,71 @@
+use super::{address, hex, keys, merkle, message, transaction};
+use std::convert::TryInto;
+use wasm_bindgen::prelude::*;
+
+// Bindings for browser wallets. Keys never leave the page, the serialized
+// transaction is what gets submitted to a node.
+
+fn secret_key(bytes: &[u8]) -> Result<crate::SecretKey, JsValue> {
+    let mut secret_key = crate::SecretKey::zeroed();
+
+    if bytes.len() != secret_key.as_bytes().len() {
+        return Err(JsValue::from_str("Secret keys must have 32 bytes"));
+    }
+
+    secret_key.as_mut_bytes().copy_from_slice(bytes);
+
+    Ok(secret_key)
+}
+
+#[wasm_bindgen]
+pub fn address_from_secret_key(secret: &[u8]) -> Result<String, JsValue> {
+    Ok(address::encode(&keys::public_key(&secret_key(secret)?)))
+}
+
+#[wasm_bindgen]
+pub fn is_valid_address(text: &str) -> bool {
+    address::decode(text).is_ok()
+}
+
+// Returns the bincode encoding of the signed transaction
+#[wasm_bindgen]
+pub fn create_signed_transaction(
+    secret: &[u8],
+    destination: &str,
+    amount: u64,
+    fee: u64,
+) -> Result<Vec<u8>, JsValue> {
+    let destination = address::decode(destination).map_err(|issue| JsValue::from_str(&issue))?;
+    let transaction =
+        transaction::Transaction::create_signed(&secret_key(secret)?, destination, amount, fee);
+
+    bincode::serialize(&transaction).map_err(|issue| JsValue::from_str(&issue.to_string()))
+}
+
+// Checks a bincode encoded Merkle proof for `leaf` against `root`
+#[wasm_bindgen]
+pub fn verify_merkle_proof(proof: &[u8], leaf: &[u8], root: &[u8]) -> bool {
+    let proof: merkle::MerkleProof = match bincode::deserialize(proof) {
+        Ok(proof) => proof,
+        Err(_) => return false,
+    };
+
+    match (leaf.try_into(), root.try_into()) {
+        (Ok(leaf), Ok(root)) => proof.verify(leaf, root),
+        _ => false,
+    }
+}
+
+// Hex encoded signature proving control of the address of `secret`
+#[wasm_bindgen]
+pub fn sign_message(secret: &[u8], text: &str) -> Result<String, JsValue> {
+    Ok(hex::encode(&message::sign(&secret_key(secret)?, text)))
+}
+
+#[wasm_bindgen]
+pub fn verify_message(address: &str, signature: &str, text: &str) -> bool {
+    match (address::decode(address), hex::decode_array::<64>(signature)) {
+        (Ok(public_key), Ok(signature)) => message::verify(&public_key, text, &signature),
+        _ => false,
+    }
+}
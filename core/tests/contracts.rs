@@ -0,0 +1,284 @@
+// Spending from a contract takes the signatures its terms name, at the
+// heights they allow
+use fcoin_core::contract::{
+    hash_lock, Channel, ChannelState, Close, Contract, ContractSignature, SignedState, Unlock,
+};
+use fcoin_core::keys::{self, SecretKey};
+use fcoin_core::predicate::{Predicate, Witness};
+use fcoin_core::transaction::{sign, Transaction, TransactionDetails};
+
+const RECIPIENT_KEY: [u8; 32] = [1; 32];
+const REFUND_KEY: [u8; 32] = [2; 32];
+const SECRET: [u8; 32] = [3; 32];
+const TIMEOUT: u64 = 10;
+
+fn key(bytes: &[u8; 32]) -> SecretKey {
+    SecretKey::from_bytes(&mut bytes.clone())
+}
+
+fn contract() -> Contract {
+    Contract::HashTimeLock {
+        recipient: keys::public_key(&key(&RECIPIENT_KEY)),
+        hash_lock: hash_lock(&SECRET),
+        timeout: TIMEOUT,
+        refund: keys::public_key(&key(&REFUND_KEY)),
+    }
+}
+
+fn spend(secret_key: &[u8; 32], preimage: Option<[u8; 32]>) -> Transaction {
+    let contract = contract();
+    let details =
+        TransactionDetails::new(contract.address(), keys::public_key(&key(secret_key)), 5, 1);
+    let signature = sign(&details, &key(secret_key));
+
+    Transaction::new(details, signature).with_unlock(Unlock {
+        preimage,
+        ..Unlock::new(contract)
+    })
+}
+
+#[test]
+fn recipient_redeems_with_the_secret_until_the_timeout() {
+    let transaction = spend(&RECIPIENT_KEY, Some(SECRET));
+
+    assert!(transaction.has_valid_signature());
+    assert!(!transaction.is_locked_at(0));
+    assert!(!transaction.is_expired_at(TIMEOUT));
+    assert!(transaction.is_expired_at(TIMEOUT + 1));
+}
+
+#[test]
+fn wrong_secret_is_rejected() {
+    assert!(!spend(&RECIPIENT_KEY, Some([4; 32])).has_valid_signature());
+}
+
+#[test]
+fn refund_is_locked_until_the_timeout() {
+    let transaction = spend(&REFUND_KEY, None);
+
+    assert!(transaction.has_valid_signature());
+    assert!(transaction.is_locked_at(TIMEOUT));
+    assert!(!transaction.is_locked_at(TIMEOUT + 1));
+    assert!(!transaction.is_expired_at(u64::MAX));
+}
+
+#[test]
+fn only_the_named_keys_can_spend() {
+    assert!(!spend(&REFUND_KEY, Some(SECRET)).has_valid_signature());
+    assert!(!spend(&RECIPIENT_KEY, None).has_valid_signature());
+}
+
+#[test]
+fn unlock_must_match_the_source() {
+    let other = Contract::HashTimeLock {
+        recipient: keys::public_key(&key(&RECIPIENT_KEY)),
+        hash_lock: hash_lock(&SECRET),
+        timeout: TIMEOUT + 1,
+        refund: keys::public_key(&key(&REFUND_KEY)),
+    };
+    let details = TransactionDetails::new(
+        contract().address(),
+        keys::public_key(&key(&RECIPIENT_KEY)),
+        5,
+        1,
+    );
+    let signature = sign(&details, &key(&RECIPIENT_KEY));
+    let transaction = Transaction::new(details, signature).with_unlock(Unlock {
+        preimage: Some(SECRET),
+        ..Unlock::new(other)
+    });
+
+    assert!(!transaction.has_valid_signature());
+}
+
+fn channel() -> Channel {
+    Channel {
+        parties: [
+            keys::public_key(&key(&RECIPIENT_KEY)),
+            keys::public_key(&key(&REFUND_KEY)),
+        ],
+        dispute: 5,
+        nonce: 7,
+    }
+}
+
+fn signed_state(sequence: u64, balances: [u64; 2]) -> SignedState {
+    let state = ChannelState { sequence, balances };
+    let signatures = [
+        state.sign(&channel(), &key(&RECIPIENT_KEY)),
+        state.sign(&channel(), &key(&REFUND_KEY)),
+    ];
+
+    SignedState { state, signatures }
+}
+
+fn close(from: Contract, state: SignedState, timeout: u64, destination: [u8; 32]) -> Transaction {
+    let details = TransactionDetails::new(from.address(), destination, 5, 1);
+    let signature = sign(&details, &key(&REFUND_KEY));
+    let close = Close { state, timeout };
+
+    Transaction::new(details, signature).with_unlock(Unlock {
+        close: Some(close),
+        ..Unlock::new(from)
+    })
+}
+
+#[test]
+fn channel_closes_to_the_settlement_of_a_signed_state() {
+    let state = signed_state(3, [4, 6]);
+    let settlement = Close {
+        state: state.clone(),
+        timeout: 20,
+    }
+    .settlement(&channel());
+    let transaction = close(
+        Contract::Channel(channel()),
+        state.clone(),
+        20,
+        settlement.address(),
+    );
+
+    assert!(transaction.has_valid_signature());
+    // Leaves the whole dispute window to the other side
+    assert!(!transaction.is_expired_at(15));
+    assert!(transaction.is_expired_at(16));
+
+    assert!(!close(Contract::Channel(channel()), state, 20, [9; 32]).has_valid_signature());
+}
+
+#[test]
+fn state_signed_by_one_side_cannot_close() {
+    let mut state = signed_state(3, [4, 6]);
+
+    state.signatures[0] = state.signatures[1];
+
+    let destination = Close {
+        state: state.clone(),
+        timeout: 20,
+    }
+    .settlement(&channel())
+    .address();
+
+    assert!(!close(Contract::Channel(channel()), state, 20, destination).has_valid_signature());
+}
+
+#[test]
+fn settlement_is_only_replaced_by_later_states() {
+    let settlement = Contract::Settlement {
+        channel: channel(),
+        state: signed_state(3, [4, 6]).state,
+        timeout: 20,
+    };
+    let dispute = |sequence| {
+        let state = signed_state(sequence, [6, 4]);
+        let destination = Close {
+            state: state.clone(),
+            timeout: 30,
+        }
+        .settlement(&channel())
+        .address();
+
+        close(settlement.clone(), state, 30, destination)
+    };
+
+    assert!(dispute(4).has_valid_signature());
+    assert!(!dispute(4).is_expired_at(20));
+    assert!(dispute(4).is_expired_at(21));
+    assert!(!dispute(3).has_valid_signature());
+}
+
+#[test]
+fn settlement_pays_out_its_state_after_the_timeout() {
+    let settlement = Contract::Settlement {
+        channel: channel(),
+        state: signed_state(3, [4, 0]).state,
+        timeout: 20,
+    };
+    let payout = |destination: [u8; 32]| {
+        let details = TransactionDetails::new(settlement.address(), destination, 4, 1);
+        let signature = sign(&details, &key(&REFUND_KEY));
+
+        Transaction::new(details, signature).with_unlock(Unlock::new(settlement.clone()))
+    };
+    let transaction = payout(keys::public_key(&key(&RECIPIENT_KEY)));
+
+    assert!(transaction.has_valid_signature());
+    assert!(transaction.is_locked_at(20));
+    assert!(!transaction.is_locked_at(21));
+    // However little each side gets
+    assert!(!transaction.pays_dust(100));
+
+    assert!(!payout(keys::public_key(&key(&REFUND_KEY))).has_valid_signature());
+}
+
+const ARBITER_KEY: [u8; 32] = [5; 32];
+
+// Two of the recipient, the refund key and the arbiter, or the refund key
+// alone once the timeout passed
+fn predicate() -> Predicate {
+    let keys = [RECIPIENT_KEY, REFUND_KEY, ARBITER_KEY]
+        .iter()
+        .map(|bytes| keys::public_key(&key(bytes)))
+        .collect();
+
+    Predicate::multisig(2, keys).or(Predicate::signature(keys::public_key(&key(&REFUND_KEY)))
+        .and(Predicate::valid_from(TIMEOUT + 1)))
+}
+
+fn spend_predicate(signers: &[[u8; 32]], choice: u64) -> Transaction {
+    let contract = Contract::Predicate(predicate());
+    let details = TransactionDetails::new(contract.address(), [9; 32], 5, 1);
+    let signatures = signers
+        .iter()
+        .map(|bytes| {
+            (
+                keys::public_key(&key(bytes)),
+                ContractSignature(sign(&details, &key(bytes))),
+            )
+        })
+        .collect();
+    let witness = Witness {
+        signatures,
+        choices: vec![choice],
+        ..Witness::default()
+    };
+    let signature = sign(&details, &key(&signers[0]));
+
+    Transaction::new(details, signature).with_unlock(Unlock {
+        witness: Some(witness),
+        ..Unlock::new(contract)
+    })
+}
+
+#[test]
+fn multisig_takes_enough_of_the_keys() {
+    let transaction = spend_predicate(&[RECIPIENT_KEY, ARBITER_KEY], 0);
+
+    assert!(transaction.has_valid_signature());
+    assert!(!transaction.is_locked_at(0));
+    assert!(!spend_predicate(&[RECIPIENT_KEY], 0).has_valid_signature());
+    assert!(!spend_predicate(&[RECIPIENT_KEY, RECIPIENT_KEY], 0).has_valid_signature());
+}
+
+#[test]
+fn branch_picked_by_the_witness_sets_the_heights() {
+    let transaction = spend_predicate(&[REFUND_KEY], 1);
+
+    assert!(transaction.has_valid_signature());
+    assert!(transaction.is_locked_at(TIMEOUT));
+    assert!(!transaction.is_locked_at(TIMEOUT + 1));
+    assert!(!spend_predicate(&[ARBITER_KEY], 1).has_valid_signature());
+    assert!(!spend_predicate(&[REFUND_KEY], 2).has_valid_signature());
+}
+
+#[test]
+fn preimage_conditions_take_the_secret() {
+    let predicate = Predicate::preimage(hash_lock(&SECRET));
+    let revealed = Witness {
+        preimages: vec![SECRET],
+        ..Witness::default()
+    };
+
+    assert!(predicate.holds(&revealed, b"anything"));
+    assert!(!predicate.holds(&Witness::default(), b"anything"));
+}
@@ -0,0 +1,226 @@
+// Golden vectors for the consensus encoding. If any of these change, so do
+// every transaction hash and signature, which forks the network.
+use fcoin_core::asset::AssetAction;
+use fcoin_core::encoding::Encode;
+use fcoin_core::hex;
+use fcoin_core::keys::{self, SecretKey};
+use fcoin_core::transaction::{burn_address, sign, Output, Transaction, TransactionDetails};
+
+const DESTINATION: [u8; 32] = [2; 32];
+
+const PUBLIC_KEY: &str = "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+
+fn secret_key() -> SecretKey {
+    SecretKey::from_bytes(&mut [1; 32])
+}
+
+fn signed(details: TransactionDetails) -> Transaction {
+    let signature = sign(&details, &secret_key());
+
+    Transaction::new(details, signature)
+}
+
+#[test]
+fn primitives() {
+    assert_eq!(hex::encode(&5u64.encoded()), "0500000000000000");
+    assert_eq!(hex::encode(&None::<u64>.encoded()), "00");
+    assert_eq!(hex::encode(&Some(10u64).encoded()), "010a00000000000000");
+    assert_eq!(
+        hex::encode(&[[7u8; 2], [8u8; 2]][..].encoded()),
+        "020000000000000007070808"
+    );
+}
+
+#[test]
+fn transaction_without_expiry() {
+    let details = TransactionDetails::new(keys::public_key(&secret_key()), DESTINATION, 5, 1);
+    let transaction = signed(details.clone());
+
+    let expected_details = format!(
+        "{}{}{}{}{}",
+        PUBLIC_KEY,
+        "0202020202020202020202020202020202020202020202020202020202020202",
+        "0500000000000000",
+        "0100000000000000",
+        "00"
+    );
+
+    assert_eq!(hex::encode(&details.encoded()), expected_details);
+    assert_eq!(
+        hex::encode(&transaction.encoded()),
+        format!(
+            "{}{}",
+            "bc8186314b48d3ac56206a090f7428337911e67366a66dc14ec0731155c95d34\
+             875a8c895ac9c48d38d76a325157b14e42dde447974cfe37d92be19aa4f5f709",
+            expected_details
+        )
+    );
+    assert_eq!(
+        hex::encode(&transaction.hash()),
+        "8a1b3abebefbe9e8a8b6b5eb8d57bb35093628b6c0e9eac6d93f3e31f12f42e0"
+    );
+    assert!(transaction.has_valid_signature());
+}
+
+#[test]
+fn transaction_with_expiry() {
+    let details = TransactionDetails::new(keys::public_key(&secret_key()), DESTINATION, 5, 1)
+        .with_expiry(Some(10));
+    let transaction = signed(details.clone());
+
+    let expected_details = format!(
+        "{}{}{}{}{}",
+        PUBLIC_KEY,
+        "0202020202020202020202020202020202020202020202020202020202020202",
+        "0500000000000000",
+        "0100000000000000",
+        "010a00000000000000"
+    );
+
+    assert_eq!(hex::encode(&details.encoded()), expected_details);
+    assert_eq!(
+        hex::encode(&transaction.encoded()),
+        format!(
+            "{}{}",
+            "b72bd6c1f9a07bbb32a11814c26a9c67f55d16d685179d6d74f9bc5527963b46\
+             e8d8fcd94421e9a27b7d4f09f02400f5d76899c61eb27665b5c49303e0788c0a",
+            expected_details
+        )
+    );
+    assert_eq!(
+        hex::encode(&transaction.hash()),
+        "916d549bc577dd43c016caeb552bc2cbf64e3b112dd6c1f45989c78231919dda"
+    );
+}
+
+#[test]
+fn transaction_with_several_outputs() {
+    let outputs = [
+        Output {
+            destination: DESTINATION,
+            amount: 5,
+        },
+        Output {
+            destination: [3; 32],
+            amount: 6,
+        },
+    ];
+    let details =
+        TransactionDetails::to_many(keys::public_key(&secret_key()), &outputs, 1).unwrap();
+    let transaction = signed(details.clone());
+
+    let expected_details = format!(
+        "{}{}{}{}{}{}{}{}",
+        PUBLIC_KEY,
+        "0202020202020202020202020202020202020202020202020202020202020202",
+        "0500000000000000",
+        "0100000000000000",
+        "00",
+        "0100000000000000",
+        "0303030303030303030303030303030303030303030303030303030303030303",
+        "0600000000000000"
+    );
+
+    assert_eq!(hex::encode(&details.encoded()), expected_details);
+    assert_eq!(
+        hex::encode(&transaction.hash()),
+        "2eb08003a23c8b00d44bf6a4a3fa28ad8543141302ba8f914f39adc754b3eb3e"
+    );
+    assert_eq!(transaction.amount(), 11);
+    assert!(transaction.has_valid_signature());
+}
+
+#[test]
+fn transaction_registering_a_name() {
+    let details = TransactionDetails::register(keys::public_key(&secret_key()), "alice", 10);
+    let transaction = signed(details.clone());
+
+    let expected_details = format!(
+        "{}{}{}{}{}{}{}{}",
+        PUBLIC_KEY,
+        PUBLIC_KEY,
+        "0000000000000000",
+        "0a00000000000000",
+        "00",
+        "0000000000000000",
+        "0500000000000000",
+        "616c696365"
+    );
+
+    assert_eq!(hex::encode(&details.encoded()), expected_details);
+    assert_eq!(
+        hex::encode(&transaction.hash()),
+        "ad10dc867d35e3973f7b795bc780e2f8632f2147f5e4e4a21bf57912b5236198"
+    );
+    assert!(transaction.moves_no_coins());
+    assert!(transaction.has_valid_signature());
+}
+
+#[test]
+fn transaction_moving_tokens() {
+    let action = AssetAction::Transfer {
+        asset: "gold".to_string(),
+        outputs: vec![Output {
+            destination: DESTINATION,
+            amount: 7,
+        }],
+    };
+    let details = TransactionDetails::with_tokens(keys::public_key(&secret_key()), action, 10);
+    let transaction = signed(details.clone());
+
+    let expected_details = format!(
+        "{}{}{}{}{}{}{}{}{}{}{}{}{}",
+        PUBLIC_KEY,
+        PUBLIC_KEY,
+        "0000000000000000",
+        "0a00000000000000",
+        "00",
+        "0000000000000000",
+        "00",
+        "01",
+        "0400000000000000",
+        "676f6c64",
+        "0100000000000000",
+        "0202020202020202020202020202020202020202020202020202020202020202",
+        "0700000000000000"
+    );
+
+    assert_eq!(hex::encode(&details.encoded()), expected_details);
+    assert_eq!(
+        hex::encode(&transaction.hash()),
+        "7d1b0609ae11b93bde2c410d9ba52165a5282da1b9bf1a3de185352a5f572b46"
+    );
+    assert!(transaction.moves_no_coins());
+    assert!(transaction.has_valid_signature());
+}
+
+#[test]
+fn transaction_burning_with_data() {
+    let details = TransactionDetails::new(keys::public_key(&secret_key()), burn_address(), 5, 1)
+        .with_data(Some(b"hi".to_vec()));
+    let transaction = signed(details.clone());
+
+    let expected_details = format!(
+        "{}{}{}{}{}{}{}{}{}",
+        PUBLIC_KEY,
+        hex::encode(&burn_address()),
+        "0500000000000000",
+        "0100000000000000",
+        "00",
+        "0000000000000000",
+        "00",
+        "00",
+        "02000000000000006869"
+    );
+
+    assert_eq!(hex::encode(&details.encoded()), expected_details);
+    assert_eq!(
+        hex::encode(&burn_address()),
+        "aae241287f8c0497d998ad8d56ea851cf52340fafd2850821c57326fbc7baf14"
+    );
+    assert_eq!(
+        hex::encode(&transaction.hash()),
+        "274931906531f677c1236da88de6225f6fd19ed2468ebaeab70a5f30d93d7afb"
+    );
+    assert_eq!(transaction.burned(), 5);
+}
@@ -0,0 +1,73 @@
+// Third parties must not be able to produce another valid signature for a
+// transaction, nor change its hash by touching the signature
+use fcoin_core::keys::{self, SecretKey};
+use fcoin_core::transaction::{sign, Transaction, TransactionDetails};
+
+const DESTINATION: [u8; 32] = [2; 32];
+
+// Order of the ed25519 group, little-endian
+const GROUP_ORDER: [u8; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+fn secret_key() -> SecretKey {
+    SecretKey::from_bytes(&mut [1; 32])
+}
+
+fn details() -> TransactionDetails {
+    TransactionDetails::new(keys::public_key(&secret_key()), DESTINATION, 5, 1)
+}
+
+// Adds the group order to S (the second half of the signature), the usual
+// way of malleating an ed25519 signature
+fn non_canonical(signature: [u8; 64]) -> [u8; 64] {
+    let mut mutated = signature;
+    let mut carry = 0u16;
+
+    for (i, byte) in GROUP_ORDER.iter().enumerate() {
+        let sum = mutated[32 + i] as u16 + *byte as u16 + carry;
+
+        mutated[32 + i] = sum as u8;
+        carry = sum >> 8;
+    }
+
+    mutated
+}
+
+#[test]
+fn canonical_signature_is_accepted() {
+    let details = details();
+    let transaction = Transaction::new(details.clone(), sign(&details, &secret_key()));
+
+    assert!(transaction.has_valid_signature());
+}
+
+#[test]
+fn non_canonical_signature_is_rejected() {
+    let details = details();
+    let signature = non_canonical(sign(&details, &secret_key()));
+
+    assert!(!Transaction::new(details, signature).has_valid_signature());
+}
+
+#[test]
+fn flipped_signature_bit_is_rejected() {
+    let details = details();
+    let mut signature = sign(&details, &secret_key());
+
+    signature[10] ^= 1;
+
+    assert!(!Transaction::new(details, signature).has_valid_signature());
+}
+
+#[test]
+fn signature_does_not_change_the_hash() {
+    let details = details();
+    let signature = sign(&details, &secret_key());
+
+    let original = Transaction::new(details.clone(), signature);
+    let mutated = Transaction::new(details, non_canonical(signature));
+
+    assert_eq!(original.hash(), mutated.hash());
+}
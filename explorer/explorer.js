@@ -0,0 +1,171 @@
+// Pages are picked from the part of the URL after #, so the node only has to
+// serve this file, the stylesheet and index.html
+const PAGE_SIZE = 20;
+
+const page = document.getElementById("page");
+
+function escape(text) {
+  const element = document.createElement("span");
+  element.textContent = String(text);
+  return element.innerHTML;
+}
+
+function link(kind, value) {
+  return `<a class="hash" href="#/${kind}/${encodeURIComponent(value)}">${escape(value)}</a>`;
+}
+
+function time(seconds) {
+  return new Date(seconds * 1000).toLocaleString();
+}
+
+function table(headings, rows) {
+  const head = headings.map((heading) => `<th>${heading}</th>`).join("");
+  const body = rows
+    .map((row) => `<tr>${row.map((cell) => `<td>${cell}</td>`).join("")}</tr>`)
+    .join("");
+
+  return `<table><tr>${head}</tr>${body}</table>`;
+}
+
+function details(fields) {
+  const rows = fields.map(([name, value]) => `<tr><th>${name}</th><td>${value}</td></tr>`);
+
+  return `<table>${rows.join("")}</table>`;
+}
+
+async function get(path) {
+  const response = await fetch(path);
+
+  if (!response.ok) {
+    throw new Error(await response.text());
+  }
+
+  return response.json();
+}
+
+async function showBlocks(offset) {
+  const result = await get(`/blocks?offset=${offset}&limit=${PAGE_SIZE}`);
+  const rows = result.blocks.map((block) => [
+    block.height,
+    link("block", block.hash),
+    time(block.time),
+    block.transactions,
+    block.size,
+  ]);
+
+  let navigation = "";
+
+  if (offset > 0) {
+    navigation += `<a href="#/blocks/${Math.max(offset - PAGE_SIZE, 0)}">Newer</a> `;
+  }
+
+  if (offset + PAGE_SIZE < result.total) {
+    navigation += `<a href="#/blocks/${offset + PAGE_SIZE}">Older</a>`;
+  }
+
+  page.innerHTML = `
+    <h2>Latest blocks</h2>
+    <p>${result.total} blocks in the chain</p>
+    ${table(["Height", "Hash", "Time", "Transactions", "Size"], rows)}
+    <p>${navigation}</p>`;
+}
+
+async function showBlock(hash) {
+  const block = await get(`/blocks/${hash}`);
+  const rows = block.transactions.map((transaction) => [
+    link("transaction", transaction.hash),
+    link("address", transaction.source),
+    transaction.outputs.length > 1
+      ? `${transaction.outputs.length} recipients`
+      : link("address", transaction.destination),
+    transaction.amount,
+    transaction.fee,
+  ]);
+
+  page.innerHTML = `
+    <h2>Block ${block.height}</h2>
+    ${details([
+      ["Hash", `<span class="hash">${escape(block.hash)}</span>`],
+      ["Previous", link("block", block.previous_hash)],
+      ["Time", time(block.time)],
+      ["Producer", link("address", block.producer)],
+      ["Confirmations", block.confirmations],
+      ["Size", `${block.size} bytes`],
+    ])}
+    <h3>Transactions</h3>
+    ${table(["Hash", "From", "To", "Amount", "Fee"], rows)}`;
+}
+
+async function showTransaction(hash) {
+  const transaction = await get(`/transactions/${hash}`);
+
+  page.innerHTML = `
+    <h2>Transaction</h2>
+    ${details([
+      ["Hash", `<span class="hash">${escape(transaction.hash)}</span>`],
+      ["Block", link("block", transaction.block_hash)],
+      ["Height", transaction.height],
+      ["Confirmations", transaction.confirmations],
+      ["From", link("address", transaction.source)],
+      [
+        "To",
+        transaction.outputs
+          .map((output) => `${link("address", output.destination)} ${output.amount}`)
+          .join("<br>"),
+      ],
+      ["Amount", transaction.amount],
+      ["Fee", transaction.fee],
+    ])}`;
+}
+
+async function showAddress(address) {
+  const info = await get(`/addresses/${address}`);
+
+  page.innerHTML = `
+    <h2>Address</h2>
+    ${details([
+      ["Address", `<span class="hash">${escape(info.address)}</span>`],
+      ["Balance", info.balance],
+      ["Transactions", info.transactions],
+      ["First seen at", info.first_seen ?? "-"],
+      ["Last seen at", info.last_seen ?? "-"],
+    ])}`;
+}
+
+async function route() {
+  const [kind, value] = location.hash.replace(/^#\/?/, "").split("/");
+
+  try {
+    switch (kind) {
+      case "block":
+        return await showBlock(value);
+      case "transaction":
+        return await showTransaction(value);
+      case "address":
+        return await showAddress(decodeURIComponent(value));
+      case "blocks":
+        return await showBlocks(Number(value) || 0);
+      default:
+        return await showBlocks(0);
+    }
+  } catch (error) {
+    page.innerHTML = `<p class="error">${escape(error.message)}</p>`;
+  }
+}
+
+// Hashes are 64 hex digits and could be either a block or a transaction
+document.getElementById("search").addEventListener("submit", async (event) => {
+  event.preventDefault();
+
+  const query = document.getElementById("query").value.trim();
+
+  if (/^[0-9a-fA-F]{64}$/.test(query)) {
+    const block = await fetch(`/blocks/${query}`);
+    location.hash = block.ok ? `#/block/${query}` : `#/transaction/${query}`;
+  } else {
+    location.hash = `#/address/${encodeURIComponent(query)}`;
+  }
+});
+
+window.addEventListener("hashchange", route);
+route();
@@ -0,0 +1,288 @@
+use super::migrations::{self, Format};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+
+// Entries in each table
+const MAX_NEW: usize = 1000;
+const MAX_TRIED: usize = 1000;
+// Addresses we never got through to are forgotten after this many attempts
+const MAX_FAILED_ATTEMPTS: u32 = 10;
+// Seconds before dialing an address again
+pub const RETRY_DELAY: u64 = 10 * 60;
+// Outbound peers we reconnect to first after a restart
+pub const MAX_ANCHORS: usize = 2;
+
+pub const ADDRESS_BOOK_FORMAT: Format = Format {
+    name: "address book",
+    migrations: &[migrations::unversioned],
+};
+pub const ANCHORS_FORMAT: Format = Format {
+    name: "anchors",
+    migrations: &[migrations::unversioned],
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AddressInfo {
+    // Unix time of when a peer last told us about the address, or we were
+    // last connected to it
+    pub last_seen: u64,
+    pub last_attempt: Option<u64>,
+    pub last_success: Option<u64>,
+    pub attempts: u32,
+    pub successes: u32,
+}
+
+impl AddressInfo {
+    // Share of our connections that got through, starting at one half
+    pub fn success_rate(&self) -> f64 {
+        (self.successes as f64 + 1.0) / (self.attempts as f64 + 2.0)
+    }
+}
+
+// Addresses of other nodes, kept across restarts to pick who to connect to.
+// Those we heard of go to `new`, and move to `tried` once we connect to
+// them, so addresses that may not even exist can't crowd out good ones.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AddressBook {
+    new: HashMap<SocketAddr, AddressInfo>,
+    tried: HashMap<SocketAddr, AddressInfo>,
+}
+
+impl AddressBook {
+    // An empty book when there is no file yet
+    pub fn load(path: &str) -> Result<Self, String> {
+        match std::fs::read(path) {
+            Ok(file) => Self::decode(file)
+                .map_err(|issue| format!("Could not read the address book {}: {}", path, issue)),
+            Err(issue) if issue.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(issue) => Err(format!("Could not read {}: {}", path, issue)),
+        }
+    }
+
+    // From the content of a file saved by any version
+    pub fn decode(file: Vec<u8>) -> Result<Self, String> {
+        let (_, content) = ADDRESS_BOOK_FORMAT.decode(file)?;
+
+        bincode::deserialize(&content).map_err(|issue| issue.to_string())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let content = bincode::serialize(self).map_err(|issue| issue.to_string())?;
+
+        super::backup::write_atomically(path, &ADDRESS_BOOK_FORMAT.encode(&content))
+    }
+
+    pub fn len(&self) -> usize {
+        self.new.len() + self.tried.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn tried_len(&self) -> usize {
+        self.tried.len()
+    }
+
+    // Returns whether the address is new to us
+    pub fn add(&mut self, address: SocketAddr, now: u64) -> bool {
+        if let Some(info) = self.tried.get_mut(&address) {
+            info.last_seen = info.last_seen.max(now);
+
+            return false;
+        }
+
+        if let Some(info) = self.new.get_mut(&address) {
+            info.last_seen = info.last_seen.max(now);
+
+            return false;
+        }
+
+        if self.new.len() >= MAX_NEW {
+            evict(&mut self.new, |info| info.last_seen as f64);
+        }
+
+        self.new.insert(
+            address,
+            AddressInfo {
+                last_seen: now,
+                ..Default::default()
+            },
+        );
+
+        true
+    }
+
+    pub fn attempted(&mut self, address: &SocketAddr, now: u64) {
+        let info = match self.tried.get_mut(address) {
+            Some(info) => info,
+            None => self.new.entry(*address).or_default(),
+        };
+
+        info.attempts += 1;
+        info.last_attempt = Some(now);
+
+        if info.successes == 0 && info.attempts >= MAX_FAILED_ATTEMPTS {
+            self.new.remove(address);
+        }
+    }
+
+    pub fn connected(&mut self, address: &SocketAddr, now: u64) {
+        let mut info = self
+            .new
+            .remove(address)
+            .or_else(|| self.tried.remove(address))
+            .unwrap_or_default();
+
+        info.successes += 1;
+        info.last_success = Some(now);
+        info.last_seen = now;
+
+        if self.tried.len() >= MAX_TRIED {
+            evict(&mut self.tried, AddressInfo::success_rate);
+        }
+
+        self.tried.insert(*address, info);
+    }
+
+    // Up to `count` addresses to connect to, the best tried and new ones in
+    // turns, each from a network group not in `groups`. Skips `exclude`, and
+    // the ones we failed to connect to (or are still connecting to) in the
+    // last `RETRY_DELAY` seconds.
+    pub fn select(
+        &self,
+        count: usize,
+        exclude: &HashSet<SocketAddr>,
+        mut groups: HashSet<Vec<u8>>,
+        now: u64,
+    ) -> Vec<SocketAddr> {
+        let tried = ranked(&self.tried, exclude, now);
+        let new = ranked(&self.new, exclude, now);
+        let mut selected = Vec::new();
+
+        for index in 0..tried.len().max(new.len()) {
+            for table in [&tried, &new] {
+                if let Some(address) = table.get(index) {
+                    if selected.len() < count && groups.insert(network_group(address)) {
+                        selected.push(*address);
+                    }
+                }
+            }
+        }
+
+        selected
+    }
+
+    // An address we heard of but couldn't connect to yet, the ones we tried
+    // the least first
+    pub fn feeler_candidate(&self, exclude: &HashSet<SocketAddr>, now: u64) -> Option<SocketAddr> {
+        ranked(&self.new, exclude, now).into_iter().next()
+    }
+
+    // What we share with peers, the ones we connected to first
+    pub fn addresses(&self, count: usize) -> Vec<SocketAddr> {
+        self.tried
+            .keys()
+            .chain(self.new.keys())
+            .take(count)
+            .cloned()
+            .collect()
+    }
+}
+
+// Reads the anchors saved by the last run, if any
+pub fn load_anchors(path: &str) -> Vec<SocketAddr> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|file| decode_anchors(file).ok())
+        .unwrap_or_default()
+}
+
+// From the content of a file saved by any version
+pub fn decode_anchors(file: Vec<u8>) -> Result<Vec<SocketAddr>, String> {
+    let (_, content) = ANCHORS_FORMAT.decode(file)?;
+
+    bincode::deserialize(&content).map_err(|issue| issue.to_string())
+}
+
+pub fn save_anchors(path: &str, anchors: &[SocketAddr]) -> Result<(), String> {
+    let content = bincode::serialize(anchors).map_err(|issue| issue.to_string())?;
+
+    super::backup::write_atomically(path, &ANCHORS_FORMAT.encode(&content))
+}
+
+// Whether other nodes on the internet could reach `ip`
+pub fn is_routable(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation())
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+
+            // Unique local (fc00::/7) and link local (fe80::/10) ones aren't
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
+
+// Addresses an attacker could easily get many of: the same /16 for IPv4 and
+// /32 for IPv6. Unroutable addresses are each their own group, so nodes on a
+// local network can all be connected to.
+pub fn network_group(address: &SocketAddr) -> Vec<u8> {
+    match address.ip() {
+        ip if !is_routable(&ip) => address.to_string().into_bytes(),
+        IpAddr::V4(ip) => ip.octets()[..2].to_vec(),
+        IpAddr::V6(ip) => ip.octets()[..4].to_vec(),
+    }
+}
+
+// Best first
+fn ranked(
+    table: &HashMap<SocketAddr, AddressInfo>,
+    exclude: &HashSet<SocketAddr>,
+    now: u64,
+) -> Vec<SocketAddr> {
+    let mut candidates: Vec<(&SocketAddr, &AddressInfo)> = table
+        .iter()
+        .filter(|(address, info)| {
+            !exclude.contains(address)
+                && info.last_attempt.is_none_or(|attempt| {
+                    attempt + RETRY_DELAY <= now || info.last_success >= Some(attempt)
+                })
+        })
+        .collect();
+
+    candidates.sort_by(|(_, a), (_, b)| {
+        b.success_rate()
+            .partial_cmp(&a.success_rate())
+            .unwrap()
+            .then(b.last_seen.cmp(&a.last_seen))
+    });
+
+    candidates
+        .into_iter()
+        .map(|(address, _)| *address)
+        .collect()
+}
+
+// Removes the entry `value` is lowest for
+fn evict(table: &mut HashMap<SocketAddr, AddressInfo>, value: impl Fn(&AddressInfo) -> f64) {
+    let worst = table
+        .iter()
+        .min_by(|(_, a), (_, b)| value(a).partial_cmp(&value(b)).unwrap())
+        .map(|(address, _)| *address);
+
+    if let Some(address) = worst {
+        table.remove(&address);
+    }
+}
@@ -0,0 +1,533 @@
+use super::blockchain::{Block, Hash, PublicKey};
+use super::events::{self, ChainEvent};
+use super::migrations::{self, Format};
+use super::network::Emission;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+use tokio::sync::Mutex;
+
+// Blocks between the snapshots of the index saved to disk
+pub const SNAPSHOT_INTERVAL: u64 = 1000;
+// How many of the latest snapshots are kept. The older ones are used when
+// the chain no longer has the tip of the newer.
+const SNAPSHOTS_KEPT: usize = 2;
+
+pub const ADDRESS_INDEX_FORMAT: Format = Format {
+    name: "address index",
+    migrations: &[migrations::unversioned],
+};
+
+// What is known about a single address in the main chain
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AddressEntry {
+    balance: u64,
+    // Transactions it sent or received, mined blocks are not counted
+    transactions: u64,
+    // Heights of the blocks it appears in, oldest first
+    heights: Vec<u64>,
+    // Every change to its balance, oldest first
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub height: u64,
+    // Of the block
+    pub time: u64,
+    // Of the transaction, or of the block for rewards and slashings
+    pub hash: Hash,
+    pub kind: HistoryKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum HistoryKind {
+    Sent {
+        destination: PublicKey,
+        amount: u64,
+        fee: u64,
+    },
+    Received {
+        source: PublicKey,
+        amount: u64,
+    },
+    // Reward and fees of a block the address produced
+    Mined {
+        reward: u64,
+        fees: u64,
+    },
+    // The whole balance, burned by a slashing
+    Slashed(u64),
+}
+
+impl HistoryEntry {
+    // How much the balance went up or down
+    pub fn change(&self) -> i128 {
+        match self.kind {
+            HistoryKind::Sent { amount, fee, .. } => -((amount + fee) as i128),
+            HistoryKind::Received { amount, .. } => amount as i128,
+            HistoryKind::Mined { reward, fees } => (reward + fees) as i128,
+            HistoryKind::Slashed(burned) => -(burned as i128),
+        }
+    }
+}
+
+impl AddressEntry {
+    pub fn balance(&self) -> u64 {
+        self.balance
+    }
+
+    pub fn transactions(&self) -> u64 {
+        self.transactions
+    }
+
+    pub fn first_seen(&self) -> Option<u64> {
+        self.heights.first().copied()
+    }
+
+    pub fn last_seen(&self) -> Option<u64> {
+        self.heights.last().copied()
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+}
+
+// Balances and activity of every address in the main chain, updated as
+// blocks are applied and reverted so explorers don't need to go through
+// the whole chain
+pub struct AddressIndex {
+    entries: HashMap<PublicKey, AddressEntry>,
+    // Addresses sorted from the largest balance down
+    ranking: BTreeSet<(Reverse<u64>, PublicKey)>,
+    // Balances burned by slashings, by address and height, to give them
+    // back if the block is reverted
+    burned: HashMap<(PublicKey, u64), u64>,
+    emission: Emission,
+    // Snapshots from the last run the chain may still get to, oldest
+    // first. Until it does, connected blocks are only kept in `skipped`.
+    pending: Vec<Snapshot>,
+    skipped: Vec<(Arc<Block>, u64)>,
+    // The latest snapshots of the index, oldest first, to be saved
+    snapshots: VecDeque<Snapshot>,
+}
+
+// The index as of a block of the main chain, saved so it doesn't have to be
+// rebuilt from the start of the chain on every run
+#[derive(Clone, Serialize, Deserialize)]
+struct Snapshot {
+    tip: Hash,
+    height: u64,
+    // Hash of `state`, which has to match for the snapshot to be used
+    commitment: Hash,
+    // `State`, encoded with bincode
+    state: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    entries: HashMap<PublicKey, AddressEntry>,
+    burned: HashMap<(PublicKey, u64), u64>,
+}
+
+impl AddressIndex {
+    pub fn new(emission: Emission) -> Self {
+        AddressIndex {
+            emission,
+            entries: HashMap::new(),
+            ranking: BTreeSet::new(),
+            burned: HashMap::new(),
+            pending: Vec::new(),
+            skipped: Vec::new(),
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    // Starts from the snapshots saved at `path`, if any. Those that don't
+    // match their commitment are dropped, and so are the rest when the file
+    // can't be read, leaving the index to be rebuilt from the chain.
+    pub fn load(emission: Emission, path: &str) -> Self {
+        let mut index = Self::new(emission);
+        let snapshots: Vec<Snapshot> = match std::fs::read(path) {
+            Ok(file) => ADDRESS_INDEX_FORMAT
+                .decode(file)
+                .and_then(|(_, content)| {
+                    bincode::deserialize(&content).map_err(|issue| issue.to_string())
+                })
+                .unwrap_or_else(|issue| {
+                    println!("COULD NOT READ THE ADDRESS INDEX {}: {}", path, issue);
+
+                    Vec::new()
+                }),
+            Err(_) => Vec::new(),
+        };
+
+        for snapshot in snapshots {
+            if Sha256::digest(&snapshot.state).as_slice() == snapshot.commitment {
+                index.pending.push(snapshot);
+            } else {
+                println!(
+                    "ADDRESS INDEX SNAPSHOT AT HEIGHT {} IS CORRUPTED",
+                    snapshot.height
+                );
+            }
+        }
+
+        index.pending.sort_by_key(|snapshot| snapshot.height);
+        index.snapshots = index.pending.iter().cloned().collect();
+
+        index
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let snapshots: Vec<&Snapshot> = self.snapshots.iter().collect();
+        let content = bincode::serialize(&snapshots).map_err(|issue| issue.to_string())?;
+
+        super::backup::write_atomically(path, &ADDRESS_INDEX_FORMAT.encode(&content))
+    }
+
+    pub fn get(&self, address: &PublicKey) -> Option<&AddressEntry> {
+        self.entries.get(address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Addresses with the largest balances, skipping the first `offset`
+    pub fn richest(&self, offset: usize, limit: usize) -> Vec<(PublicKey, &AddressEntry)> {
+        self.ranking
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|(_, address)| (*address, &self.entries[address]))
+            .collect()
+    }
+
+    // Applies `block`, unless the chain is yet to get to a snapshot. When it
+    // gets to the tip of one, the index continues from it instead of the
+    // blocks before. Returns whether a new snapshot was taken.
+    pub fn connect(&mut self, block: Arc<Block>, height: u64) -> bool {
+        if self.pending.is_empty() {
+            self.apply(&block, height);
+
+            return self.snapshot(&block, height);
+        }
+
+        let hash = block.hash();
+
+        self.skipped.push((block, height));
+
+        if let Some(position) = self
+            .pending
+            .iter()
+            .position(|snapshot| snapshot.height == height)
+        {
+            let snapshot = self.pending.remove(position);
+
+            if snapshot.tip == hash && self.restore(&snapshot) {
+                println!("ADDRESS INDEX CONTINUES FROM HEIGHT {}", height);
+
+                self.skipped.clear();
+                self.pending.retain(|pending| pending.height > height);
+            } else {
+                println!(
+                    "ADDRESS INDEX SNAPSHOT AT HEIGHT {} IS NOT IN THE CHAIN",
+                    height
+                );
+
+                self.forget_snapshot(&snapshot.tip);
+            }
+        }
+
+        if self.pending.is_empty() {
+            self.replay_skipped();
+        }
+
+        false
+    }
+
+    // Undoes `connect`
+    pub fn disconnect(&mut self, block: &Block, height: u64) {
+        match self.skipped.last() {
+            Some((_, skipped)) if *skipped == height => {
+                self.skipped.pop();
+            }
+            _ => self.revert(block, height),
+        }
+
+        // Snapshots taken from `height` up are of blocks no longer in the
+        // chain. Those still pending are checked once it gets back there.
+        let pending = &self.pending;
+
+        self.snapshots.retain(|snapshot| {
+            snapshot.height < height || pending.iter().any(|other| other.tip == snapshot.tip)
+        });
+    }
+
+    fn replay_skipped(&mut self) {
+        if !self.skipped.is_empty() {
+            println!(
+                "REBUILDING THE ADDRESS INDEX FROM HEIGHT {}",
+                self.skipped[0].1
+            );
+        }
+
+        for (block, height) in std::mem::take(&mut self.skipped) {
+            self.apply(&block, height);
+        }
+    }
+
+    // Takes a snapshot every `SNAPSHOT_INTERVAL` blocks
+    fn snapshot(&mut self, block: &Block, height: u64) -> bool {
+        if !height.is_multiple_of(SNAPSHOT_INTERVAL) {
+            return false;
+        }
+
+        let state = State {
+            entries: self.entries.clone(),
+            burned: self.burned.clone(),
+        };
+        let state = match bincode::serialize(&state) {
+            Ok(state) => state,
+            Err(issue) => {
+                println!("COULD NOT SNAPSHOT THE ADDRESS INDEX: {}", issue);
+
+                return false;
+            }
+        };
+
+        self.snapshots.push_back(Snapshot {
+            tip: block.hash(),
+            height,
+            commitment: Sha256::digest(&state).into(),
+            state,
+        });
+
+        while self.snapshots.len() > SNAPSHOTS_KEPT {
+            self.snapshots.pop_front();
+        }
+
+        true
+    }
+
+    // Whether the index could be taken from `snapshot`
+    fn restore(&mut self, snapshot: &Snapshot) -> bool {
+        let state: State = match bincode::deserialize(&snapshot.state) {
+            Ok(state) => state,
+            Err(issue) => {
+                println!("COULD NOT RESTORE THE ADDRESS INDEX: {}", issue);
+
+                return false;
+            }
+        };
+
+        self.ranking = state
+            .entries
+            .iter()
+            .map(|(address, entry)| (Reverse(entry.balance), *address))
+            .collect();
+        self.entries = state.entries;
+        self.burned = state.burned;
+
+        true
+    }
+
+    fn forget_snapshot(&mut self, tip: &Hash) {
+        self.snapshots.retain(|snapshot| &snapshot.tip != tip);
+    }
+
+    // `block` must be the new tip, at `height`
+    pub fn apply(&mut self, block: &Block, height: u64) {
+        let reward = self.emission.reward(height);
+        let fees: u64 = block.transactions().iter().map(|t| t.fee()).sum();
+        let history_entry = |hash, kind| HistoryEntry {
+            height,
+            time: block.header().time(),
+            hash,
+            kind,
+        };
+
+        self.update(block.header().node_public_key(), height, |entry| {
+            entry.balance += reward + fees;
+            entry.history.push(history_entry(
+                block.hash(),
+                HistoryKind::Mined { reward, fees },
+            ));
+        });
+
+        for transaction in block.transactions() {
+            let hash = transaction.hash();
+
+            let outputs = transaction.outputs();
+
+            // An entry for each output, the fee going with the first
+            self.update(transaction.source(), height, |entry| {
+                entry.balance -= transaction.amount() + transaction.fee();
+                entry.transactions += 1;
+
+                for (index, output) in outputs.iter().enumerate() {
+                    entry.history.push(history_entry(
+                        hash,
+                        HistoryKind::Sent {
+                            destination: output.destination,
+                            amount: output.amount,
+                            fee: if index == 0 { transaction.fee() } else { 0 },
+                        },
+                    ));
+                }
+            });
+
+            for output in &outputs {
+                self.update(&output.destination, height, |entry| {
+                    entry.balance += output.amount;
+                    entry.transactions += 1;
+                    entry.history.push(history_entry(
+                        hash,
+                        HistoryKind::Received {
+                            source: *transaction.source(),
+                            amount: output.amount,
+                        },
+                    ));
+                });
+            }
+        }
+
+        for evidence in block.slashings() {
+            let offender = *evidence.offender();
+            let mut burned = 0;
+
+            self.update(&offender, height, |entry| {
+                burned = std::mem::take(&mut entry.balance);
+                entry
+                    .history
+                    .push(history_entry(block.hash(), HistoryKind::Slashed(burned)));
+            });
+            self.burned.insert((offender, height), burned);
+        }
+    }
+
+    // Undoes `apply`, `block` must be the current tip, at `height`
+    pub fn revert(&mut self, block: &Block, height: u64) {
+        for evidence in block.slashings() {
+            let offender = *evidence.offender();
+            let burned = self.burned.remove(&(offender, height)).unwrap_or(0);
+
+            self.update(&offender, height, |entry| entry.balance = burned);
+        }
+
+        for transaction in block.transactions().iter().rev() {
+            for output in transaction.outputs().iter().rev() {
+                self.update(&output.destination, height, |entry| {
+                    entry.balance -= output.amount;
+                    entry.transactions -= 1;
+                });
+            }
+
+            self.update(transaction.source(), height, |entry| {
+                entry.balance += transaction.amount() + transaction.fee();
+                entry.transactions -= 1;
+            });
+        }
+
+        let reward = self.emission.reward(height);
+        let fees: u64 = block.transactions().iter().map(|t| t.fee()).sum();
+
+        self.update(block.header().node_public_key(), height, |entry| {
+            entry.balance -= reward + fees;
+        });
+
+        self.forget(block.header().node_public_key(), height);
+
+        for transaction in block.transactions() {
+            self.forget(transaction.source(), height);
+
+            for destination in transaction.destinations() {
+                self.forget(destination, height);
+            }
+        }
+
+        for evidence in block.slashings() {
+            self.forget(evidence.offender(), height);
+        }
+    }
+
+    fn update(&mut self, address: &PublicKey, height: u64, change: impl FnOnce(&mut AddressEntry)) {
+        let entry = self.entries.entry(*address).or_default();
+
+        self.ranking.remove(&(Reverse(entry.balance), *address));
+
+        change(entry);
+
+        if entry.last_seen() != Some(height) {
+            entry.heights.push(height);
+        }
+
+        self.ranking.insert((Reverse(entry.balance), *address));
+    }
+
+    // Drops `height` from the activity of `address`, and the address itself
+    // once nothing is left
+    fn forget(&mut self, address: &PublicKey, height: u64) {
+        if let Some(entry) = self.entries.get_mut(address) {
+            if entry.last_seen() == Some(height) {
+                entry.heights.pop();
+            }
+
+            while entry
+                .history
+                .last()
+                .is_some_and(|item| item.height == height)
+            {
+                entry.history.pop();
+            }
+
+            if entry.heights.is_empty() {
+                self.ranking.remove(&(Reverse(entry.balance), *address));
+                self.entries.remove(address);
+            }
+        }
+    }
+}
+
+impl Default for AddressIndex {
+    fn default() -> Self {
+        Self::new(Emission::default())
+    }
+}
+
+// Keeps `index` in step with the main chain, including blocks that get
+// disconnected when it switches to another branch. Snapshots are saved to
+// `path` as they are taken.
+pub async fn follow(
+    index: Arc<Mutex<AddressIndex>>,
+    mut events: broadcast::Receiver<ChainEvent>,
+    path: &str,
+) {
+    while let Some(event) = events::next(&mut events).await {
+        match event {
+            ChainEvent::Connected { block, height } => {
+                let mut index = index.lock().await;
+
+                if index.connect(block, height) {
+                    if let Err(issue) = index.save(path) {
+                        println!("{}", issue);
+                    }
+                }
+            }
+            ChainEvent::Disconnected { block, height } => {
+                index.lock().await.disconnect(&block, height)
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use super::blockchain::{self, HashFmt, Node};
+use super::events::{self, ChainEvent};
+use std::collections::{HashSet, VecDeque};
+use std::ffi::CString;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::{broadcast, Mutex};
+
+// Alerts kept for `getalerts`
+const MAX_ALERTS: usize = 100;
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The `[alerts]` table of the configuration file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AlertSettings {
+    // Minutes without a new block in the main chain
+    pub stalled_after: u64,
+    // Blocks disconnected by a reorganization
+    pub reorg_depth: u64,
+    // Megabytes left on the disk the node runs from
+    pub min_free_disk_mb: u64,
+    // Seconds our clock can be off from the network's
+    pub max_clock_skew: u64,
+    // Alerts are POSTed to it as JSON
+    pub webhook: Option<String>,
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        AlertSettings {
+            stalled_after: 60,
+            reorg_depth: 6,
+            min_free_disk_mb: 1024,
+            max_clock_skew: 5 * 60,
+            webhook: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    ChainStalled,
+    NoPeers,
+    DeepReorg,
+    // A branch forking deeper than the finality depth, or below the latest
+    // checkpoint, which we refuse to switch to
+    DeepFork,
+    DiskNearlyFull,
+    ClockSkew,
+}
+
+impl AlertKind {
+    // Whether it is a condition that lasts until it clears, rather than
+    // something that happened once
+    fn lasts(self) -> bool {
+        !matches!(self, AlertKind::DeepReorg | AlertKind::DeepFork)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    // Unix time it was raised at
+    pub time: u64,
+    pub message: String,
+}
+
+// Conditions an operator should look into, kept by the node. Conditions that
+// last are only raised again once they cleared, reorgs and forks every time.
+pub struct Alerts {
+    recent: VecDeque<Alert>,
+    active: HashSet<AlertKind>,
+    sender: broadcast::Sender<Alert>,
+}
+
+impl Alerts {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(MAX_ALERTS);
+
+        Alerts {
+            recent: VecDeque::new(),
+            active: HashSet::new(),
+            sender,
+        }
+    }
+
+    pub fn raise(&mut self, kind: AlertKind, message: String) {
+        if kind.lasts() && !self.active.insert(kind) {
+            return;
+        }
+
+        let alert = Alert {
+            kind,
+            time: blockchain::timestamp(),
+            message,
+        };
+
+        if self.recent.len() == MAX_ALERTS {
+            self.recent.pop_front();
+        }
+
+        self.recent.push_back(alert.clone());
+
+        // Nobody listening is fine
+        let _ = self.sender.send(alert);
+    }
+
+    // The condition went away
+    pub fn clear(&mut self, kind: AlertKind) {
+        if self.active.remove(&kind) {
+            println!("ALERT CLEARED: {:?}", kind);
+        }
+    }
+
+    // Oldest first
+    pub fn recent(&self) -> Vec<Alert> {
+        self.recent.iter().cloned().collect()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Alert> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for Alerts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Raises alerts for reorgs as they happen, and checks every minute for the
+// conditions that build up over time
+pub async fn monitor(
+    node: Arc<Mutex<Node>>,
+    settings: AlertSettings,
+    mut events: broadcast::Receiver<ChainEvent>,
+) {
+    let mut last_block = Instant::now();
+    // Not right away, peers take a moment to connect
+    let mut checks =
+        tokio::time::interval_at(tokio::time::Instant::now() + CHECK_INTERVAL, CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = events::next(&mut events) => match event {
+                Some(ChainEvent::Connected { .. }) => last_block = Instant::now(),
+                Some(ChainEvent::Reorged {
+                    old_tip,
+                    new_tip,
+                    depth,
+                }) if depth >= settings.reorg_depth => node.lock().await.raise_alert(
+                    AlertKind::DeepReorg,
+                    format!(
+                        "Reorganized {} blocks deep, from {} to {}",
+                        depth,
+                        HashFmt(old_tip),
+                        HashFmt(new_tip)
+                    ),
+                ),
+                Some(_) => {}
+                None => return,
+            },
+            _ = checks.tick() => check(&node, &settings, last_block).await,
+        }
+    }
+}
+
+async fn check(node: &Arc<Mutex<Node>>, settings: &AlertSettings, last_block: Instant) {
+    let free_disk_mb = free_disk_space(".").map(|bytes| bytes / (1024 * 1024));
+    let mut node = node.lock().await;
+
+    let stalled_minutes = last_block.elapsed().as_secs() / 60;
+    let peers = node.peer_count();
+    let clock_offset = node.clock_offset();
+
+    let conditions = [
+        (
+            AlertKind::ChainStalled,
+            stalled_minutes >= settings.stalled_after,
+            format!("No new block for {} minutes", stalled_minutes),
+        ),
+        (
+            AlertKind::NoPeers,
+            peers == 0,
+            "No peers connected".to_string(),
+        ),
+        (
+            AlertKind::DiskNearlyFull,
+            free_disk_mb.is_some_and(|free| free < settings.min_free_disk_mb),
+            format!("Only {} MB left on disk", free_disk_mb.unwrap_or(0)),
+        ),
+        (
+            AlertKind::ClockSkew,
+            clock_offset.unsigned_abs() > settings.max_clock_skew,
+            format!(
+                "Our clock is {} seconds off from the network",
+                -clock_offset
+            ),
+        ),
+    ];
+
+    for (kind, raised, message) in conditions {
+        if raised {
+            node.raise_alert(kind, message);
+        } else {
+            node.clear_alert(kind);
+        }
+    }
+}
+
+// Logs the alerts raised from now on, and POSTs them to `webhook` if set
+pub async fn deliver(mut alerts: broadcast::Receiver<Alert>, webhook: Option<String>) {
+    let client = reqwest::Client::new();
+
+    loop {
+        let alert = match alerts.recv().await {
+            Ok(alert) => alert,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                println!("MISSED {} ALERTS", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        println!("ALERT: {}", alert.message.to_uppercase());
+
+        if let Some(url) = &webhook {
+            let result = client
+                .post(url)
+                .json(&alert)
+                .timeout(WEBHOOK_TIMEOUT)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+
+            if let Err(issue) = result {
+                println!("COULD NOT DELIVER ALERT TO {}: {}", url, issue);
+            }
+        }
+    }
+}
+
+// Bytes available to us on the file system `path` is in
+fn free_disk_space(path: &str) -> Option<u64> {
+    let path = CString::new(path).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // Safe as `path` is a valid C string and `stats` is ours to write to
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
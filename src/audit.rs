@@ -0,0 +1,237 @@
+use super::blockchain::{self, Hash, HashFmt, RejectReason};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+// How often `tail` looks for new lines when following the log
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+// A line of the audit log, which is JSON Lines so logs of different nodes
+// can be compared when looking into a fork
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    // Unix time
+    pub time: u64,
+    #[serde(flatten)]
+    pub decision: Decision,
+}
+
+// Hashes are in hex, as they are shown everywhere else
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum Decision {
+    // Stored, on the main chain or on a branch, `tip` when it became the tip
+    BlockAccepted {
+        hash: String,
+        previous_hash: String,
+        height: u64,
+        tip: bool,
+    },
+    BlockRejected {
+        hash: String,
+        reason: RejectReason,
+    },
+    // The main chain moved to another branch
+    Reorg {
+        old_tip: String,
+        new_tip: String,
+        fork_point: String,
+        depth: u64,
+    },
+    // Disconnected for breaking the protocol
+    PeerDropped {
+        peer: SocketAddr,
+        reason: String,
+    },
+}
+
+impl Decision {
+    pub fn block_accepted(hash: &Hash, previous_hash: &Hash, height: u64, tip: bool) -> Self {
+        Decision::BlockAccepted {
+            hash: HashFmt(*hash).to_string(),
+            previous_hash: HashFmt(*previous_hash).to_string(),
+            height,
+            tip,
+        }
+    }
+
+    pub fn block_rejected(hash: &Hash, reason: RejectReason) -> Self {
+        Decision::BlockRejected {
+            hash: HashFmt(*hash).to_string(),
+            reason,
+        }
+    }
+
+    pub fn reorg(old_tip: &Hash, new_tip: &Hash, fork_point: &Hash, depth: u64) -> Self {
+        Decision::Reorg {
+            old_tip: HashFmt(*old_tip).to_string(),
+            new_tip: HashFmt(*new_tip).to_string(),
+            fork_point: HashFmt(*fork_point).to_string(),
+            depth,
+        }
+    }
+}
+
+impl std::fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ", self.time)?;
+
+        match &self.decision {
+            Decision::BlockAccepted {
+                hash, height, tip, ..
+            } => write!(
+                f,
+                "BLOCK {} ACCEPTED AT {}{}",
+                hash,
+                height,
+                if *tip { ", NEW TIP" } else { "" }
+            ),
+            Decision::BlockRejected { hash, reason } => {
+                write!(f, "BLOCK {} REJECTED: {}", hash, reason)
+            }
+            Decision::Reorg {
+                old_tip,
+                new_tip,
+                fork_point,
+                depth,
+            } => write!(
+                f,
+                "REORGANIZED FROM {} TO {} AT {}, {} BLOCKS DEEP",
+                old_tip, new_tip, fork_point, depth
+            ),
+            Decision::PeerDropped { peer, reason } => {
+                write!(f, "DROPPED {}: {}", peer, reason)
+            }
+        }
+    }
+}
+
+// Kept by the node, which records its decisions as it takes them. They are
+// written to the log by `append`, so the node never waits for the disk.
+pub struct Audit {
+    sender: mpsc::UnboundedSender<AuditRecord>,
+    receiver: Option<mpsc::UnboundedReceiver<AuditRecord>>,
+}
+
+impl Audit {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        Audit {
+            sender,
+            receiver: Some(receiver),
+        }
+    }
+
+    // Dropped until `records` is called, so nodes that don't write a log
+    // don't keep them
+    pub fn record(&self, decision: Decision) {
+        if self.receiver.is_some() {
+            return;
+        }
+
+        let _ = self.sender.send(AuditRecord {
+            time: blockchain::timestamp(),
+            decision,
+        });
+    }
+
+    // The records, for `append`. Only the first call gets them.
+    pub fn records(&mut self) -> Option<mpsc::UnboundedReceiver<AuditRecord>> {
+        self.receiver.take()
+    }
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Appends each record to the log at `path` as it comes
+pub async fn append(mut records: mpsc::UnboundedReceiver<AuditRecord>, path: &str) {
+    let mut file = match OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+    {
+        Ok(file) => file,
+        Err(issue) => {
+            println!("COULD NOT OPEN THE AUDIT LOG {}: {}", path, issue);
+
+            return;
+        }
+    };
+
+    while let Some(record) = records.recv().await {
+        let mut line = serde_json::to_string(&record).unwrap();
+
+        line.push('\n');
+
+        if let Err(issue) = file.write_all(line.as_bytes()).await {
+            println!("COULD NOT WRITE TO THE AUDIT LOG {}: {}", path, issue);
+        }
+    }
+}
+
+// Prints the last `lines` records of the log at `path`, then, when
+// `follow`, the new ones as they are appended
+pub async fn tail(path: &str, lines: usize, follow: bool) -> Result<(), String> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|issue| format!("Could not open the audit log {}: {}", path, issue))?;
+    let mut reader = BufReader::new(file);
+    let mut last = std::collections::VecDeque::new();
+    let mut line = String::new();
+
+    while read_line(&mut reader, &mut line).await? {
+        last.push_back(std::mem::take(&mut line));
+
+        if last.len() > lines {
+            last.pop_front();
+        }
+    }
+
+    for line in last {
+        show(&line);
+    }
+
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        if read_line(&mut reader, &mut line).await? {
+            show(&std::mem::take(&mut line));
+        } else {
+            tokio::time::sleep(FOLLOW_INTERVAL).await;
+        }
+    }
+}
+
+// Reads a whole line into `line`, false at the end of the file. A line
+// still being written is left for the next call.
+async fn read_line(
+    reader: &mut BufReader<tokio::fs::File>,
+    line: &mut String,
+) -> Result<bool, String> {
+    reader
+        .read_line(line)
+        .await
+        .map_err(|issue| format!("Could not read the audit log: {}", issue))?;
+
+    Ok(line.ends_with('\n'))
+}
+
+fn show(line: &str) {
+    match serde_json::from_str::<AuditRecord>(line) {
+        Ok(record) => println!("{}", record),
+        Err(_) => println!("{}", line.trim_end()),
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use sha2::{Digest, Sha256};
+
+// Lists the files of a backup, with their hashes
+const MANIFEST: &str = "manifest.json";
+// Suffix of a file being written, until it is renamed over the old one
+const PARTIAL_SUFFIX: &str = "partial";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    // Unix time
+    pub created: u64,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub name: String,
+    pub size: u64,
+    // Hex encoded SHA-256 of the content
+    pub sha256: String,
+}
+
+// Replaces the file at `path` with `content` in one go, so a backup taken
+// while the node runs never copies it half written
+pub fn write_atomically(path: &str, content: &[u8]) -> Result<(), String> {
+    let partial = format!("{}.{}", path, PARTIAL_SUFFIX);
+
+    std::fs::write(&partial, content)
+        .and_then(|_| std::fs::rename(&partial, path))
+        .map_err(|issue| format!("Could not write {}: {}", path, issue))
+}
+
+// Copies `files`, those that exist, to the new directory `to`, along with
+// their manifest
+pub fn backup(files: &[&str], to: &Path) -> Result<Manifest, String> {
+    std::fs::create_dir(to)
+        .map_err(|issue| format!("Could not create {}: {}", to.display(), issue))?;
+
+    let mut manifest = Manifest {
+        created: super::blockchain::timestamp(),
+        files: Vec::new(),
+    };
+
+    for name in files {
+        let copy = to.join(name);
+
+        // Copied rather than read and written, to keep the key private
+        match std::fs::copy(name, &copy) {
+            Ok(_) => {}
+            Err(issue) if issue.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(issue) => return Err(format!("Could not copy {}: {}", name, issue)),
+        }
+
+        let content = std::fs::read(&copy)
+            .map_err(|issue| format!("Could not read {}: {}", copy.display(), issue))?;
+
+        manifest.files.push(BackupFile {
+            name: name.to_string(),
+            size: content.len() as u64,
+            sha256: fcoin_core::hex::encode(&Sha256::digest(&content)),
+        });
+    }
+
+    let content = serde_json::to_vec_pretty(&manifest).map_err(|issue| issue.to_string())?;
+
+    std::fs::write(to.join(MANIFEST), content)
+        .map_err(|issue| format!("Could not write the manifest: {}", issue))?;
+
+    Ok(manifest)
+}
+
+// Reads the backup in `from`, checking each file against the manifest and
+// with `check`, which gets each name and content, before touching anything.
+// Then replaces each file with the one in the backup. Those it doesn't have
+// are left as they are, as losing a key can't be undone.
+pub fn restore(
+    files: &[&str],
+    from: &Path,
+    check: impl Fn(&str, &[u8]) -> Result<(), String>,
+) -> Result<Manifest, String> {
+    let content = std::fs::read(from.join(MANIFEST))
+        .map_err(|issue| format!("Could not read the manifest: {}", issue))?;
+    let manifest: Manifest =
+        serde_json::from_slice(&content).map_err(|issue| format!("Invalid manifest: {}", issue))?;
+
+    for file in &manifest.files {
+        // Only ever write the files we back up, whatever the manifest says
+        if !files.contains(&file.name.as_str()) {
+            return Err(format!("Unexpected file {} in the backup", file.name));
+        }
+
+        let content = std::fs::read(from.join(&file.name))
+            .map_err(|issue| format!("Could not read {}: {}", file.name, issue))?;
+
+        if content.len() as u64 != file.size
+            || fcoin_core::hex::encode(&Sha256::digest(&content)) != file.sha256
+        {
+            return Err(format!("{} is corrupted", file.name));
+        }
+
+        check(&file.name, &content)?;
+    }
+
+    for file in &manifest.files {
+        let partial = format!("{}.{}", file.name, PARTIAL_SUFFIX);
+
+        // Copied, like in `backup`
+        std::fs::copy(from.join(&file.name), &partial)
+            .and_then(|_| std::fs::rename(&partial, &file.name))
+            .map_err(|issue| format!("Could not restore {}: {}", file.name, issue))?;
+    }
+
+    Ok(manifest)
+}
@@ -1,360 +1,4109 @@
+use super::address_book::{self, AddressBook};
+use super::alerts::{AlertKind, Alerts};
+use super::audit::{Audit, Decision};
+use super::bloom::BloomFilter;
+use super::checkpoint::{self, Checkpoint};
+use super::compact_filter::CompactFilter;
+use super::consensus::{Consensus, DoubleSign};
+use super::deployment::{DeploymentInfo, Deployments};
+use super::events::{self, ChainEvent, EventBus};
 use super::framing;
-use crate::BigArray;
+use super::framing::{Frame, Payload, RelayPolicy, Traffic, TrafficStats};
+use super::keystore::Keystore;
+use super::lru::Lru;
+use super::memory::{MemoryInfo, MemorySettings, MemoryUsage};
+use super::mempool::Mempool;
+use super::merkle;
+use super::merkle::MerkleProof;
+use super::miner::{
+    self, Grinder, HashrateMeter, MiningInfo, MiningSettings, OrphanTracker, PropagationTracker,
+    Throttle,
+};
+use super::network::{Fork, NetworkProfile, PowHash};
+use super::network_time::NetworkTime;
+use super::peer::Latencies;
+use super::policy::SpendingPolicy;
+use super::replay::Recorder;
+use super::stats::{ChainStats, ChainStatsIndex};
+use super::store::{ChainStore, TransactionLocation};
+use super::verify::{ChainVerification, ChainViolation, VERIFY_SEALS, VERIFY_TRANSACTIONS};
+use super::view::{ChainView, ChainViews, ViewPublisher};
+use super::wallets::Wallets;
+use fcoin_core::asset::AssetAction;
+use fcoin_core::encoding::Encode;
 use num::BigUint;
 use sha2::Digest;
 use sha2::Sha256;
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Instant, SystemTime};
 
 use serde::{Deserialize, Serialize};
 
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TransactionDetails {
-    #[serde(with = "BigArray")]
-    source_public_key: PublicKey,
-    #[serde(with = "BigArray")]
-    destination_public_key: PublicKey,
-    amount: u64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Transaction {
-    details: TransactionDetails,
-    #[serde(with = "BigArray")]
-    source_signature: Signature,
-}
+// How many headers are sent at most in a single `Frame::Headers`
+pub const MAX_HEADERS: usize = 2000;
+// How many full blocks are sent at most in a single `Frame::Blocks`
+pub const MAX_BLOCKS: usize = 16;
+// How long the miner waits before checking again if we are synced
+const SYNC_WAIT: std::time::Duration = std::time::Duration::from_secs(1);
+// How long the miner tries nonces of a block before assembling it again on
+// the latest tip, and how many it tries between looking at the time
+const GRIND_TIME: std::time::Duration = std::time::Duration::from_secs(1);
+const GRIND_BATCH: u64 = 64;
+// Blocks handed out to external workers we still take nonces for
+const MAX_WORK: usize = 16;
+// How often the miner gets a new template even when no transaction came in
+const TEMPLATE_REFRESH: std::time::Duration = std::time::Duration::from_secs(30);
+// New blocks go to this many of the peers with the lowest round trips at
+// once, then to as many more every `RELAY_STAGGER`
+const RELAY_FANOUT: usize = 4;
+const RELAY_STAGGER: std::time::Duration = std::time::Duration::from_millis(50);
+// How far ahead of the network time (in seconds) block times can be
+const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+// How many of the latest blocks are looked at when estimating fees
+const FEE_ESTIMATION_BLOCKS: usize = 20;
+// Largest encoded sizes (in bytes) blocks and transactions can have. Part
+// of consensus, so changing them splits the network.
+pub const MAX_BLOCK_SIZE: u64 = 1_000_000;
+pub const MAX_TRANSACTION_SIZE: u64 = 1_000;
+// How many rejected transactions we remember the reason for
+const MAX_REMEMBERED_REJECTIONS: usize = 1000;
+// Rough memory taken by each remembered rejection and checked block or
+// transaction, with what keeps track of their order
+const REJECTION_SIZE: u64 = 96;
+const VERIFIED_SIZE: u64 = 160;
+// Blocks this close to the tip are still served once the daily upload cap
+// is reached, so peers can keep up with the chain
+const RECENT_BLOCKS: u64 = 144;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// Most addresses sent or taken from a `Frame::Addr`
+pub const MAX_ADDRESSES: usize = 1000;
+// Peers that must agree on where they see us connecting from before we
+// announce that address
+const MIN_ADDRESS_VOTES: usize = 2;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Block {
+pub struct BlockHeader {
     time: u64,
-    // This is used to give whoever created this block a +1 balance
-    #[serde(with = "BigArray")]
+    // This is used to give whoever created this block a +1 balance, plus
+    // the fees of all its transactions
     node_public_key: PublicKey,
     // Linking to the previous block
     previous_hash: Hash,
+    // Commits to the transactions, so light clients can check them with
+    // just the header
+    merkle_root: Hash,
     // Used for the proof-of-work
     // (increment this until the hash of the block is < n)
     nonce: [u8; 32],
-    // The actual money transfer in this block
-    transaction: Transaction,
+    // Proof of stake producers sign their blocks. Not part of the hash,
+    // which is what gets signed.
+    signature: Option<BlockSignature>,
+    // Bits of the deployments the producer signals for, see
+    // `deployment.rs`
+    version: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct BlockSignature(#[serde(with = "fcoin_core::BigArray")] Signature);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    header: BlockHeader,
+    // The actual money transfers in this block
+    transactions: Vec<Transaction>,
+    // Proof of stake producers caught signing twice, whose coins this block
+    // burns
+    slashings: Vec<DoubleSign>,
+}
+
+// A block header along with only the transactions a light client asked for
+// (through its filter) and the proofs that they are part of the block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleBlock {
+    header: BlockHeader,
+    transactions: Vec<(Transaction, MerkleProof)>,
 }
 
 pub struct ProtoBlock {
     nonce: [u8; 32],
-    transaction: Transaction,
+    transactions: Vec<Transaction>,
 }
 
-type Hash = [u8; 32];
-type Signature = [u8; 128];
-type PublicKey = [u8; 128];
-type Blockchain = HashMap<Hash, Block>;
+pub use fcoin_core::display::{HashFmt, PublicKeyFmt};
+pub use fcoin_core::transaction::{Output, Transaction, TransactionDetails};
+pub use fcoin_core::{Hash, PublicKey, SecretKey, Signature};
 
-struct HashFmt(Hash);
-struct PublicKeyFmt(PublicKey);
-struct BlockchainFmt(Blockchain, Hash);
+struct BlockchainFmt<'a>(&'a ChainStore, Hash);
+
+// Tunables of the node, from its configuration file
+pub struct NodeSettings {
+    // Seconds a transaction may wait in the mempool before being dropped
+    pub mempool_ttl: u64,
+    // Whether to index transactions by hash
+    pub transaction_index: bool,
+    // How many blocks behind the best chain our peers know about we can be
+    // while still mining and accepting transactions
+    pub sync_tolerance: u64,
+    pub network: NetworkProfile,
+    // Bytes we upload in a day before we stop serving historical blocks
+    pub daily_upload_cap: Option<u64>,
+    // Whether we take unconfirmed transactions from our peers
+    pub relay: RelayPolicy,
+    // Port other nodes can connect to us on, None when we don't listen
+    pub listen_port: Option<u16>,
+    pub memory: MemorySettings,
+    // Deployments of the network our blocks signal for, by name
+    pub signal: Vec<String>,
+    pub mining: MiningSettings,
+    // Peers that can push blocks whatever the state of the connection
+    pub trusted_peers: Vec<IpAddr>,
+    // What the node signs for RPC clients, with any of its keys
+    pub policy: SpendingPolicy,
+}
 
 pub struct Node {
-    public_key: [u8; 128],
-    blockchain: Blockchain,
+    // None for public nodes run with `--no-wallet`, which hold no keys
+    keystore: Option<Keystore>,
+    // Named wallets, besides the key of the node
+    wallets: Wallets,
+    // Blocks we mine pay it
+    public_key: Option<PublicKey>,
+    blockchain: ChainStore,
     tip_hash: Hash,
+    // Statistics about the main chain
+    stats: ChainStatsIndex,
+    // What readers see of the main chain, without taking the node lock
+    views: ViewPublisher,
+    mempool: Mempool,
+    settings: NodeSettings,
     peers: HashMap<SocketAddr, framing::WriteConnection>,
+    // Where other nodes said they can be reached, and how that went
+    address_book: AddressBook,
+    // Peers we connected to, rather than them to us, and since when
+    outbound_peers: HashMap<SocketAddr, u64>,
+    // Where we can be reached, when our router told us
+    mapped_address: Option<SocketAddr>,
+    // The IP each peer we connected to sees us connecting from
+    address_votes: HashMap<SocketAddr, IpAddr>,
+    // Peers that asked not to be sent unconfirmed transactions
+    blocks_only_peers: HashSet<SocketAddr>,
+    // Heights of the chains our peers told us about
+    peer_heights: HashMap<SocketAddr, u64>,
+    network_time: NetworkTime,
+    events: EventBus,
+    alerts: Alerts,
+    // Blocks accepted and rejected, reorganizations and dropped peers
+    audit: Audit,
+    // Where the frames we receive, and the blocks we mine, are recorded
+    recorder: Option<Arc<Recorder>>,
+    // Where the last branch we refused to switch to forked, so it is only
+    // alerted about once
+    ignored_fork: Option<Hash>,
+    // The highest checkpoint we verified, which we never reorganize below
+    checkpoint: Option<Checkpoint>,
+    // Filters loaded by light clients connected to us
+    filters: HashMap<SocketAddr, BloomFilter>,
+    consensus: Arc<dyn Consensus>,
+    deployments: Deployments,
+    // Witness hashes (see `witness_hash`) of the blocks and transactions
+    // that passed `check_block` and `check_transaction`, which don't need
+    // to be checked again when mined, relayed twice or reclaimed
+    verified: Arc<Lru<Hash, ()>>,
+    // Headers of the blocks we got for each producer and slot, to catch
+    // producers signing twice
+    signed_slots: HashMap<(PublicKey, u64), BlockHeader>,
+    // Double signs waiting to be included in one of our blocks
+    evidence: Vec<DoubleSign>,
+    // Why the latest transactions we turned down were not accepted, oldest
+    // first in `rejection_order`
+    rejections: HashMap<Hash, RejectReason>,
+    rejection_order: VecDeque<Hash>,
+    // Traffic of the peers that already disconnected
+    past_traffic: Traffic,
+    // Day (since the UNIX epoch) the upload cap is being counted for, and
+    // how much had been sent before it started
+    upload_day: u64,
+    sent_before_upload_day: u64,
+    // Hashes our own miner tried lately
+    hashrate: HashrateMeter,
+    // Which of our own blocks are off the main chain
+    orphans: OrphanTracker,
+    // How fast the blocks of each peer reach us
+    propagation: PropagationTracker,
+    // Blocks handed out to external workers by work id, newest last
+    work: VecDeque<(u64, Block)>,
+    next_work: u64,
+    // Nodes of our network that caught up with us, which new blocks are
+    // relayed to
+    block_relay_peers: HashSet<SocketAddr>,
+    latencies: Latencies,
+    next_ping: u64,
 }
 
 impl Node {
-    pub fn new() -> Node {
+    pub fn new(secret_key: SecretKey, settings: NodeSettings, address_book: AddressBook) -> Node {
+        Node::with_keystore(Keystore::plain(secret_key), settings, address_book)
+    }
+
+    pub fn with_keystore(
+        keystore: Keystore,
+        settings: NodeSettings,
+        address_book: AddressBook,
+    ) -> Node {
+        Node::build(Some(keystore), settings, address_book)
+    }
+
+    // Syncs, relays and answers read-only requests, but can't mine or sign
+    pub fn without_wallet(settings: NodeSettings, address_book: AddressBook) -> Node {
+        Node::build(None, settings, address_book)
+    }
+
+    fn build(
+        keystore: Option<Keystore>,
+        settings: NodeSettings,
+        address_book: AddressBook,
+    ) -> Node {
         Node {
-            public_key: read_public_key_from_disk(),
-            blockchain: HashMap::new(),
+            public_key: keystore.as_ref().map(|keystore| *keystore.public_key()),
+            keystore,
+            wallets: Wallets::default(),
+            blockchain: if settings.transaction_index {
+                ChainStore::with_transaction_index(settings.network.emission)
+            } else {
+                ChainStore::new(settings.network.emission)
+            },
             tip_hash: [0; 32],
+            stats: ChainStatsIndex::new(settings.network.emission),
+            views: ViewPublisher::new(settings.network.name_lifetime),
+            mempool: Mempool::new(),
             peers: HashMap::new(),
+            address_book,
+            outbound_peers: HashMap::new(),
+            mapped_address: None,
+            address_votes: HashMap::new(),
+            blocks_only_peers: HashSet::new(),
+            peer_heights: HashMap::new(),
+            network_time: NetworkTime::new(),
+            events: EventBus::new(),
+            alerts: Alerts::new(),
+            audit: Audit::new(),
+            recorder: None,
+            ignored_fork: None,
+            checkpoint: None,
+            filters: HashMap::new(),
+            consensus: settings.network.consensus(),
+            deployments: settings.network.deployments(),
+            verified: Arc::new(Lru::new(
+                (settings.memory.verification_cache_budget() / VERIFIED_SIZE) as usize,
+            )),
+            signed_slots: HashMap::new(),
+            evidence: Vec::new(),
+            rejections: HashMap::new(),
+            rejection_order: VecDeque::new(),
+            past_traffic: Traffic::default(),
+            upload_day: timestamp() / SECONDS_PER_DAY,
+            sent_before_upload_day: 0,
+            hashrate: HashrateMeter::default(),
+            orphans: OrphanTracker::default(),
+            propagation: PropagationTracker::default(),
+            work: VecDeque::new(),
+            next_work: 0,
+            block_relay_peers: HashSet::new(),
+            latencies: Latencies::default(),
+            next_ping: 0,
+            settings,
         }
     }
 
-    pub fn add_peer(&mut self, addr: SocketAddr, con: framing::WriteConnection) {
+    pub fn add_peer(
+        &mut self,
+        addr: SocketAddr,
+        con: framing::WriteConnection,
+        relay: RelayPolicy,
+        outbound: bool,
+    ) {
         self.peers.insert(addr, con);
+
+        // We know they listen there
+        if outbound {
+            self.outbound_peers.insert(addr, timestamp());
+            self.address_book.connected(&addr, timestamp());
+        }
+
+        if relay == RelayPolicy::BlocksOnly {
+            self.blocks_only_peers.insert(addr);
+        }
+    }
+
+    pub fn remove_peer(&mut self, addr: &SocketAddr) {
+        if let Some(peer) = self.peers.remove(addr) {
+            self.past_traffic.add(&peer.traffic().total);
+        }
+
+        self.filters.remove(addr);
+        self.blocks_only_peers.remove(addr);
+        self.outbound_peers.remove(addr);
+        self.address_votes.remove(addr);
+        self.peer_heights.remove(addr);
+        self.network_time.remove_sample(addr);
+        self.propagation.remove(addr);
+        self.block_relay_peers.remove(addr);
+        self.latencies.remove(addr);
     }
-}
 
-impl TransactionDetails {
-    pub fn new(source: PublicKey, destination: PublicKey, amount: u64) -> TransactionDetails {
-        TransactionDetails {
-            source_public_key: source,
-            destination_public_key: destination,
-            amount: amount,
+    // Clients, which follow no network, are never sent blocks or pings they
+    // didn't ask for
+    pub fn relay_blocks_to(&mut self, addr: SocketAddr) {
+        if self.peers.contains_key(&addr) {
+            self.block_relay_peers.insert(addr);
         }
     }
-}
 
-impl Transaction {
-    pub fn new(details: TransactionDetails, signature: [u8; 128]) -> Transaction {
-        Transaction {
-            details: details,
-            source_signature: signature,
+    pub async fn ping(&mut self, addr: SocketAddr) {
+        let nonce = self.next_ping;
+
+        self.next_ping += 1;
+        self.latencies.ping_sent(addr, nonce);
+        self.send(&addr, Frame::Ping(nonce)).await;
+    }
+
+    pub async fn ping_peers(&mut self) {
+        for addr in self.block_relay_peers.clone() {
+            self.ping(addr).await;
         }
     }
-}
 
-impl std::fmt::Display for BlockchainFmt {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let mut tip = self.1;
+    pub fn pong_received(&mut self, addr: SocketAddr, nonce: u64) {
+        self.latencies.pong_received(addr, nonce);
+    }
 
-        loop {
-            match self.0.get(&tip) {
-                Some(block) => {
-                    write!(f, "{}\n", block.transaction)?;
-                    tip = block.previous_hash;
-                }
-                None => return Ok(()),
+    // Peers to relay a block to, lowest round trip first. Those we have no
+    // round trip for yet go last.
+    fn block_relay_order(&self, origin: Option<SocketAddr>) -> Vec<SocketAddr> {
+        let mut peers: Vec<SocketAddr> = self
+            .block_relay_peers
+            .iter()
+            .filter(|addr| Some(**addr) != origin)
+            .copied()
+            .collect();
+
+        peers.sort_by_key(|addr| {
+            (
+                self.latencies
+                    .round_trip(addr)
+                    .unwrap_or(std::time::Duration::MAX),
+                *addr,
+            )
+        });
+        peers
+    }
+
+    // Only the clocks of peers we connected to are used, so others can't
+    // skew our time by connecting to us many times
+    pub fn add_time_sample(&mut self, addr: SocketAddr, time: u64) {
+        self.network_time.add_sample(addr, time);
+    }
+
+    // Unix time adjusted to the clocks of our peers
+    pub fn adjusted_time(&self) -> u64 {
+        self.network_time.now()
+    }
+
+    // Seconds the clocks of our peers are ahead of ours
+    pub fn clock_offset(&self) -> i64 {
+        self.network_time.offset()
+    }
+
+    // Peers following another network profile are turned away
+    pub fn network_id(&self) -> Hash {
+        self.settings.network.id()
+    }
+
+    // A block on top of our tip with the transactions of `proto_block` that
+    // are still pending and valid, and the evidence waiting to be included,
+    // left for the consensus to seal. `None` when none of the transactions
+    // are, or without a wallet.
+    fn assemble_block(&mut self, proto_block: ProtoBlock) -> Option<Block> {
+        // Without a wallet there is nobody to pay
+        let producer = self.public_key?;
+        let mut block = Block {
+            header: BlockHeader::new(
+                self.adjusted_time()
+                    .max(self.blockchain.median_time_past(&self.tip_hash) + 1),
+                producer,
+                self.tip_hash,
+                [0; 32],
+                proto_block.nonce,
+            )
+            .with_version(self.deployments.version(
+                &self.blockchain,
+                &self.tip_hash,
+                &self.settings.signal,
+            )),
+            transactions: Vec::new(),
+            slashings: Vec::new(),
+        };
+        let mut size = block.size();
+        let height = self.height() + 1;
+        // Balance changes of the transactions already in the block, which
+        // children need to be paid for by their parents
+        let mut changes: HashMap<PublicKey, i128> = HashMap::new();
+        let mut registered = HashMap::new();
+        let mut tokens = TokenChanges::default();
+
+        for transaction in proto_block.transactions {
+            if !self.mempool.contains(&transaction.hash())
+                || size + transaction.size() > MAX_BLOCK_SIZE
+                || valid_transaction(
+                    &transaction,
+                    &self.blockchain,
+                    &self.tip_hash,
+                    height,
+                    &changes,
+                    &self.settings.network,
+                    &self.verified,
+                )
+                .is_err()
+                || claim_name(&mut registered, &transaction).is_err()
+                || tokens
+                    .check_on_chain(&transaction, &self.blockchain, &self.tip_hash)
+                    .is_err()
+            {
+                continue;
             }
+
+            apply_changes(&mut changes, &transaction);
+            tokens.apply(&transaction);
+
+            size += transaction.size();
+            block.transactions.push(transaction);
         }
-    }
-}
 
-impl std::fmt::Display for HashFmt {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        for byte in self.0 {
-            write!(f, "{:02x}", byte)?
+        if block.transactions.is_empty() {
+            return None;
         }
 
-        Ok(())
+        // Evidence only counts once per offender and chain
+        let tip_hash = self.tip_hash;
+        let blockchain = &self.blockchain;
+
+        self.evidence
+            .retain(|evidence| !is_slashed(evidence.offender(), blockchain, &tip_hash));
+
+        for evidence in &self.evidence {
+            if !block.slashes(evidence.offender()) {
+                block.slashings.push(evidence.clone());
+            }
+        }
+
+        block.header.merkle_root = merkle::merkle_root(&block.merkle_leaves());
+
+        Some(block)
     }
-}
 
-impl std::fmt::Display for PublicKeyFmt {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        for byte in &self.0[0..8] {
-            write!(f, "{:02x}", byte)?
+    // Work for the workers of a mining pool, like the block our own miner
+    // would seal: `None` while there is nothing to mine
+    pub fn block_template(&mut self) -> Option<Block> {
+        let transactions = self.mempool.block_template(MAX_BLOCK_SIZE);
+
+        self.assemble_block(template_to_proto_block(transactions))
+    }
+
+    // What the proof of work hash of the next block must be below, `None`
+    // with proof of stake
+    pub fn next_target(&self) -> Option<BigUint> {
+        self.consensus.target(&self.blockchain, &self.tip_hash)
+    }
+
+    // Hands `block` out to external workers, returning the id they submit
+    // nonces for it with
+    pub fn add_work(&mut self, block: Block) -> u64 {
+        let id = self.next_work;
+
+        self.next_work += 1;
+        self.work.push_back((id, block));
+
+        while self.work.len() > MAX_WORK {
+            self.work.pop_front();
         }
 
-        Ok(())
+        id
     }
-}
 
-impl std::fmt::Display for Transaction {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "transfer ${} from {} to {}",
-            self.details.amount,
-            PublicKeyFmt(self.details.source_public_key),
-            PublicKeyFmt(self.details.destination_public_key),
-        )
+    // Only the latest work is remembered
+    pub fn work(&self, id: u64) -> Option<&Block> {
+        self.work
+            .iter()
+            .find(|(work, _)| *work == id)
+            .map(|(_, block)| block)
     }
-}
 
-pub fn sign(details: &TransactionDetails) -> Signature {
-    [0; 128]
-}
+    // Which hash the proof of work of our network is
+    pub fn pow_hash(&self) -> PowHash {
+        self.settings.network.pow_hash
+    }
 
-fn read_public_key_from_disk() -> PublicKey {
-    [0; 128]
-}
+    // Where each deployment of the network stands, for the next block
+    pub fn deployment_info(&self) -> Vec<DeploymentInfo> {
+        self.deployments.info(&self.blockchain, &self.tip_hash)
+    }
 
-fn transaction_to_proto_block(transaction: Transaction) -> ProtoBlock {
-    ProtoBlock {
-        nonce: [0; 32],
-        transaction: transaction,
+    // Whether the rules of a deployment apply to the next block
+    pub fn is_active(&self, deployment: &str) -> bool {
+        self.deployments
+            .is_active(deployment, &self.blockchain, &self.tip_hash)
     }
-}
 
-fn to_32bytes(byte_vector: &[u8]) -> [u8; 32] {
-    let n = byte_vector.len();
+    // Least fee of a transaction registering a name
+    pub fn name_fee(&self) -> u64 {
+        self.settings.network.name_fee
+    }
 
-    let mut bytes: [u8; 32] = [0; 32];
-    let slice = &mut bytes[32 - n..32];
+    // Least fee of a transaction carrying data, for each byte of it
+    pub fn data_byte_fee(&self) -> u64 {
+        self.settings.network.data_byte_fee
+    }
 
-    slice.copy_from_slice(byte_vector);
+    // Everything sent and received since we started
+    pub fn net_totals(&self) -> Traffic {
+        let mut totals = self.past_traffic;
 
-    bytes
-}
+        for peer in self.peers.values() {
+            totals.add(&peer.traffic().total);
+        }
 
-fn hash_block(block: &Block) -> Hash {
-    let hasher = Sha256::new();
-
-    to_32bytes(
-        &hasher
-            .chain(block.time.to_le_bytes())
-            .chain(&block.node_public_key)
-            .chain(&block.previous_hash)
-            .chain(&block.nonce)
-            .chain(&block.transaction.source_signature)
-            .chain(&block.transaction.details.source_public_key)
-            .chain(&block.transaction.details.destination_public_key)
-            .chain(block.transaction.details.amount.to_le_bytes())
-            .finalize(),
-    )
-}
+        totals
+    }
 
-fn timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
-}
+    // Where we can be reached: the address our router gave us or else,
+    // once most of our peers agree on it, the IP they see us connecting
+    // from with the port we listen on
+    pub fn external_address(&self) -> Option<SocketAddr> {
+        if self.mapped_address.is_some() {
+            return self.mapped_address;
+        }
 
-fn amount(
-    mut value: i128,
-    blockchain: &Blockchain,
-    tip_hash: &Hash,
-    id: &PublicKey,
-) -> Result<i128, String> {
-    if tip_hash == &[0; 32] {
-        Ok(value)
-    } else {
-        match blockchain.get(tip_hash) {
-            Some(block) => {
-                // TODO: cannot process transactions that involve ourselves only
+        let port = self.settings.listen_port?;
+        let mut votes: HashMap<IpAddr, usize> = HashMap::new();
 
-                if block.transaction.details.source_public_key
-                    == block.transaction.details.destination_public_key
-                {
-                    return Err("Source and destination are the same!".to_string());
-                }
+        for ip in self.address_votes.values() {
+            *votes.entry(*ip).or_default() += 1;
+        }
 
-                if id == &block.transaction.details.source_public_key {
-                    value -= block.transaction.details.amount as i128;
-                }
+        votes
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .filter(|(_, count)| {
+                *count >= MIN_ADDRESS_VOTES && count * 2 > self.address_votes.len()
+            })
+            .map(|(ip, _)| SocketAddr::new(ip, port))
+    }
 
-                if id == &block.transaction.details.destination_public_key {
-                    value += block.transaction.details.amount as i128;
-                }
+    pub async fn set_mapped_address(&mut self, address: SocketAddr) {
+        let before = self.external_address();
 
-                if id == &block.node_public_key {
-                    value += 1;
-                }
+        self.mapped_address = Some(address);
+        self.announce_external_address(before).await;
+    }
 
-                amount(value, blockchain, &block.previous_hash, id)
-            }
-            None => Err("Previous hash not found in the blockchain!".to_string()),
+    // Counts `ip` as where `peer` sees us connecting from. Private and
+    // loopback addresses are of no use to other nodes.
+    pub async fn add_address_vote(&mut self, peer: SocketAddr, ip: IpAddr) {
+        if !address_book::is_routable(&ip) {
+            return;
         }
+
+        let before = self.external_address();
+
+        self.address_votes.insert(peer, ip);
+        self.announce_external_address(before).await;
     }
-}
 
-// TODO: verifying signatures
-fn valid_block(block: &Block, blockchain: &Blockchain) -> bool {
-    match amount(
-        0,
-        blockchain,
-        &block.previous_hash,
-        &block.transaction.details.source_public_key,
-    ) {
-        Ok(value) => {
-            println!(
-                "FUNDS CHECK: {} has ${}. Trying to transfer ${}",
-                PublicKeyFmt(block.transaction.details.source_public_key),
-                value,
-                block.transaction.details.amount
-            );
+    // Tells every peer when we are reachable somewhere new
+    async fn announce_external_address(&mut self, before: Option<SocketAddr>) {
+        let address = match self.external_address() {
+            Some(address) if Some(address) != before => address,
+            _ => return,
+        };
+
+        println!("REACHABLE AT {}", address);
 
-            value >= block.transaction.details.amount as i128
-                && block.transaction.details.source_public_key
-                    != block.transaction.details.destination_public_key
+        for peer in self.peers.values_mut() {
+            peer.write(Frame::Addr(vec![address])).await;
         }
-        Err(err) => {
-            println!("{}", err);
+    }
+
+    // Returns how many of the addresses were new to us
+    pub fn add_addresses(&mut self, addresses: Vec<SocketAddr>) -> usize {
+        let external_address = self.external_address();
+        let now = timestamp();
+
+        addresses
+            .into_iter()
+            .take(MAX_ADDRESSES)
+            .filter(|address| Some(*address) != external_address)
+            .filter(|address| self.address_book.add(*address, now))
+            .count()
+    }
+
+    // What we answer `Frame::GetAddr` with, our own address first
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.external_address()
+            .into_iter()
+            .chain(self.address_book.addresses(MAX_ADDRESSES))
+            .take(MAX_ADDRESSES)
+            .collect()
+    }
+
+    pub fn address_book(&self) -> &AddressBook {
+        &self.address_book
+    }
+
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        self.peers.contains_key(addr)
+    }
+
+    pub fn outbound_count(&self) -> usize {
+        self.outbound_peers.len()
+    }
+
+    // Our oldest outbound peers, to reconnect to first on restart. Someone
+    // trying to surround us with their nodes would have to keep us from
+    // connecting to them, besides filling our address book.
+    pub fn anchors(&self) -> Vec<SocketAddr> {
+        let mut outbound: Vec<(&SocketAddr, &u64)> = self.outbound_peers.iter().collect();
+
+        outbound.sort_by_key(|(_, since)| **since);
+
+        outbound
+            .into_iter()
+            .take(address_book::MAX_ANCHORS)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    // Picks up to `count` addresses from the address book to connect to,
+    // counting each as an attempt. Only one per network group is picked,
+    // skipping the groups of our outbound peers and of the `pending`
+    // addresses we are already dialing.
+    pub fn select_outbound(&mut self, count: usize, pending: &[SocketAddr]) -> Vec<SocketAddr> {
+        let now = timestamp();
+        let mut exclude: HashSet<SocketAddr> = self.peers.keys().cloned().collect();
+
+        exclude.extend(self.external_address());
+        exclude.extend(pending);
+
+        let groups = self
+            .outbound_peers
+            .keys()
+            .chain(pending)
+            .map(address_book::network_group)
+            .collect();
+
+        let selected = self.address_book.select(count, &exclude, groups, now);
 
-            false
+        for address in &selected {
+            self.address_book.attempted(address, now);
         }
+
+        selected
     }
-}
 
-pub async fn block_received(node: Arc<Mutex<Node>>, block: Block) {
-    let hash = hash_block(&block);
-    let mut node = node.lock().await;
+    // Picks an address to check is still reachable, counting it as an
+    // attempt
+    pub fn select_feeler(&mut self) -> Option<SocketAddr> {
+        let now = timestamp();
+        let mut exclude: HashSet<SocketAddr> = self.peers.keys().cloned().collect();
 
-    println!("BLOCKCHAIN TIP IS {}", HashFmt(node.tip_hash));
-    println!("BLOCK HASH IS {}", HashFmt(hash));
-    println!("BLOCK PREVIOUS HASH IS {}", HashFmt(block.previous_hash));
+        exclude.extend(self.external_address());
 
-    match node.blockchain.get(&hash) {
-        Some(_) => println!("BLOCKCHAIN ALREADY HAS BLOCK. STOPPING."),
-        None => {
-            if valid_block(&block, &node.blockchain) {
-                println!("BLOCK IS VALID");
+        let address = self.address_book.feeler_candidate(&exclude, now)?;
 
-                // FIXME: handling timestamps
-                if block.previous_hash == node.tip_hash {
-                    node.tip_hash = hash;
-                }
+        self.address_book.attempted(&address, now);
+
+        Some(address)
+    }
+
+    // For connections that complete the handshake without becoming peers
+    pub fn address_reachable(&mut self, address: &SocketAddr) {
+        self.address_book.connected(address, timestamp());
+    }
+
+    // Counts an attempt to connect to `address` made outside of
+    // `select_outbound`, such as to a seed
+    pub fn address_attempted(&mut self, address: &SocketAddr) {
+        self.address_book.attempted(address, timestamp());
+    }
+
+    // Protocol version, height (when they told us) and traffic of each peer
+    pub fn peer_traffic(&self) -> Vec<(SocketAddr, u32, Option<u64>, TrafficStats)> {
+        self.peers
+            .iter()
+            .map(|(addr, peer)| {
+                (
+                    *addr,
+                    peer.protocol_version(),
+                    self.peer_heights.get(addr).copied(),
+                    peer.traffic(),
+                )
+            })
+            .collect()
+    }
 
-                node.blockchain.insert(hash, block);
+    // Smoothed over the last pings, once the peer answered one
+    pub fn round_trip(&self, addr: &SocketAddr) -> Option<std::time::Duration> {
+        self.latencies.round_trip(addr)
+    }
+
+    // Whether we uploaded more than the cap since the start of the (UTC)
+    // day
+    pub fn upload_cap_reached(&mut self) -> bool {
+        let cap = match self.settings.daily_upload_cap {
+            Some(cap) => cap,
+            None => return false,
+        };
+        let sent = self.net_totals().bytes_sent;
+        let today = timestamp() / SECONDS_PER_DAY;
+
+        if today != self.upload_day {
+            self.upload_day = today;
+            self.sent_before_upload_day = sent;
+        }
+
+        sent - self.sent_before_upload_day >= cap
+    }
+
+    // Past the upload cap only blocks close to the tip are served
+    fn may_serve(&mut self, hash: &Hash) -> bool {
+        match self.blockchain.height(hash) {
+            Some(height) if height + RECENT_BLOCKS < self.height() => !self.upload_cap_reached(),
+            _ => true,
+        }
+    }
+
+    pub fn set_peer_height(&mut self, addr: SocketAddr, height: u64) {
+        self.peer_heights.insert(addr, height);
+    }
+
+    // How many of `headers` a peer sent go on from a block we have, each
+    // from the one before, sealed as the consensus wants, and the height of
+    // the last of those. Only they count towards the height of the peer.
+    pub fn valid_headers(&self, headers: &[BlockHeader]) -> (usize, u64) {
+        let now = self.adjusted_time();
+        let mut height = match headers
+            .first()
+            .and_then(|first| self.blockchain.height(first.previous_hash()))
+        {
+            Some(height) => height,
+            None => return (0, 0),
+        };
 
-                println!("** BLOCK ADDED TO BLOCKCHAIN **");
-                println!("{}", BlockchainFmt(node.blockchain.clone(), node.tip_hash));
+        for (index, header) in headers.iter().enumerate() {
+            // Blocks with only their header, which is all that is checked
+            let block = Block::new(header.clone(), Vec::new());
+
+            if (index > 0 && header.previous_hash() != &headers[index - 1].hash())
+                || header.time() > now + MAX_FUTURE_BLOCK_TIME
+                || self.consensus.check(&block).is_err()
+                || self
+                    .consensus
+                    .verify(&block, &self.blockchain, now)
+                    .is_err()
+            {
+                return (index, height);
             }
+
+            height += 1;
         }
+
+        (headers.len(), height)
     }
-}
 
-async fn block_created(node: Arc<Mutex<Node>>, block: Block) {
-    block_received(node, block).await
-}
+    // The height of the best chain we know of, ours or a peer's
+    pub fn best_known_height(&self) -> u64 {
+        self.peer_heights
+            .values()
+            .copied()
+            .chain(std::iter::once(self.height()))
+            .max()
+            .unwrap_or(0)
+    }
 
-pub async fn transaction_received(transaction: Transaction, tx: mpsc::Sender<ProtoBlock>) {
-    println!("TRANSACTION {}", transaction);
+    // Whether we are close enough to the best chain to mine and take
+    // transactions
+    pub fn is_synced(&self) -> bool {
+        self.best_known_height() <= self.height() + self.settings.sync_tolerance
+    }
 
-    match tx.send(transaction_to_proto_block(transaction)).await {
-        Ok(_) => (),
-        Err(_) => (),
+    pub fn peer_count(&self) -> usize {
+        self.peers.len()
     }
 
-    // TODO: replicate transaction in the network
-}
+    pub fn mempool_len(&self) -> usize {
+        self.mempool.len()
+    }
 
-async fn proof_of_work(
-    node: Arc<Mutex<Node>>,
-    proto_block: ProtoBlock,
-) -> Result<Block, ProtoBlock> {
-    let unlocked_node = node.lock().await;
-
-    let block = Block {
-        time: timestamp(),
-        node_public_key: unlocked_node.public_key,
-        previous_hash: unlocked_node.tip_hash,
-        nonce: proto_block.nonce,
-        transaction: proto_block.transaction.clone(),
-    };
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
 
-    let hash = hash_block(&block);
+    pub fn set_filter(&mut self, addr: SocketAddr, filter: BloomFilter) {
+        if filter.is_within_limits() {
+            self.filters.insert(addr, filter);
+        } else {
+            println!("IGNORING OVERSIZED FILTER FROM {}", addr);
+        }
+    }
 
-    println!("PROOF OF WORK {}", HashFmt(hash));
+    // Fails while the wallet is locked, or when there is none
+    pub fn secret_key(&self) -> Result<&SecretKey, String> {
+        self.wallet(None)?.secret_key()
+    }
 
-    if BigUint::from_bytes_le(&hash) < BigUint::from(2u32).pow(255) - BigUint::from(1u32) {
-        println!("PROOF OF WORK ACCEPTED");
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
 
-        Ok(block)
-    } else {
-        println!("PROOF OF WORK WRONG");
+    pub fn has_wallet(&self) -> bool {
+        self.keystore.is_some()
+    }
 
-        Err(ProtoBlock {
-            nonce: to_32bytes(
-                &(BigUint::from_bytes_le(&proto_block.nonce) + BigUint::from(1u32)).to_bytes_le(),
-            ),
-            transaction: proto_block.transaction,
-        })
+    pub fn wallets(&self) -> &Wallets {
+        &self.wallets
     }
-}
 
-pub async fn block_generator(
-    node: Arc<Mutex<Node>>,
-    mut rx: mpsc::Receiver<ProtoBlock>,
-    tx: mpsc::Sender<ProtoBlock>,
-) {
-    loop {
-        match rx.recv().await {
-            Some(proto_block) => match proof_of_work(node.clone(), proto_block).await {
-                Ok(block) => block_created(node.clone(), block).await,
-                Err(proto_block) => match tx.send(proto_block).await {
-                    Ok(()) => {}
-                    Err(_) => {}
-                },
-            },
-            None => {}
+    pub fn wallets_mut(&mut self) -> &mut Wallets {
+        &mut self.wallets
+    }
+
+    // A named wallet, or the key of the node without a name
+    pub fn wallet(&self, name: Option<&str>) -> Result<&Keystore, String> {
+        match name {
+            Some(name) => self.wallets.get(name),
+            None => self.keystore.as_ref().ok_or_else(no_wallet),
+        }
+    }
+
+    pub fn wallet_mut(&mut self, name: Option<&str>) -> Result<&mut Keystore, String> {
+        match name {
+            Some(name) => self.wallets.get_mut(name),
+            None => self.keystore.as_mut().ok_or_else(no_wallet),
         }
     }
+
+    // Taken once, then read from without the node lock
+    pub fn chain_views(&self) -> ChainViews {
+        self.views.views()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn raise_alert(&mut self, kind: AlertKind, message: String) {
+        self.alerts.raise(kind, message);
+    }
+
+    pub fn clear_alert(&mut self, kind: AlertKind) {
+        self.alerts.clear(kind);
+    }
+
+    pub fn alerts(&self) -> &Alerts {
+        &self.alerts
+    }
+
+    pub fn audit(&mut self) -> &mut Audit {
+        &mut self.audit
+    }
+
+    pub fn record_to(&mut self, recorder: Arc<Recorder>) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.clone()
+    }
+
+    pub fn tip_hash(&self) -> Hash {
+        self.tip_hash
+    }
+
+    // Number of blocks in the main chain
+    pub fn height(&self) -> u64 {
+        self.blockchain.height(&self.tip_hash).unwrap_or(0)
+    }
+
+    // Blocks up to this height are never reorganized away, as we don't
+    // switch to branches forking deeper than `max_reorg_depth` or below the
+    // latest checkpoint
+    pub fn finalized_height(&self) -> u64 {
+        let checkpointed = match &self.checkpoint {
+            Some(checkpoint) if self.on_main_chain(checkpoint) => checkpoint.height,
+            _ => 0,
+        };
+
+        self.height()
+            .saturating_sub(self.settings.network.max_reorg_depth)
+            .max(checkpointed)
+    }
+
+    fn on_main_chain(&self, checkpoint: &Checkpoint) -> bool {
+        self.blockchain.main_chain_hash(checkpoint.height) == Some(checkpoint.hash)
+    }
+
+    pub fn checkpoint(&self) -> Option<&Checkpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    // Keeps `checkpoint` if it is newer than ours and signed by a signer of
+    // the network, passing it on to every peer but `origin`. Returns whether
+    // it was new.
+    pub async fn checkpoint_received(
+        &mut self,
+        checkpoint: Checkpoint,
+        origin: Option<SocketAddr>,
+    ) -> Result<bool, String> {
+        checkpoint.verify(&self.settings.network.checkpoint_signers)?;
+
+        if let Some(latest) = &self.checkpoint {
+            if checkpoint.height <= latest.height {
+                return Ok(false);
+            }
+        }
+
+        println!(
+            "CHECKPOINT AT {} IS {} (SIGNED BY {})",
+            checkpoint.height,
+            HashFmt(checkpoint.hash),
+            PublicKeyFmt(checkpoint.signer)
+        );
+
+        if self.height() >= checkpoint.height && !self.on_main_chain(&checkpoint) {
+            println!("OUR MAIN CHAIN DOES NOT HAVE THE CHECKPOINTED BLOCK");
+        }
+
+        for (addr, peer) in self.peers.iter_mut() {
+            if Some(*addr) != origin {
+                peer.write(Frame::Checkpoint(checkpoint.clone())).await;
+            }
+        }
+
+        self.checkpoint = Some(checkpoint);
+
+        Ok(true)
+    }
+
+    // Signs and announces a checkpoint when the tip reaches a height one is
+    // due at and we are one of the signers of the network
+    async fn sign_checkpoint(&mut self) {
+        let signers = &self.settings.network.checkpoint_signers;
+
+        if !self
+            .public_key
+            .is_some_and(|public_key| signers.contains(&public_key))
+        {
+            return;
+        }
+
+        let height = match checkpoint::due(self.height()) {
+            Some(height) => height,
+            None => return,
+        };
+
+        let secret_key = match self.secret_key() {
+            Ok(secret_key) => secret_key.clone(),
+            Err(issue) => {
+                println!("COULD NOT CHECKPOINT HEIGHT {}: {}", height, issue);
+
+                return;
+            }
+        };
+
+        if let Some(hash) = self.blockchain.main_chain_hash(height) {
+            let checkpoint = Checkpoint::new(height, hash, &secret_key);
+
+            if let Err(issue) = self.checkpoint_received(checkpoint, None).await {
+                println!("COULD NOT CHECKPOINT {}: {}", HashFmt(hash), issue);
+            }
+        }
+    }
+
+    pub fn chain_stats(&self) -> ChainStats {
+        // Proof of stake has no target
+        let target = self
+            .consensus
+            .target(&self.blockchain, &self.tip_hash)
+            .map(|target| to_32bytes(&target.to_bytes_le()))
+            .unwrap_or([0; 32]);
+
+        self.stats.stats(target)
+    }
+
+    // Finds a mined transaction through the transaction index, along with
+    // where it is
+    pub fn transaction(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<(&Transaction, TransactionLocation)>, String> {
+        if !self.blockchain.has_transaction_index() {
+            return Err("The transaction index is disabled (txindex)".to_string());
+        }
+
+        Ok(self
+            .blockchain
+            .transaction_location(hash)
+            .and_then(|location| {
+                let block = self.blockchain.get(&location.block_hash)?;
+
+                Some((block.transactions.get(location.index)?, location))
+            }))
+    }
+
+    pub fn block_height(&self, hash: &Hash) -> Option<u64> {
+        self.blockchain.height(hash)
+    }
+
+    // 1 for a block at the tip, 0 for unknown blocks
+    pub fn confirmations(&self, hash: &Hash) -> u64 {
+        match self.blockchain.height(hash) {
+            Some(height) => (self.height() + 1).saturating_sub(height),
+            None => 0,
+        }
+    }
+
+    pub fn block(&self, hash: &Hash) -> Option<&Block> {
+        self.blockchain.get(hash)
+    }
+
+    // Where a transaction stands as far as we know
+    pub fn transaction_status(&self, hash: &Hash) -> TransactionStatus {
+        if self.mempool.contains(hash) {
+            return TransactionStatus::InMempool;
+        }
+
+        if let Some(block_hash) = confirming_block(hash, &self.blockchain, &self.tip_hash) {
+            return TransactionStatus::Confirmed {
+                height: self.blockchain.height(&block_hash).unwrap_or(0),
+                confirmations: self.confirmations(&block_hash),
+            };
+        }
+
+        match self.rejections.get(hash) {
+            Some(reason) => TransactionStatus::Rejected(*reason),
+            None => TransactionStatus::Unknown,
+        }
+    }
+
+    // What `check_block` needs, to run it without holding the node
+    fn checkers(&self) -> (Arc<dyn Consensus>, Arc<Lru<Hash, ()>>) {
+        (self.consensus.clone(), self.verified.clone())
+    }
+
+    fn remember_rejection(&mut self, hash: Hash, reason: RejectReason) {
+        if self.rejections.insert(hash, reason).is_none() {
+            self.rejection_order.push_back(hash);
+        }
+
+        if self.rejection_order.len() > MAX_REMEMBERED_REJECTIONS {
+            if let Some(oldest) = self.rejection_order.pop_front() {
+                self.rejections.remove(&oldest);
+            }
+        }
+    }
+
+    // Evicts the cheapest transactions while the mempool is over its
+    // memory budget. Returns the hashes of those evicted. Whoever sent them
+    // is told, except for `incoming`, which is being answered already.
+    fn trim_mempool(&mut self, incoming: Option<&Hash>) -> HashSet<Hash> {
+        let evicted = self.mempool.trim(self.settings.memory.mempool_budget());
+        let mut hashes = HashSet::new();
+
+        for (hash, entry) in evicted {
+            println!("MEMPOOL IS FULL. EVICTED TRANSACTION {}", HashFmt(hash));
+
+            self.remember_rejection(hash, RejectReason::MempoolFull);
+
+            if incoming != Some(&hash) {
+                self.events.publish(ChainEvent::MempoolExpired {
+                    hash,
+                    origin: entry.origin(),
+                });
+            }
+
+            hashes.insert(hash);
+        }
+
+        hashes
+    }
+
+    // How hard our own miner works
+    pub fn mining_info(&self) -> MiningInfo {
+        MiningInfo {
+            cpu_percent: self.settings.mining.cpu_percent,
+            hashrate: self.hashrate.hashrate(),
+            blocks_mined: self.orphans.mined_count(),
+            blocks_orphaned: self.orphans.orphaned_count(),
+            orphan_rate: self.orphans.orphan_rate(),
+            propagation: self.propagation.peers(),
+        }
+    }
+
+    // A block `peer` was the first to send us took `delay` from arriving
+    // to being validated
+    pub fn block_propagated(&mut self, peer: SocketAddr, delay: std::time::Duration) {
+        self.propagation.record(peer, delay);
+    }
+
+    // How much memory each bounded part of the node takes, and may take
+    pub fn memory_info(&self) -> MemoryInfo {
+        MemoryInfo {
+            mempool: MemoryUsage {
+                entries: self.mempool.len(),
+                bytes: self.mempool.bytes(),
+                budget: self.settings.memory.mempool_budget(),
+            },
+            verification_cache: MemoryUsage {
+                entries: self.verified.len(),
+                bytes: self.verified.len() as u64 * VERIFIED_SIZE,
+                budget: self.verified.capacity() as u64 * VERIFIED_SIZE,
+            },
+            rejections: MemoryUsage {
+                entries: self.rejections.len(),
+                bytes: self.rejections.len() as u64 * REJECTION_SIZE,
+                budget: MAX_REMEMBERED_REJECTIONS as u64 * REJECTION_SIZE,
+            },
+        }
+    }
+
+    // Checks the main chain again at `level` (see `verify.rs`), from the
+    // genesis block up, stopping at the first block breaking the rules
+    pub fn verify_chain(&self, level: u8) -> ChainVerification {
+        let mut chain = Vec::new();
+        let mut hash = self.tip_hash;
+
+        while hash != [0; 32] {
+            match self.blockchain.get(&hash) {
+                Some(block) => {
+                    chain.push((hash, block));
+                    hash = block.header.previous_hash;
+                }
+                // Nothing under it can be checked
+                None => {
+                    return ChainVerification {
+                        level,
+                        blocks: 0,
+                        violation: Some(ChainViolation {
+                            height: self.height() - chain.len() as u64,
+                            hash,
+                            issue: "Missing from the store".to_string(),
+                        }),
+                    }
+                }
+            }
+        }
+
+        // Always checked again, the cache is for blocks we receive
+        let verified = Lru::new(0);
+        let mut view = ChainView::default();
+        let mut confirmed = HashSet::new();
+        let mut slashed = HashSet::new();
+
+        for (index, (hash, block)) in chain.iter().rev().enumerate() {
+            let height = index as u64 + 1;
+            let mut result = self.verify_linkage(hash, block, height);
+
+            if result.is_ok() && level >= VERIFY_SEALS {
+                result = check_block(block, &*self.consensus, &verified)
+                    .and_then(|_| {
+                        self.consensus
+                            .verify(block, &self.blockchain, self.adjusted_time())
+                    })
+                    .map_err(|reason| reason.to_string());
+            }
+
+            if result.is_ok() && level >= VERIFY_TRANSACTIONS {
+                result =
+                    self.verify_transactions(block, height, &view, &mut confirmed, &mut slashed);
+                view = view.connected(
+                    *hash,
+                    block,
+                    self.blockchain.reward(hash),
+                    self.settings.network.name_lifetime,
+                );
+            }
+
+            if let Err(issue) = result {
+                return ChainVerification {
+                    level,
+                    blocks: index as u64,
+                    violation: Some(ChainViolation {
+                        height,
+                        hash: *hash,
+                        issue,
+                    }),
+                };
+            }
+        }
+
+        let violation = match chain.first() {
+            Some((hash, _))
+                if level >= VERIFY_TRANSACTIONS && view != *self.views.views().load() =>
+            {
+                Some(ChainViolation {
+                    height: chain.len() as u64,
+                    hash: *hash,
+                    issue: "Balances differ from the ones the node has".to_string(),
+                })
+            }
+            _ => None,
+        };
+
+        ChainVerification {
+            level,
+            blocks: chain.len() as u64,
+            violation,
+        }
+    }
+
+    fn verify_linkage(&self, hash: &Hash, block: &Block, height: u64) -> Result<(), String> {
+        if &hash_block(block) != hash {
+            return Err(format!("Stored under {}", HashFmt(*hash)));
+        }
+
+        match self.blockchain.height(hash) {
+            Some(stored) if stored == height => Ok(()),
+            stored => Err(format!("Stored at height {:?}", stored)),
+        }
+    }
+
+    // Like `valid_block` would, with the balances in `view`, the
+    // transactions already mined and the producers already slashed
+    fn verify_transactions(
+        &self,
+        block: &Block,
+        height: u64,
+        view: &ChainView,
+        confirmed: &mut HashSet<Hash>,
+        slashed: &mut HashSet<PublicKey>,
+    ) -> Result<(), String> {
+        let mut changes: HashMap<PublicKey, i128> = HashMap::new();
+        let mut registered = HashMap::new();
+        let mut tokens = TokenChanges::default();
+        let network = &self.settings.network;
+
+        for transaction in &block.transactions {
+            let owner = transaction
+                .name()
+                .and_then(|name| view.name(name))
+                .filter(|record| record.expires_at >= height)
+                .map(|record| record.owner);
+            let reason = if transaction.is_expired_at(height) {
+                Some(RejectReason::Expired)
+            } else if transaction.is_locked_at(height) {
+                Some(RejectReason::Locked)
+            } else if transaction.pays_dust(network.dust_threshold) {
+                Some(RejectReason::Dust)
+            } else if !confirmed.insert(transaction.hash()) {
+                Some(RejectReason::Duplicate)
+            } else if transaction.name().is_some() && transaction.fee() < network.name_fee {
+                Some(RejectReason::NameFeeTooLow)
+            } else if network.is_active(Fork::DataFee, height)
+                && transaction.fee() < network.data_fee(transaction.data().map_or(0, <[u8]>::len))
+            {
+                Some(RejectReason::DataFeeTooLow)
+            } else if owner.is_some_and(|owner| &owner != transaction.source()) {
+                Some(RejectReason::NameTaken)
+            } else if view.balance(transaction.source())
+                + changes.get(transaction.source()).unwrap_or(&0)
+                < (transaction.amount() + transaction.fee()) as i128
+            {
+                Some(RejectReason::InsufficientFunds)
+            } else {
+                None
+            };
+
+            let reason = reason
+                .or(claim_name(&mut registered, transaction).err())
+                .or(tokens
+                    .check(
+                        transaction,
+                        |asset| view.asset(asset).is_some(),
+                        |asset, id| view.token_balance(asset, id) as i128,
+                    )
+                    .err());
+
+            if let Some(reason) = reason {
+                return Err(format!(
+                    "Transaction {}: {}",
+                    HashFmt(transaction.hash()),
+                    reason
+                ));
+            }
+
+            apply_changes(&mut changes, transaction);
+            tokens.apply(transaction);
+        }
+
+        for evidence in &block.slashings {
+            if !slashed.insert(*evidence.offender()) {
+                return Err(format!(
+                    "{} was already slashed",
+                    PublicKeyFmt(*evidence.offender())
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Drops the transactions that waited for too long or can't be mined
+    // anymore
+    pub fn evict_expired_transactions(&mut self) {
+        let next_height = self.height() + 1;
+        let expired =
+            self.mempool
+                .remove_expired(timestamp(), self.settings.mempool_ttl, next_height);
+
+        for (hash, entry) in expired {
+            println!("TRANSACTION {} EXPIRED", HashFmt(hash));
+
+            self.remember_rejection(hash, RejectReason::Expired);
+
+            self.events.publish(ChainEvent::MempoolExpired {
+                hash,
+                origin: entry.origin(),
+            });
+        }
+    }
+
+    // Balance of `id` as of the current tip
+    pub fn balance(&self, id: &PublicKey) -> Result<i128, String> {
+        balance_at(&self.blockchain, &self.tip_hash, id)
+    }
+
+    // Suggests a fee rate (per 1000 bytes) for a transaction to be confirmed
+    // within `target_blocks`. Starts from what recent blocks paid and raises
+    // it when the mempool has more than fits in that many blocks.
+    pub fn estimate_fee(&self, target_blocks: u32) -> u64 {
+        let mut rates = Vec::new();
+        let mut blocks = 0;
+        let mut hash = self.tip_hash;
+
+        while let Some(block) = self.blockchain.get(&hash) {
+            if blocks == FEE_ESTIMATION_BLOCKS {
+                break;
+            }
+
+            rates.extend(block.transactions.iter().map(Transaction::fee_rate));
+            blocks += 1;
+            hash = block.header.previous_hash;
+        }
+
+        rates.sort_unstable();
+
+        let recent = rates.get(rates.len() / 2).copied().unwrap_or(0);
+
+        let per_block = (rates.len() / blocks.max(1)).max(1);
+        let capacity = per_block * target_blocks.max(1) as usize;
+
+        let congestion = match self.mempool.fee_rates().get(capacity - 1) {
+            // Outbid whatever would be the last one to make it in time
+            Some(rate) => rate + 1,
+            None => 0,
+        };
+
+        recent.max(congestion)
+    }
+
+    pub async fn send(&mut self, addr: &SocketAddr, frame: Frame) {
+        if let Some(peer) = self.peers.get_mut(addr) {
+            peer.write(frame).await;
+        }
+    }
+
+    // Checks a transaction can wait in the mempool to be mined in the next
+    // block, returning the transactions already waiting it depends on
+    fn mempool_admission(&self, transaction: &Transaction) -> Result<HashSet<Hash>, RejectReason> {
+        let height = self.height() + 1;
+        let source = *transaction.source();
+        // What the source already spends in the mempool counts against it
+        let (spent, credits) = self.mempool.pending(&source);
+        let mut changes = HashMap::from([(source, -(spent as i128))]);
+
+        // First come, first served, in the mempool as in blocks
+        if let Some(name) = transaction.name() {
+            if self
+                .mempool
+                .name_registrant(name)
+                .is_some_and(|registrant| registrant != &source)
+            {
+                return Err(RejectReason::NameTaken);
+            }
+        }
+
+        // Tokens the source already moves in the mempool count against it
+        // too, and assets can't be issued twice
+        if let Some(action) = transaction.asset() {
+            let asset = action.asset().to_string();
+            let mut tokens = TokenChanges::default();
+
+            if self.mempool.asset_issuer(&asset).is_some() {
+                tokens.issued.insert(asset.clone());
+            }
+
+            tokens.balances.insert(
+                (asset.clone(), source),
+                -(self.mempool.pending_tokens(&source, &asset) as i128),
+            );
+            tokens.check_on_chain(transaction, &self.blockchain, &self.tip_hash)?;
+        }
+
+        let validity = valid_transaction(
+            transaction,
+            &self.blockchain,
+            &self.tip_hash,
+            height,
+            &changes,
+            &self.settings.network,
+            &self.verified,
+        );
+
+        // It may still be paid for by transactions that aren't mined yet,
+        // which then have to be mined first
+        if validity != Err(RejectReason::InsufficientFunds) || credits.is_empty() {
+            return validity.map(|_| HashSet::new());
+        }
+
+        *changes.get_mut(&source).unwrap() += credits
+            .iter()
+            .map(|(_, amount)| *amount as i128)
+            .sum::<i128>();
+
+        valid_transaction(
+            transaction,
+            &self.blockchain,
+            &self.tip_hash,
+            height,
+            &changes,
+            &self.settings.network,
+            &self.verified,
+        )?;
+
+        Ok(credits.into_iter().map(|(hash, _)| hash).collect())
+    }
+
+    pub fn relay_policy(&self) -> RelayPolicy {
+        self.settings.relay
+    }
+
+    pub fn is_trusted(&self, addr: &SocketAddr) -> bool {
+        self.settings.trusted_peers.contains(&addr.ip())
+    }
+
+    pub fn policy(&self) -> &SpendingPolicy {
+        &self.settings.policy
+    }
+
+    // Passes a transaction we just accepted on to every peer that wants
+    // them, except the one it came from
+    // The frame is encoded once for each codec, reusing `payload`, what it
+    // came in as, when there is one
+    async fn relay_transaction(
+        &mut self,
+        transaction: &Transaction,
+        origin: Option<SocketAddr>,
+        payload: Option<Payload>,
+    ) {
+        let frame = Frame::Transaction(transaction.clone());
+        let mut payloads: HashMap<_, Payload> = payload
+            .into_iter()
+            .map(|payload| (payload.codec(), payload))
+            .collect();
+
+        for (addr, peer) in self.peers.iter_mut() {
+            if Some(*addr) != origin && !self.blocks_only_peers.contains(addr) {
+                let payload = payloads
+                    .entry(peer.codec())
+                    .or_insert_with(|| Payload::encode(&frame, peer.codec()));
+
+                peer.write_payload(&frame, payload).await;
+            }
+        }
+    }
+
+    // Headers of the main chain that come after `locator`, oldest first.
+    // Unknown locators get the chain from the beginning.
+    fn headers_after(&self, locator: &Hash) -> Vec<BlockHeader> {
+        let mut headers = Vec::new();
+        let mut hash = self.tip_hash;
+
+        while &hash != locator {
+            match self.blockchain.get(&hash) {
+                Some(block) => {
+                    headers.push(block.header.clone());
+                    hash = block.header.previous_hash;
+                }
+                None => break,
+            }
+        }
+
+        headers.reverse();
+        headers.truncate(MAX_HEADERS);
+
+        headers
+    }
+
+    // Keeps evidence of producers that signed another block for the same
+    // slot, so their coins can be burned
+    fn watch_double_signs(&mut self, header: &BlockHeader) {
+        let slot = match self.consensus.slot(header) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        match self.signed_slots.get(&(header.node_public_key, slot)) {
+            Some(first) if first.hash() != header.hash() => {
+                println!(
+                    "{} SIGNED TWO BLOCKS FOR SLOT {}",
+                    PublicKeyFmt(header.node_public_key),
+                    slot
+                );
+
+                self.evidence.push(DoubleSign {
+                    first: first.clone(),
+                    second: header.clone(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                self.signed_slots
+                    .insert((header.node_public_key, slot), header.clone());
+            }
+        }
+    }
+
+    // Moves the main chain over to the branch ending at `new_tip`, which
+    // must already be in the store. Blocks are disconnected down to where
+    // both branches meet and the new ones connected from there.
+    fn switch_tip(&mut self, new_tip: Hash) {
+        let old_tip = self.tip_hash;
+        let parent = |hash: &Hash| self.blockchain.get(hash).unwrap().header.previous_hash;
+
+        let mut connecting = Vec::new();
+        let mut branch = new_tip;
+        let mut fork_point = old_tip;
+
+        while self.blockchain.height(&branch) > self.blockchain.height(&fork_point) {
+            connecting.push(branch);
+            branch = parent(&branch);
+        }
+
+        // A heavier branch can be the shorter one
+        while self.blockchain.height(&fork_point) > self.blockchain.height(&branch) {
+            fork_point = parent(&fork_point);
+        }
+
+        while branch != fork_point {
+            connecting.push(branch);
+            branch = parent(&branch);
+            fork_point = parent(&fork_point);
+        }
+
+        let depth = self.height() - self.blockchain.height(&fork_point).unwrap_or(0);
+
+        if depth > self.settings.network.max_reorg_depth {
+            println!(
+                "IGNORING {} BLOCKS DEEP FORK AT {}",
+                depth,
+                HashFmt(fork_point)
+            );
+
+            if self.ignored_fork.replace(fork_point) != Some(fork_point) {
+                self.alerts.raise(
+                    AlertKind::DeepFork,
+                    format!(
+                        "Refused to switch to a branch forking {} blocks deep at {}, past the finality depth",
+                        depth,
+                        HashFmt(fork_point)
+                    ),
+                );
+            }
+
+            return;
+        }
+
+        // The checkpointed block is on the old branch, under the fork
+        if let Some(checkpoint) = &self.checkpoint {
+            let fork_height = self.blockchain.height(&fork_point).unwrap_or(0);
+
+            if fork_height < checkpoint.height && self.on_main_chain(checkpoint) {
+                println!(
+                    "IGNORING FORK AT {} BELOW THE CHECKPOINT AT {}",
+                    HashFmt(fork_point),
+                    checkpoint.height
+                );
+
+                if self.ignored_fork.replace(fork_point) != Some(fork_point) {
+                    self.alerts.raise(
+                        AlertKind::DeepFork,
+                        format!(
+                            "Refused to switch to a branch forking at {}, below the checkpoint at height {}",
+                            HashFmt(fork_point),
+                            checkpoint.height
+                        ),
+                    );
+                }
+
+                return;
+            }
+        }
+
+        // Transactions of the blocks taken off, newest block first
+        let mut disconnected = Vec::new();
+
+        while self.tip_hash != fork_point {
+            disconnected.push(self.disconnect_tip());
+        }
+
+        let mut confirmed = HashSet::new();
+
+        for hash in connecting.into_iter().rev() {
+            confirmed.extend(self.connect_block(hash));
+        }
+
+        if old_tip != fork_point {
+            println!(
+                "REORGANIZED FROM {} TO {} AT {}",
+                HashFmt(old_tip),
+                HashFmt(new_tip),
+                HashFmt(fork_point)
+            );
+
+            self.audit
+                .record(Decision::reorg(&old_tip, &new_tip, &fork_point, depth));
+            self.events.publish(ChainEvent::Reorged {
+                old_tip,
+                new_tip,
+                depth,
+            });
+        }
+
+        self.reclaim_transactions(disconnected, &confirmed);
+    }
+
+    // Puts the transactions of disconnected blocks that the new branch did
+    // not confirm back in the mempool, so payments aren't lost with their
+    // block. They go back in the order they were mined, so those paid for by
+    // earlier ones can wait on them. Those that conflict with the new branch
+    // or the mempool are dropped, with why remembered for their status.
+    fn reclaim_transactions(
+        &mut self,
+        disconnected: Vec<Vec<Transaction>>,
+        confirmed: &HashSet<Hash>,
+    ) {
+        let mut reclaimed_hashes = HashSet::new();
+        let mut dropped = 0;
+
+        for transaction in disconnected.into_iter().rev().flatten() {
+            let hash = transaction.hash();
+
+            if confirmed.contains(&hash) || self.mempool.contains(&hash) {
+                continue;
+            }
+
+            let parents = match self.mempool_admission(&transaction) {
+                Ok(parents) => parents,
+                Err(reason) => {
+                    println!(
+                        "TRANSACTION {} OF A DISCONNECTED BLOCK DROPPED: {}",
+                        HashFmt(hash),
+                        reason
+                    );
+
+                    self.remember_rejection(hash, reason);
+                    dropped += 1;
+
+                    continue;
+                }
+            };
+
+            self.mempool.insert(transaction, timestamp(), None, parents);
+            reclaimed_hashes.insert(hash);
+
+            println!("TRANSACTION {} IS UNCONFIRMED AGAIN", HashFmt(hash));
+
+            self.events
+                .publish(ChainEvent::MempoolAdded { hash, origin: None });
+        }
+
+        // Those not worth their space are dropped last, so their
+        // dependencies had a chance to get back in
+        for hash in self.trim_mempool(None) {
+            if reclaimed_hashes.remove(&hash) {
+                dropped += 1;
+            }
+        }
+
+        let reclaimed = reclaimed_hashes.len();
+
+        if reclaimed + dropped > 0 {
+            println!(
+                "RECLAIMED {} TRANSACTIONS FROM DISCONNECTED BLOCKS, DROPPED {}",
+                reclaimed, dropped
+            );
+        }
+    }
+
+    // `hash` must be a child of the tip. Returns the hashes of the
+    // transactions it confirmed.
+    fn connect_block(&mut self, hash: Hash) -> Vec<Hash> {
+        let block = Arc::new(self.blockchain.get(&hash).unwrap().clone());
+        let height = self.height() + 1;
+        let states = self.deployments.states(&self.blockchain, &self.tip_hash);
+
+        for (info, before) in self
+            .deployments
+            .info(&self.blockchain, &hash)
+            .iter()
+            .zip(states)
+            .filter(|(info, before)| &info.state != before)
+        {
+            println!(
+                "DEPLOYMENT {} WENT FROM {:?} TO {:?} AFTER HEIGHT {}",
+                info.name, before, info.state, height
+            );
+        }
+
+        self.tip_hash = hash;
+        self.orphans.connected(&hash);
+        self.stats.apply(&block);
+        self.views
+            .connect(hash, &block, self.blockchain.reward(&hash));
+        self.blockchain.index_transactions(&hash);
+        self.mempool.remove_confirmed(&block);
+        self.events.publish(ChainEvent::Connected {
+            block: block.clone(),
+            height,
+        });
+
+        block.transactions.iter().map(Transaction::hash).collect()
+    }
+
+    // After a reorganization deeper than the views kept
+    fn rebuild_views(&mut self) {
+        let mut blocks = Vec::new();
+        let mut hash = self.tip_hash;
+
+        while let Some(block) = self.blockchain.get(&hash) {
+            blocks.push((hash, block, self.blockchain.reward(&hash)));
+            hash = block.header.previous_hash;
+        }
+
+        self.views.rebuild(blocks.into_iter().rev());
+    }
+
+    // Returns the transactions of the block taken off the tip
+    fn disconnect_tip(&mut self) -> Vec<Transaction> {
+        let hash = self.tip_hash;
+        let block = Arc::new(self.blockchain.get(&hash).unwrap().clone());
+        let height = self.height();
+
+        self.tip_hash = block.header.previous_hash;
+        self.stats.revert(&block);
+
+        if !self.views.disconnect() {
+            self.rebuild_views();
+        }
+
+        self.blockchain.unindex_transactions(&hash);
+
+        if Some(block.header.node_public_key) == self.public_key {
+            println!("OUR BLOCK {} WAS ORPHANED", HashFmt(hash));
+
+            self.orphans.disconnected(hash);
+        }
+
+        self.events.publish(ChainEvent::Disconnected {
+            block: block.clone(),
+            height,
+        });
+
+        block.transactions.clone()
+    }
+}
+
+// Light clients only ask for blocks involving the keys in their filter
+fn transaction_matches(transaction: &Transaction, filter: &BloomFilter) -> bool {
+    filter.contains(transaction.source())
+        || transaction
+            .destinations()
+            .any(|destination| filter.contains(destination))
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (before, after) = self.encoded_around_nonce();
+
+        out.extend_from_slice(&before);
+        self.nonce.encode(out);
+        out.extend_from_slice(&after);
+    }
+}
+
+impl Encode for Block {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.header.encode(out);
+        self.header.signature().encode(out);
+        self.transactions.encode(out);
+        self.slashings.encode(out);
+    }
+}
+
+impl BlockHeader {
+    pub fn new(
+        time: u64,
+        node_public_key: PublicKey,
+        previous_hash: Hash,
+        merkle_root: Hash,
+        nonce: [u8; 32],
+    ) -> Self {
+        BlockHeader {
+            time,
+            node_public_key,
+            previous_hash,
+            merkle_root,
+            nonce,
+            signature: None,
+            version: 0,
+        }
+    }
+
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn hash(&self) -> Hash {
+        hash_header(self)
+    }
+
+    pub fn previous_hash(&self) -> &Hash {
+        &self.previous_hash
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn merkle_root(&self) -> &Hash {
+        &self.merkle_root
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn node_public_key(&self) -> &PublicKey {
+        &self.node_public_key
+    }
+
+    pub fn signature(&self) -> Option<Signature> {
+        self.signature.map(|signature| signature.0)
+    }
+
+    // Signed by the producer, `node_public_key`
+    pub fn sign(&mut self, secret_key: &SecretKey) {
+        self.signature = Some(BlockSignature(fcoin_core::keys::sign(
+            secret_key,
+            &self.hash(),
+        )));
+    }
+
+    pub fn has_valid_signature(&self) -> bool {
+        match &self.signature {
+            Some(signature) => {
+                fcoin_core::keys::verify(&self.node_public_key, &self.hash(), &signature.0)
+            }
+            None => false,
+        }
+    }
+
+    pub fn nonce(&self) -> &[u8; 32] {
+        &self.nonce
+    }
+
+    pub fn set_nonce(&mut self, nonce: [u8; 32]) {
+        self.nonce = nonce;
+    }
+
+    // Its encoding before and after the nonce, for miners that only try
+    // nonces
+    pub fn encoded_around_nonce(&self) -> (Vec<u8>, Vec<u8>) {
+        let mut before = Vec::new();
+        let mut after = Vec::new();
+
+        self.time.encode(&mut before);
+        self.node_public_key.encode(&mut before);
+        self.previous_hash.encode(&mut before);
+        self.merkle_root.encode(&mut before);
+
+        // Left out when signaling for nothing, as before there were
+        // versions
+        if self.version != 0 {
+            self.version.encode(&mut after);
+        }
+
+        (before, after)
+    }
+
+    pub fn next_nonce(&mut self) {
+        self.nonce =
+            to_32bytes(&(BigUint::from_bytes_le(&self.nonce) + BigUint::from(1u32)).to_bytes_le());
+    }
+}
+
+impl Block {
+    // With the Merkle root of `transactions` in its header
+    pub fn new(header: BlockHeader, transactions: Vec<Transaction>) -> Self {
+        let mut block = Block {
+            header,
+            transactions,
+            slashings: Vec::new(),
+        };
+
+        block.header.merkle_root = merkle::merkle_root(&block.merkle_leaves());
+        block
+    }
+
+    pub fn hash(&self) -> Hash {
+        hash_block(self)
+    }
+
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut BlockHeader {
+        &mut self.header
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn slashings(&self) -> &[DoubleSign] {
+        &self.slashings
+    }
+
+    fn slashes(&self, id: &PublicKey) -> bool {
+        self.slashings
+            .iter()
+            .any(|evidence| evidence.offender() == id)
+    }
+
+    // Encoded size in bytes
+    pub fn size(&self) -> u64 {
+        self.encoded().len() as u64
+    }
+
+    // What the Merkle root commits to, the transactions followed by the
+    // slashings
+    fn merkle_leaves(&self) -> Vec<Hash> {
+        self.transactions
+            .iter()
+            .map(Transaction::hash)
+            .chain(self.slashings.iter().map(DoubleSign::hash))
+            .collect()
+    }
+
+    pub fn has_valid_merkle_root(&self) -> bool {
+        merkle::merkle_root(&self.merkle_leaves()) == self.header.merkle_root
+    }
+
+    // Every public key whose balance this block changes
+    pub fn public_keys(&self) -> Vec<&[u8]> {
+        let mut keys: Vec<&[u8]> = vec![&self.header.node_public_key];
+
+        for transaction in &self.transactions {
+            keys.push(transaction.source());
+            keys.extend(
+                transaction
+                    .destinations()
+                    .map(|destination| &destination[..]),
+            );
+        }
+
+        for evidence in &self.slashings {
+            keys.push(evidence.offender());
+        }
+
+        keys
+    }
+
+    pub fn compact_filter(&self) -> CompactFilter {
+        CompactFilter::build(&self.hash(), &self.public_keys())
+    }
+
+    // Proves the transaction at `index` is part of this block
+    pub fn merkle_proof(&self, index: usize) -> Option<MerkleProof> {
+        merkle::merkle_proof(&self.merkle_leaves(), index)
+    }
+
+    fn filtered(&self, filter: &BloomFilter) -> MerkleBlock {
+        let hashes = self.merkle_leaves();
+
+        MerkleBlock {
+            header: self.header.clone(),
+            transactions: self
+                .transactions
+                .iter()
+                .enumerate()
+                .filter(|(_, transaction)| transaction_matches(transaction, filter))
+                .filter_map(|(index, transaction)| {
+                    merkle::merkle_proof(&hashes, index).map(|proof| (transaction.clone(), proof))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl MerkleBlock {
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.iter().map(|(transaction, _)| transaction)
+    }
+
+    // Checks every transaction against the Merkle root in the header
+    pub fn verify(&self) -> bool {
+        self.transactions
+            .iter()
+            .all(|(transaction, proof)| proof.verify(&transaction.hash(), &self.header.merkle_root))
+    }
+}
+
+// Where a transaction is on its way into the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionStatus {
+    Unknown,
+    InMempool,
+    Confirmed { height: u64, confirmations: u64 },
+    Rejected(RejectReason),
+}
+
+impl std::fmt::Display for TransactionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransactionStatus::Unknown => write!(f, "unknown"),
+            TransactionStatus::InMempool => write!(f, "in the mempool"),
+            TransactionStatus::Confirmed {
+                height,
+                confirmations,
+            } => write!(
+                f,
+                "confirmed at height {} ({} confirmations)",
+                height, confirmations
+            ),
+            TransactionStatus::Rejected(reason) => write!(f, "rejected: {}", reason),
+        }
+    }
+}
+
+// Why a block or transaction was not accepted, sent back to whoever
+// submitted it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    InvalidProofOfWork,
+    InvalidMerkleRoot,
+    UnknownParent,
+    InvalidSignature,
+    Expired,
+    SelfTransfer,
+    // Moves no coins at all
+    ZeroAmount,
+    InsufficientFunds,
+    Duplicate,
+    // The node is still catching up with the network
+    NotSynced,
+    // Timestamp too far in the future
+    TimeTooNew,
+    // Over `MAX_BLOCK_SIZE` or `MAX_TRANSACTION_SIZE`, or carrying more
+    // than `MAX_DATA_SIZE` bytes of data
+    TooLarge,
+    // Not produced according to the consensus rules of the network
+    InvalidSeal,
+    // Slashing evidence that doesn't prove anything, or was already used
+    InvalidEvidence,
+    // Pays less than the dust threshold of the network, in any output
+    Dust,
+    // Its outputs and fee add up to more coins than can exist
+    AmountOverflow,
+    // Spends from a contract in a way its terms don't allow yet
+    Locked,
+    // Registers a name that isn't one, see `fcoin_core::name::is_valid`
+    InvalidName,
+    // Registers a name someone else holds
+    NameTaken,
+    // Registers a name paying less than the name fee of the network
+    NameFeeTooLow,
+    // Carries data paying less than the data fee of the network
+    DataFeeTooLow,
+    // Issues or moves tokens in a way that never makes sense, see
+    // `AssetAction::is_valid`
+    InvalidAsset,
+    // Issues an asset that was already issued
+    AssetExists,
+    // Moves more tokens than the source holds
+    InsufficientTokens,
+    // Spends from the burn address
+    Unspendable,
+    // Evicted, or turned down, for paying a lower fee rate than the rest
+    // of a full mempool
+    MempoolFull,
+    // Timestamp not after the median time past of its parent
+    TimeTooOld,
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let reason = match self {
+            RejectReason::InvalidProofOfWork => "does not meet the proof of work target",
+            RejectReason::InvalidMerkleRoot => "merkle root does not match the transactions",
+            RejectReason::UnknownParent => "previous block is unknown",
+            RejectReason::InvalidSignature => "invalid signature",
+            RejectReason::Expired => "expired",
+            RejectReason::SelfTransfer => "source and destination are the same",
+            RejectReason::ZeroAmount => "amount is zero",
+            RejectReason::InsufficientFunds => "not enough funds",
+            RejectReason::Duplicate => "already known",
+            RejectReason::NotSynced => "node is still syncing",
+            RejectReason::TimeTooNew => "timestamp too far in the future",
+            RejectReason::TooLarge => "too large",
+            RejectReason::InvalidSeal => "not produced according to the consensus rules",
+            RejectReason::InvalidEvidence => "invalid slashing evidence",
+            RejectReason::Dust => "amount is below the dust threshold",
+            RejectReason::AmountOverflow => "amounts add up to more coins than can exist",
+            RejectReason::Locked => "the contract can't be spent this way yet",
+            RejectReason::InvalidName => "not a valid name",
+            RejectReason::NameTaken => "the name is registered to someone else",
+            RejectReason::NameFeeTooLow => "pays less than the name fee",
+            RejectReason::DataFeeTooLow => "pays less than the fee for its data",
+            RejectReason::InvalidAsset => "not a valid token issuance or transfer",
+            RejectReason::AssetExists => "the asset was already issued",
+            RejectReason::InsufficientTokens => "not enough tokens",
+            RejectReason::Unspendable => "burned coins can't be spent",
+            RejectReason::MempoolFull => "mempool is full",
+            RejectReason::TimeTooOld => "timestamp not after the median time of the blocks before",
+        };
+
+        write!(f, "{}", reason)
+    }
+}
+
+impl std::fmt::Display for BlockchainFmt<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut tip = self.1;
+
+        while let Some(block) = self.0.get(&tip) {
+            for transaction in &block.transactions {
+                writeln!(f, "{}", transaction)?;
+            }
+
+            tip = block.header.previous_hash;
+        }
+
+        Ok(())
+    }
+}
+
+fn template_to_proto_block(transactions: Vec<Transaction>) -> ProtoBlock {
+    ProtoBlock {
+        nonce: miner::random_nonce(),
+        transactions,
+    }
+}
+
+fn to_32bytes(byte_vector: &[u8]) -> [u8; 32] {
+    let n = byte_vector.len();
+
+    let mut bytes: [u8; 32] = [0; 32];
+    let slice = &mut bytes[32 - n..32];
+
+    slice.copy_from_slice(byte_vector);
+
+    bytes
+}
+
+fn hash_header(header: &BlockHeader) -> Hash {
+    Sha256::digest(&header.encoded()).into()
+}
+
+fn hash_block(block: &Block) -> Hash {
+    hash_header(&block.header)
+}
+
+// Whether a hash is below the easiest target any block can have. Light
+// clients don't follow target adjustments, so this is all they check.
+pub fn meets_target(hash: &Hash) -> bool {
+    BigUint::from_bytes_le(hash) < max_target()
+}
+
+// Where proof of work starts, targets never get any easier
+pub fn max_target() -> BigUint {
+    BigUint::from(2u32).pow(255) - BigUint::from(1u32)
+}
+
+fn no_wallet() -> String {
+    "The node runs without a wallet".to_string()
+}
+
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// Balance of `id` as of `tip_hash`
+pub fn balance_at(
+    blockchain: &ChainStore,
+    tip_hash: &Hash,
+    id: &PublicKey,
+) -> Result<i128, String> {
+    amount(0, blockchain, tip_hash, id)
+}
+
+fn amount(
+    mut value: i128,
+    blockchain: &ChainStore,
+    tip_hash: &Hash,
+    id: &PublicKey,
+) -> Result<i128, String> {
+    if tip_hash == &[0; 32] {
+        Ok(value)
+    } else {
+        match blockchain.get(tip_hash) {
+            // Nothing was left after this block, whatever came before
+            Some(block) if block.slashes(id) => Ok(value),
+            Some(block) => {
+                // Self-transfers and zero amounts are rejected by
+                // `valid_transaction`, but a block mined before that must
+                // still be readable: a self-transfer just costs its fee.
+                for transaction in &block.transactions {
+                    if id == transaction.source() {
+                        value -= (transaction.amount() + transaction.fee()) as i128;
+                    }
+
+                    value += transaction.amount_to(id) as i128;
+                }
+
+                if id == &block.header.node_public_key {
+                    value += blockchain.reward(tip_hash) as i128
+                        + block
+                            .transactions
+                            .iter()
+                            .map(|transaction| transaction.fee() as i128)
+                            .sum::<i128>();
+                }
+
+                amount(value, blockchain, &block.header.previous_hash, id)
+            }
+            None => Err("Previous hash not found in the blockchain!".to_string()),
+        }
+    }
+}
+
+// Whether the chain ending at `tip_hash` already has the transaction. The
+// same transaction can only be mined once, otherwise its payment would be
+// counted again each time.
+fn is_confirmed(hash: &Hash, blockchain: &ChainStore, tip_hash: &Hash) -> bool {
+    confirming_block(hash, blockchain, tip_hash).is_some()
+}
+
+// Hash of the block of the chain ending at `tip_hash` that has the
+// transaction. Only the blocks of a side branch are gone through, down to
+// where it meets the main chain, whose transactions are indexed.
+fn confirming_block(hash: &Hash, blockchain: &ChainStore, tip_hash: &Hash) -> Option<Hash> {
+    let mut current = *tip_hash;
+
+    while !blockchain.is_on_main_chain(&current) {
+        let block = blockchain.get(&current)?;
+
+        if block
+            .transactions
+            .iter()
+            .any(|transaction| &transaction.hash() == hash)
+        {
+            return Some(current);
+        }
+
+        current = block.header.previous_hash;
+    }
+
+    let block_hash = blockchain.transaction_location(hash)?.block_hash;
+
+    // Mined below where the branch meets the main chain
+    Some(block_hash).filter(|block_hash| {
+        blockchain.is_on_main_chain(block_hash)
+            && blockchain.height(block_hash) <= blockchain.height(&current)
+    })
+}
+
+// Whether the chain ending at `tip_hash` already burned the coins of `id`
+fn is_slashed(id: &PublicKey, blockchain: &ChainStore, tip_hash: &Hash) -> bool {
+    let mut current = *tip_hash;
+
+    while let Some(block) = blockchain.get(&current) {
+        if block.slashes(id) {
+            return true;
+        }
+
+        current = block.header.previous_hash;
+    }
+
+    false
+}
+
+// Checks that need nothing but the block itself. Being the bulk of the
+// work (hashing and signatures), they run in parallel and without the node
+// lock, leaving `valid_block` to check it against the chain.
+fn check_block(
+    block: &Block,
+    consensus: &dyn Consensus,
+    verified: &Lru<Hash, ()>,
+) -> Result<(), RejectReason> {
+    let witness = witness_hash(block);
+
+    if verified.get(&witness).is_some() {
+        return Ok(());
+    }
+
+    if block.size() > MAX_BLOCK_SIZE {
+        println!("BLOCK IS TOO LARGE");
+
+        return Err(RejectReason::TooLarge);
+    }
+
+    consensus.check(block)?;
+
+    if !block.has_valid_merkle_root() {
+        println!("BLOCK MERKLE ROOT DOES NOT MATCH ITS TRANSACTIONS");
+
+        return Err(RejectReason::InvalidMerkleRoot);
+    }
+
+    let mut hashes = HashSet::new();
+
+    for transaction in &block.transactions {
+        if !hashes.insert(transaction.hash()) {
+            println!("TRANSACTION {} IS TWICE IN THE BLOCK", transaction);
+
+            return Err(RejectReason::Duplicate);
+        }
+
+        check_transaction(transaction, verified)?;
+    }
+
+    let mut offenders = HashSet::new();
+
+    for evidence in &block.slashings {
+        if !consensus.valid_evidence(evidence) || !offenders.insert(*evidence.offender()) {
+            println!(
+                "INVALID SLASHING EVIDENCE AGAINST {}",
+                PublicKeyFmt(*evidence.offender())
+            );
+
+            return Err(RejectReason::InvalidEvidence);
+        }
+    }
+
+    verified.insert(witness, ());
+
+    Ok(())
+}
+
+// Checks a block that passed `check_block` against the chain. `now` is the
+// adjusted network time.
+fn valid_block(
+    block: &Block,
+    blockchain: &ChainStore,
+    consensus: &dyn Consensus,
+    now: u64,
+    network: &NetworkProfile,
+) -> Result<(), RejectReason> {
+    if block.header.time > now + MAX_FUTURE_BLOCK_TIME {
+        println!("BLOCK TIME IS TOO FAR IN THE FUTURE");
+
+        return Err(RejectReason::TimeTooNew);
+    }
+
+    // Times only have to move forward across several blocks, which keeps
+    // them from being set back to skew targets
+    if block.header.time <= blockchain.median_time_past(&block.header.previous_hash) {
+        println!("BLOCK TIME IS NOT AFTER THE MEDIAN TIME PAST");
+
+        return Err(RejectReason::TimeTooOld);
+    }
+
+    consensus.verify(block, blockchain, now)?;
+
+    let height = match blockchain.height(&block.header.previous_hash) {
+        Some(height) => height + 1,
+        None => {
+            println!("BLOCK PREVIOUS HASH NOT FOUND IN THE BLOCKCHAIN");
+
+            return Err(RejectReason::UnknownParent);
+        }
+    };
+
+    // Balance changes made by the transactions earlier in this same block
+    let mut changes: HashMap<PublicKey, i128> = HashMap::new();
+    let mut registered = HashMap::new();
+    let mut tokens = TokenChanges::default();
+
+    for transaction in &block.transactions {
+        valid_on_chain(
+            transaction,
+            blockchain,
+            &block.header.previous_hash,
+            height,
+            &changes,
+            network,
+        )?;
+
+        claim_name(&mut registered, transaction)?;
+        tokens.check_on_chain(transaction, blockchain, &block.header.previous_hash)?;
+        apply_changes(&mut changes, transaction);
+        tokens.apply(transaction);
+    }
+
+    for evidence in &block.slashings {
+        if is_slashed(evidence.offender(), blockchain, &block.header.previous_hash) {
+            println!(
+                "SLASHING EVIDENCE AGAINST {} WAS ALREADY USED",
+                PublicKeyFmt(*evidence.offender())
+            );
+
+            return Err(RejectReason::InvalidEvidence);
+        }
+    }
+
+    Ok(())
+}
+
+// Adds the name `transaction` registers to those the transactions before it
+// in a block registered, for their sources. Only one source can register a
+// name in a block.
+fn claim_name(
+    registered: &mut HashMap<String, PublicKey>,
+    transaction: &Transaction,
+) -> Result<(), RejectReason> {
+    if let Some(name) = transaction.name() {
+        match registered.get(name) {
+            Some(owner) if owner != transaction.source() => {
+                println!("NAME OF {} IS TAKEN IN THE SAME BLOCK", transaction);
+
+                return Err(RejectReason::NameTaken);
+            }
+            _ => {
+                registered.insert(name.to_string(), *transaction.source());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Who holds `name` for a block at `height` on top of `tip_hash`: whoever
+// registered it last, unless that was more than `lifetime` blocks before
+fn name_owner(
+    name: &str,
+    blockchain: &ChainStore,
+    tip_hash: &Hash,
+    height: u64,
+    lifetime: u64,
+) -> Option<PublicKey> {
+    let mut current = *tip_hash;
+
+    while let Some(block) = blockchain.get(&current) {
+        if blockchain.height(&current)? + lifetime < height {
+            return None;
+        }
+
+        let registration = block
+            .transactions
+            .iter()
+            .rev()
+            .find(|transaction| transaction.name() == Some(name));
+
+        if let Some(transaction) = registration {
+            return Some(*transaction.source());
+        }
+
+        current = block.header.previous_hash;
+    }
+
+    None
+}
+
+// What the transactions before one in a block did with tokens
+#[derive(Default)]
+struct TokenChanges {
+    // Assets they issued
+    issued: HashSet<String>,
+    // Balance changes, by asset and holder
+    balances: HashMap<(String, PublicKey), i128>,
+}
+
+impl TokenChanges {
+    // Checks what `transaction` does with tokens, `issued` telling whether
+    // an asset was issued before the block and `balance` how many tokens of
+    // it a holder had then
+    fn check(
+        &self,
+        transaction: &Transaction,
+        issued: impl FnOnce(&str) -> bool,
+        balance: impl FnOnce(&str, &PublicKey) -> i128,
+    ) -> Result<(), RejectReason> {
+        let action = match transaction.asset() {
+            Some(action) => action,
+            None => return Ok(()),
+        };
+        let asset = action.asset();
+
+        match action {
+            AssetAction::Issue { .. } => {
+                if self.issued.contains(asset) || issued(asset) {
+                    println!("ASSET OF {} WAS ALREADY ISSUED", transaction);
+
+                    return Err(RejectReason::AssetExists);
+                }
+            }
+            AssetAction::Transfer { .. } => {
+                let source = transaction.source();
+                let held = balance(asset, source)
+                    + self
+                        .balances
+                        .get(&(asset.to_string(), *source))
+                        .unwrap_or(&0);
+
+                if held < action.spent().unwrap_or(u64::MAX) as i128 {
+                    println!("NOT ENOUGH TOKENS FOR {}", transaction);
+
+                    return Err(RejectReason::InsufficientTokens);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Like `check`, with the chain ending at `previous_hash`
+    fn check_on_chain(
+        &self,
+        transaction: &Transaction,
+        blockchain: &ChainStore,
+        previous_hash: &Hash,
+    ) -> Result<(), RejectReason> {
+        self.check(
+            transaction,
+            |asset| is_issued(asset, blockchain, previous_hash),
+            |asset, id| token_balance(asset, id, blockchain, previous_hash),
+        )
+    }
+
+    fn apply(&mut self, transaction: &Transaction) {
+        if let Some(action) = transaction.asset() {
+            let asset = action.asset().to_string();
+
+            if let AssetAction::Issue { .. } = action {
+                self.issued.insert(asset.clone());
+            }
+
+            *self
+                .balances
+                .entry((asset.clone(), *transaction.source()))
+                .or_insert(0) -= action.spent().unwrap_or(0) as i128;
+
+            for output in action.credits(transaction.source()) {
+                *self
+                    .balances
+                    .entry((asset.clone(), output.destination))
+                    .or_insert(0) += output.amount as i128;
+            }
+        }
+    }
+}
+
+// Whether the chain ending at `tip_hash` issued `asset`
+fn is_issued(asset: &str, blockchain: &ChainStore, tip_hash: &Hash) -> bool {
+    let mut current = *tip_hash;
+
+    while let Some(block) = blockchain.get(&current) {
+        if block.transactions.iter().any(|transaction| {
+            matches!(transaction.asset(), Some(action @ AssetAction::Issue { .. }) if action.asset() == asset)
+        }) {
+            return true;
+        }
+
+        current = block.header.previous_hash;
+    }
+
+    false
+}
+
+// Tokens of `asset` held by `id` as of `tip_hash`
+fn token_balance(asset: &str, id: &PublicKey, blockchain: &ChainStore, tip_hash: &Hash) -> i128 {
+    let mut balance = 0;
+    let mut current = *tip_hash;
+
+    while let Some(block) = blockchain.get(&current) {
+        for transaction in &block.transactions {
+            let action = match transaction.asset() {
+                Some(action) if action.asset() == asset => action,
+                _ => continue,
+            };
+
+            if transaction.source() == id {
+                balance -= action.spent().unwrap_or(0) as i128;
+            }
+
+            for output in action.credits(transaction.source()) {
+                if &output.destination == id {
+                    balance += output.amount as i128;
+                }
+            }
+        }
+
+        current = block.header.previous_hash;
+    }
+
+    balance
+}
+
+// Adds what `transaction` moves to the balance `changes` of a block
+fn apply_changes(changes: &mut HashMap<PublicKey, i128>, transaction: &Transaction) {
+    *changes.entry(*transaction.source()).or_insert(0) -=
+        (transaction.amount() + transaction.fee()) as i128;
+
+    for output in transaction.outputs() {
+        *changes.entry(output.destination).or_insert(0) += output.amount as i128;
+    }
+}
+
+// Checks a transaction can be mined at `height` on top of `previous_hash`,
+// `changes` being the balance changes of the transactions before it
+fn valid_transaction(
+    transaction: &Transaction,
+    blockchain: &ChainStore,
+    previous_hash: &Hash,
+    height: u64,
+    changes: &HashMap<PublicKey, i128>,
+    network: &NetworkProfile,
+    verified: &Lru<Hash, ()>,
+) -> Result<(), RejectReason> {
+    check_transaction(transaction, verified)?;
+
+    valid_on_chain(
+        transaction,
+        blockchain,
+        previous_hash,
+        height,
+        changes,
+        network,
+    )
+}
+
+// The part of `valid_transaction` that doesn't depend on the chain
+fn check_transaction(
+    transaction: &Transaction,
+    verified: &Lru<Hash, ()>,
+) -> Result<(), RejectReason> {
+    let witness = witness_hash(transaction);
+
+    if verified.get(&witness).is_some() {
+        return Ok(());
+    }
+
+    if transaction.size() > MAX_TRANSACTION_SIZE {
+        println!("TRANSACTION {} IS TOO LARGE", transaction);
+
+        return Err(RejectReason::TooLarge);
+    }
+
+    if transaction
+        .data()
+        .is_some_and(|data| data.len() > fcoin_core::transaction::MAX_DATA_SIZE)
+    {
+        println!("TRANSACTION {} CARRIES TOO MUCH DATA", transaction);
+
+        return Err(RejectReason::TooLarge);
+    }
+
+    if transaction.source() == &fcoin_core::transaction::burn_address() {
+        println!("TRANSACTION {} SPENDS BURNED COINS", transaction);
+
+        return Err(RejectReason::Unspendable);
+    }
+
+    if !transaction.has_valid_signature() {
+        println!("INVALID SIGNATURE ON {}", transaction);
+
+        return Err(RejectReason::InvalidSignature);
+    }
+
+    if transaction
+        .name()
+        .is_some_and(|name| !fcoin_core::name::is_valid(name))
+    {
+        println!("TRANSACTION {} REGISTERS AN INVALID NAME", transaction);
+
+        return Err(RejectReason::InvalidName);
+    }
+
+    if transaction
+        .asset()
+        .is_some_and(|action| !action.is_valid(transaction.source()))
+    {
+        println!(
+            "TRANSACTION {} DOES SOMETHING INVALID WITH TOKENS",
+            transaction
+        );
+
+        return Err(RejectReason::InvalidAsset);
+    }
+
+    // Registering a name, moving tokens or anchoring data pays nobody
+    // coins, the only output paying nothing to the source
+    if transaction.moves_no_coins() {
+        verified.insert(witness, ());
+
+        return Ok(());
+    }
+
+    if transaction
+        .destinations()
+        .any(|destination| destination == transaction.source())
+    {
+        println!("SOURCE AND DESTINATION ARE THE SAME ON {}", transaction);
+
+        return Err(RejectReason::SelfTransfer);
+    }
+
+    if transaction
+        .outputs()
+        .iter()
+        .any(|output| output.amount == 0)
+    {
+        println!("TRANSACTION {} MOVES NO COINS", transaction);
+
+        return Err(RejectReason::ZeroAmount);
+    }
+
+    // So amounts and fees can be added up anywhere without overflowing
+    let spent = transaction
+        .total_amount()
+        .and_then(|amount| amount.checked_add(transaction.fee()));
+
+    if spent.is_none() {
+        println!(
+            "TRANSACTION {} MOVES MORE COINS THAN CAN EXIST",
+            transaction
+        );
+
+        return Err(RejectReason::AmountOverflow);
+    }
+
+    verified.insert(witness, ());
+
+    Ok(())
+}
+
+// Hash of everything in `item`, signatures included. Ids leave signatures
+// out, so they can't tell whether what was checked is what we have now.
+fn witness_hash(item: &impl Encode) -> Hash {
+    Sha256::digest(&item.encoded()).into()
+}
+
+// The part of `valid_transaction` that depends on the chain ending at
+// `previous_hash`
+fn valid_on_chain(
+    transaction: &Transaction,
+    blockchain: &ChainStore,
+    previous_hash: &Hash,
+    height: u64,
+    changes: &HashMap<PublicKey, i128>,
+    network: &NetworkProfile,
+) -> Result<(), RejectReason> {
+    if transaction.is_expired_at(height) {
+        println!("EXPIRED TRANSACTION {} AT HEIGHT {}", transaction, height);
+
+        return Err(RejectReason::Expired);
+    }
+
+    if transaction.is_locked_at(height) {
+        println!("LOCKED TRANSACTION {} AT HEIGHT {}", transaction, height);
+
+        return Err(RejectReason::Locked);
+    }
+
+    if transaction.pays_dust(network.dust_threshold) {
+        println!(
+            "TRANSACTION {} PAYS LESS THAN THE DUST THRESHOLD",
+            transaction
+        );
+
+        return Err(RejectReason::Dust);
+    }
+
+    if is_confirmed(&transaction.hash(), blockchain, previous_hash) {
+        println!("TRANSACTION {} WAS ALREADY MINED", transaction);
+
+        return Err(RejectReason::Duplicate);
+    }
+
+    if let Some(data) = transaction.data() {
+        if network.is_active(Fork::DataFee, height)
+            && transaction.fee() < network.data_fee(data.len())
+        {
+            println!(
+                "TRANSACTION {} PAYS LESS THAN THE FEE FOR ITS DATA",
+                transaction
+            );
+
+            return Err(RejectReason::DataFeeTooLow);
+        }
+    }
+
+    if let Some(name) = transaction.name() {
+        if transaction.fee() < network.name_fee {
+            println!("TRANSACTION {} PAYS LESS THAN THE NAME FEE", transaction);
+
+            return Err(RejectReason::NameFeeTooLow);
+        }
+
+        let owner = name_owner(
+            name,
+            blockchain,
+            previous_hash,
+            height,
+            network.name_lifetime,
+        );
+
+        if owner.is_some_and(|owner| &owner != transaction.source()) {
+            println!("NAME OF {} IS TAKEN", transaction);
+
+            return Err(RejectReason::NameTaken);
+        }
+    }
+
+    match amount(0, blockchain, previous_hash, transaction.source()) {
+        Ok(value) => {
+            let value = value + changes.get(transaction.source()).unwrap_or(&0);
+
+            println!(
+                "FUNDS CHECK: {} has ${}. Trying to transfer ${} with a ${} fee",
+                PublicKeyFmt(*transaction.source()),
+                value,
+                transaction.amount(),
+                transaction.fee()
+            );
+
+            if value >= (transaction.amount() + transaction.fee()) as i128 {
+                Ok(())
+            } else {
+                Err(RejectReason::InsufficientFunds)
+            }
+        }
+        Err(err) => {
+            println!("{}", err);
+
+            Err(RejectReason::UnknownParent)
+        }
+    }
+}
+
+pub async fn block_received(node: Arc<Mutex<Node>>, block: Block) -> Result<(), RejectReason> {
+    let (consensus, verified) = node.lock().await.checkers();
+    let (block, checked) = checked_block(consensus, verified, block).await;
+
+    block_checked(node, block, checked).await
+}
+
+// A block a trusted peer pushed, most likely one of our own miners, is
+// checked on a thread of its own instead of waiting for the blocking pool,
+// which may be busy checking a sync batch
+pub async fn block_pushed(node: Arc<Mutex<Node>>, block: Block) -> Result<(), RejectReason> {
+    let (consensus, verified) = node.lock().await.checkers();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+
+    std::thread::spawn(move || {
+        let checked = check_block(&block, &*consensus, &verified);
+        let _ = tx.send((block, checked));
+    });
+
+    let (block, checked) = rx.await.expect("block check panicked");
+
+    block_checked(node, block, checked).await
+}
+
+async fn block_checked(
+    node: Arc<Mutex<Node>>,
+    block: Block,
+    checked: Result<(), RejectReason>,
+) -> Result<(), RejectReason> {
+    let hash = hash_block(&block);
+    let result = match checked {
+        Ok(()) => connect_block(node.clone(), block).await,
+        Err(reason) => Err(reason),
+    };
+
+    if let Err(reason) = result {
+        node.lock()
+            .await
+            .audit
+            .record(Decision::block_rejected(&hash, reason));
+    }
+
+    result
+}
+
+// A batch of blocks (from syncing) is checked all at once, then connected
+// one by one, in order, as each needs its parent. Returns the result for
+// each block.
+pub async fn blocks_received(
+    node: Arc<Mutex<Node>>,
+    blocks: Vec<Block>,
+) -> Vec<(Hash, Result<(), RejectReason>)> {
+    let (consensus, verified) = node.lock().await.checkers();
+    let checked = futures::future::join_all(
+        blocks
+            .into_iter()
+            .map(|block| checked_block(consensus.clone(), verified.clone(), block)),
+    )
+    .await;
+
+    let mut results = Vec::new();
+
+    for (block, checked) in checked {
+        let hash = hash_block(&block);
+
+        let result = match checked {
+            Ok(()) => connect_block(node.clone(), block).await,
+            Err(reason) => Err(reason),
+        };
+
+        if let Err(reason) = result {
+            node.lock()
+                .await
+                .audit
+                .record(Decision::block_rejected(&hash, reason));
+        }
+
+        results.push((hash, result));
+    }
+
+    results
+}
+
+// Runs `check_block` on the blocking thread pool, so blocks from several
+// peers, or from the same batch, are checked in parallel
+async fn checked_block(
+    consensus: Arc<dyn Consensus>,
+    verified: Arc<Lru<Hash, ()>>,
+    block: Block,
+) -> (Block, Result<(), RejectReason>) {
+    tokio::task::spawn_blocking(move || {
+        let checked = check_block(&block, &*consensus, &verified);
+
+        (block, checked)
+    })
+    .await
+    .expect("block check panicked")
+}
+
+// Adds a block that passed `check_block` to the chain, which only takes the
+// node lock for the checks against the chain
+async fn connect_block(node: Arc<Mutex<Node>>, block: Block) -> Result<(), RejectReason> {
+    let hash = hash_block(&block);
+    let mut node = node.lock().await;
+
+    println!("BLOCKCHAIN TIP IS {}", HashFmt(node.tip_hash));
+    println!("BLOCK HASH IS {}", HashFmt(hash));
+    println!(
+        "BLOCK PREVIOUS HASH IS {}",
+        HashFmt(block.header.previous_hash)
+    );
+
+    if node.blockchain.contains(&hash) {
+        println!("BLOCKCHAIN ALREADY HAS BLOCK. STOPPING.");
+    } else {
+        valid_block(
+            &block,
+            &node.blockchain,
+            &*node.consensus,
+            node.adjusted_time(),
+            &node.settings.network,
+        )?;
+
+        println!("BLOCK IS VALID");
+
+        node.watch_double_signs(block.header());
+
+        let previous_hash = block.header.previous_hash;
+
+        // Off the main chain until connected
+        if Some(block.header.node_public_key) == node.public_key {
+            node.orphans.mined(hash);
+        }
+
+        let work = node.consensus.work(&node.blockchain, block.header());
+
+        node.blockchain.insert(hash, block, work);
+
+        println!("** BLOCK ADDED TO BLOCKCHAIN **");
+
+        let height = node.blockchain.height(&hash).unwrap_or(0);
+
+        // The branch with the most work wins, ties go to the branch we saw
+        // first
+        if node.blockchain.chain_work(&hash) > node.blockchain.chain_work(&node.tip_hash) {
+            node.switch_tip(hash);
+            node.sign_checkpoint().await;
+        }
+
+        let tip = node.tip_hash == hash;
+
+        node.audit
+            .record(Decision::block_accepted(&hash, &previous_hash, height, tip));
+
+        println!("{}", BlockchainFmt(&node.blockchain, node.tip_hash));
+
+        node.evict_expired_transactions();
+    }
+
+    Ok(())
+}
+
+async fn block_created(node: Arc<Mutex<Node>>, block: Block) {
+    // Replaying needs them as much as the blocks of our peers
+    if let Some(recorder) = node.lock().await.recorder() {
+        recorder.record(
+            None,
+            &Payload::encode(&Frame::Block(block.clone()), framing::Codec::Bincode),
+        );
+    }
+
+    match block_received(node.clone(), block.clone()).await {
+        Ok(()) => relay_block(node, block, None),
+        Err(reason) => println!("OWN BLOCK REJECTED: {}", reason),
+    }
+}
+
+// Passes a block we just accepted on to the nodes relaying blocks with us,
+// except the one it came from, when it is still our tip. The fastest get it
+// first, so they can pass it on while the slower ones still wait for it on
+// our uplink. Runs in a task of its own.
+pub fn relay_block(node: Arc<Mutex<Node>>, block: Block, origin: Option<SocketAddr>) {
+    tokio::spawn(async move {
+        let hash = block.hash();
+        let order = {
+            let node = node.lock().await;
+
+            if node.tip_hash != hash {
+                return;
+            }
+
+            node.block_relay_order(origin)
+        };
+        let frame = Frame::Block(block);
+        let mut payloads: HashMap<_, Payload> = HashMap::new();
+
+        for (round, addrs) in order.chunks(RELAY_FANOUT).enumerate() {
+            if round > 0 {
+                tokio::time::sleep(RELAY_STAGGER).await;
+            }
+
+            let mut node = node.lock().await;
+
+            for addr in addrs {
+                if let Some(peer) = node.peers.get_mut(addr) {
+                    let payload = payloads
+                        .entry(peer.codec())
+                        .or_insert_with(|| Payload::encode(&frame, peer.codec()));
+
+                    peer.write_payload(&frame, payload).await;
+                }
+            }
+        }
+    });
+}
+
+// `origin` is the connection the transaction came from, if any, and
+// `payload` the frame it came in
+pub async fn transaction_received(
+    node: Arc<Mutex<Node>>,
+    transaction: Transaction,
+    origin: Option<SocketAddr>,
+    payload: Option<Payload>,
+) -> Result<(), RejectReason> {
+    println!("TRANSACTION {}", transaction);
+
+    let mut node = node.lock().await;
+
+    if !node.is_synced() {
+        println!("NOT SYNCED. DROPPING TRANSACTION.");
+
+        node.remember_rejection(transaction.hash(), RejectReason::NotSynced);
+
+        return Err(RejectReason::NotSynced);
+    }
+
+    // Checked first, as it would otherwise spend its own funds
+    if node.mempool.contains(&transaction.hash()) {
+        println!("MEMPOOL ALREADY HAS TRANSACTION. STOPPING.");
+
+        return Err(RejectReason::Duplicate);
+    }
+
+    let validity = node.mempool_admission(&transaction);
+
+    let parents = match validity {
+        Ok(parents) => parents,
+        Err(reason) => {
+            println!("DROPPING TRANSACTION: {}", reason);
+
+            // Already mined, which its status shows anyway
+            if reason != RejectReason::Duplicate {
+                node.remember_rejection(transaction.hash(), reason);
+            }
+
+            return Err(reason);
+        }
+    };
+
+    let hash = transaction.hash();
+
+    node.mempool
+        .insert(transaction.clone(), timestamp(), origin, parents);
+
+    // It may be the cheapest, in which case it goes right away
+    if node.trim_mempool(Some(&hash)).contains(&hash) {
+        return Err(RejectReason::MempoolFull);
+    }
+
+    node.events
+        .publish(ChainEvent::MempoolAdded { hash, origin });
+
+    node.relay_transaction(&transaction, origin, payload).await;
+
+    Ok(())
+}
+
+// Periodically drops transactions that have been pending for too long
+pub async fn mempool_expiry_loop(node: Arc<Mutex<Node>>, interval: std::time::Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        node.lock().await.evict_expired_transactions();
+    }
+}
+
+// Replies to a light client with the main chain headers after `locator`
+// Returns how many were sent
+pub async fn headers_requested(
+    node: Arc<Mutex<Node>>,
+    address: SocketAddr,
+    locator: Hash,
+) -> usize {
+    let mut node = node.lock().await;
+    let headers = node.headers_after(&locator);
+    let count = headers.len();
+
+    println!("SENDING {} HEADERS TO {}", count, address);
+
+    node.send(&address, Frame::Headers(headers)).await;
+
+    count
+}
+
+// Replies to a light client with the requested blocks, stripped down to the
+// transactions that match the filter it loaded
+pub async fn merkle_blocks_requested(
+    node: Arc<Mutex<Node>>,
+    address: SocketAddr,
+    hashes: Vec<Hash>,
+) {
+    let mut node = node.lock().await;
+
+    let merkle_blocks = match node.filters.get(&address) {
+        Some(filter) => hashes
+            .iter()
+            .take(MAX_HEADERS)
+            .filter_map(|hash| node.blockchain.get(hash))
+            .map(|block| block.filtered(filter))
+            .collect(),
+        None => {
+            println!("{} ASKED FOR MERKLE BLOCKS WITHOUT A FILTER", address);
+
+            Vec::new()
+        }
+    };
+
+    node.send(&address, Frame::MerkleBlocks(merkle_blocks))
+        .await;
+}
+
+// Replies with the compact filters of the requested blocks
+pub async fn compact_filters_requested(
+    node: Arc<Mutex<Node>>,
+    address: SocketAddr,
+    hashes: Vec<Hash>,
+) {
+    let mut node = node.lock().await;
+
+    let filters = hashes
+        .iter()
+        .take(MAX_HEADERS)
+        .filter_map(|hash| {
+            node.blockchain
+                .filter(hash)
+                .map(|filter| (*hash, filter.clone()))
+        })
+        .collect();
+
+    node.send(&address, Frame::CompactFilters(filters)).await;
+}
+
+pub async fn blocks_requested(node: Arc<Mutex<Node>>, address: SocketAddr, hashes: Vec<Hash>) {
+    let mut node = node.lock().await;
+
+    let requested = hashes.len().min(MAX_BLOCKS);
+    let served: Vec<Hash> = hashes
+        .into_iter()
+        .take(MAX_BLOCKS)
+        .filter(|hash| node.may_serve(hash))
+        .collect();
+
+    if served.len() < requested {
+        println!(
+            "UPLOAD CAP REACHED. NOT SENDING {} HISTORICAL BLOCKS TO {}",
+            requested - served.len(),
+            address
+        );
+    }
+
+    let blocks = served
+        .iter()
+        .filter_map(|hash| node.blockchain.get(hash).cloned())
+        .collect();
+
+    node.send(&address, Frame::Blocks(blocks)).await;
+}
+
+// Tries a batch of nonces for a block on the latest tip, or whether it is
+// our turn with proof of stake. `Ok(None)` when none of its transactions
+// are pending anymore, as someone else mined them first, and `Err` with the
+// block to try again with when it isn't sealed yet.
+async fn seal_block(
+    node: Arc<Mutex<Node>>,
+    proto_block: ProtoBlock,
+) -> Result<Option<Block>, ProtoBlock> {
+    let (mut block, target, pow_hash) = {
+        let mut unlocked_node = node.lock().await;
+        let node = &mut *unlocked_node;
+
+        let mut block = match node.assemble_block(proto_block) {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+
+        match node.next_target() {
+            Some(target) => (block, target, node.pow_hash()),
+            // Producers take turns instead of grinding. A locked key can't
+            // sign, so it waits like when it is not our turn.
+            None => {
+                let sealed = match node.secret_key() {
+                    Ok(secret_key) => node
+                        .consensus
+                        .seal(&mut block, &node.blockchain, secret_key),
+                    Err(_) => false,
+                };
+
+                return if sealed {
+                    Ok(Some(block))
+                } else {
+                    Err(ProtoBlock {
+                        nonce: block.header.nonce,
+                        transactions: block.transactions,
+                    })
+                };
+            }
+        }
+    };
+
+    // Without the node lock, which everything else needs
+    let (block, found, hashes) = tokio::task::spawn_blocking(move || {
+        let mut grinder = Grinder::new(&block, target, pow_hash);
+        let mut nonce = block.header.nonce;
+        let started = Instant::now();
+
+        let found = loop {
+            if let Some(found) = grinder.grind(&mut nonce, GRIND_BATCH) {
+                break Some(found);
+            }
+
+            if started.elapsed() >= GRIND_TIME {
+                break None;
+            }
+        };
+
+        block.header.nonce = found.unwrap_or(nonce);
+
+        (block, found.is_some(), grinder.hashes())
+    })
+    .await
+    .expect("nonce search panicked");
+
+    node.lock().await.hashrate.record(hashes);
+
+    if found {
+        println!("PROOF OF WORK ACCEPTED");
+
+        Ok(Some(block))
+    } else {
+        println!("PROOF OF WORK NOT FOUND IN {} NONCES", hashes);
+
+        Err(ProtoBlock {
+            nonce: block.header.nonce,
+            transactions: block.transactions,
+        })
+    }
+}
+
+// Gives the miner a new block template whenever a transaction makes it
+// into the mempool, be it new or back from a disconnected block, and every
+// `TEMPLATE_REFRESH` for what changed in it otherwise
+pub async fn feed_miner(
+    node: Arc<Mutex<Node>>,
+    mut events: broadcast::Receiver<ChainEvent>,
+    tx: mpsc::Sender<ProtoBlock>,
+) {
+    let mut refresh = tokio::time::interval(TEMPLATE_REFRESH);
+
+    loop {
+        tokio::select! {
+            event = events::next(&mut events) => match event {
+                Some(ChainEvent::MempoolAdded { .. }) => {}
+                Some(_) => continue,
+                None => return,
+            },
+            _ = refresh.tick() => {}
+        }
+
+        let transactions = node.lock().await.mempool.block_template(MAX_BLOCK_SIZE);
+
+        if !transactions.is_empty() {
+            let _ = tx.send(template_to_proto_block(transactions)).await;
+        }
+    }
+}
+
+pub async fn block_generator(node: Arc<Mutex<Node>>, mut rx: mpsc::Receiver<ProtoBlock>) {
+    // The block we are working on. Templates have everything waiting in the
+    // mempool, so a new one replaces it. Kept here rather than sent back
+    // through `rx`, which could block us on our own channel.
+    let mut current = None;
+    let mut throttle = Throttle::new(&node.lock().await.settings.mining);
+
+    loop {
+        while let Ok(proto_block) = rx.try_recv() {
+            current = Some(proto_block);
+        }
+
+        let proto_block = match current.take() {
+            Some(proto_block) => proto_block,
+            None => match rx.recv().await {
+                Some(proto_block) => proto_block,
+                None => return,
+            },
+        };
+
+        // Anything we mine before catching up would be on a stale tip
+        if !node.lock().await.is_synced() {
+            println!("NOT SYNCED. WAITING TO MINE.");
+
+            tokio::time::sleep(SYNC_WAIT).await;
+
+            current = Some(proto_block);
+
+            continue;
+        }
+
+        let started = Instant::now();
+        let sealed = seal_block(node.clone(), proto_block).await;
+
+        // Mining gently, when told to
+        if let Some(rest) = throttle.rest(started.elapsed()) {
+            tokio::time::sleep(rest).await;
+        }
+
+        match sealed {
+            Ok(Some(block)) => block_created(node.clone(), block).await,
+            Ok(None) => println!("BLOCK ABANDONED, TRANSACTIONS ALREADY MINED"),
+            Err(proto_block) => {
+                current = Some(proto_block);
+
+                let delay = node.lock().await.consensus.retry_delay();
+
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+// Helpers are shared with the tests of the modules serving the node
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::keyfile;
+    use crate::network::Emission;
+    use fcoin_core::keys;
+
+    fn settings(network: NetworkProfile) -> NodeSettings {
+        NodeSettings {
+            mempool_ttl: 60,
+            transaction_index: false,
+            sync_tolerance: 6,
+            network,
+            daily_upload_cap: None,
+            relay: RelayPolicy::Full,
+            listen_port: None,
+            memory: MemorySettings::default(),
+            signal: Vec::new(),
+            mining: MiningSettings::default(),
+            trusted_peers: Vec::new(),
+            policy: SpendingPolicy::default(),
+        }
+    }
+
+    pub(crate) fn node(secret_key: &SecretKey, network: &str) -> Arc<Mutex<Node>> {
+        let network = toml::from_str(network).unwrap();
+
+        Arc::new(Mutex::new(Node::new(
+            secret_key.clone(),
+            settings(network),
+            AddressBook::default(),
+        )))
+    }
+
+    // Sealed on top of `parent` as the node would check it
+    pub(crate) async fn mine(
+        node: &Arc<Mutex<Node>>,
+        secret_key: &SecretKey,
+        parent: Hash,
+        time: u64,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        let header = BlockHeader::new(time, keys::public_key(secret_key), parent, [0; 32], [0; 32]);
+        let mut block = Block::new(header, transactions);
+        let node = node.lock().await;
+
+        while !node
+            .consensus
+            .seal(&mut block, &node.blockchain, secret_key)
+        {}
+
+        block
+    }
+
+    // Mines `count` blocks `interval` seconds apart on top of `parent`,
+    // returning the last
+    pub(crate) async fn branch(
+        node: &Arc<Mutex<Node>>,
+        secret_key: &SecretKey,
+        mut parent: Hash,
+        mut time: u64,
+        interval: u64,
+        count: usize,
+    ) -> Hash {
+        for _ in 0..count {
+            time += interval;
+
+            let block = mine(node, secret_key, parent, time, Vec::new()).await;
+
+            parent = block.hash();
+            block_received(node.clone(), block).await.unwrap();
+        }
+
+        parent
+    }
+
+    pub(crate) fn payment(
+        secret_key: &SecretKey,
+        destination: PublicKey,
+        amount: u64,
+        fee: u64,
+    ) -> Transaction {
+        let details =
+            TransactionDetails::new(keys::public_key(secret_key), destination, amount, fee);
+        let signature = fcoin_core::transaction::sign(&details, secret_key);
+
+        Transaction::new(details, signature)
+    }
+
+    #[tokio::test]
+    async fn sources_pay_fees_to_the_producer() {
+        let secret_key = keyfile::generate();
+        let producer = keyfile::generate();
+        let source = keys::public_key(&secret_key);
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let first = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let paid = payment(&secret_key, [7; 32], 100, 5);
+        transaction_received(node.clone(), paid.clone(), None, None)
+            .await
+            .unwrap();
+
+        // What is waiting is spent already
+        let too_much = payment(&secret_key, [7; 32], 890, 6);
+        assert_eq!(
+            transaction_received(node.clone(), too_much, None, None).await,
+            Err(RejectReason::InsufficientFunds)
+        );
+
+        let block = mine(&node, &producer, first, start + 20, vec![paid]).await;
+        block_received(node.clone(), block).await.unwrap();
+
+        let node = node.lock().await;
+
+        assert_eq!(node.balance(&source), Ok(1000 - 100 - 5));
+        assert_eq!(node.balance(&[7; 32]), Ok(100));
+        assert_eq!(node.balance(&keys::public_key(&producer)), Ok(1000 + 5));
+        assert!(node.mempool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fee_estimates_outbid_a_full_mempool() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let first = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let mined = payment(&secret_key, [7; 32], 100, 5);
+        let block = mine(&node, &secret_key, first, start + 20, vec![mined.clone()]).await;
+        block_received(node.clone(), block).await.unwrap();
+
+        // What recent blocks paid, with nothing waiting
+        assert_eq!(node.lock().await.estimate_fee(1), mined.fee_rate());
+
+        let high = payment(&secret_key, [7; 32], 1, 50);
+        let low = payment(&secret_key, [7; 32], 2, 20);
+
+        for transaction in [high.clone(), low.clone()] {
+            transaction_received(node.clone(), transaction, None, None)
+                .await
+                .unwrap();
+        }
+
+        // Blocks took a transaction each, so the next one takes the highest
+        let node = node.lock().await;
+
+        assert_eq!(node.estimate_fee(1), high.fee_rate() + 1);
+        assert_eq!(node.estimate_fee(2), low.fee_rate() + 1);
+        assert_eq!(node.estimate_fee(3), mined.fee_rate());
+    }
+
+    #[tokio::test]
+    async fn expired_transactions_are_rejected_and_evicted() {
+        let secret_key = keyfile::generate();
+        let source = keys::public_key(&secret_key);
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let first = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let expiring = |amount, expires_at| {
+            let details =
+                TransactionDetails::new(source, [7; 32], amount, 1).with_expiry(Some(expires_at));
+            let signature = fcoin_core::transaction::sign(&details, &secret_key);
+
+            Transaction::new(details, signature)
+        };
+
+        // The next block is at height 2
+        assert_eq!(
+            transaction_received(node.clone(), expiring(10, 1), None, None).await,
+            Err(RejectReason::Expired)
+        );
+
+        let last_chance = expiring(20, 2);
+        transaction_received(node.clone(), last_chance.clone(), None, None)
+            .await
+            .unwrap();
+
+        let second = branch(&node, &secret_key, first, start + 10, 10, 1).await;
+        node.lock().await.evict_expired_transactions();
+
+        assert_eq!(
+            node.lock().await.transaction_status(&last_chance.hash()),
+            TransactionStatus::Rejected(RejectReason::Expired)
+        );
+
+        let late = mine(&node, &secret_key, second, start + 30, vec![last_chance]).await;
+        assert_eq!(
+            block_received(node.clone(), late).await,
+            Err(RejectReason::Expired)
+        );
+    }
+
+    #[tokio::test]
+    async fn every_block_of_a_batch_gets_its_reason() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "");
+        let start = timestamp() - 100;
+
+        let first = mine(&node, &secret_key, [0; 32], start, Vec::new()).await;
+        let mut tampered = mine(&node, &secret_key, first.hash(), start + 10, Vec::new()).await;
+        tampered
+            .transactions
+            .push(payment(&secret_key, [7; 32], 1, 1));
+        let orphan = mine(&node, &secret_key, [9; 32], start + 10, Vec::new()).await;
+
+        let hashes = [first.hash(), tampered.hash(), orphan.hash()];
+        let results = blocks_received(node.clone(), vec![first, tampered, orphan]).await;
+
+        assert_eq!(
+            results,
+            vec![
+                (hashes[0], Ok(())),
+                (hashes[1], Err(RejectReason::InvalidMerkleRoot)),
+                (hashes[2], Err(RejectReason::UnknownParent)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_transactions_keep_their_reason() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "");
+        branch(&node, &secret_key, [0; 32], timestamp() - 100, 10, 1).await;
+
+        let overdrawn = payment(&secret_key, [7; 32], 5, 1);
+        assert_eq!(
+            transaction_received(node.clone(), overdrawn.clone(), None, None).await,
+            Err(RejectReason::InsufficientFunds)
+        );
+
+        assert_eq!(
+            node.lock().await.transaction_status(&overdrawn.hash()),
+            TransactionStatus::Rejected(RejectReason::InsufficientFunds)
+        );
+    }
+
+    #[tokio::test]
+    async fn payments_can_spend_what_is_still_waiting() {
+        let secret_key = keyfile::generate();
+        let recipient = keyfile::generate();
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let first = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let parent = payment(&secret_key, keys::public_key(&recipient), 100, 1);
+        let child = payment(&recipient, [7; 32], 50, 10);
+
+        // Nothing to spend yet
+        assert_eq!(
+            transaction_received(node.clone(), child.clone(), None, None).await,
+            Err(RejectReason::InsufficientFunds)
+        );
+
+        for transaction in [parent.clone(), child.clone()] {
+            transaction_received(node.clone(), transaction, None, None)
+                .await
+                .unwrap();
+        }
+
+        let template = {
+            let node = node.lock().await;
+            let entry = node
+                .mempool
+                .entries()
+                .find(|(hash, _)| **hash == child.hash())
+                .unwrap()
+                .1;
+
+            assert_eq!(entry.parents(), &HashSet::from([parent.hash()]));
+
+            node.mempool.block_template(MAX_BLOCK_SIZE)
+        };
+
+        assert_eq!(template.len(), 2);
+        assert_eq!(template[0].hash(), parent.hash());
+
+        // The child can't be mined before its parent
+        let early = mine(&node, &secret_key, first, start + 20, vec![child]).await;
+        assert_eq!(
+            block_received(node.clone(), early).await,
+            Err(RejectReason::InsufficientFunds)
+        );
+
+        let block = mine(&node, &secret_key, first, start + 20, template).await;
+        block_received(node.clone(), block).await.unwrap();
+
+        assert_eq!(node.lock().await.balance(&[7; 32]), Ok(50));
+    }
+
+    // A chain of a first block, then one paying `recipient`, then one where
+    // `recipient` pays that on. Returns the first block and both payments.
+    async fn spent_twice(
+        node: &Arc<Mutex<Node>>,
+        secret_key: &SecretKey,
+        recipient: &SecretKey,
+        start: u64,
+    ) -> (Hash, Transaction, Transaction) {
+        let first = branch(node, secret_key, [0; 32], start, 10, 1).await;
+        let parent = payment(secret_key, keys::public_key(recipient), 100, 1);
+        let child = payment(recipient, [7; 32], 50, 1);
+        let mut tip = first;
+
+        for (time, transaction) in [(start + 20, &parent), (start + 30, &child)] {
+            let block = mine(node, secret_key, tip, time, vec![transaction.clone()]).await;
+            tip = block.hash();
+            block_received(node.clone(), block).await.unwrap();
+        }
+
+        (first, parent, child)
+    }
+
+    #[tokio::test]
+    async fn disconnected_transactions_wait_again_with_their_dependencies() {
+        let secret_key = keyfile::generate();
+        let recipient = keyfile::generate();
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let (first, parent, child) = spent_twice(&node, &secret_key, &recipient, start).await;
+
+        // A longer branch without either
+        let tip = branch(&node, &secret_key, first, start + 11, 10, 3).await;
+
+        let node = node.lock().await;
+
+        assert_eq!(node.tip_hash(), tip);
+        assert_eq!(
+            node.mempool.ancestors(&child.hash()),
+            HashSet::from([parent.hash()])
+        );
+        assert_eq!(
+            node.mempool.block_template(MAX_BLOCK_SIZE)[0].hash(),
+            parent.hash()
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnected_transactions_conflicting_with_the_new_branch_are_dropped() {
+        let secret_key = keyfile::generate();
+        let recipient = keyfile::generate();
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let (first, parent, child) = spent_twice(&node, &secret_key, &recipient, start).await;
+
+        // A longer branch, by someone else, where the coins of the parent
+        // went elsewhere
+        let producer = keyfile::generate();
+        let elsewhere = payment(&secret_key, [8; 32], 990, 1);
+        let block = mine(&node, &producer, first, start + 11, vec![elsewhere]).await;
+        let fork = block.hash();
+        block_received(node.clone(), block).await.unwrap();
+        branch(&node, &producer, fork, start + 11, 10, 2).await;
+
+        let node = node.lock().await;
+
+        assert!(node.mempool.is_empty());
+
+        for transaction in [parent, child] {
+            assert_eq!(
+                node.transaction_status(&transaction.hash()),
+                TransactionStatus::Rejected(RejectReason::InsufficientFunds)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn payments_must_move_coins_to_someone_else() {
+        let secret_key = keyfile::generate();
+        let source = keys::public_key(&secret_key);
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let first = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let nothing = payment(&secret_key, [7; 32], 0, 1);
+        let to_itself = payment(&secret_key, source, 10, 1);
+
+        for (transaction, reason) in [
+            (nothing, RejectReason::ZeroAmount),
+            (to_itself, RejectReason::SelfTransfer),
+        ] {
+            assert_eq!(
+                transaction_received(node.clone(), transaction.clone(), None, None).await,
+                Err(reason)
+            );
+
+            let block = mine(&node, &secret_key, first, start + 20, vec![transaction]).await;
+            assert_eq!(block_received(node.clone(), block).await, Err(reason));
+        }
+
+        // Registering a name only pays the fee
+        let details = TransactionDetails::register(source, "alice", 10);
+        let signature = fcoin_core::transaction::sign(&details, &secret_key);
+        transaction_received(
+            node.clone(),
+            Transaction::new(details, signature),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn self_transfers_mined_before_they_were_rejected_cost_their_fee() {
+        let secret_key = keyfile::generate();
+        let source = keys::public_key(&secret_key);
+        let mut blockchain = ChainStore::new(Emission {
+            initial_reward: 1000,
+            halving_interval: 0,
+        });
+
+        let first = Block::new(
+            BlockHeader::new(0, source, [0; 32], [0; 32], [0; 32]),
+            Vec::new(),
+        );
+        let first_hash = first.hash();
+        blockchain.insert(first_hash, first, BigUint::from(1u32));
+
+        // Stored as is, like a block from before the rule
+        let second = Block::new(
+            BlockHeader::new(10, [5; 32], first_hash, [0; 32], [0; 32]),
+            vec![payment(&secret_key, source, 10, 2)],
+        );
+        let tip = second.hash();
+        blockchain.insert(tip, second, BigUint::from(1u32));
+
+        assert_eq!(balance_at(&blockchain, &tip, &source), Ok(1000 - 2));
+        assert_eq!(balance_at(&blockchain, &tip, &[5; 32]), Ok(1000 + 2));
+    }
+
+    #[tokio::test]
+    async fn a_longer_branch_with_less_work_loses() {
+        let secret_key = keyfile::generate();
+        // Targets move every other block, by up to 4 times
+        let node = node(&secret_key, "block_time = 10\nretarget_window = 2");
+        let start = timestamp() - 1000;
+
+        let first = mine(&node, &secret_key, [0; 32], start, Vec::new()).await;
+        let fork = first.hash();
+        block_received(node.clone(), first).await.unwrap();
+
+        // Blocks far apart keep the easiest target
+        let light = branch(&node, &secret_key, fork, start, 40, 6).await;
+        assert_eq!(node.lock().await.tip_hash(), light);
+
+        // Blocks close together make it harder, so fewer weigh more
+        let heavy = branch(&node, &secret_key, fork, start, 1, 4).await;
+
+        let node = node.lock().await;
+
+        assert!(node.blockchain.height(&light) > node.blockchain.height(&heavy));
+        assert!(node.blockchain.chain_work(&heavy) > node.blockchain.chain_work(&light));
+        assert_eq!(node.tip_hash(), heavy);
+    }
+
+    #[tokio::test]
+    async fn a_late_last_block_does_not_ease_the_target() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "block_time = 10\nretarget_window = 4");
+        let start = timestamp() - 100;
+
+        // Three windows of blocks a second apart
+        let fast = branch(&node, &secret_key, [0; 32], start, 1, 12).await;
+        let before = {
+            let node = node.lock().await;
+            node.consensus.target(&node.blockchain, &fast).unwrap()
+        };
+
+        // Another, its last block claiming the window took most of an hour
+        let parent = branch(&node, &secret_key, fast, start + 12, 1, 3).await;
+        let late = mine(&node, &secret_key, parent, start + 3000, Vec::new()).await;
+        let late_hash = late.hash();
+        block_received(node.clone(), late).await.unwrap();
+
+        let node = node.lock().await;
+        let after = node.consensus.target(&node.blockchain, &late_hash).unwrap();
+
+        assert!(after <= before);
+    }
+
+    #[tokio::test]
+    async fn block_times_must_pass_the_median_time_past() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "");
+        let start = timestamp() - 100;
+
+        // Median time past of start + 20
+        let tip = branch(&node, &secret_key, [0; 32], start, 10, 3).await;
+
+        let old = mine(&node, &secret_key, tip, start + 20, Vec::new()).await;
+        assert_eq!(
+            block_received(node.clone(), old).await,
+            Err(RejectReason::TimeTooOld)
+        );
+
+        // Earlier than its parent is fine, as long as it passes the median
+        let late = mine(&node, &secret_key, tip, start + 21, Vec::new()).await;
+        block_received(node.clone(), late).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_valid_headers_count_towards_the_height_of_a_peer() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "");
+        let start = timestamp() - 100;
+        let tip = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let first = mine(&node, &secret_key, tip, start + 20, Vec::new()).await;
+        let second = mine(&node, &secret_key, first.hash(), start + 30, Vec::new()).await;
+        let unknown = mine(&node, &secret_key, [9; 32], start + 20, Vec::new()).await;
+        // Proof of work headers can't be signed
+        let mut signed = second.header.clone();
+        signed.sign(&secret_key);
+
+        let address = "127.0.0.1:7125".parse().unwrap();
+        let mut node = node.lock().await;
+
+        let headers = [first.header.clone(), second.header.clone()];
+        assert_eq!(node.valid_headers(&headers), (2, 3));
+
+        // After one that doesn't follow on, none do
+        let headers = [first.header.clone(), unknown.header.clone(), second.header];
+        assert_eq!(node.valid_headers(&headers), (1, 2));
+
+        assert_eq!(node.valid_headers(&[unknown.header]), (0, 0));
+        assert_eq!(node.valid_headers(&[first.header.clone(), signed]), (1, 2));
+
+        // What a peer claimed goes away with it
+        node.set_peer_height(address, 3);
+        assert_eq!(node.best_known_height(), 3);
+
+        node.remove_peer(&address);
+        assert_eq!(node.best_known_height(), 1);
+    }
+
+    #[tokio::test]
+    async fn transactions_are_only_mined_once_in_a_chain() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let start = timestamp() - 100;
+        let first = branch(&node, &secret_key, [0; 32], start, 10, 1).await;
+
+        let payment = payment(&secret_key, [7; 32], 10, 1);
+        let second = mine(&node, &secret_key, first, start + 20, vec![payment.clone()]).await;
+        let second_hash = second.hash();
+        block_received(node.clone(), second).await.unwrap();
+        branch(&node, &secret_key, second_hash, start + 20, 10, 1).await;
+
+        assert_eq!(
+            node.lock().await.transaction_status(&payment.hash()),
+            TransactionStatus::Confirmed {
+                height: 2,
+                confirmations: 2
+            }
+        );
+
+        // Again on a branch that has it
+        let again = mine(
+            &node,
+            &secret_key,
+            second_hash,
+            start + 40,
+            vec![payment.clone()],
+        )
+        .await;
+        assert_eq!(
+            block_received(node.clone(), again).await,
+            Err(RejectReason::Duplicate)
+        );
+
+        // A branch forking before it doesn't
+        let other = mine(&node, &secret_key, first, start + 40, vec![payment.clone()]).await;
+        block_received(node.clone(), other).await.unwrap();
+
+        assert_eq!(node.lock().await.height(), 3);
+    }
+
+    #[tokio::test]
+    async fn blocks_for_future_slots_are_rejected() {
+        let secret_key = keyfile::generate();
+        // Slots of 10 seconds, the first block can be produced by anyone
+        let node = node(&secret_key, "consensus = \"proof-of-stake\"");
+        let now = timestamp();
+
+        let future = mine(&node, &secret_key, [0; 32], now + 100, Vec::new()).await;
+        assert_eq!(
+            block_received(node.clone(), future).await,
+            Err(RejectReason::TimeTooNew)
+        );
+
+        let current = mine(&node, &secret_key, [0; 32], now, Vec::new()).await;
+        block_received(node.clone(), current).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ground_slots_are_rejected() {
+        let secret_key = keyfile::generate();
+        let staker = keyfile::generate();
+        let node = node(
+            &secret_key,
+            "consensus = \"proof-of-stake\"\n[emission]\ninitial_reward = 1000",
+        );
+        let now = timestamp();
+
+        let first = mine(&node, &secret_key, [0; 32], now - 100, Vec::new()).await;
+        let first_hash = first.hash();
+        block_received(node.clone(), first).await.unwrap();
+
+        // Half the coins go to the staker, who then only wins some slots
+        let reward = node.lock().await.blockchain.reward(&first_hash);
+        let stake = payment(&secret_key, keys::public_key(&staker), reward / 2, 1);
+        let second = mine(&node, &secret_key, first_hash, now - 90, vec![stake]).await;
+        let tip = second.hash();
+        block_received(node.clone(), second).await.unwrap();
+
+        // The first slot it wins after the next ten
+        let mut time = now + 100;
+        let ground = loop {
+            let header = BlockHeader::new(time, keys::public_key(&staker), tip, [0; 32], [0; 32]);
+            let mut block = Block::new(header, Vec::new());
+            let node = node.lock().await;
+
+            if node.consensus.seal(&mut block, &node.blockchain, &staker) {
+                break block;
+            }
+
+            time += 10;
+        };
+
+        assert_eq!(
+            block_received(node.clone(), ground).await,
+            Err(RejectReason::TimeTooNew)
+        );
+    }
 }
@@ -0,0 +1,71 @@
+use sha2::Digest;
+use sha2::Sha256;
+
+use serde::{Deserialize, Serialize};
+
+// Upper bounds so a peer cannot make us allocate or hash without limit
+const MAX_FILTER_BYTES: usize = 36_000;
+const MAX_HASH_FUNCTIONS: u32 = 50;
+
+// Probabilistic set of the public keys a light client is interested in. Full
+// nodes only ever see the filter, and false positives hide which keys are the
+// real ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_functions: u32,
+    tweak: u32,
+}
+
+impl BloomFilter {
+    pub fn new(elements: usize, false_positive_rate: f64, tweak: u32) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let elements = elements.max(1) as f64;
+
+        let bits = (-elements * false_positive_rate.ln() / (ln2 * ln2)).ceil() as usize;
+        let bytes = (bits / 8 + 1).min(MAX_FILTER_BYTES);
+        let hash_functions = ((bytes * 8) as f64 / elements * ln2).round() as u32;
+
+        BloomFilter {
+            bits: vec![0; bytes],
+            hash_functions: hash_functions.clamp(1, MAX_HASH_FUNCTIONS),
+            tweak,
+        }
+    }
+
+    // Filters come from the network, so they must be checked before use
+    pub fn is_within_limits(&self) -> bool {
+        !self.bits.is_empty()
+            && self.bits.len() <= MAX_FILTER_BYTES
+            && self.hash_functions <= MAX_HASH_FUNCTIONS
+    }
+
+    fn bit_index(&self, function: u32, data: &[u8]) -> usize {
+        let digest = Sha256::new()
+            .chain(function.to_le_bytes())
+            .chain(self.tweak.to_le_bytes())
+            .chain(data)
+            .finalize();
+
+        let mut word = [0; 8];
+        word.copy_from_slice(&digest[0..8]);
+
+        (u64::from_le_bytes(word) % (self.bits.len() as u64 * 8)) as usize
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        for function in 0..self.hash_functions {
+            let index = self.bit_index(function, data);
+
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn contains(&self, data: &[u8]) -> bool {
+        (0..self.hash_functions).all(|function| {
+            let index = self.bit_index(function, data);
+
+            self.bits[index / 8] & (1 << (index % 8)) != 0
+        })
+    }
+}
@@ -0,0 +1,478 @@
+use super::address_index::HistoryKind;
+use super::blockchain::{Hash, PublicKey, Transaction, TransactionDetails};
+use super::client;
+use super::framing::{Connection, Frame, RelayPolicy};
+use fcoin_core::contract::{
+    Channel, ChannelState, Close, Contract, ContractSignature, SignedState, Unlock,
+};
+use fcoin_core::{keys, raw, transaction, SecretKey};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+
+// Payment channels. The opener locks coins in a channel with the other side,
+// once both signed the first state, which gives them all back to the opener.
+// Each payment is then a new state both sign, exchanged directly between
+// them in `Frame::ChannelUpdate`s. Either side closes the channel alone by
+// moving its coins to the settlement of the latest state, which the other
+// side can replace with a later one until its timeout, and which either side
+// pays out after it.
+
+const USER_AGENT: &str = concat!("fcoin-channel/", env!("CARGO_PKG_VERSION"));
+// Blocks a close has to be mined in, on top of the dispute window
+const CLOSE_MARGIN: u64 = 10;
+
+// Proposes a new state of `channel`, signed by the sender. Answered with the
+// state signed by the other side, or a closed connection when refused.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelUpdate {
+    pub channel: Channel,
+    pub state: ChannelState,
+    pub signature: ContractSignature,
+}
+
+// Each channel we are on, with the latest state, kept in the channels file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelRecord {
+    pub channel: Channel,
+    pub state: SignedState,
+}
+
+impl ChannelRecord {
+    pub fn balances(&self, us: &PublicKey) -> Option<(u64, u64)> {
+        let ours = self.channel.party(us)?;
+
+        Some((
+            self.state.state.balances[ours],
+            self.state.state.balances[1 - ours],
+        ))
+    }
+}
+
+pub fn decode(content: &[u8]) -> Result<Vec<ChannelRecord>, String> {
+    serde_json::from_slice(content).map_err(|issue| issue.to_string())
+}
+
+pub fn load(path: &str) -> Result<Vec<ChannelRecord>, String> {
+    match std::fs::read(path) {
+        Ok(content) => {
+            decode(&content).map_err(|issue| format!("Invalid channels file {}: {}", path, issue))
+        }
+        Err(issue) if issue.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(issue) => Err(format!("Could not read {}: {}", path, issue)),
+    }
+}
+
+// Losing the latest state of a channel loses what we were paid in it, so it
+// is never left half written
+pub fn save(path: &str, records: &[ChannelRecord]) -> Result<(), String> {
+    let content = serde_json::to_vec_pretty(records).map_err(|issue| issue.to_string())?;
+
+    super::backup::write_atomically(path, &content)
+}
+
+pub fn find<'a>(records: &'a [ChannelRecord], address: &PublicKey) -> Option<&'a ChannelRecord> {
+    records
+        .iter()
+        .find(|record| &record.channel.address() == address)
+}
+
+// Sends `state` to the other side at `peer`, for them to sign it too
+async fn exchange(
+    peer: &str,
+    secret_key: &SecretKey,
+    channel: &Channel,
+    state: ChannelState,
+) -> Result<SignedState, String> {
+    let ours = channel
+        .party(&keys::public_key(secret_key))
+        .ok_or("We aren't on the channel")?;
+    let theirs = 1 - ours;
+    let stream = TcpStream::connect(peer)
+        .await
+        .map_err(|issue| format!("Could not connect to {}: {}", peer, issue))?;
+    let (mut writter, mut reader) = Connection::new(stream);
+
+    Connection::handshake(
+        &mut writter,
+        &mut reader,
+        USER_AGENT,
+        None,
+        RelayPolicy::BlocksOnly,
+    )
+    .await?;
+
+    let signature = state.sign(channel, secret_key);
+
+    writter
+        .write(Frame::ChannelUpdate(ChannelUpdate {
+            channel: channel.clone(),
+            state: state.clone(),
+            signature,
+        }))
+        .await;
+
+    let answer = loop {
+        match reader.read().await {
+            Some(Frame::ChannelUpdate(answer)) => break answer,
+            Some(_) => {}
+            None => return Err(format!("{} refused the update", peer)),
+        }
+    };
+
+    if answer.channel != *channel
+        || answer.state != state
+        || !state.is_signed_by(channel, &channel.parties[theirs], &answer.signature)
+    {
+        return Err(format!("{} answered with an invalid signature", peer));
+    }
+
+    let mut signatures = [signature; 2];
+
+    signatures[theirs] = answer.signature;
+
+    Ok(SignedState { state, signatures })
+}
+
+// Agrees on the first state of a new channel with `with`, who listens at
+// `peer`. The channel has to be funded with `amount` right after, of which
+// `reserve` pays for closing it.
+pub async fn open(
+    peer: &str,
+    secret_key: &SecretKey,
+    with: PublicKey,
+    amount: u64,
+    reserve: u64,
+    dispute: u64,
+) -> Result<ChannelRecord, String> {
+    if amount <= reserve {
+        return Err(format!(
+            "The channel needs more than the ${} reserved for closing it",
+            reserve
+        ));
+    }
+
+    let mut nonce = [0; 8];
+
+    getrandom::getrandom(&mut nonce).map_err(|issue| issue.to_string())?;
+
+    let channel = Channel {
+        parties: [keys::public_key(secret_key), with],
+        dispute,
+        nonce: u64::from_le_bytes(nonce),
+    };
+    let state = ChannelState {
+        sequence: 0,
+        balances: [amount - reserve, 0],
+    };
+    let state = exchange(peer, secret_key, &channel, state).await?;
+
+    Ok(ChannelRecord { channel, state })
+}
+
+// Pays `amount` to the other side, who listens at `peer`, returning the new
+// state
+pub async fn pay(
+    peer: &str,
+    secret_key: &SecretKey,
+    record: &ChannelRecord,
+    amount: u64,
+) -> Result<SignedState, String> {
+    let ours = record
+        .channel
+        .party(&keys::public_key(secret_key))
+        .ok_or("We aren't on the channel")?;
+    let mut state = record.state.state.clone();
+
+    state.sequence += 1;
+    state.balances[ours] = state.balances[ours]
+        .checked_sub(amount)
+        .ok_or("Not enough coins of ours left in the channel")?;
+    state.balances[1 - ours] += amount;
+
+    exchange(peer, secret_key, &record.channel, state).await
+}
+
+// Signs `update` when it only ever gives us more, returning what it gave us,
+// the record it is now and our signature
+fn accept(
+    records: &[ChannelRecord],
+    secret_key: &SecretKey,
+    update: ChannelUpdate,
+) -> Result<(u64, ChannelRecord, ContractSignature), String> {
+    let ChannelUpdate {
+        channel,
+        state,
+        signature,
+    } = update;
+    let ours = channel
+        .party(&keys::public_key(secret_key))
+        .ok_or("We aren't on the channel")?;
+    let theirs = 1 - ours;
+
+    if !state.is_signed_by(&channel, &channel.parties[theirs], &signature) {
+        return Err("Invalid signature".to_string());
+    }
+
+    let received = match records.iter().find(|record| record.channel == channel) {
+        None if state.sequence == 0 && state.balances[ours] == 0 => 0,
+        None => return Err("New channels have to start with nothing of ours".to_string()),
+        Some(record) => {
+            let latest = &record.state.state;
+            let total =
+                |state: &ChannelState| state.balances[0] as u128 + state.balances[1] as u128;
+
+            if state.sequence != latest.sequence + 1 {
+                return Err(format!(
+                    "Expected state {}, not {}",
+                    latest.sequence + 1,
+                    state.sequence
+                ));
+            }
+
+            if total(&state) != total(latest) || state.balances[ours] < latest.balances[ours] {
+                return Err("The update doesn't pay us".to_string());
+            }
+
+            state.balances[ours] - latest.balances[ours]
+        }
+    };
+
+    let mut signatures = [signature; 2];
+
+    signatures[ours] = state.sign(&channel, secret_key);
+
+    Ok((
+        received,
+        ChannelRecord {
+            channel,
+            state: SignedState { state, signatures },
+        },
+        signatures[ours],
+    ))
+}
+
+// Signs the updates of whoever connects on `port`, keeping the channels in
+// `path`. Connections are served one at a time, so updates never race.
+pub async fn listen(port: u16, secret_key: &SecretKey, path: &str) -> Result<(), String> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .map_err(|issue| format!("Could not listen on port {}: {}", port, issue))?;
+
+    println!("Listening for channel updates on port {}", port);
+
+    loop {
+        let (stream, from) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(issue) => {
+                println!("Could not accept a connection: {}", issue);
+                continue;
+            }
+        };
+        let (mut writter, mut reader) = Connection::new(stream);
+
+        if let Err(issue) = Connection::handshake(
+            &mut writter,
+            &mut reader,
+            USER_AGENT,
+            None,
+            RelayPolicy::BlocksOnly,
+        )
+        .await
+        {
+            println!("Handshake with {} failed: {}", from, issue);
+            continue;
+        }
+
+        while let Some(frame) = reader.read().await {
+            let Frame::ChannelUpdate(update) = frame else {
+                continue;
+            };
+            let mut records = load(path)?;
+
+            match accept(&records, secret_key, update) {
+                Ok((received, record, signature)) => {
+                    let address = fcoin_core::address::encode(&record.channel.address());
+                    let answer = ChannelUpdate {
+                        channel: record.channel.clone(),
+                        state: record.state.state.clone(),
+                        signature,
+                    };
+
+                    match records
+                        .iter_mut()
+                        .find(|known| known.channel == record.channel)
+                    {
+                        Some(known) => *known = record,
+                        None => {
+                            println!("Channel {} opened by {}", address, from);
+
+                            records.push(record);
+                        }
+                    }
+
+                    // Saved before signing back, so a crash can't lose it
+                    save(path, &records)?;
+
+                    if received > 0 {
+                        println!("Received ${} in channel {}", received, address);
+                    }
+
+                    writter.write(Frame::ChannelUpdate(answer)).await;
+                }
+                Err(issue) => {
+                    println!("Refused an update from {}: {}", from, issue);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The settlement the coins of `channel` are in, `None` while it isn't
+// closed. The node needs the transaction index to find it.
+pub async fn settlement(node: &str, channel: &Channel) -> Result<Option<Contract>, String> {
+    let mut current: Option<Contract> = None;
+
+    loop {
+        let address = match &current {
+            None => channel.address(),
+            Some(contract) => contract.address(),
+        };
+        let (_, entries) = client::address_history(node, address, 0).await?;
+        let Some(entry) = entries
+            .iter()
+            .find(|entry| matches!(entry.kind, HistoryKind::Sent { .. }))
+        else {
+            return Ok(current);
+        };
+        let (transaction, _, _) = client::transaction(node, entry.hash).await?;
+
+        match transaction
+            .unlock()
+            .and_then(|unlock| unlock.close.as_ref())
+        {
+            Some(close) => current = Some(close.settlement(channel)),
+            // Paid out
+            None => return Ok(current),
+        }
+    }
+}
+
+// Signs a transaction from `unlock` that pays everything it has to
+// `destination`, less the fee
+async fn sweep(
+    node: &str,
+    secret_key: &SecretKey,
+    unlock: Unlock,
+    destination: PublicKey,
+    fee: Option<u64>,
+    confirm_within: u32,
+) -> Result<Hash, String> {
+    let source = unlock.contract.address();
+    let balance = client::balance(node, source).await?;
+    let signed = |amount, fee| {
+        let details = TransactionDetails::new(source, destination, amount, fee);
+        let signature = transaction::sign(&details, secret_key);
+
+        Transaction::new(details, signature).with_unlock(unlock.clone())
+    };
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+
+            (signed(0, 0).size() * rate).div_ceil(1000)
+        }
+    };
+
+    if balance <= fee as i128 {
+        return Err(format!(
+            "Nothing to take: the channel has ${} and the fee is ${}",
+            balance, fee
+        ));
+    }
+
+    let (hash, _) = client::broadcast_raw_transaction(
+        node,
+        &raw::encode_signed(&signed((balance - fee as i128) as u64, fee)),
+    )
+    .await?;
+
+    Ok(hash)
+}
+
+// Closes the channel of `record` alone, with its latest state. Also replaces
+// a settlement the other side closed with in an earlier state. Returns the
+// transaction and the height after which the settlement can be paid out.
+pub async fn close(
+    node: &str,
+    secret_key: &SecretKey,
+    record: &ChannelRecord,
+    fee: Option<u64>,
+    confirm_within: u32,
+) -> Result<(Hash, u64), String> {
+    let channel = &record.channel;
+    let contract = match settlement(node, channel).await? {
+        None => Contract::Channel(channel.clone()),
+        Some(Contract::Settlement { state, timeout, .. })
+            if state.sequence >= record.state.state.sequence =>
+        {
+            return Err(format!(
+                "Already closing in state {}, which can be paid out after block {}",
+                state.sequence, timeout
+            ));
+        }
+        Some(settlement) => settlement,
+    };
+    let height = client::node_info(node).await?.height;
+    let close = Close {
+        state: record.state.clone(),
+        timeout: height + channel.dispute + CLOSE_MARGIN,
+    };
+    let destination = close.settlement(channel).address();
+    let timeout = close.timeout;
+    let unlock = Unlock {
+        close: Some(close),
+        ..Unlock::new(contract)
+    };
+    let hash = sweep(node, secret_key, unlock, destination, fee, confirm_within).await?;
+
+    Ok((hash, timeout))
+}
+
+// Pays out the settlement of a closed channel to both sides, with what the
+// state leaves over as the fee
+pub async fn settle(node: &str, secret_key: &SecretKey, channel: &Channel) -> Result<Hash, String> {
+    let Some(settlement) = settlement(node, channel).await? else {
+        return Err("The channel has to be closed first".to_string());
+    };
+    let Contract::Settlement { state, timeout, .. } = &settlement else {
+        return Err("Not a settlement".to_string());
+    };
+    let height = client::node_info(node).await?.height;
+
+    if height < *timeout {
+        return Err(format!("Can be paid out after block {}", timeout));
+    }
+
+    let source = settlement.address();
+    let outputs = state.outputs(channel);
+    let total: u64 = outputs.iter().map(|output| output.amount).sum();
+    let balance = client::balance(node, source).await?;
+
+    if balance <= total as i128 {
+        return Err(format!(
+            "The settlement has ${}, not enough to pay out ${} and a fee",
+            balance, total
+        ));
+    }
+
+    let details = TransactionDetails::to_many(source, &outputs, (balance - total as i128) as u64)
+        .ok_or("Nothing to pay out")?;
+    let signature = transaction::sign(&details, secret_key);
+    let transaction = Transaction::new(details, signature).with_unlock(Unlock::new(settlement));
+    let (hash, _) =
+        client::broadcast_raw_transaction(node, &raw::encode_signed(&transaction)).await?;
+
+    Ok(hash)
+}
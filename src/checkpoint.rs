@@ -0,0 +1,74 @@
+use super::blockchain::{Hash, PublicKey, SecretKey, Signature};
+use fcoin_core::encoding::Encode;
+use fcoin_core::keys;
+
+use serde::{Deserialize, Serialize};
+
+// Signers checkpoint a block every this many blocks...
+pub const CHECKPOINT_INTERVAL: u64 = 10;
+// ...once it is this deep under the tip, so they don't pin a block that
+// could still be orphaned
+pub const CHECKPOINT_DEPTH: u64 = 6;
+
+// Like with messages, keeps checkpoint signatures from passing as anything
+// else the same key signs
+const CHECKPOINT_PREFIX: &[u8] = b"fcoin checkpoint, not a transaction:\n";
+
+// A signer of the network vouching for the block at `height` of the main
+// chain. Nodes never reorganize below the latest one they verified.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub hash: Hash,
+    pub signer: PublicKey,
+    #[serde(with = "fcoin_core::BigArray")]
+    signature: Signature,
+}
+
+fn signed_bytes(height: u64, hash: &Hash) -> Vec<u8> {
+    let mut bytes = CHECKPOINT_PREFIX.to_vec();
+
+    height.encode(&mut bytes);
+    bytes.extend_from_slice(hash);
+
+    bytes
+}
+
+impl Checkpoint {
+    pub fn new(height: u64, hash: Hash, secret_key: &SecretKey) -> Self {
+        Checkpoint {
+            height,
+            hash,
+            signer: keys::public_key(secret_key),
+            signature: keys::sign(secret_key, &signed_bytes(height, &hash)),
+        }
+    }
+
+    // Whether it was signed by one of `signers`
+    pub fn verify(&self, signers: &[PublicKey]) -> Result<(), String> {
+        if !signers.contains(&self.signer) {
+            return Err("Not signed by a checkpoint signer of the network".to_string());
+        }
+
+        if !keys::verify(
+            &self.signer,
+            &signed_bytes(self.height, &self.hash),
+            &self.signature,
+        ) {
+            return Err("Invalid checkpoint signature".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+// Height of the block a signer checkpoints once the tip reaches `height`
+pub fn due(height: u64) -> Option<u64> {
+    let checkpointed = height.checked_sub(CHECKPOINT_DEPTH)?;
+
+    if checkpointed > 0 && checkpointed % CHECKPOINT_INTERVAL == 0 {
+        Some(checkpointed)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,378 @@
+use super::address_index::HistoryEntry;
+use super::alerts::Alert;
+use super::blockchain::{Block, BlockHeader, Hash, PublicKey, Transaction, TransactionStatus};
+use super::deployment::DeploymentInfo;
+use super::framing::{Connection, Frame, RelayPolicy, Traffic};
+use super::keystore::Passphrase;
+use super::memory::MemoryInfo;
+use super::merkle::MerkleProof;
+use super::miner::MiningInfo;
+use super::rpc::{
+    BlockTemplate, MempoolInfo, MempoolTransaction, NodeInfo, PeerInfo, Request, Response, Work,
+};
+use super::stats::ChainStats;
+use super::verify::ChainVerification;
+use super::view::{AssetRecord, NameRecord, Supply};
+use super::wallets::WalletInfo;
+use tokio::net::TcpStream;
+
+const USER_AGENT: &str = concat!("fcoin-client/", env!("CARGO_PKG_VERSION"));
+
+// Sends a single request to a node and waits for its response
+pub async fn call(node_address: &str, request: Request) -> Result<Response, String> {
+    let stream = TcpStream::connect(node_address)
+        .await
+        .map_err(|issue| format!("Could not connect to {}: {}", node_address, issue))?;
+    let (mut writter, mut reader) = Connection::new(stream);
+
+    // Clients have no use for the transactions the node relays
+    Connection::handshake(
+        &mut writter,
+        &mut reader,
+        USER_AGENT,
+        None,
+        RelayPolicy::BlocksOnly,
+    )
+    .await?;
+
+    writter.write(Frame::Request(request)).await;
+
+    loop {
+        match reader.read().await {
+            Some(Frame::Response(Response::Error { message, .. })) => return Err(message),
+            Some(Frame::Response(response)) => return Ok(response),
+            Some(_) => {}
+            None => return Err(format!("{} closed the connection", node_address)),
+        }
+    }
+}
+
+// Along with the status of the transaction when the node already had it
+pub async fn broadcast_raw_transaction(
+    node_address: &str,
+    transaction: &str,
+) -> Result<(Hash, Option<TransactionStatus>), String> {
+    match call(
+        node_address,
+        Request::BroadcastRawTransaction(transaction.to_string()),
+    )
+    .await?
+    {
+        Response::TransactionHash(hash) => Ok((hash, None)),
+        Response::KnownTransaction { hash, status } => Ok((hash, Some(status))),
+        _ => Err("Unexpected response to the broadcast".to_string()),
+    }
+}
+
+pub async fn estimate_fee(node_address: &str, target_blocks: u32) -> Result<u64, String> {
+    match call(node_address, Request::EstimateFee(target_blocks)).await? {
+        Response::FeeRate(rate) => Ok(rate),
+        _ => Err("Unexpected response to the fee estimation".to_string()),
+    }
+}
+
+pub async fn chain_stats(node_address: &str) -> Result<ChainStats, String> {
+    match call(node_address, Request::GetChainStats).await? {
+        Response::ChainStats(stats) => Ok(stats),
+        _ => Err("Unexpected response to the chain stats request".to_string()),
+    }
+}
+
+// The transaction and the hash and height of the block it was mined in
+pub async fn transaction(
+    node_address: &str,
+    hash: Hash,
+) -> Result<(Transaction, Hash, u64), String> {
+    match call(node_address, Request::GetTransaction(hash)).await? {
+        Response::Transaction {
+            transaction,
+            block_hash,
+            height,
+            ..
+        } => Ok((*transaction, block_hash, height)),
+        _ => Err("Unexpected response to the transaction request".to_string()),
+    }
+}
+
+// The header of the block the transaction was mined in, and the proof it
+// is part of it
+pub async fn transaction_proof(
+    node_address: &str,
+    hash: Hash,
+) -> Result<(BlockHeader, MerkleProof), String> {
+    match call(node_address, Request::GetTransactionProof(hash)).await? {
+        Response::TransactionProof { header, proof } => Ok((*header, proof)),
+        _ => Err("Unexpected response to the transaction proof request".to_string()),
+    }
+}
+
+pub async fn node_info(node_address: &str) -> Result<NodeInfo, String> {
+    match call(node_address, Request::GetNodeInfo).await? {
+        Response::NodeInfo(info) => Ok(info),
+        _ => Err("Unexpected response to the node info request".to_string()),
+    }
+}
+
+pub async fn mempool_info(node_address: &str) -> Result<MempoolInfo, String> {
+    match call(node_address, Request::GetMempoolInfo).await? {
+        Response::MempoolInfo(info) => Ok(info),
+        _ => Err("Unexpected response to the mempool info request".to_string()),
+    }
+}
+
+pub async fn raw_mempool(node_address: &str) -> Result<Vec<MempoolTransaction>, String> {
+    match call(node_address, Request::GetRawMempool).await? {
+        Response::RawMempool(transactions) => Ok(transactions),
+        _ => Err("Unexpected response to the mempool request".to_string()),
+    }
+}
+
+pub async fn transaction_status(
+    node_address: &str,
+    hash: Hash,
+) -> Result<TransactionStatus, String> {
+    match call(node_address, Request::GetTransactionStatus(hash)).await? {
+        Response::TransactionStatus(status) => Ok(status),
+        _ => Err("Unexpected response to the transaction status request".to_string()),
+    }
+}
+
+pub async fn balance(node_address: &str, id: PublicKey) -> Result<i128, String> {
+    match call(node_address, Request::GetBalance(id)).await? {
+        Response::Balance(balance) => Ok(balance),
+        _ => Err("Unexpected response to the balance request".to_string()),
+    }
+}
+
+pub async fn net_totals(node_address: &str) -> Result<Traffic, String> {
+    match call(node_address, Request::GetNetTotals).await? {
+        Response::NetTotals(totals) => Ok(totals),
+        _ => Err("Unexpected response to the network totals request".to_string()),
+    }
+}
+
+pub async fn peer_info(node_address: &str) -> Result<Vec<PeerInfo>, String> {
+    match call(node_address, Request::GetPeerInfo).await? {
+        Response::PeerInfo(peers) => Ok(peers),
+        _ => Err("Unexpected response to the peer info request".to_string()),
+    }
+}
+
+// The balance of the address before `from_height`, and every change to it
+// from there on
+pub async fn address_history(
+    node_address: &str,
+    address: PublicKey,
+    from_height: u64,
+) -> Result<(i128, Vec<HistoryEntry>), String> {
+    match call(
+        node_address,
+        Request::GetAddressHistory {
+            address,
+            from_height,
+        },
+    )
+    .await?
+    {
+        Response::AddressHistory {
+            opening_balance,
+            entries,
+        } => Ok((opening_balance, entries)),
+        _ => Err("Unexpected response to the address history request".to_string()),
+    }
+}
+
+pub async fn alerts(node_address: &str) -> Result<Vec<Alert>, String> {
+    match call(node_address, Request::GetAlerts).await? {
+        Response::Alerts(alerts) => Ok(alerts),
+        _ => Err("Unexpected response to the alerts request".to_string()),
+    }
+}
+
+pub async fn memory_info(node_address: &str) -> Result<MemoryInfo, String> {
+    match call(node_address, Request::GetMemoryInfo).await? {
+        Response::MemoryInfo(info) => Ok(info),
+        _ => Err("Unexpected response to the memory info request".to_string()),
+    }
+}
+
+pub async fn supply(node_address: &str) -> Result<Supply, String> {
+    match call(node_address, Request::GetSupply).await? {
+        Response::Supply(supply) => Ok(supply),
+        _ => Err("Unexpected response to the supply request".to_string()),
+    }
+}
+
+pub async fn deployment_info(node_address: &str) -> Result<Vec<DeploymentInfo>, String> {
+    match call(node_address, Request::GetDeploymentInfo).await? {
+        Response::DeploymentInfo(deployments) => Ok(deployments),
+        _ => Err("Unexpected response to the deployment info request".to_string()),
+    }
+}
+
+// `None` when there is nothing to mine
+pub async fn block_template(node_address: &str) -> Result<Option<BlockTemplate>, String> {
+    match call(node_address, Request::GetBlockTemplate).await? {
+        Response::BlockTemplate(template) => Ok(template.map(|template| *template)),
+        _ => Err("Unexpected response to the block template request".to_string()),
+    }
+}
+
+pub async fn submit_block(node_address: &str, block: Block) -> Result<Hash, String> {
+    match call(node_address, Request::SubmitBlock(Box::new(block))).await? {
+        Response::BlockAccepted(hash) => Ok(hash),
+        _ => Err("Unexpected response to the block submission".to_string()),
+    }
+}
+
+// `None` when there is nothing to mine
+pub async fn work(node_address: &str) -> Result<Option<Work>, String> {
+    match call(node_address, Request::GetWork).await? {
+        Response::Work(work) => Ok(work.map(|work| *work)),
+        _ => Err("Unexpected response to the work request".to_string()),
+    }
+}
+
+// The hash of the block the nonce completes
+pub async fn submit_work(node_address: &str, id: u64, nonce: [u8; 32]) -> Result<Hash, String> {
+    match call(node_address, Request::SubmitWork { id, nonce }).await? {
+        Response::BlockAccepted(hash) => Ok(hash),
+        _ => Err("Unexpected response to the work submission".to_string()),
+    }
+}
+
+// With the key of the node without a wallet name. `approval` is needed
+// beyond its spending policy.
+pub async fn sign_raw_transaction(
+    node_address: &str,
+    transaction: &str,
+    wallet: Option<&str>,
+    approval: Option<&str>,
+) -> Result<String, String> {
+    let request = Request::SignRawTransaction {
+        transaction: transaction.to_string(),
+        wallet: wallet.map(str::to_string),
+        approval: approval.map(str::to_string),
+    };
+
+    match call(node_address, request).await? {
+        Response::RawTransaction(signed) => Ok(signed),
+        _ => Err("Unexpected response to the sign raw transaction request".to_string()),
+    }
+}
+
+// Of the node itself without a wallet name
+pub async fn wallet_unlock(
+    node_address: &str,
+    passphrase: &str,
+    timeout: u64,
+    wallet: Option<&str>,
+) -> Result<(), String> {
+    match call(
+        node_address,
+        Request::WalletUnlock {
+            passphrase: Passphrase::new(passphrase),
+            timeout,
+            wallet: wallet.map(str::to_string),
+        },
+    )
+    .await?
+    {
+        Response::WalletUnlocked => Ok(()),
+        _ => Err("Unexpected response to the wallet unlock request".to_string()),
+    }
+}
+
+pub async fn wallet_lock(node_address: &str, wallet: Option<&str>) -> Result<(), String> {
+    let request = Request::WalletLock {
+        wallet: wallet.map(str::to_string),
+    };
+
+    match call(node_address, request).await? {
+        Response::WalletLocked => Ok(()),
+        _ => Err("Unexpected response to the wallet lock request".to_string()),
+    }
+}
+
+pub async fn create_wallet(
+    node_address: &str,
+    name: &str,
+    passphrase: Option<&str>,
+) -> Result<WalletInfo, String> {
+    let request = Request::CreateWallet {
+        name: name.to_string(),
+        passphrase: passphrase.map(Passphrase::new),
+    };
+
+    match call(node_address, request).await? {
+        Response::Wallet(info) => Ok(info),
+        _ => Err("Unexpected response to the create wallet request".to_string()),
+    }
+}
+
+pub async fn load_wallet(node_address: &str, name: &str) -> Result<WalletInfo, String> {
+    match call(node_address, Request::LoadWallet(name.to_string())).await? {
+        Response::Wallet(info) => Ok(info),
+        _ => Err("Unexpected response to the load wallet request".to_string()),
+    }
+}
+
+pub async fn unload_wallet(node_address: &str, name: &str) -> Result<(), String> {
+    match call(node_address, Request::UnloadWallet(name.to_string())).await? {
+        Response::WalletUnloaded => Ok(()),
+        _ => Err("Unexpected response to the unload wallet request".to_string()),
+    }
+}
+
+pub async fn list_wallets(node_address: &str) -> Result<Vec<WalletInfo>, String> {
+    match call(node_address, Request::ListWallets).await? {
+        Response::Wallets(wallets) => Ok(wallets),
+        _ => Err("Unexpected response to the list wallets request".to_string()),
+    }
+}
+
+pub async fn mining_info(node_address: &str) -> Result<MiningInfo, String> {
+    match call(node_address, Request::GetMiningInfo).await? {
+        Response::MiningInfo(info) => Ok(info),
+        _ => Err("Unexpected response to the mining info request".to_string()),
+    }
+}
+
+pub async fn verify_chain(node_address: &str, level: u8) -> Result<ChainVerification, String> {
+    match call(node_address, Request::VerifyChain(level)).await? {
+        Response::ChainVerification(verification) => Ok(verification),
+        _ => Err("Unexpected response to the chain verification request".to_string()),
+    }
+}
+
+// Who holds `name`, if anyone, and the least fee to register it
+pub async fn resolve_name(
+    node_address: &str,
+    name: &str,
+) -> Result<(Option<NameRecord>, u64), String> {
+    match call(node_address, Request::ResolveName(name.to_string())).await? {
+        Response::Name { record, fee } => Ok((record, fee)),
+        _ => Err("Unexpected response to the name resolution".to_string()),
+    }
+}
+
+// Tokens of `asset` held by `id`
+pub async fn asset_balance(node_address: &str, asset: &str, id: PublicKey) -> Result<u64, String> {
+    let request = Request::GetAssetBalance {
+        asset: asset.to_string(),
+        address: id,
+    };
+
+    match call(node_address, request).await? {
+        Response::AssetBalance(balance) => Ok(balance),
+        _ => Err("Unexpected response to the asset balance request".to_string()),
+    }
+}
+
+// `None` when `asset` was never issued
+pub async fn asset(node_address: &str, asset: &str) -> Result<Option<AssetRecord>, String> {
+    match call(node_address, Request::GetAsset(asset.to_string())).await? {
+        Response::Asset(record) => Ok(record),
+        _ => Err("Unexpected response to the asset request".to_string()),
+    }
+}
@@ -0,0 +1,190 @@
+use sha2::Digest;
+use sha2::Sha256;
+
+use serde::{Deserialize, Serialize};
+
+type Hash = [u8; 32];
+
+// Golomb-Rice parameters from BIP158
+const P: u8 = 19;
+const M: u64 = 784931;
+
+// BIP158-style Golomb-coded set of every public key touched by a block.
+// Light clients download these and test their own keys locally, so full
+// nodes never learn which keys they are looking for.
+//
+// Unlike BIP158 items are hashed with SHA-256 (keyed by the block hash)
+// instead of SipHash, as that is what the rest of the node already uses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactFilter {
+    items: u32,
+    data: Vec<u8>,
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bits: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            bits: 0,
+        }
+    }
+
+    fn write_bit(&mut self, bit: bool) {
+        if self.bits.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+
+        self.bits += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for bit in (0..count).rev() {
+            self.write_bit(value >> bit & 1 == 1);
+        }
+    }
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, bits: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.bits / 8)?;
+        let bit = byte & (0x80 >> (self.bits % 8)) != 0;
+
+        self.bits += 1;
+
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, count: u8) -> Option<u64> {
+        let mut value = 0;
+
+        for _ in 0..count {
+            value = value << 1 | self.read_bit()? as u64;
+        }
+
+        Some(value)
+    }
+}
+
+// Maps an item uniformly into [0, range)
+fn hash_to_range(key: &[u8], item: &[u8], range: u64) -> u64 {
+    let digest = Sha256::new().chain(key).chain(item).finalize();
+
+    let mut word = [0; 8];
+    word.copy_from_slice(&digest[0..8]);
+
+    ((u64::from_le_bytes(word) as u128 * range as u128) >> 64) as u64
+}
+
+fn hashed_set<'a>(
+    block_hash: &Hash,
+    items: impl Iterator<Item = &'a [u8]>,
+    count: u32,
+) -> Vec<u64> {
+    let range = count as u64 * M;
+
+    let mut values: Vec<u64> = items
+        .map(|item| hash_to_range(&block_hash[0..16], item, range))
+        .collect();
+
+    values.sort_unstable();
+    values.dedup();
+
+    values
+}
+
+impl CompactFilter {
+    pub fn build(block_hash: &Hash, items: &[&[u8]]) -> Self {
+        let mut unique = items.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+
+        let count = unique.len() as u32;
+        let mut writer = BitWriter::new();
+        let mut last = 0;
+
+        for value in hashed_set(block_hash, unique.into_iter(), count) {
+            let delta = value - last;
+
+            // Quotient in unary, remainder in P bits
+            for _ in 0..delta >> P {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta, P);
+
+            last = value;
+        }
+
+        CompactFilter {
+            items: count,
+            data: writer.bytes,
+        }
+    }
+
+    fn decode(&self) -> Option<Vec<u64>> {
+        let mut reader = BitReader::new(&self.data);
+        let mut values = Vec::with_capacity(self.items as usize);
+        let mut last = 0;
+
+        for _ in 0..self.items {
+            let mut quotient = 0;
+
+            while reader.read_bit()? {
+                quotient += 1;
+            }
+
+            let remainder = reader.read_bits(P)?;
+
+            last += quotient << P | remainder;
+            values.push(last);
+        }
+
+        Some(values)
+    }
+
+    // Whether any of the items might be in the set. False positives happen
+    // about once every M queries, false negatives never.
+    pub fn matches_any(&self, block_hash: &Hash, items: &[&[u8]]) -> bool {
+        if self.items == 0 || items.is_empty() {
+            return false;
+        }
+
+        let values = match self.decode() {
+            Some(values) => values,
+            None => return false,
+        };
+
+        let queries = hashed_set(block_hash, items.iter().copied(), self.items);
+
+        let mut i = 0;
+        let mut j = 0;
+
+        while i < values.len() && j < queries.len() {
+            match values[i].cmp(&queries[j]) {
+                std::cmp::Ordering::Less => i += 1,
+                std::cmp::Ordering::Greater => j += 1,
+                std::cmp::Ordering::Equal => return true,
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,404 @@
+use super::blockchain::{self, Block, BlockHeader, RejectReason};
+use super::lru::Lru;
+use super::network::Emission;
+use super::pow::PowHasher;
+use super::store::ChainStore;
+use fcoin_core::encoding::Encode;
+use fcoin_core::{Hash, PublicKey, SecretKey};
+use num::BigUint;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Targets never move by more than this factor in a single adjustment
+const MAX_RETARGET_FACTOR: u64 = 4;
+// How often a proof of stake node checks whether it won the current slot
+const STAKE_RETRY_DELAY: Duration = Duration::from_secs(1);
+// How many proof of work hashes of verified blocks are kept
+const MAX_CACHED_WORKS: usize = 10_000;
+// Slots past the current one blocks can be produced for, for clocks a little
+// ahead of ours
+const MAX_FUTURE_SLOTS: u64 = 2;
+
+// Two different blocks signed by the same producer for the same slot. Once
+// in the chain, all the coins of the producer are burned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoubleSign {
+    pub first: BlockHeader,
+    pub second: BlockHeader,
+}
+
+impl DoubleSign {
+    pub fn offender(&self) -> &PublicKey {
+        self.first.node_public_key()
+    }
+
+    pub fn hash(&self) -> Hash {
+        Sha256::digest(&self.encoded()).into()
+    }
+}
+
+// The signatures are not part of the header encoding, so they are added
+// here to commit to the whole evidence
+impl Encode for DoubleSign {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for header in [&self.first, &self.second] {
+            header.encode(out);
+            header.signature().encode(out);
+        }
+    }
+}
+
+// Decides who may produce blocks and how they prove it
+pub trait Consensus: Send + Sync {
+    // Checks what can be told from `block` alone, before the chain is
+    // looked at. Runs without holding the node lock.
+    fn check(&self, _block: &Block) -> Result<(), RejectReason> {
+        Ok(())
+    }
+
+    // Checks whoever produced `block` was allowed to, on top of its parent
+    // in `blockchain`, `now` being the network adjusted time
+    fn verify(&self, block: &Block, blockchain: &ChainStore, now: u64) -> Result<(), RejectReason>;
+
+    // One attempt at sealing `block`. When it fails the block is left
+    // ready for the next attempt.
+    fn seal(&self, block: &mut Block, blockchain: &ChainStore, secret_key: &SecretKey) -> bool;
+
+    // How long to wait after a failed attempt
+    fn retry_delay(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+
+    // The slot `header` was produced for, when producers take turns
+    fn slot(&self, _header: &BlockHeader) -> Option<u64> {
+        None
+    }
+
+    // Whether `evidence` proves its offender signed two blocks for the
+    // same slot
+    fn valid_evidence(&self, _evidence: &DoubleSign) -> bool {
+        false
+    }
+
+    // What the hash of the block after `parent` must be below, when there
+    // is such a thing
+    fn target(&self, _blockchain: &ChainStore, _parent: &Hash) -> Option<BigUint> {
+        None
+    }
+
+    // What `header` adds to the weight of its branch, the heaviest branch
+    // being the main chain. By default every block weighs the same, so the
+    // branch filling the most slots wins.
+    fn work(&self, _blockchain: &ChainStore, _header: &BlockHeader) -> BigUint {
+        BigUint::from(1u32)
+    }
+}
+
+// Anyone can produce a block by finding a nonce that makes the proof of
+// work hash of its header meet the target
+pub struct ProofOfWork {
+    hasher: Box<dyn PowHasher>,
+    // Seconds blocks should be apart on average
+    block_time: u64,
+    // Blocks between target adjustments
+    retarget_window: u64,
+    // Target of the block after each parent, so the chain is only gone
+    // through once
+    targets: Mutex<HashMap<Hash, BigUint>>,
+    // Proof of work hashes of the blocks that met their target, by header
+    // hash, so a branch connected again doesn't pay for them twice
+    works: Lru<Hash, Hash>,
+}
+
+impl ProofOfWork {
+    pub fn new(hasher: Box<dyn PowHasher>, block_time: u64, retarget_window: u64) -> Self {
+        ProofOfWork {
+            hasher,
+            block_time,
+            retarget_window: retarget_window.max(1),
+            targets: Mutex::new(HashMap::new()),
+            works: Lru::new(MAX_CACHED_WORKS),
+        }
+    }
+
+    fn work(&self, header: &BlockHeader) -> Hash {
+        self.hasher.hash(&header.encoded())
+    }
+
+    fn meets_target(&self, work: &Hash, blockchain: &ChainStore, parent: &Hash) -> bool {
+        BigUint::from_bytes_le(work) < self.next_target(blockchain, parent)
+    }
+
+    // The target stays the same for `retarget_window` blocks, then moves by
+    // how far the window was from taking `block_time` per block
+    fn next_target(&self, blockchain: &ChainStore, parent: &Hash) -> BigUint {
+        let mut targets = self.targets.lock().unwrap();
+
+        // Back to the newest block we know the target after
+        let mut unknown = Vec::new();
+        let mut current = *parent;
+
+        while !targets.contains_key(&current) {
+            match blockchain.get(&current) {
+                Some(block) => {
+                    unknown.push(current);
+                    current = *block.header().previous_hash();
+                }
+                None if current == [0; 32] => {
+                    targets.insert(current, blockchain::max_target());
+                }
+                // Gets rejected for its unknown parent anyway
+                None => return blockchain::max_target(),
+            }
+        }
+
+        for hash in unknown.into_iter().rev() {
+            let previous = targets[blockchain.get(&hash).unwrap().header().previous_hash()].clone();
+            let height = blockchain.height(&hash).unwrap_or(0);
+
+            let target = if height.is_multiple_of(self.retarget_window) {
+                self.retarget(blockchain, &hash, previous)
+            } else {
+                previous
+            };
+
+            targets.insert(hash, target);
+        }
+
+        targets[parent].clone()
+    }
+
+    // `last` is the last block of the window that just ended
+    fn retarget(&self, blockchain: &ChainStore, last: &Hash, target: BigUint) -> BigUint {
+        let mut first = blockchain.get(last).unwrap();
+
+        for _ in 1..self.retarget_window {
+            match blockchain.get(first.header().previous_hash()) {
+                Some(block) => first = block,
+                None => break,
+            }
+        }
+
+        // Between median times past, as the time of a single block, even
+        // the last of a window, can be set back to make the window look slow
+        let expected = self.block_time * (self.retarget_window - 1).max(1);
+        let actual = blockchain
+            .median_time_past(last)
+            .saturating_sub(blockchain.median_time_past(&first.header().hash()))
+            .clamp(
+                expected / MAX_RETARGET_FACTOR,
+                expected * MAX_RETARGET_FACTOR,
+            )
+            .max(1);
+
+        (target * actual / expected.max(1)).min(blockchain::max_target())
+    }
+}
+
+impl Consensus for ProofOfWork {
+    fn check(&self, block: &Block) -> Result<(), RejectReason> {
+        if block.header().signature().is_some() || !block.slashings().is_empty() {
+            println!("PROOF OF WORK BLOCK HAS PROOF OF STAKE FIELDS");
+
+            return Err(RejectReason::InvalidSeal);
+        }
+
+        Ok(())
+    }
+
+    fn verify(
+        &self,
+        block: &Block,
+        blockchain: &ChainStore,
+        _now: u64,
+    ) -> Result<(), RejectReason> {
+        let header = block.header();
+        let hash = header.hash();
+        let work = self.works.get(&hash).unwrap_or_else(|| self.work(header));
+
+        if !self.meets_target(&work, blockchain, header.previous_hash()) {
+            println!("BLOCK DOES NOT MEET THE PROOF OF WORK TARGET");
+
+            return Err(RejectReason::InvalidProofOfWork);
+        }
+
+        self.works.insert(hash, work);
+
+        Ok(())
+    }
+
+    fn seal(&self, block: &mut Block, blockchain: &ChainStore, _secret_key: &SecretKey) -> bool {
+        println!(
+            "PROOF OF WORK {}",
+            blockchain::HashFmt(self.work(block.header()))
+        );
+
+        if self.meets_target(
+            &self.work(block.header()),
+            blockchain,
+            block.header().previous_hash(),
+        ) {
+            println!("PROOF OF WORK ACCEPTED");
+
+            true
+        } else {
+            println!("PROOF OF WORK WRONG");
+
+            block.header_mut().next_nonce();
+
+            false
+        }
+    }
+
+    fn target(&self, blockchain: &ChainStore, parent: &Hash) -> Option<BigUint> {
+        Some(self.next_target(blockchain, parent))
+    }
+
+    // The hashes it takes on average to meet the target of the block, so a
+    // longer branch mined at easier targets doesn't outweigh a harder one
+    fn work(&self, blockchain: &ChainStore, header: &BlockHeader) -> BigUint {
+        let target = self.next_target(blockchain, header.previous_hash());
+
+        (BigUint::from(1u32) << 256u32) / target.max(BigUint::from(1u32))
+    }
+}
+
+// Time is split in slots, and in each slot holders of coins win the right
+// to produce a block with a chance proportional to their balance (their
+// stake). Blocks are signed by their producer, and signing two blocks for
+// the same slot gets the producer's coins burned.
+pub struct ProofOfStake {
+    // Seconds each slot lasts
+    slot_duration: u64,
+    emission: Emission,
+}
+
+impl ProofOfStake {
+    pub fn new(slot_duration: u64, emission: Emission) -> Self {
+        ProofOfStake {
+            slot_duration: slot_duration.max(1),
+            emission,
+        }
+    }
+
+    fn slot_of(&self, header: &BlockHeader) -> u64 {
+        header.time() / self.slot_duration
+    }
+
+    // Stakes are taken as of the parent block, so they can't be changed
+    // by the block that is being produced
+    fn is_eligible(&self, header: &BlockHeader, blockchain: &ChainStore) -> bool {
+        let parent = header.previous_hash();
+        let height = blockchain.height(parent).unwrap_or(0);
+
+        // The first block has nobody with a stake to produce it
+        if height == 0 {
+            return true;
+        }
+
+        let stake = blockchain::balance_at(blockchain, parent, header.node_public_key())
+            .unwrap_or(0)
+            .max(0) as u64;
+        // Burned coins are still counted, which only makes slots a bit
+        // more likely to go empty
+        let total_stake = self.emission.issued(height);
+
+        // Won when the ticket, taken as a fraction of 2^256, falls below
+        // the share of the stake
+        let ticket = BigUint::from_bytes_le(&self.ticket(header));
+
+        ticket * BigUint::from(total_stake) < (BigUint::from(1u32) << 256u32) * stake
+    }
+
+    // Random but fixed for a producer in a slot on top of a parent, so it
+    // can't be ground by changing the block contents
+    fn ticket(&self, header: &BlockHeader) -> Hash {
+        let mut bytes = Vec::new();
+
+        header.previous_hash().encode(&mut bytes);
+        (self.slot_of(header)).encode(&mut bytes);
+        header.node_public_key().encode(&mut bytes);
+
+        Sha256::digest(&bytes).into()
+    }
+}
+
+impl Consensus for ProofOfStake {
+    fn check(&self, block: &Block) -> Result<(), RejectReason> {
+        if !block.header().has_valid_signature() {
+            println!("BLOCK IS NOT SIGNED BY ITS PRODUCER");
+
+            return Err(RejectReason::InvalidSeal);
+        }
+
+        Ok(())
+    }
+
+    fn verify(&self, block: &Block, blockchain: &ChainStore, now: u64) -> Result<(), RejectReason> {
+        let header = block.header();
+
+        // Otherwise producers could look for a slot they win ahead of time,
+        // as far ahead as block times are allowed to be
+        if self.slot_of(header) > now / self.slot_duration + MAX_FUTURE_SLOTS {
+            println!("BLOCK IS FOR A SLOT THAT HASN'T COME YET");
+
+            return Err(RejectReason::TimeTooNew);
+        }
+
+        if let Some(parent) = blockchain.get(header.previous_hash()) {
+            if self.slot_of(header) <= self.slot_of(parent.header()) {
+                println!("BLOCK IS NOT IN A LATER SLOT THAN ITS PARENT");
+
+                return Err(RejectReason::InvalidSeal);
+            }
+        }
+
+        if !self.is_eligible(header, blockchain) {
+            println!("BLOCK PRODUCER DID NOT WIN THE SLOT");
+
+            return Err(RejectReason::InvalidSeal);
+        }
+
+        Ok(())
+    }
+
+    fn seal(&self, block: &mut Block, blockchain: &ChainStore, secret_key: &SecretKey) -> bool {
+        let parent_slot = blockchain
+            .get(block.header().previous_hash())
+            .map(|parent| self.slot_of(parent.header()));
+
+        if parent_slot >= Some(self.slot_of(block.header()))
+            || !self.is_eligible(block.header(), blockchain)
+        {
+            return false;
+        }
+
+        block.header_mut().sign(secret_key);
+
+        println!("WON SLOT {}", self.slot_of(block.header()));
+
+        true
+    }
+
+    fn retry_delay(&self) -> Duration {
+        STAKE_RETRY_DELAY
+    }
+
+    fn slot(&self, header: &BlockHeader) -> Option<u64> {
+        Some(self.slot_of(header))
+    }
+
+    fn valid_evidence(&self, evidence: &DoubleSign) -> bool {
+        let (first, second) = (&evidence.first, &evidence.second);
+
+        first.node_public_key() == second.node_public_key()
+            && self.slot_of(first) == self.slot_of(second)
+            && first.hash() != second.hash()
+            && first.has_valid_signature()
+            && second.has_valid_signature()
+    }
+}
@@ -0,0 +1,152 @@
+use fcoin_core::{address, PublicKey};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// The contact book of the wallet: labels for the addresses we deal with,
+// kept in the contacts file as label to address. `@label` stands for the
+// address wherever `send-fcoin` takes one, and labels show in the history
+// export. Import and export use `label,address` lines.
+
+// Labels end up in CSV files, so only plain ones are allowed
+const MAX_LABEL_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Default)]
+pub struct Contacts {
+    addresses: BTreeMap<String, PublicKey>,
+}
+
+impl Contacts {
+    pub fn load(path: &str) -> Result<Self, String> {
+        match std::fs::read(path) {
+            Ok(content) => decode(&content)
+                .map_err(|issue| format!("Invalid contacts file {}: {}", path, issue)),
+            Err(issue) if issue.kind() == std::io::ErrorKind::NotFound => Ok(Contacts::default()),
+            Err(issue) => Err(format!("Could not read {}: {}", path, issue)),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let encoded: BTreeMap<&str, String> = self
+            .addresses
+            .iter()
+            .map(|(label, public_key)| (label.as_str(), address::encode(public_key)))
+            .collect();
+        let content = serde_json::to_vec_pretty(&encoded).map_err(|issue| issue.to_string())?;
+
+        super::backup::write_atomically(path, &content)
+    }
+
+    // Replaces the address a label had, if any
+    pub fn add(&mut self, label: &str, public_key: PublicKey) -> Result<(), String> {
+        check_label(label)?;
+
+        self.addresses.insert(label.to_string(), public_key);
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, label: &str) -> Result<(), String> {
+        self.addresses
+            .remove(label)
+            .map(|_| ())
+            .ok_or_else(|| format!("No contact @{}", label))
+    }
+
+    pub fn get(&self, label: &str) -> Option<&PublicKey> {
+        self.addresses.get(label)
+    }
+
+    // The first label of an address, by name
+    pub fn label(&self, public_key: &PublicKey) -> Option<&str> {
+        self.addresses
+            .iter()
+            .find(|(_, address)| *address == public_key)
+            .map(|(label, _)| label.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PublicKey)> {
+        self.addresses
+            .iter()
+            .map(|(label, public_key)| (label.as_str(), public_key))
+    }
+
+    // `label,address` lines, sorted by label
+    pub fn export(&self) -> String {
+        let mut csv = String::from("label,address\n");
+
+        for (label, public_key) in self.iter() {
+            writeln!(csv, "{},{}", label, address::encode(public_key)).unwrap();
+        }
+
+        csv
+    }
+
+    // Adds the contacts of an export, keeping ours when a label is taken
+    // by another address. Returns the labels added and those skipped.
+    pub fn import(&mut self, csv: &str) -> Result<(Vec<String>, Vec<String>), String> {
+        let mut added = Vec::new();
+        let mut skipped = Vec::new();
+
+        for (number, line) in csv.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || (number == 0 && line == "label,address") {
+                continue;
+            }
+
+            let (label, encoded) = line
+                .split_once(',')
+                .ok_or_else(|| format!("Line {} is not label,address", number + 1))?;
+            let label = label.trim().trim_start_matches('@');
+            let public_key = address::decode(encoded.trim())
+                .map_err(|issue| format!("Line {}: {}", number + 1, issue))?;
+
+            check_label(label).map_err(|issue| format!("Line {}: {}", number + 1, issue))?;
+
+            match self.addresses.get(label) {
+                Some(known) if *known == public_key => {}
+                Some(_) => skipped.push(label.to_string()),
+                None => {
+                    self.addresses.insert(label.to_string(), public_key);
+                    added.push(label.to_string());
+                }
+            }
+        }
+
+        Ok((added, skipped))
+    }
+}
+
+// `@label` for a contact, anything else is left to the caller
+pub fn parse(text: &str) -> Option<&str> {
+    text.strip_prefix('@')
+}
+
+pub fn decode(content: &[u8]) -> Result<Contacts, String> {
+    let encoded: BTreeMap<String, String> =
+        serde_json::from_slice(content).map_err(|issue| issue.to_string())?;
+    let mut contacts = Contacts::default();
+
+    for (label, encoded) in encoded {
+        contacts.add(&label, address::decode(&encoded)?)?;
+    }
+
+    Ok(contacts)
+}
+
+fn check_label(label: &str) -> Result<(), String> {
+    let valid = !label.is_empty()
+        && label.len() <= MAX_LABEL_LENGTH
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if !valid {
+        return Err(format!(
+            "Invalid label {:?}: use up to {} letters, digits, -, _ and .",
+            label, MAX_LABEL_LENGTH
+        ));
+    }
+
+    Ok(())
+}
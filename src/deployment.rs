@@ -0,0 +1,244 @@
+use super::store::ChainStore;
+use fcoin_core::encoding::Encode;
+use fcoin_core::Hash;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+// Rule changes miners vote on, version bits style. Each deployment of the
+// network has a bit of the block header version, which miners that support
+// it set. Blocks are tallied by retarget window: once enough blocks of a
+// window signal for a deployment it locks in, and its rules activate a
+// window later. Deployments that don't lock in by their timeout fail.
+
+// A rule change, from the `[[network.deployments]]` tables
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Deployment {
+    pub name: String,
+    // Of the block version, 0 to 31
+    pub bit: u8,
+    // First block height signaling counts from
+    pub start_height: u64,
+    // Fails when not locked in by the window starting at this height
+    pub timeout_height: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeploymentState {
+    // Before its start height
+    Defined,
+    // Blocks signal for it
+    Started,
+    // Enough blocks signaled, active from the next window
+    LockedIn,
+    Active,
+    // Timed out before locking in
+    Failed,
+}
+
+// Where a deployment stands as of a block, see `Request::GetDeploymentInfo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentInfo {
+    pub name: String,
+    pub bit: u8,
+    pub start_height: u64,
+    pub timeout_height: u64,
+    // For the next block
+    pub state: DeploymentState,
+    // Blocks of the current window signaling so far, out of `window`, and
+    // how many it takes to lock in
+    pub signals: u64,
+    pub threshold: u64,
+    pub window: u64,
+}
+
+impl Encode for Deployment {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.name.encode(out);
+        self.bit.encode(out);
+        self.start_height.encode(out);
+        self.timeout_height.encode(out);
+    }
+}
+
+impl Deployment {
+    pub fn signals(&self, version: u32) -> bool {
+        version.checked_shr(self.bit as u32).unwrap_or(0) & 1 == 1
+    }
+
+    // State during the window starting at `height`, from the state during
+    // the previous one, in which `signals` blocks signaled
+    fn next(
+        &self,
+        state: DeploymentState,
+        signals: u64,
+        threshold: u64,
+        height: u64,
+    ) -> DeploymentState {
+        match state {
+            DeploymentState::Defined if height >= self.timeout_height => DeploymentState::Failed,
+            DeploymentState::Defined if height >= self.start_height => DeploymentState::Started,
+            DeploymentState::Started if signals >= threshold => DeploymentState::LockedIn,
+            DeploymentState::Started if height >= self.timeout_height => DeploymentState::Failed,
+            DeploymentState::LockedIn => DeploymentState::Active,
+            state => state,
+        }
+    }
+}
+
+pub struct Deployments {
+    deployments: Vec<Deployment>,
+    // Blocks tallied together, the retarget window of the network
+    window: u64,
+    // Blocks of a window that have to signal for a deployment to lock in
+    threshold: u64,
+    // States during the window after the last block of each window, so
+    // the chain is only gone through once
+    states: Mutex<HashMap<Hash, Vec<DeploymentState>>>,
+}
+
+impl Deployments {
+    // `threshold` is in percent of the blocks of a window
+    pub fn new(deployments: Vec<Deployment>, window: u64, threshold: u64) -> Self {
+        let window = window.max(1);
+
+        Deployments {
+            deployments,
+            window,
+            threshold: (window * threshold.min(100)).div_ceil(100),
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Of each deployment, for the block after `parent`
+    pub fn states(&self, blockchain: &ChainStore, parent: &Hash) -> Vec<DeploymentState> {
+        let (last, _) = self.window_start(blockchain, parent);
+
+        self.states_after(blockchain, &last)
+    }
+
+    // Whether the rules of the deployment named `name` apply to the block
+    // after `parent`. Deployments the network doesn't have never do.
+    pub fn is_active(&self, name: &str, blockchain: &ChainStore, parent: &Hash) -> bool {
+        let states = self.states(blockchain, parent);
+
+        self.deployments
+            .iter()
+            .zip(states)
+            .any(|(deployment, state)| deployment.name == name && state == DeploymentState::Active)
+    }
+
+    // Version of a block after `parent`, signaling for the deployments
+    // named in `supported` that are waiting for signals
+    pub fn version(&self, blockchain: &ChainStore, parent: &Hash, supported: &[String]) -> u32 {
+        self.deployments
+            .iter()
+            .zip(self.states(blockchain, parent))
+            .filter(|(deployment, state)| {
+                state == &DeploymentState::Started && supported.contains(&deployment.name)
+            })
+            .fold(0, |version, (deployment, _)| {
+                version | 1u32.checked_shl(deployment.bit as u32).unwrap_or(0)
+            })
+    }
+
+    pub fn info(&self, blockchain: &ChainStore, parent: &Hash) -> Vec<DeploymentInfo> {
+        let (last, versions) = self.window_start(blockchain, parent);
+
+        self.deployments
+            .iter()
+            .zip(self.states_after(blockchain, &last))
+            .map(|(deployment, state)| DeploymentInfo {
+                name: deployment.name.clone(),
+                bit: deployment.bit,
+                start_height: deployment.start_height,
+                timeout_height: deployment.timeout_height,
+                state,
+                signals: versions
+                    .iter()
+                    .filter(|version| deployment.signals(**version))
+                    .count() as u64,
+                threshold: self.threshold,
+                window: self.window,
+            })
+            .collect()
+    }
+
+    // The last block of the window before the one the block after `parent`
+    // is in, and the versions of the blocks in between
+    fn window_start(&self, blockchain: &ChainStore, parent: &Hash) -> (Hash, Vec<u32>) {
+        let mut current = *parent;
+        let mut versions = Vec::new();
+
+        while let Some(block) = blockchain.get(&current) {
+            if blockchain
+                .height(&current)
+                .unwrap_or(0)
+                .is_multiple_of(self.window)
+            {
+                break;
+            }
+
+            versions.push(block.header().version());
+            current = *block.header().previous_hash();
+        }
+
+        (current, versions)
+    }
+
+    // During the window after the one `last` ends
+    fn states_after(&self, blockchain: &ChainStore, last: &Hash) -> Vec<DeploymentState> {
+        let mut states = self.states.lock().unwrap();
+
+        // Back to the newest window we know the states after
+        let mut unknown = Vec::new();
+        let mut current = *last;
+
+        while !states.contains_key(&current) {
+            match blockchain.get(&current) {
+                Some(block) => {
+                    unknown.push(current);
+
+                    let (previous, _) =
+                        self.window_start(blockchain, block.header().previous_hash());
+
+                    current = previous;
+                }
+                None => {
+                    states.insert(
+                        current,
+                        vec![DeploymentState::Defined; self.deployments.len()],
+                    );
+                }
+            }
+        }
+
+        for hash in unknown.into_iter().rev() {
+            let block = blockchain.get(&hash).unwrap();
+            let (previous, mut versions) =
+                self.window_start(blockchain, block.header().previous_hash());
+            let height = blockchain.height(&hash).unwrap_or(0) + 1;
+
+            versions.push(block.header().version());
+
+            let next = self
+                .deployments
+                .iter()
+                .zip(&states[&previous])
+                .map(|(deployment, state)| {
+                    let signals = versions
+                        .iter()
+                        .filter(|version| deployment.signals(**version))
+                        .count() as u64;
+
+                    deployment.next(*state, signals, self.threshold, height)
+                })
+                .collect();
+
+            states.insert(hash, next);
+        }
+
+        states[last].clone()
+    }
+}
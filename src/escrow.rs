@@ -0,0 +1,188 @@
+use super::blockchain::{Hash, PublicKey, Transaction, TransactionDetails};
+use super::client;
+use fcoin_core::contract::{Contract, ContractSignature, Unlock};
+use fcoin_core::predicate::{Condition, Predicate, Witness};
+use fcoin_core::{keys, raw, transaction, SecretKey};
+
+// Escrows between a buyer and a seller, with an arbiter for when they
+// disagree. The buyer pays the coins to a predicate any two of the three can
+// spend from: buyer and seller release them to the seller or refund them to
+// the buyer when they agree, and the arbiter sides with one of them when
+// they don't. A spend is signed by one side and passed, partially signed,
+// to another to add theirs.
+
+// Two of the three parties
+const REQUIRED_SIGNATURES: u64 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Escrow {
+    pub buyer: PublicKey,
+    pub seller: PublicKey,
+    pub arbiter: PublicKey,
+    // Height it was created at, which tells escrows between the same
+    // parties apart
+    pub created_at: u64,
+}
+
+impl Escrow {
+    pub async fn new(
+        node: &str,
+        buyer: PublicKey,
+        seller: PublicKey,
+        arbiter: PublicKey,
+    ) -> Result<Self, String> {
+        Ok(Escrow {
+            buyer,
+            seller,
+            arbiter,
+            created_at: client::node_info(node).await?.height,
+        })
+    }
+
+    // Spendable by two of the parties, from the height it was created at,
+    // which has always passed
+    pub fn contract(&self) -> Contract {
+        let parties = vec![self.buyer, self.seller, self.arbiter];
+
+        Contract::Predicate(
+            Predicate::multisig(REQUIRED_SIGNATURES, parties)
+                .and(Predicate::valid_from(self.created_at)),
+        )
+    }
+
+    // `None` when the contract isn't an escrow
+    pub fn from_contract(contract: &Contract) -> Option<Self> {
+        let conditions = match contract {
+            Contract::Predicate(Predicate(conditions)) => conditions,
+            _ => return None,
+        };
+
+        match &conditions[..] {
+            [Condition::Multisig { keys, .. }, Condition::ValidFrom(created_at), Condition::All(_)] =>
+            {
+                let escrow = match keys[..] {
+                    [buyer, seller, arbiter] => Escrow {
+                        buyer,
+                        seller,
+                        arbiter,
+                        created_at: *created_at,
+                    },
+                    _ => return None,
+                };
+
+                Some(escrow).filter(|escrow| &escrow.contract() == contract)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_party(&self, id: &PublicKey) -> bool {
+        [self.buyer, self.seller, self.arbiter].contains(id)
+    }
+}
+
+// Adds our signature to a spend from an escrow
+fn add_signature(transaction: Transaction, secret_key: &SecretKey) -> Transaction {
+    let details = transaction.details().clone();
+    let signature = transaction::sign(&details, secret_key);
+    let mut unlock = transaction.unlock().cloned().unwrap();
+    let witness = unlock.witness.get_or_insert_with(Witness::default);
+
+    witness
+        .signatures
+        .retain(|(key, _)| key != &keys::public_key(secret_key));
+    witness
+        .signatures
+        .push((keys::public_key(secret_key), ContractSignature(signature)));
+
+    Transaction::new(details, signature).with_unlock(unlock)
+}
+
+// Everything in the escrow to `destination`, signed by us alone
+pub async fn spend(
+    node: &str,
+    secret_key: &SecretKey,
+    escrow: &Escrow,
+    destination: PublicKey,
+    fee: Option<u64>,
+    confirm_within: u32,
+) -> Result<Transaction, String> {
+    if !escrow.is_party(&keys::public_key(secret_key)) {
+        return Err("We are not a party to the escrow".to_string());
+    }
+
+    let contract = escrow.contract();
+    let source = contract.address();
+    let balance = client::balance(node, source).await?;
+    let unsigned = |amount, fee| {
+        let details = TransactionDetails::new(source, destination, amount, fee);
+        let signature = transaction::sign(&details, secret_key);
+
+        Transaction::new(details, signature).with_unlock(Unlock::new(contract.clone()))
+    };
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+            // With the signatures of both sides, the other by any key
+            let signed = add_signature(
+                add_signature(unsigned(0, 0), secret_key),
+                &SecretKey::zeroed(),
+            );
+
+            (signed.size() * rate).div_ceil(1000)
+        }
+    };
+
+    if balance <= fee as i128 {
+        return Err(format!(
+            "Nothing to spend: the escrow has ${} and the fee is ${}",
+            balance, fee
+        ));
+    }
+
+    Ok(add_signature(
+        unsigned((balance - fee as i128) as u64, fee),
+        secret_key,
+    ))
+}
+
+// Adds our signature to a spend another party signed, which is then ready
+// to be broadcast. Checks it pays `destination` from `escrow`.
+pub fn cosign(
+    partial: &str,
+    secret_key: &SecretKey,
+    escrow: &Escrow,
+    destination: &PublicKey,
+) -> Result<Transaction, String> {
+    let transaction = raw::decode_signed(partial)?;
+
+    if transaction.unlock().map(|unlock| &unlock.contract) != Some(&escrow.contract()) {
+        return Err("The transaction doesn't spend from the escrow".to_string());
+    }
+
+    if transaction.outputs().len() != 1 || transaction.destination() != destination {
+        return Err("The transaction doesn't pay where it should".to_string());
+    }
+
+    if !escrow.is_party(&keys::public_key(secret_key)) {
+        return Err("We are not a party to the escrow".to_string());
+    }
+
+    let signed = add_signature(transaction, secret_key);
+
+    if !signed.has_valid_signature() {
+        return Err("The other signature on the transaction is not valid".to_string());
+    }
+
+    Ok(signed)
+}
+
+// Sends a fully signed spend to the node
+pub async fn broadcast(node: &str, transaction: &Transaction) -> Result<Hash, String> {
+    let (hash, _) =
+        client::broadcast_raw_transaction(node, &raw::encode_signed(transaction)).await?;
+
+    Ok(hash)
+}
@@ -0,0 +1,83 @@
+use super::blockchain::{Block, Hash};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+// How many events a slow subscriber can fall behind before missing some
+const EVENT_BUS_CAPACITY: usize = 1024;
+
+// What happens to the chain and the mempool, for the subsystems that need
+// to follow along without `blockchain.rs` knowing about them
+#[derive(Debug, Clone)]
+pub enum ChainEvent {
+    // Added to the tip of the main chain, at `height`
+    Connected {
+        block: Arc<Block>,
+        height: u64,
+    },
+    // Removed from the tip of the main chain, was at `height`
+    Disconnected {
+        block: Arc<Block>,
+        height: u64,
+    },
+    // The main chain moved to another branch. Sent after the blocks of the
+    // old branch were disconnected and the ones of the new connected.
+    Reorged {
+        old_tip: Hash,
+        new_tip: Hash,
+        // Blocks disconnected from the old branch
+        depth: u64,
+    },
+    MempoolAdded {
+        hash: Hash,
+        origin: Option<SocketAddr>,
+    },
+    // Dropped from the mempool without being mined
+    MempoolExpired {
+        hash: Hash,
+        origin: Option<SocketAddr>,
+    },
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<ChainEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+
+        EventBus { sender }
+    }
+
+    // Nobody listening is fine
+    pub fn publish(&self, event: ChainEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    // Gets the events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Waits for the next event, skipping over the ones we were too slow to get.
+// `None` once the bus is gone.
+pub async fn next(events: &mut broadcast::Receiver<ChainEvent>) -> Option<ChainEvent> {
+    loop {
+        match events.recv().await {
+            Ok(event) => return Some(event),
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                println!("SUBSCRIBER MISSED {} EVENTS", missed)
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
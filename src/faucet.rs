@@ -0,0 +1,190 @@
+use super::blockchain::{self, Node, PublicKey, Transaction, TransactionDetails};
+use super::ratelimit::ClientIp;
+use fcoin_core::{address, keys, transaction};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::Mutex;
+
+// Faucet payments are only valid for this many blocks, which also keeps
+// two payments of the same amount to the same address apart
+const EXPIRY_BLOCKS: u64 = 100;
+// Fee estimates aim to be confirmed within this many blocks
+const CONFIRMATION_TARGET: u32 = 6;
+
+// The `[faucet]` table of the configuration file
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaucetSettings {
+    // Sent to every address that asks
+    pub amount: u64,
+    // Defaults to the fee estimated by the node
+    pub fee: Option<u64>,
+    // Seconds before the same address can ask again
+    #[serde(default = "default_address_cooldown")]
+    pub address_cooldown: u64,
+    // Seconds before someone from the same IP can ask again
+    #[serde(default = "default_ip_cooldown")]
+    pub ip_cooldown: u64,
+}
+
+fn default_address_cooldown() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_ip_cooldown() -> u64 {
+    60 * 60
+}
+
+// Gives coins of a test network away from the node's own balance, so
+// whoever tries it out doesn't have to mine first
+pub struct Faucet {
+    settings: FaucetSettings,
+    // When we last paid each address and IP
+    paid_addresses: HashMap<PublicKey, u64>,
+    paid_ips: HashMap<IpAddr, u64>,
+}
+
+#[derive(Deserialize)]
+struct FaucetRequest {
+    address: String,
+}
+
+#[derive(Serialize)]
+struct FaucetPayment {
+    hash: String,
+    amount: u64,
+}
+
+type Error = (StatusCode, String);
+
+#[derive(Clone)]
+struct FaucetState {
+    node: Arc<Mutex<Node>>,
+    faucet: Arc<Mutex<Faucet>>,
+}
+
+impl Faucet {
+    pub fn new(settings: FaucetSettings) -> Self {
+        Faucet {
+            settings,
+            paid_addresses: HashMap::new(),
+            paid_ips: HashMap::new(),
+        }
+    }
+
+    // Seconds left before `address` or `ip` can be paid again, if any
+    fn cooldown(&mut self, address: &PublicKey, ip: &IpAddr, now: u64) -> Option<u64> {
+        let address_cooldown = self.settings.address_cooldown;
+        let ip_cooldown = self.settings.ip_cooldown;
+
+        self.paid_addresses
+            .retain(|_, paid| *paid + address_cooldown > now);
+        self.paid_ips.retain(|_, paid| *paid + ip_cooldown > now);
+
+        let address_left = self
+            .paid_addresses
+            .get(address)
+            .map(|paid| paid + address_cooldown - now);
+        let ip_left = self.paid_ips.get(ip).map(|paid| paid + ip_cooldown - now);
+
+        address_left.max(ip_left)
+    }
+
+    fn paid(&mut self, address: PublicKey, ip: IpAddr, now: u64) {
+        self.paid_addresses.insert(address, now);
+        self.paid_ips.insert(ip, now);
+    }
+}
+
+// POST /faucet, to be served along with the REST API
+pub fn router(node: Arc<Mutex<Node>>, faucet: Faucet) -> Router {
+    Router::new()
+        .route("/faucet", post(faucet_requested))
+        .with_state(FaucetState {
+            node,
+            faucet: Arc::new(Mutex::new(faucet)),
+        })
+}
+
+async fn faucet_requested(
+    State(state): State<FaucetState>,
+    client: Option<Extension<ClientIp>>,
+    Json(request): Json<FaucetRequest>,
+) -> Result<Json<FaucetPayment>, Error> {
+    // Only missing behind a proxy that didn't forward it, and it is what
+    // keeps anyone from draining the faucet
+    let Some(Extension(ClientIp(ip))) = client else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Could not tell where the request came from".to_string(),
+        ));
+    };
+    let destination =
+        address::decode(&request.address).map_err(|issue| (StatusCode::BAD_REQUEST, issue))?;
+    let now = blockchain::timestamp();
+    // Held until we paid, so the same address can't be paid twice
+    let mut faucet = state.faucet.lock().await;
+
+    if let Some(left) = faucet.cooldown(&destination, &ip, now) {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("Try again in {} seconds", left),
+        ));
+    }
+
+    let transaction = {
+        let node = state.node.lock().await;
+        let secret_key = node.secret_key().map_err(|issue| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("The faucet could not pay: {}", issue),
+            )
+        })?;
+        let source = keys::public_key(secret_key);
+        let amount = faucet.settings.amount;
+
+        if source == destination {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "That is the address of the faucet".to_string(),
+            ));
+        }
+
+        let fee = faucet.settings.fee.unwrap_or_else(|| {
+            TransactionDetails::new(source, destination, amount, 0)
+                .fee_for_rate(node.estimate_fee(CONFIRMATION_TARGET))
+        });
+        let details = TransactionDetails::new(source, destination, amount, fee)
+            .with_expiry(Some(node.height() + EXPIRY_BLOCKS));
+
+        Transaction::new(details.clone(), transaction::sign(&details, secret_key))
+    };
+
+    let hash = transaction.hash();
+    let amount = transaction.amount();
+
+    blockchain::transaction_received(state.node.clone(), transaction, None, None)
+        .await
+        .map_err(|reason| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("The faucet could not pay: {}", reason),
+            )
+        })?;
+
+    faucet.paid(destination, ip, now);
+
+    println!("FAUCET PAID ${} TO {} FOR {}", amount, request.address, ip);
+
+    Ok(Json(FaucetPayment {
+        hash: blockchain::HashFmt(hash).to_string(),
+        amount,
+    }))
+}
@@ -0,0 +1,446 @@
+use clap::{Parser, Subcommand};
+use fcoin::blockchain::{Block, Hash, Output, PublicKey};
+use fcoin::client;
+use fcoin::keystore::Passphrase;
+use fcoin::rpc::{Request, Response, RpcSettings};
+use fcoin_core::{address, hex};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::Write;
+use zeroize::Zeroizing;
+
+const NODE_ADDRESS: &str = "localhost:7123";
+// Where the node run from the current directory is configured
+const CONFIGURATION_PATH: &str = "fcoin.toml";
+
+// Calls the RPC methods of a node, each subcommand being the method of the
+// same name, and prints the answer as JSON
+#[derive(Parser)]
+#[command(name = "fcoin-cli", about = "Calls the RPC methods of a fcoin node")]
+struct Arguments {
+    /// host:port of the node. Defaults to the RPC port of the node configured
+    /// in ./fcoin.toml, or to localhost:7123 without one.
+    #[arg(long)]
+    rpcurl: Option<String>,
+    #[command(subcommand)]
+    method: Method,
+}
+
+// One per `rpc::Request`. Addresses can also be given as hex public keys,
+// which is how they print.
+#[derive(Subcommand)]
+#[command(rename_all = "lower")]
+enum Method {
+    /// Builds an unsigned transaction paying each address:amount
+    CreateRawTransaction {
+        source: String,
+        #[arg(required = true)]
+        outputs: Vec<String>,
+        #[arg(long)]
+        fee: u64,
+        #[arg(long)]
+        expires_at: Option<u64>,
+    },
+    /// Signs with the key of the node, or of a loaded wallet
+    SignRawTransaction {
+        transaction: String,
+        #[arg(long)]
+        wallet: Option<String>,
+        /// Hex signature of an approver, beyond the spending policy
+        #[arg(long)]
+        approval: Option<String>,
+    },
+    BroadcastRawTransaction {
+        transaction: String,
+    },
+    /// Fee rate per 1000 bytes to be confirmed within that many blocks
+    EstimateFee {
+        target_blocks: u32,
+    },
+    GetChainStats,
+    GetTransaction {
+        hash: String,
+    },
+    GetTransactionProof {
+        hash: String,
+    },
+    GetNodeInfo,
+    GetMempoolInfo,
+    GetRawMempool,
+    GetTransactionStatus {
+        hash: String,
+    },
+    GetBalance {
+        address: String,
+    },
+    GetNetTotals,
+    GetPeerInfo,
+    GetAddressHistory {
+        address: String,
+        #[arg(long, default_value_t = 0)]
+        from_height: u64,
+    },
+    GetAlerts,
+    GetMemoryInfo,
+    VerifyChain {
+        #[arg(default_value_t = 1)]
+        level: u8,
+    },
+    /// Who holds a name, without the `name/` prefix
+    ResolveName {
+        name: String,
+    },
+    GetAssetBalance {
+        asset: String,
+        address: String,
+    },
+    GetAsset {
+        asset: String,
+    },
+    GetSupply,
+    GetDeploymentInfo,
+    GetBlockTemplate,
+    /// Submits the block in a JSON file
+    SubmitBlock {
+        file: String,
+    },
+    GetMiningInfo,
+    GetWork,
+    SubmitWork {
+        id: u64,
+        /// In hex
+        nonce: String,
+    },
+    /// Asks for the passphrase
+    WalletUnlock {
+        /// Seconds before it locks again
+        #[arg(long, default_value_t = 300)]
+        timeout: u64,
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    WalletLock {
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    CreateWallet {
+        name: String,
+        /// Asks for a passphrase to encrypt its key with
+        #[arg(long)]
+        encrypt: bool,
+    },
+    LoadWallet {
+        name: String,
+    },
+    UnloadWallet {
+        name: String,
+    },
+    ListWallets,
+}
+
+// The little of fcoin.toml needed to find the node
+#[derive(Deserialize)]
+struct NodeConfiguration {
+    port: u16,
+    #[serde(default)]
+    rpc: RpcSettings,
+}
+
+#[tokio::main]
+async fn main() {
+    let arguments = Arguments::parse();
+    let node_address = arguments.rpcurl.unwrap_or_else(node_address);
+
+    let result = match request(arguments.method) {
+        Ok(request) => client::call(&node_address, request).await,
+        Err(issue) => Err(issue),
+    };
+
+    match result {
+        Ok(response) => println!(
+            "{}",
+            serde_json::to_string_pretty(&readable(answer(response))).unwrap()
+        ),
+        Err(issue) => {
+            eprintln!("{}", issue);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The node configured in the current directory, as `fcoin` commands find it
+fn node_address() -> String {
+    let configuration = std::fs::read_to_string(CONFIGURATION_PATH)
+        .ok()
+        .and_then(|content| toml::from_str::<NodeConfiguration>(&content).ok());
+
+    match configuration {
+        Some(configuration) => format!(
+            "localhost:{}",
+            configuration.rpc.port.unwrap_or(configuration.port)
+        ),
+        None => NODE_ADDRESS.to_string(),
+    }
+}
+
+fn request(method: Method) -> Result<Request, String> {
+    Ok(match method {
+        Method::CreateRawTransaction {
+            source,
+            outputs,
+            fee,
+            expires_at,
+        } => Request::CreateRawTransaction {
+            source: public_key(&source)?,
+            outputs: outputs
+                .iter()
+                .map(|output| parse_output(output))
+                .collect::<Result<_, _>>()?,
+            fee,
+            expires_at,
+        },
+        Method::SignRawTransaction {
+            transaction,
+            wallet,
+            approval,
+        } => Request::SignRawTransaction {
+            transaction,
+            wallet,
+            approval,
+        },
+        Method::BroadcastRawTransaction { transaction } => {
+            Request::BroadcastRawTransaction(transaction)
+        }
+        Method::EstimateFee { target_blocks } => Request::EstimateFee(target_blocks),
+        Method::GetChainStats => Request::GetChainStats,
+        Method::GetTransaction { hash } => Request::GetTransaction(parse_hash(&hash)?),
+        Method::GetTransactionProof { hash } => Request::GetTransactionProof(parse_hash(&hash)?),
+        Method::GetNodeInfo => Request::GetNodeInfo,
+        Method::GetMempoolInfo => Request::GetMempoolInfo,
+        Method::GetRawMempool => Request::GetRawMempool,
+        Method::GetTransactionStatus { hash } => Request::GetTransactionStatus(parse_hash(&hash)?),
+        Method::GetBalance { address } => Request::GetBalance(public_key(&address)?),
+        Method::GetNetTotals => Request::GetNetTotals,
+        Method::GetPeerInfo => Request::GetPeerInfo,
+        Method::GetAddressHistory {
+            address,
+            from_height,
+        } => Request::GetAddressHistory {
+            address: public_key(&address)?,
+            from_height,
+        },
+        Method::GetAlerts => Request::GetAlerts,
+        Method::GetMemoryInfo => Request::GetMemoryInfo,
+        Method::VerifyChain { level } => Request::VerifyChain(level),
+        Method::ResolveName { name } => Request::ResolveName(name),
+        Method::GetAssetBalance { asset, address } => Request::GetAssetBalance {
+            asset,
+            address: public_key(&address)?,
+        },
+        Method::GetAsset { asset } => Request::GetAsset(asset),
+        Method::GetSupply => Request::GetSupply,
+        Method::GetDeploymentInfo => Request::GetDeploymentInfo,
+        Method::GetBlockTemplate => Request::GetBlockTemplate,
+        Method::SubmitBlock { file } => {
+            let content = std::fs::read(&file)
+                .map_err(|issue| format!("Could not read {}: {}", file, issue))?;
+            let block: Block = serde_json::from_slice(&content)
+                .map_err(|issue| format!("Invalid block in {}: {}", file, issue))?;
+
+            Request::SubmitBlock(Box::new(block))
+        }
+        Method::GetMiningInfo => Request::GetMiningInfo,
+        Method::GetWork => Request::GetWork,
+        Method::SubmitWork { id, nonce } => Request::SubmitWork {
+            id,
+            nonce: hex::decode_array(&nonce)?,
+        },
+        Method::WalletUnlock { timeout, wallet } => Request::WalletUnlock {
+            passphrase: Passphrase::new(&read_passphrase("Passphrase")?),
+            timeout,
+            wallet,
+        },
+        Method::WalletLock { wallet } => Request::WalletLock { wallet },
+        Method::CreateWallet { name, encrypt } => {
+            let passphrase = if encrypt {
+                let passphrase = read_passphrase("Passphrase")?;
+
+                if read_passphrase("Repeat the passphrase")? != passphrase {
+                    return Err("The passphrases don't match".to_string());
+                }
+
+                Some(Passphrase::new(&passphrase))
+            } else {
+                None
+            };
+
+            Request::CreateWallet { name, passphrase }
+        }
+        Method::LoadWallet { name } => Request::LoadWallet(name),
+        Method::UnloadWallet { name } => Request::UnloadWallet(name),
+        Method::ListWallets => Request::ListWallets,
+    })
+}
+
+// What the response carries, without the name of its variant
+fn answer(response: Response) -> Value {
+    match serde_json::to_value(response).unwrap() {
+        Value::Object(mut fields) if fields.len() == 1 => {
+            let (_, value) = fields.iter_mut().next().unwrap();
+
+            value.take()
+        }
+        // Responses carrying nothing, such as `WalletLocked`
+        value => value,
+    }
+}
+
+// Hashes, keys and signatures serialize as arrays of bytes, which print
+// better in hex
+fn readable(value: Value) -> Value {
+    match value {
+        Value::Array(items) if is_bytes(&items) => {
+            let bytes: Vec<u8> = items
+                .iter()
+                .filter_map(|item| item.as_u64())
+                .map(|byte| byte as u8)
+                .collect();
+
+            Value::String(hex::encode(&bytes))
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(readable).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name, readable(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+fn is_bytes(items: &[Value]) -> bool {
+    (items.len() == 32 || items.len() == 64)
+        && items
+            .iter()
+            .all(|item| item.as_u64().is_some_and(|byte| byte <= 255))
+}
+
+fn public_key(text: &str) -> Result<PublicKey, String> {
+    match hex::decode_array(text) {
+        Ok(public_key) => Ok(public_key),
+        Err(_) => address::decode(text),
+    }
+}
+
+fn parse_hash(text: &str) -> Result<Hash, String> {
+    hex::decode_array(text).map_err(|issue| format!("Invalid hash {}: {}", text, issue))
+}
+
+// address:amount
+fn parse_output(text: &str) -> Result<Output, String> {
+    let (destination, amount) = text
+        .split_once(':')
+        .ok_or_else(|| format!("{} is not address:amount", text))?;
+
+    Ok(Output {
+        destination: public_key(destination)?,
+        amount: amount
+            .parse()
+            .map_err(|_| format!("Invalid amount in {}", text))?,
+    })
+}
+
+fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>, String> {
+    print!("{}: ", prompt);
+    std::io::stdout().flush().unwrap();
+
+    let mut passphrase = Zeroizing::new(String::new());
+
+    std::io::stdin()
+        .read_line(&mut passphrase)
+        .map_err(|issue| format!("Could not read the passphrase: {}", issue))?;
+
+    let length = passphrase.trim_end_matches(&['\r', '\n'][..]).len();
+
+    passphrase.truncate(length);
+
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outputs_are_address_amount_pairs() {
+        let source = hex::encode(&[1; 32]);
+        let request = request(Method::CreateRawTransaction {
+            source: source.clone(),
+            outputs: vec![
+                format!("{}:5", hex::encode(&[2; 32])),
+                format!("{}:7", address::encode(&[3; 32])),
+            ],
+            fee: 1,
+            expires_at: None,
+        })
+        .unwrap();
+
+        match request {
+            Request::CreateRawTransaction {
+                source, outputs, ..
+            } => {
+                assert_eq!(source, [1; 32]);
+                assert_eq!(outputs.len(), 2);
+                assert_eq!((outputs[0].destination, outputs[0].amount), ([2; 32], 5));
+                assert_eq!((outputs[1].destination, outputs[1].amount), ([3; 32], 7));
+            }
+            request => panic!("Unexpected {:?}", request),
+        }
+
+        for output in ["nocolon", "nothex:5", &format!("{}:lots", source)] {
+            assert!(request_for_output(output).is_err());
+        }
+    }
+
+    fn request_for_output(output: &str) -> Result<Request, String> {
+        request(Method::CreateRawTransaction {
+            source: hex::encode(&[1; 32]),
+            outputs: vec![output.to_string()],
+            fee: 1,
+            expires_at: None,
+        })
+    }
+
+    #[test]
+    fn addresses_can_be_hex_keys() {
+        for address in [hex::encode(&[4; 32]), address::encode(&[4; 32])] {
+            match request(Method::GetBalance { address }).unwrap() {
+                Request::GetBalance(public_key) => assert_eq!(public_key, [4; 32]),
+                request => panic!("Unexpected {:?}", request),
+            }
+        }
+
+        assert!(request(Method::GetBalance {
+            address: "nobody".to_string()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn nonces_are_hex() {
+        let nonce = hex::encode(&[9; 32]);
+
+        match request(Method::SubmitWork { id: 3, nonce }).unwrap() {
+            Request::SubmitWork { id, nonce } => assert_eq!((id, nonce), (3, [9; 32])),
+            request => panic!("Unexpected {:?}", request),
+        }
+
+        // Nonces are 32 bytes
+        assert!(request(Method::SubmitWork {
+            id: 3,
+            nonce: "0909".to_string()
+        })
+        .is_err());
+    }
+}
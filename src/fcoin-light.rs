@@ -0,0 +1,108 @@
+use fcoin::blockchain;
+use fcoin::blockchain::TransactionDetails;
+use fcoin::framing::{Connection, Frame, RelayPolicy};
+use fcoin::keyfile;
+use fcoin::light::{self, FilterMode, LightClient};
+use tokio::net::TcpStream;
+
+// Fee estimates aim to be confirmed within this many blocks
+const CONFIRMATION_TARGET: u32 = 6;
+const USER_AGENT: &str = concat!("fcoin-light/", env!("CARGO_PKG_VERSION"));
+
+const USAGE: &str = "usage: fcoin-light [--bloom] <node address> <key file>
+       fcoin-light [--bloom] <node address> <key file> send <destination address> <amount>";
+
+#[tokio::main]
+async fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+
+    // Compact filters keep our keys private, bloom filters are cheaper
+    let mode = match args.iter().position(|arg| arg == "--bloom") {
+        Some(position) => {
+            args.remove(position);
+            FilterMode::Bloom
+        }
+        None => FilterMode::Compact,
+    };
+
+    if args.len() != 3 && !(args.len() == 6 && args[3] == "send") {
+        println!("{}", USAGE);
+        std::process::exit(1);
+    }
+
+    let secret_key = or_exit(keyfile::load(&args[2]));
+    let public_key = fcoin_core::keys::public_key(&secret_key);
+
+    let stream = or_exit(
+        TcpStream::connect(&args[1])
+            .await
+            .map_err(|issue| format!("Could not connect to {}: {}", args[1], issue)),
+    );
+    let (mut writter, mut reader) = Connection::new(stream);
+
+    or_exit(
+        Connection::handshake(
+            &mut writter,
+            &mut reader,
+            USER_AGENT,
+            None,
+            RelayPolicy::BlocksOnly,
+        )
+        .await,
+    );
+
+    let mut client = LightClient::new(public_key, mode);
+
+    or_exit(client.sync(&mut writter, &mut reader).await);
+
+    println!(
+        "Synced {} headers. Tip is {}.",
+        client.height(),
+        blockchain::HashFmt(client.tip())
+    );
+    println!(
+        "Balance of {}: ${}",
+        fcoin_core::address::encode(&public_key),
+        client.balance()
+    );
+
+    if args.len() == 6 {
+        let destination = or_exit(fcoin_core::address::decode(&args[4]));
+        let amount: u64 = or_exit(
+            args[5]
+                .parse()
+                .map_err(|_| format!("Invalid amount: {}", args[5])),
+        );
+
+        let rate =
+            or_exit(light::estimate_fee(&mut writter, &mut reader, CONFIRMATION_TARGET).await);
+        let fee = TransactionDetails::new(public_key, destination, amount, 0).fee_for_rate(rate);
+
+        if client.balance() < (amount + fee) as i128 {
+            println!(
+                "Not enough funds to transfer ${} with a ${} fee.",
+                amount, fee
+            );
+            std::process::exit(1);
+        }
+
+        let transaction =
+            blockchain::Transaction::create_signed(&secret_key, destination, amount, fee);
+
+        println!("Broadcasting {}", transaction);
+
+        writter.write(Frame::Transaction(transaction)).await;
+        // Frames go out in the background, which exiting would cut short
+        writter.flush().await;
+    }
+}
+
+fn or_exit<T>(result: Result<T, String>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(issue) => {
+            println!("{}", issue);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use clap::Parser;
+use fcoin::blockchain::HashFmt;
+use fcoin::client;
+use fcoin::miner;
+use std::time::Duration;
+
+const NODE_ADDRESS: &str = "localhost:7123";
+// A template is mined on for this long before asking for a new one, with
+// the latest tip and transactions
+const TEMPLATE_INTERVAL: Duration = Duration::from_secs(10);
+// How long to wait when the node has nothing to mine or can't be reached
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// Mines on the block templates of a node, which then doesn't need to mine
+// itself (`mine = false`)
+#[derive(Parser)]
+#[command(name = "fcoin-miner", about = "Mines fcoin for a node")]
+struct Arguments {
+    #[arg(long, default_value = NODE_ADDRESS)]
+    node: String,
+    /// Defaults to one per core
+    #[arg(long)]
+    threads: Option<usize>,
+}
+
+#[tokio::main]
+async fn main() {
+    let arguments = Arguments::parse();
+    let threads = arguments.threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1)
+    });
+
+    println!("Mining for {} on {} threads", arguments.node, threads);
+
+    loop {
+        let template = match client::block_template(&arguments.node).await {
+            Ok(Some(template)) => template,
+            Ok(None) => {
+                tokio::time::sleep(RETRY_DELAY).await;
+
+                continue;
+            }
+            Err(issue) => {
+                println!("{}", issue);
+                tokio::time::sleep(RETRY_DELAY).await;
+
+                continue;
+            }
+        };
+
+        let height = template.height;
+        let (found, hashrate) = tokio::task::spawn_blocking(move || {
+            let started = std::time::Instant::now();
+            let (found, hashes) = miner::search(&template, threads, TEMPLATE_INTERVAL);
+
+            (found, hashes as f64 / started.elapsed().as_secs_f64())
+        })
+        .await
+        .unwrap();
+
+        println!("Height {}: {:.1} hashes/s", height, hashrate);
+
+        if let Some(block) = found {
+            match client::submit_block(&arguments.node, block).await {
+                Ok(hash) => println!("Mined block {} at height {}", HashFmt(hash), height),
+                Err(issue) => println!("Block rejected: {}", issue),
+            }
+        }
+    }
+}
@@ -0,0 +1,1211 @@
+use chrono::DateTime;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use fcoin::address_index::{HistoryEntry, HistoryKind};
+use fcoin::blockchain::HashFmt;
+use fcoin::channel;
+use fcoin::client;
+use fcoin::contacts::Contacts;
+use fcoin::escrow::{self, Escrow};
+use fcoin::keyfile;
+use fcoin::paper;
+use fcoin::swap;
+use fcoin_core::contract::{self, Contract};
+use fcoin_core::transaction::TransactionDetails;
+use fcoin_core::uri::PaymentRequest;
+use fcoin_core::{address, hex, keys, message, raw};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::SystemTime;
+use zeroize::Zeroizing;
+
+const KEY_FILE_PATH: &str = "fcoin.key";
+const NODE_ADDRESS: &str = "localhost:7123";
+// Fee estimates aim to be confirmed within this many blocks by default
+const CONFIRMATION_TARGET: u32 = 6;
+// Blocks each side of a swap waits before taking their coins back
+const INITIATOR_LOCKTIME: u64 = 48;
+const PARTICIPANT_LOCKTIME: u64 = 24;
+const CHANNELS_PATH: &str = "channels.json";
+const CONTACTS_PATH: &str = "contacts.json";
+// Where `channel listen` listens by default
+const CHANNEL_PORT: u16 = 7125;
+const CHANNEL_RESERVE: u64 = 10;
+const DISPUTE_WINDOW: u64 = 144;
+
+#[derive(Parser)]
+#[command(name = "fcoin-wallet", about = "Manages an fcoin wallet")]
+struct Arguments {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows a payment request for our address
+    Receive {
+        #[arg(long)]
+        amount: Option<u64>,
+        #[arg(long)]
+        memo: Option<String>,
+        /// Seconds after which the request should no longer be paid
+        #[arg(long)]
+        expires_in: Option<u64>,
+        /// Prints the request as a QR code in the terminal
+        #[arg(long)]
+        qr: bool,
+        /// Writes the QR code to a PNG image
+        #[arg(long)]
+        png: Option<String>,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Prints the key and address with QR codes, to be kept on paper
+    ExportPaper {
+        /// Asks for a passphrase to encrypt the private key with
+        #[arg(long)]
+        encrypt: bool,
+        /// Writes the paper wallet to a file instead of printing it
+        #[arg(long)]
+        output: Option<String>,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Signs a message to prove we control our address
+    SignMessage {
+        message: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Moves all the funds of a paper wallet private key to our address
+    ImportPaper {
+        private_key: String,
+        /// Defaults to the fee estimated by the node
+        #[arg(long)]
+        fee: Option<u64>,
+        /// How many blocks we are willing to wait for when estimating the fee
+        #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+        confirm_within: u32,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Exports every change to our balance, for accounting
+    ExportHistory {
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Csv)]
+        format: HistoryFormat,
+        /// Leaves out older blocks, the running balance starts from what we
+        /// had before this height
+        #[arg(long, default_value_t = 0)]
+        from_height: u64,
+        /// Writes the export to a file instead of printing it
+        #[arg(long)]
+        output: Option<String>,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+        /// Whose labels name the counterparties
+        #[arg(long, default_value = CONTACTS_PATH)]
+        contacts_file: String,
+    },
+    /// Labels for the addresses we deal with, which `send-fcoin` takes as
+    /// @label
+    Contact {
+        #[command(subcommand)]
+        command: ContactCommand,
+    },
+    /// Swaps coins with someone on another fcoin network, without trusting
+    /// them
+    Swap {
+        #[command(subcommand)]
+        command: SwapCommand,
+    },
+    /// Pays someone many times off chain, settling once on chain
+    Channel {
+        #[command(subcommand)]
+        command: ChannelCommand,
+    },
+    /// Holds a payment until two of the buyer, the seller and an arbiter
+    /// agree on where it goes
+    Escrow {
+        #[command(subcommand)]
+        command: EscrowCommand,
+    },
+}
+
+// For transactions paying to contracts or taking from them
+#[derive(Args)]
+struct ContractFee {
+    /// Defaults to the fee estimated by the node
+    #[arg(long)]
+    fee: Option<u64>,
+    /// How many blocks we are willing to wait for when estimating the fee
+    #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+    confirm_within: u32,
+    /// A node of the network the contract is on
+    #[arg(long, default_value = NODE_ADDRESS)]
+    node: String,
+    #[arg(long, default_value = KEY_FILE_PATH)]
+    key_file: String,
+}
+
+// See `swap.rs` for how a swap goes
+#[derive(Subcommand)]
+enum SwapCommand {
+    /// Locks coins for the other side under the hash of a new secret
+    Initiate {
+        /// Address of the other side on this network
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: u64,
+        /// Blocks before we can take the coins back. Must leave the other
+        /// side time to redeem after we do
+        #[arg(long, default_value_t = INITIATOR_LOCKTIME)]
+        locktime: u64,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Locks coins for the initiator under the hash lock of their contract
+    Participate {
+        /// Address of the initiator on this network
+        #[arg(long)]
+        to: String,
+        #[arg(long)]
+        amount: u64,
+        /// From the contract of the initiator
+        #[arg(long)]
+        hash_lock: String,
+        /// Blocks before we can take the coins back, fewer than the
+        /// initiator's
+        #[arg(long, default_value_t = PARTICIPANT_LOCKTIME)]
+        locktime: u64,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Takes the coins of a contract for us, with the secret
+    Redeem {
+        contract: String,
+        #[arg(long)]
+        secret: String,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Takes back the coins of our contract once it timed out
+    Refund {
+        contract: String,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Shows the secret used to redeem a contract, which needs the
+    /// transaction index enabled on the node
+    Secret {
+        contract: String,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+    },
+}
+
+// See `escrow.rs` for how an escrow goes
+#[derive(Subcommand)]
+enum EscrowCommand {
+    /// Creates an escrow with us as the buyer
+    Create {
+        #[arg(long)]
+        seller: String,
+        #[arg(long)]
+        arbiter: String,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Pays coins into an escrow
+    Fund {
+        escrow: String,
+        #[arg(long)]
+        amount: u64,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Pays everything in the escrow to the seller. Prints the spend signed
+    /// by us, for another party to pass to --cosign.
+    Release {
+        escrow: String,
+        /// A spend another party signed, which we sign and broadcast
+        #[arg(long)]
+        cosign: Option<String>,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Pays everything in the escrow back to the buyer, signed like with
+    /// `release`
+    Refund {
+        escrow: String,
+        #[arg(long)]
+        cosign: Option<String>,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+}
+
+// See `channel.rs` for how channels work
+#[derive(Subcommand)]
+enum ChannelCommand {
+    /// Opens a channel with someone listening for channel updates, and
+    /// funds it
+    Open {
+        /// Their address
+        #[arg(long)]
+        with: String,
+        /// Where they listen for channel updates
+        #[arg(long)]
+        peer: String,
+        /// Locked in the channel, including the reserve
+        #[arg(long)]
+        amount: u64,
+        /// Kept aside to pay for closing the channel
+        #[arg(long, default_value_t = CHANNEL_RESERVE)]
+        reserve: u64,
+        /// Blocks each side has to dispute a close with an earlier state
+        #[arg(long, default_value_t = DISPUTE_WINDOW)]
+        dispute: u64,
+        #[arg(long, default_value = CHANNELS_PATH)]
+        channels_file: String,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Signs the updates of the channels others pay us in
+    Listen {
+        #[arg(long, default_value_t = CHANNEL_PORT)]
+        port: u16,
+        #[arg(long, default_value = CHANNELS_PATH)]
+        channels_file: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Pays the other side of a channel, off chain
+    Pay {
+        /// Address of the channel
+        channel: String,
+        #[arg(long)]
+        amount: u64,
+        /// Where the other side listens for channel updates
+        #[arg(long)]
+        peer: String,
+        #[arg(long, default_value = CHANNELS_PATH)]
+        channels_file: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Lists our channels with what each side has in them
+    List {
+        #[arg(long, default_value = CHANNELS_PATH)]
+        channels_file: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+    /// Closes a channel with its latest state, without the other side. Also
+    /// disputes a close the other side made with an earlier state. Needs the
+    /// transaction index enabled on the node.
+    Close {
+        channel: String,
+        #[arg(long, default_value = CHANNELS_PATH)]
+        channels_file: String,
+        #[command(flatten)]
+        options: ContractFee,
+    },
+    /// Pays out both sides of a closed channel once its dispute window is
+    /// over. Needs the transaction index enabled on the node.
+    Settle {
+        channel: String,
+        #[arg(long, default_value = CHANNELS_PATH)]
+        channels_file: String,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[arg(long, default_value = KEY_FILE_PATH)]
+        key_file: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContactCommand {
+    /// Labels an address, replacing what the label was for
+    Add {
+        label: String,
+        address: String,
+        #[arg(long, default_value = CONTACTS_PATH)]
+        contacts_file: String,
+    },
+    Remove {
+        label: String,
+        #[arg(long, default_value = CONTACTS_PATH)]
+        contacts_file: String,
+    },
+    List {
+        #[arg(long, default_value = CONTACTS_PATH)]
+        contacts_file: String,
+    },
+    /// Adds the label,address lines of a CSV file, keeping our contacts
+    /// when a label is taken
+    Import {
+        input: String,
+        #[arg(long, default_value = CONTACTS_PATH)]
+        contacts_file: String,
+    },
+    /// Writes every contact as label,address lines
+    Export {
+        /// Writes to a file instead of printing them
+        #[arg(long)]
+        output: Option<String>,
+        #[arg(long, default_value = CONTACTS_PATH)]
+        contacts_file: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HistoryFormat {
+    Csv,
+    Json,
+}
+
+// A line of the history export
+#[derive(Serialize)]
+struct HistoryRow {
+    // UTC, in RFC 3339
+    timestamp: String,
+    height: u64,
+    // Hash of the block for rewards and slashings
+    txid: String,
+    direction: &'static str,
+    counterparty: Option<String>,
+    // Of the counterparty in our contacts
+    label: Option<String>,
+    amount: u64,
+    fee: u64,
+    balance: i128,
+}
+
+#[tokio::main]
+async fn main() {
+    let result = match Arguments::parse().command {
+        Command::Receive {
+            amount,
+            memo,
+            expires_in,
+            qr,
+            png,
+            key_file,
+        } => keyfile::load(&key_file).and_then(|secret_key| {
+            receive(
+                PaymentRequest {
+                    address: keys::public_key(&secret_key),
+                    amount,
+                    memo,
+                    expires: expires_in.map(|seconds| timestamp() + seconds),
+                },
+                qr,
+                png,
+            )
+        }),
+        Command::ExportPaper {
+            encrypt,
+            output,
+            key_file,
+        } => keyfile::load(&key_file)
+            .and_then(|secret_key| export_paper(&secret_key, encrypt, output)),
+        Command::SignMessage { message, key_file } => {
+            keyfile::load(&key_file).map(|secret_key| sign_message(&secret_key, &message))
+        }
+        Command::ImportPaper {
+            private_key,
+            fee,
+            confirm_within,
+            node,
+            key_file,
+        } => match keyfile::load(&key_file) {
+            Ok(secret_key) => {
+                import_paper(
+                    &private_key,
+                    keys::public_key(&secret_key),
+                    fee,
+                    confirm_within,
+                    &node,
+                )
+                .await
+            }
+            Err(issue) => Err(issue),
+        },
+        Command::ExportHistory {
+            format,
+            from_height,
+            output,
+            node,
+            key_file,
+            contacts_file,
+        } => match (keyfile::load(&key_file), Contacts::load(&contacts_file)) {
+            (Ok(secret_key), Ok(contacts)) => {
+                export_history(
+                    keys::public_key(&secret_key),
+                    &contacts,
+                    format,
+                    from_height,
+                    output,
+                    &node,
+                )
+                .await
+            }
+            (Err(issue), _) | (_, Err(issue)) => Err(issue),
+        },
+        Command::Contact { command } => contact(command),
+        Command::Swap { command } => swap(command).await,
+        Command::Channel { command } => channel(command).await,
+        Command::Escrow { command } => escrow(command).await,
+    };
+
+    if let Err(issue) = result {
+        println!("{}", issue);
+        std::process::exit(1);
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn receive(request: PaymentRequest, qr: bool, png: Option<String>) -> Result<(), String> {
+    let uri = request.to_uri();
+
+    println!("{}", uri);
+
+    if qr || png.is_some() {
+        let code = QrCode::new(uri.as_bytes())
+            .map_err(|issue| format!("Could not build a QR code: {}", issue))?;
+
+        if qr {
+            println!(
+                "{}",
+                code.render::<unicode::Dense1x2>().quiet_zone(true).build()
+            );
+        }
+
+        if let Some(path) = png {
+            code.render::<image::Luma<u8>>()
+                .build()
+                .save(&path)
+                .map_err(|issue| format!("Could not write {}: {}", path, issue))?;
+
+            println!("QR code written to {}", path);
+        }
+    }
+
+    Ok(())
+}
+
+fn sign_message(secret_key: &fcoin_core::SecretKey, text: &str) {
+    println!(
+        "Address: {}",
+        address::encode(&keys::public_key(secret_key))
+    );
+    println!(
+        "Signature: {}",
+        hex::encode(&message::sign(secret_key, text))
+    );
+}
+
+// Wiped once dropped, like the keys it decrypts
+fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>, String> {
+    print!("{}: ", prompt);
+    std::io::stdout().flush().unwrap();
+
+    let mut passphrase = Zeroizing::new(String::new());
+
+    std::io::stdin()
+        .read_line(&mut passphrase)
+        .map_err(|issue| format!("Could not read the passphrase: {}", issue))?;
+
+    let length = passphrase.trim_end_matches(&['\r', '\n'][..]).len();
+
+    passphrase.truncate(length);
+
+    Ok(passphrase)
+}
+
+fn qr_text(content: &str) -> Result<String, String> {
+    let code = QrCode::new(content.as_bytes())
+        .map_err(|issue| format!("Could not build a QR code: {}", issue))?;
+
+    Ok(code.render::<unicode::Dense1x2>().quiet_zone(true).build())
+}
+
+fn export_paper(
+    secret_key: &fcoin_core::SecretKey,
+    encrypt: bool,
+    output: Option<String>,
+) -> Result<(), String> {
+    let private_key = if encrypt {
+        let passphrase = read_passphrase("Passphrase")?;
+
+        if passphrase.is_empty() {
+            return Err("The passphrase can't be empty".to_string());
+        }
+
+        if read_passphrase("Repeat the passphrase")? != passphrase {
+            return Err("The passphrases don't match".to_string());
+        }
+
+        paper::encrypt(secret_key, &passphrase)
+    } else {
+        paper::encode(secret_key)
+    };
+    let address = address::encode(&keys::public_key(secret_key));
+
+    let mut sheet = String::new();
+
+    writeln!(sheet, "fcoin paper wallet\n").unwrap();
+    writeln!(sheet, "Address, share it to get paid:\n{}", address).unwrap();
+    writeln!(sheet, "{}", qr_text(&address)?).unwrap();
+    writeln!(
+        sheet,
+        "Private key{}, whoever has it can spend the funds:\n{}",
+        if encrypt { " (encrypted)" } else { "" },
+        private_key
+    )
+    .unwrap();
+    writeln!(sheet, "{}", qr_text(&private_key)?).unwrap();
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, sheet)
+                .map_err(|issue| format!("Could not write {}: {}", path, issue))?;
+
+            println!("Paper wallet written to {}", path);
+        }
+        None => print!("{}", sheet),
+    }
+
+    Ok(())
+}
+
+// Sweeps the whole balance, as the paper key can't be trusted to be secret
+// anymore once it was typed in
+async fn import_paper(
+    private_key: &str,
+    destination: fcoin_core::PublicKey,
+    fee: Option<u64>,
+    confirm_within: u32,
+    node: &str,
+) -> Result<(), String> {
+    let secret_key = if paper::is_encrypted(private_key) {
+        paper::decode(private_key, Some(&read_passphrase("Passphrase")?))?
+    } else {
+        paper::decode(private_key, None)?
+    };
+    let source = keys::public_key(&secret_key);
+
+    if source == destination {
+        return Err("The paper wallet is the same as this wallet".to_string());
+    }
+
+    let balance = client::balance(node, source).await?;
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+
+            TransactionDetails::new(source, destination, 0, 0).fee_for_rate(rate)
+        }
+    };
+
+    if balance <= fee as i128 {
+        return Err(format!(
+            "Nothing to sweep: {} has ${} and the fee is ${}",
+            address::encode(&source),
+            balance,
+            fee
+        ));
+    }
+
+    let amount = (balance - fee as i128) as u64;
+    let details = TransactionDetails::new(source, destination, amount, fee);
+    let signed = raw::sign_raw_transaction(&raw::encode_unsigned(&details), &secret_key)?;
+
+    let (hash, known) = client::broadcast_raw_transaction(node, &signed).await?;
+
+    if let Some(status) = known {
+        println!(
+            "This sweep was already submitted in {}, it is {}",
+            HashFmt(hash),
+            status
+        );
+
+        return Ok(());
+    }
+
+    println!(
+        "Swept ${} (fee ${}) from {} in {}",
+        amount,
+        fee,
+        address::encode(&source),
+        HashFmt(hash)
+    );
+
+    Ok(())
+}
+
+fn history_row(entry: &HistoryEntry, balance: i128, contacts: &Contacts) -> HistoryRow {
+    let (direction, counterparty, amount, fee) = match &entry.kind {
+        HistoryKind::Sent {
+            destination,
+            amount,
+            fee,
+        } => ("sent", Some(destination), *amount, *fee),
+        HistoryKind::Received { source, amount } => ("received", Some(source), *amount, 0),
+        HistoryKind::Mined { reward, fees } => ("mined", None, reward + fees, 0),
+        HistoryKind::Slashed(burned) => ("slashed", None, *burned, 0),
+    };
+
+    HistoryRow {
+        timestamp: DateTime::from_timestamp(entry.time as i64, 0)
+            .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+            .unwrap_or_default(),
+        height: entry.height,
+        txid: HashFmt(entry.hash).to_string(),
+        direction,
+        counterparty: counterparty.map(address::encode),
+        label: counterparty
+            .and_then(|counterparty| contacts.label(counterparty))
+            .map(str::to_string),
+        amount,
+        fee,
+        balance,
+    }
+}
+
+// None of the fields can have commas or quotes, labels included, so nothing
+// needs escaping
+fn history_csv(rows: &[HistoryRow]) -> String {
+    let mut csv =
+        String::from("timestamp,height,txid,direction,counterparty,label,amount,fee,balance\n");
+
+    for row in rows {
+        writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{}",
+            row.timestamp,
+            row.height,
+            row.txid,
+            row.direction,
+            row.counterparty.as_deref().unwrap_or(""),
+            row.label.as_deref().unwrap_or(""),
+            row.amount,
+            row.fee,
+            row.balance
+        )
+        .unwrap();
+    }
+
+    csv
+}
+
+// Built from the address index of the node, oldest first
+async fn export_history(
+    address: fcoin_core::PublicKey,
+    contacts: &Contacts,
+    format: HistoryFormat,
+    from_height: u64,
+    output: Option<String>,
+    node: &str,
+) -> Result<(), String> {
+    let (mut balance, entries) = client::address_history(node, address, from_height).await?;
+
+    let rows: Vec<HistoryRow> = entries
+        .iter()
+        .map(|entry| {
+            balance += entry.change();
+
+            history_row(entry, balance, contacts)
+        })
+        .collect();
+
+    let export = match format {
+        HistoryFormat::Csv => history_csv(&rows),
+        HistoryFormat::Json => serde_json::to_string_pretty(&rows).unwrap() + "\n",
+    };
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, export)
+                .map_err(|issue| format!("Could not write {}: {}", path, issue))?;
+
+            println!("{} entries written to {}", rows.len(), path);
+        }
+        None => print!("{}", export),
+    }
+
+    Ok(())
+}
+
+fn contact(command: ContactCommand) -> Result<(), String> {
+    match command {
+        ContactCommand::Add {
+            label,
+            address: encoded,
+            contacts_file,
+        } => {
+            let mut contacts = Contacts::load(&contacts_file)?;
+
+            contacts.add(label.trim_start_matches('@'), address::decode(&encoded)?)?;
+            contacts.save(&contacts_file)
+        }
+        ContactCommand::Remove {
+            label,
+            contacts_file,
+        } => {
+            let mut contacts = Contacts::load(&contacts_file)?;
+
+            contacts.remove(label.trim_start_matches('@'))?;
+            contacts.save(&contacts_file)
+        }
+        ContactCommand::List { contacts_file } => {
+            for (label, public_key) in Contacts::load(&contacts_file)?.iter() {
+                println!("@{:<20} {}", label, address::encode(public_key));
+            }
+
+            Ok(())
+        }
+        ContactCommand::Import {
+            input,
+            contacts_file,
+        } => {
+            let csv = std::fs::read_to_string(&input)
+                .map_err(|issue| format!("Could not read {}: {}", input, issue))?;
+            let mut contacts = Contacts::load(&contacts_file)?;
+            let (added, skipped) = contacts.import(&csv)?;
+
+            contacts.save(&contacts_file)?;
+
+            println!("{} contacts added", added.len());
+
+            for label in skipped {
+                println!("Kept @{}, which is already another address", label);
+            }
+
+            Ok(())
+        }
+        ContactCommand::Export {
+            output,
+            contacts_file,
+        } => {
+            let contacts = Contacts::load(&contacts_file)?;
+
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, contacts.export())
+                        .map_err(|issue| format!("Could not write {}: {}", path, issue))?;
+
+                    println!("{} contacts written to {}", contacts.iter().count(), path);
+                }
+                None => print!("{}", contacts.export()),
+            }
+
+            Ok(())
+        }
+    }
+}
+
+async fn swap(command: SwapCommand) -> Result<(), String> {
+    match command {
+        SwapCommand::Initiate {
+            to,
+            amount,
+            locktime,
+            options,
+        } => {
+            let mut secret = [0; 32];
+
+            getrandom::getrandom(&mut secret).map_err(|issue| issue.to_string())?;
+
+            let hash_lock = contract::hash_lock(&secret);
+
+            lock_swap(&to, amount, hash_lock, locktime, &options).await?;
+
+            println!("Secret: {}", hex::encode(&secret));
+            println!("Keep it to yourself until you redeem the contract of the other side");
+
+            Ok(())
+        }
+        SwapCommand::Participate {
+            to,
+            amount,
+            hash_lock,
+            locktime,
+            options,
+        } => {
+            lock_swap(
+                &to,
+                amount,
+                hex::decode_array(&hash_lock)?,
+                locktime,
+                &options,
+            )
+            .await
+        }
+        SwapCommand::Redeem {
+            contract,
+            secret,
+            options,
+        } => {
+            let secret = hex::decode_array(&secret)?;
+
+            unlock_swap(&contract, Some(secret), &options).await
+        }
+        SwapCommand::Refund { contract, options } => unlock_swap(&contract, None, &options).await,
+        SwapCommand::Secret { contract, node } => {
+            let secret = swap::find_secret(&node, &Contract::from_hex(&contract)?).await?;
+
+            println!("Secret: {}", hex::encode(&secret));
+
+            Ok(())
+        }
+    }
+}
+
+async fn lock_swap(
+    to: &str,
+    amount: u64,
+    hash_lock: fcoin_core::Hash,
+    locktime: u64,
+    options: &ContractFee,
+) -> Result<(), String> {
+    let secret_key = keyfile::load(&options.key_file)?;
+    let contract = swap::hash_time_lock(
+        &options.node,
+        address::decode(to)?,
+        hash_lock,
+        locktime,
+        keys::public_key(&secret_key),
+    )
+    .await?;
+    let hash = swap::lock(
+        &options.node,
+        &secret_key,
+        &contract,
+        amount,
+        options.fee,
+        options.confirm_within,
+    )
+    .await?;
+
+    println!(
+        "Locked ${} in {} with {}",
+        amount,
+        address::encode(&contract.address()),
+        HashFmt(hash)
+    );
+
+    if let Contract::HashTimeLock {
+        hash_lock, timeout, ..
+    } = &contract
+    {
+        println!("Hash lock: {}", hex::encode(hash_lock));
+        println!("Refundable after block {}", timeout);
+    }
+
+    println!("Contract: {}", contract.to_hex());
+
+    Ok(())
+}
+
+async fn unlock_swap(
+    contract: &str,
+    secret: Option<fcoin_core::Hash>,
+    options: &ContractFee,
+) -> Result<(), String> {
+    let secret_key = keyfile::load(&options.key_file)?;
+    let (amount, hash) = swap::unlock(
+        &options.node,
+        &secret_key,
+        Contract::from_hex(contract)?,
+        secret,
+        options.fee,
+        options.confirm_within,
+    )
+    .await?;
+
+    println!(
+        "{} ${} in {}",
+        if secret.is_some() {
+            "Redeemed"
+        } else {
+            "Refunded"
+        },
+        amount,
+        HashFmt(hash)
+    );
+
+    Ok(())
+}
+
+async fn escrow(command: EscrowCommand) -> Result<(), String> {
+    match command {
+        EscrowCommand::Create {
+            seller,
+            arbiter,
+            node,
+            key_file,
+        } => {
+            let buyer = keys::public_key(&keyfile::load(&key_file)?);
+            let escrow = Escrow::new(
+                &node,
+                buyer,
+                address::decode(&seller)?,
+                address::decode(&arbiter)?,
+            )
+            .await?;
+            let contract = escrow.contract();
+
+            println!("Address: {}", address::encode(&contract.address()));
+            println!("Escrow: {}", contract.to_hex());
+
+            Ok(())
+        }
+        EscrowCommand::Fund {
+            escrow,
+            amount,
+            options,
+        } => {
+            let contract = Contract::from_hex(&escrow)?;
+
+            Escrow::from_contract(&contract).ok_or("Not an escrow")?;
+
+            let hash = swap::lock(
+                &options.node,
+                &keyfile::load(&options.key_file)?,
+                &contract,
+                amount,
+                options.fee,
+                options.confirm_within,
+            )
+            .await?;
+
+            println!(
+                "Paid ${} into {} with {}",
+                amount,
+                address::encode(&contract.address()),
+                HashFmt(hash)
+            );
+
+            Ok(())
+        }
+        EscrowCommand::Release {
+            escrow,
+            cosign,
+            options,
+        } => spend_escrow(&escrow, false, cosign.as_deref(), &options).await,
+        EscrowCommand::Refund {
+            escrow,
+            cosign,
+            options,
+        } => spend_escrow(&escrow, true, cosign.as_deref(), &options).await,
+    }
+}
+
+// Signs a spend of the escrow, to the buyer when refunding and to the
+// seller otherwise, and broadcasts it once another party signed it too
+async fn spend_escrow(
+    encoded: &str,
+    refund: bool,
+    cosign: Option<&str>,
+    options: &ContractFee,
+) -> Result<(), String> {
+    let secret_key = keyfile::load(&options.key_file)?;
+    let escrow = Escrow::from_contract(&Contract::from_hex(encoded)?).ok_or("Not an escrow")?;
+    let destination = if refund { escrow.buyer } else { escrow.seller };
+
+    match cosign {
+        Some(partial) => {
+            let transaction = escrow::cosign(partial, &secret_key, &escrow, &destination)?;
+            let hash = escrow::broadcast(&options.node, &transaction).await?;
+
+            println!(
+                "Paid ${} to {} in {}",
+                transaction.amount(),
+                address::encode(&destination),
+                HashFmt(hash)
+            );
+        }
+        None => {
+            let transaction = escrow::spend(
+                &options.node,
+                &secret_key,
+                &escrow,
+                destination,
+                options.fee,
+                options.confirm_within,
+            )
+            .await?;
+
+            println!(
+                "Signed paying ${} to {}, which another party has to sign too with --cosign:",
+                transaction.amount(),
+                address::encode(&destination)
+            );
+            println!("{}", raw::encode_signed(&transaction));
+        }
+    }
+
+    Ok(())
+}
+
+async fn channel(command: ChannelCommand) -> Result<(), String> {
+    match command {
+        ChannelCommand::Open {
+            with,
+            peer,
+            amount,
+            reserve,
+            dispute,
+            channels_file,
+            options,
+        } => {
+            let secret_key = keyfile::load(&options.key_file)?;
+            let mut records = channel::load(&channels_file)?;
+            let record = channel::open(
+                &peer,
+                &secret_key,
+                address::decode(&with)?,
+                amount,
+                reserve,
+                dispute,
+            )
+            .await?;
+            let contract = Contract::Channel(record.channel.clone());
+
+            // Saved before funding, as the first state is what gets the
+            // coins back
+            records.push(record);
+            channel::save(&channels_file, &records)?;
+
+            let hash = swap::lock(
+                &options.node,
+                &secret_key,
+                &contract,
+                amount,
+                options.fee,
+                options.confirm_within,
+            )
+            .await?;
+
+            println!(
+                "Opened channel {} with ${} in {}",
+                address::encode(&contract.address()),
+                amount,
+                HashFmt(hash)
+            );
+
+            Ok(())
+        }
+        ChannelCommand::Listen {
+            port,
+            channels_file,
+            key_file,
+        } => channel::listen(port, &keyfile::load(&key_file)?, &channels_file).await,
+        ChannelCommand::Pay {
+            channel,
+            amount,
+            peer,
+            channels_file,
+            key_file,
+        } => {
+            let secret_key = keyfile::load(&key_file)?;
+            let mut records = channel::load(&channels_file)?;
+            let address = address::decode(&channel)?;
+            let record = channel::find(&records, &address).ok_or("Unknown channel")?;
+            let state = channel::pay(&peer, &secret_key, record, amount).await?;
+
+            for record in records.iter_mut() {
+                if record.channel.address() == address {
+                    record.state = state.clone();
+                }
+            }
+
+            channel::save(&channels_file, &records)?;
+
+            println!("Paid ${} in state {}", amount, state.state.sequence);
+
+            Ok(())
+        }
+        ChannelCommand::List {
+            channels_file,
+            key_file,
+        } => {
+            let us = keys::public_key(&keyfile::load(&key_file)?);
+
+            for record in channel::load(&channels_file)? {
+                let (ours, theirs) = record.balances(&us).ok_or("Not our channel")?;
+
+                println!(
+                    "{} ours ${} theirs ${} state {}",
+                    address::encode(&record.channel.address()),
+                    ours,
+                    theirs,
+                    record.state.state.sequence
+                );
+            }
+
+            Ok(())
+        }
+        ChannelCommand::Close {
+            channel,
+            channels_file,
+            options,
+        } => {
+            let secret_key = keyfile::load(&options.key_file)?;
+            let records = channel::load(&channels_file)?;
+            let record =
+                channel::find(&records, &address::decode(&channel)?).ok_or("Unknown channel")?;
+            let (hash, timeout) = channel::close(
+                &options.node,
+                &secret_key,
+                record,
+                options.fee,
+                options.confirm_within,
+            )
+            .await?;
+
+            println!(
+                "Closing in state {} with {}, which can be settled after block {}",
+                record.state.state.sequence,
+                HashFmt(hash),
+                timeout
+            );
+
+            Ok(())
+        }
+        ChannelCommand::Settle {
+            channel,
+            channels_file,
+            node,
+            key_file,
+        } => {
+            let secret_key = keyfile::load(&key_file)?;
+            let records = channel::load(&channels_file)?;
+            let record =
+                channel::find(&records, &address::decode(&channel)?).ok_or("Unknown channel")?;
+            let hash = channel::settle(&node, &secret_key, &record.channel).await?;
+
+            println!("Settled in {}", HashFmt(hash));
+
+            Ok(())
+        }
+    }
+}
@@ -1,67 +1,1132 @@
 use super::blockchain;
+use super::bloom::BloomFilter;
+use super::channel::ChannelUpdate;
+use super::checkpoint::Checkpoint;
+use super::compact_filter::CompactFilter;
+use super::rpc;
 
+use bytes::Bytes;
 use futures::prelude::*;
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
+use tokio::sync::{Notify, Semaphore};
 use tokio_serde::formats::*;
 use tokio_serde::SymmetricallyFramed;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
 use serde::{Deserialize, Serialize};
 
+// Upgrade policy:
+//
+// * Every frame goes over the wire inside an `Envelope`, whose message type
+//   is the position of the variant in `Frame`. Variants are only ever added
+//   at the end of `Frame`, never removed or reordered.
+// * Nodes skip messages whose type they don't know, so new message types
+//   can be sent to older peers without breaking the connection.
+// * Changing the contents of an existing message needs a new
+//   `PROTOCOL_VERSION`, and the new layout can only be sent to peers that
+//   negotiated (in the `Frame::Version` handshake) a version that has it.
+// * `MIN_PROTOCOL_VERSION` is raised when support for old layouts is
+//   dropped, peers below it are disconnected during the handshake.
+//
+// Version 2 added `Frame::Codecs`, to pick how payloads are encoded.
+// Version 3 added `Frame::Chunk`, to send messages in many pieces.
+// Version 4 added `Frame::Time`, so nodes can agree on the time.
+// Version 5 added the producer signature to block headers and slashings to
+// blocks, for proof of stake. Older layouts are no longer supported.
+// Version 6 added `Frame::Network`, so nodes only talk to their network.
+// Version 7 added `Frame::Relay`, so nodes can opt out of transactions.
+// Version 8 added `Frame::ObservedAddress`, so nodes learn their address.
+// Version 9 added the version to block headers, for deployments to be
+// signaled. Older layouts are no longer supported.
+pub const PROTOCOL_VERSION: u32 = 9;
+pub const MIN_PROTOCOL_VERSION: u32 = 9;
+// From the first protocol versions that have them
+const CODECS_PROTOCOL_VERSION: u32 = 2;
+const CHUNKS_PROTOCOL_VERSION: u32 = 3;
+const TIME_PROTOCOL_VERSION: u32 = 4;
+const NETWORK_PROTOCOL_VERSION: u32 = 6;
+const RELAY_PROTOCOL_VERSION: u32 = 7;
+const OBSERVED_ADDRESS_PROTOCOL_VERSION: u32 = 8;
+
+// Largest length-delimited frame we accept
+const MAX_FRAME_SIZE: usize = 8 * 1024 * 1024;
+// Payloads larger than this are sent in chunks
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+// Largest message that can be put back together from chunks
+const MAX_MESSAGE_SIZE: usize = 256 * 1024 * 1024;
+// Bytes that can wait to be sent to a peer before writing to it waits too
+const MAX_QUEUED_BYTES: usize = 16 * 1024 * 1024;
+// Bytes each envelope takes besides its payload: the length delimiter, the
+// message type, the version and the payload length
+const ENVELOPE_OVERHEAD: u64 = 4 + 4 + 4 + 8;
+
 pub struct Connection {}
 
+// One side of a stream frames go over, for transports that are not TCP
+pub type StreamWriter = Box<dyn AsyncWrite + Send + Unpin>;
+pub type StreamReader = Box<dyn AsyncRead + Send + Unpin>;
+
+type EnvelopeWriter = SymmetricallyFramed<
+    FramedWrite<StreamWriter, LengthDelimitedCodec>,
+    Envelope,
+    SymmetricalBincode<Envelope>,
+>;
+// Envelopes from every stream of a connection, with the stream they came
+// from
+type EnvelopeReader = Pin<Box<dyn Stream<Item = (usize, std::io::Result<Envelope>)> + Send>>;
+
+// Transports with more than one stream per connection send the frames that
+// take long (full blocks and what goes with syncing) on the second, so they
+// don't hold up transactions and the rest. TCP has a single stream for all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Gossip,
+    Bulk,
+}
+
+pub const LANES: [Lane; 2] = [Lane::Gossip, Lane::Bulk];
+
+// Which frames waiting to be sent on a stream go first. Those that keep the
+// chain moving are never stuck behind blocks sent for syncing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Priority {
+    // The handshake, new blocks and checkpoints
+    Critical,
+    Normal,
+    // Frames of the bulk `Lane`
+    Bulk,
+}
+
+const PRIORITIES: [Priority; 3] = [Priority::Critical, Priority::Normal, Priority::Bulk];
+
+// Frames waiting to be sent on a stream, which a task of its own writes, so
+// writing to a peer doesn't wait for it to take them
+struct Outbox {
+    queue: Mutex<Queue>,
+    // Woken when envelopes are queued or the connection is dropped
+    queued: Notify,
+    // Bytes that can still be queued, given back as they are sent
+    room: Semaphore,
+}
+
+#[derive(Default)]
+struct Queue {
+    // By `Priority`, each message with its envelopes and the room it takes
+    messages: [VecDeque<(u32, VecDeque<Envelope>)>; 3],
+    // A message being sent in chunks, with its priority. Peers put the
+    // chunks of one message back together at a time, so only messages in a
+    // single envelope can go in between.
+    chunked: Option<(Priority, u32, VecDeque<Envelope>)>,
+    closed: bool,
+}
+
 pub struct WriteConnection {
-    writter: SymmetricallyFramed<
-        FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
-        Frame,
-        SymmetricalBincode<Frame>,
-    >,
+    // One for each stream, by `Lane`
+    outboxes: Vec<Arc<Outbox>>,
+    // Agreed on in the handshake, the latest we know until then
+    protocol_version: u32,
+    codec: Codec,
+    // Identifies the chunks of each large message
+    next_message_id: u32,
+    traffic: Arc<Mutex<TrafficStats>>,
+    // Where the other side connects from, as we see it
+    peer_address: Option<SocketAddr>,
 }
 
 pub struct ReadConnection {
-    reader: SymmetricallyFramed<
-        FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
-        Frame,
-        SymmetricalBincode<Frame>,
-    >,
+    reader: EnvelopeReader,
+    codec: Codec,
+    // A large message we are still getting the chunks of, for each stream
+    partials: Vec<Option<PartialMessage>>,
+    traffic: Arc<Mutex<TrafficStats>>,
+    // Received for the message being read so far
+    pending_bytes: u64,
+}
+
+// What went through a connection, or through all of them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct Traffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+}
+
+impl Traffic {
+    pub fn add(&mut self, other: &Traffic) {
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+        self.frames_sent += other.frames_sent;
+        self.frames_received += other.frames_received;
+    }
+}
+
+// Traffic of a connection, shared by both of its halves. Chunks count
+// towards the message they are part of.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TrafficStats {
+    pub total: Traffic,
+    // By `Frame::name`, with messages we couldn't decode as "Unknown"
+    pub by_message: BTreeMap<String, Traffic>,
+}
+
+impl TrafficStats {
+    fn sent(&mut self, name: &str, bytes: u64) {
+        for traffic in [
+            &mut self.total,
+            self.by_message.entry(name.to_string()).or_default(),
+        ] {
+            traffic.bytes_sent += bytes;
+            traffic.frames_sent += 1;
+        }
+    }
+
+    fn received(&mut self, name: &str, bytes: u64) {
+        for traffic in [
+            &mut self.total,
+            self.by_message.entry(name.to_string()).or_default(),
+        ] {
+            traffic.bytes_received += bytes;
+            traffic.frames_received += 1;
+        }
+    }
+}
+
+// Piece of a message too large to be sent in a single frame. The chunks of
+// a message are sent one after the other, in order.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Chunk {
+    message_id: u32,
+    // Of the message being sent
+    message_type: u32,
+    sequence: u32,
+    total: u32,
+    data: Vec<u8>,
+}
+
+struct PartialMessage {
+    message_id: u32,
+    next_sequence: u32,
+    data: Vec<u8>,
+}
+
+// Always bincode encoded, which for it is two little-endian u32s followed
+// by a little-endian u64 length and the payload bytes
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope {
+    message_type: u32,
+    // Protocol version the payload was encoded for
+    version: u32,
+    // The `Frame`, encoded with the codec of the connection. Bincode lays
+    // `Bytes` out like a `Vec<u8>`.
+    payload: Bytes,
+}
+
+// How frame payloads are encoded. Connections start with bincode and switch
+// to the codec later in this list that both sides support.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Codec {
+    // Positional, so only practical to decode from Rust
+    Bincode,
+    // Self-describing, with deterministic (shortest form) integers
+    Cbor,
+}
+
+const SUPPORTED_CODECS: [Codec; 2] = [Codec::Bincode, Codec::Cbor];
+
+impl Codec {
+    fn encode(&self, frame: &Frame) -> Vec<u8> {
+        match self {
+            Codec::Bincode => bincode::serialize(frame).unwrap(),
+            Codec::Cbor => {
+                let mut payload = Vec::new();
+
+                ciborium::ser::into_writer(frame, &mut payload).unwrap();
+
+                payload
+            }
+        }
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Frame, String> {
+        match self {
+            Codec::Bincode => bincode::deserialize(payload).map_err(|issue| issue.to_string()),
+            Codec::Cbor => ciborium::de::from_reader(payload).map_err(|issue| issue.to_string()),
+        }
+    }
+}
+
+// A frame as it goes over the wire, encoded with `codec`. Frames read from
+// a peer keep the bytes they came in, so relaying them to peers using the
+// same codec doesn't take encoding them again for each.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payload {
+    codec: Codec,
+    bytes: Bytes,
+}
+
+impl Payload {
+    pub fn encode(frame: &Frame, codec: Codec) -> Self {
+        Payload {
+            codec,
+            bytes: codec.encode(frame).into(),
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn decode(&self) -> Result<Frame, String> {
+        self.codec.decode(&self.bytes)
+    }
+}
+
+// What we learn about the other side during the handshake
+pub struct Handshake {
+    pub version: Version,
+    // Their clock, for peers on protocol version 4 or later
+    pub time: Option<u64>,
+    // Peers older than protocol version 7 take every transaction
+    pub relay: RelayPolicy,
+    // Where the peer sees our connection coming from, for peers on protocol
+    // version 8 or later
+    pub observed_address: Option<SocketAddr>,
+    // Id of the network profile the peer follows, None for clients
+    pub network: Option<blockchain::Hash>,
+}
+
+// Which unconfirmed transactions a node wants from its peers
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RelayPolicy {
+    #[default]
+    Full,
+    // Only blocks, for nodes that want to use less bandwidth
+    BlocksOnly,
+}
+
+// First message each side sends on a new connection
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Version {
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    pub user_agent: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Frame {
     Block(blockchain::Block),
     Transaction(blockchain::Transaction),
+    // Light client protocol
+    GetHeaders(blockchain::Hash),
+    Headers(Vec<blockchain::BlockHeader>),
+    FilterLoad(BloomFilter),
+    GetMerkleBlocks(Vec<blockchain::Hash>),
+    MerkleBlocks(Vec<blockchain::MerkleBlock>),
+    GetCompactFilters(Vec<blockchain::Hash>),
+    CompactFilters(Vec<(blockchain::Hash, CompactFilter)>),
+    GetBlocks(Vec<blockchain::Hash>),
+    Blocks(Vec<blockchain::Block>),
+    Request(rpc::Request),
+    Response(rpc::Response),
+    // Sent to whoever submitted a transaction that got evicted from the
+    // mempool without being mined
+    TransactionExpired(blockchain::Hash),
+    // Sent back to whoever submitted a block or transaction that was not
+    // accepted
+    Reject {
+        kind: RejectKind,
+        hash: blockchain::Hash,
+        reason: blockchain::RejectReason,
+    },
+    Version(Version),
+    // Sent right after `Version` from protocol version 2 on
+    Codecs(Vec<Codec>),
+    // From protocol version 3 on
+    Chunk(Chunk),
+    // Unix time of the sender, sent after `Codecs` from protocol version 4 on
+    Time(u64),
+    // Id of the network profile of the sender, sent after `Time` from
+    // protocol version 6 on. Clients that follow any network send none.
+    Network(Option<blockchain::Hash>),
+    // Relay policy of the sender, sent after `Network` from protocol
+    // version 7 on
+    Relay(RelayPolicy),
+    // Asks for addresses of other nodes, answered with `Frame::Addr`
+    GetAddr,
+    // Addresses nodes can be reached at. Also sent unprompted by nodes
+    // announcing their own.
+    Addr(Vec<SocketAddr>),
+    // Where the sender sees the connection coming from, sent after `Relay`
+    // from protocol version 8 on
+    ObservedAddress(Option<SocketAddr>),
+    // Signed by a checkpoint signer of the network and relayed to every
+    // peer. Also sent after the handshake, so new peers learn the latest.
+    Checkpoint(Checkpoint),
+    // Between the two sides of a payment channel, not nodes
+    ChannelUpdate(ChannelUpdate),
+    // Answered with a `Frame::Pong` carrying the same nonce, to measure the
+    // round trip to the peer
+    Ping(u64),
+    Pong(u64),
+}
+
+impl Frame {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Frame::Block(_) => "Block",
+            Frame::Transaction(_) => "Transaction",
+            Frame::GetHeaders(_) => "GetHeaders",
+            Frame::Headers(_) => "Headers",
+            Frame::FilterLoad(_) => "FilterLoad",
+            Frame::GetMerkleBlocks(_) => "GetMerkleBlocks",
+            Frame::MerkleBlocks(_) => "MerkleBlocks",
+            Frame::GetCompactFilters(_) => "GetCompactFilters",
+            Frame::CompactFilters(_) => "CompactFilters",
+            Frame::GetBlocks(_) => "GetBlocks",
+            Frame::Blocks(_) => "Blocks",
+            Frame::Request(_) => "Request",
+            Frame::Response(_) => "Response",
+            Frame::TransactionExpired(_) => "TransactionExpired",
+            Frame::Reject { .. } => "Reject",
+            Frame::Version(_) => "Version",
+            Frame::Codecs(_) => "Codecs",
+            Frame::Chunk(_) => "Chunk",
+            Frame::Time(_) => "Time",
+            Frame::Network(_) => "Network",
+            Frame::Relay(_) => "Relay",
+            Frame::GetAddr => "GetAddr",
+            Frame::Addr(_) => "Addr",
+            Frame::ObservedAddress(_) => "ObservedAddress",
+            Frame::Checkpoint(_) => "Checkpoint",
+            Frame::ChannelUpdate(_) => "ChannelUpdate",
+            Frame::Ping(_) => "Ping",
+            Frame::Pong(_) => "Pong",
+        }
+    }
+
+    fn priority(&self) -> Priority {
+        match self {
+            Frame::Version(_)
+            | Frame::Codecs(_)
+            | Frame::Time(_)
+            | Frame::Network(_)
+            | Frame::Relay(_)
+            | Frame::ObservedAddress(_)
+            | Frame::Block(_)
+            | Frame::Checkpoint(_)
+            | Frame::Ping(_)
+            | Frame::Pong(_) => Priority::Critical,
+            _ if self.lane() == Lane::Bulk => Priority::Bulk,
+            _ => Priority::Normal,
+        }
+    }
+
+    // Blocks sent for syncing, and what light clients sync from, take the
+    // bulk lane. A new block stays on the gossip one, to spread fast.
+    fn lane(&self) -> Lane {
+        match self {
+            Frame::Headers(_)
+            | Frame::MerkleBlocks(_)
+            | Frame::CompactFilters(_)
+            | Frame::Blocks(_) => Lane::Bulk,
+            _ => Lane::Gossip,
+        }
+    }
+
+    // Position of the variant, which never changes
+    fn message_type(&self) -> u32 {
+        match self {
+            Frame::Block(_) => 0,
+            Frame::Transaction(_) => 1,
+            Frame::GetHeaders(_) => 2,
+            Frame::Headers(_) => 3,
+            Frame::FilterLoad(_) => 4,
+            Frame::GetMerkleBlocks(_) => 5,
+            Frame::MerkleBlocks(_) => 6,
+            Frame::GetCompactFilters(_) => 7,
+            Frame::CompactFilters(_) => 8,
+            Frame::GetBlocks(_) => 9,
+            Frame::Blocks(_) => 10,
+            Frame::Request(_) => 11,
+            Frame::Response(_) => 12,
+            Frame::TransactionExpired(_) => 13,
+            Frame::Reject { .. } => 14,
+            Frame::Version(_) => 15,
+            Frame::Codecs(_) => 16,
+            Frame::Chunk(_) => 17,
+            Frame::Time(_) => 18,
+            Frame::Network(_) => 19,
+            Frame::Relay(_) => 20,
+            Frame::GetAddr => 21,
+            Frame::Addr(_) => 22,
+            Frame::ObservedAddress(_) => 23,
+            Frame::Checkpoint(_) => 24,
+            Frame::ChannelUpdate(_) => 25,
+            Frame::Ping(_) => 26,
+            Frame::Pong(_) => 27,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectKind {
+    Block,
+    Transaction,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn length_delimited() -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(MAX_FRAME_SIZE)
+        .new_codec()
 }
 
 impl Connection {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(stream: TcpStream) -> (WriteConnection, ReadConnection) {
+        let peer_address = stream.peer_addr().ok();
         let (rx, tx) = stream.into_split();
 
+        Self::from_streams(vec![(Box::new(tx), Box::new(rx))], peer_address)
+    }
+
+    // Over the streams of another transport, one for each `Lane` at most.
+    // Frames of the lanes without a stream go on the last one.
+    pub fn from_streams(
+        streams: Vec<(StreamWriter, StreamReader)>,
+        peer_address: Option<SocketAddr>,
+    ) -> (WriteConnection, ReadConnection) {
+        let traffic = Arc::new(Mutex::new(TrafficStats::default()));
+        let count = streams.len();
+        let mut outboxes = Vec::new();
+        let mut readers = Vec::new();
+
+        for (index, (tx, rx)) in streams.into_iter().enumerate() {
+            let outbox = Arc::new(Outbox {
+                queue: Mutex::new(Queue::default()),
+                queued: Notify::new(),
+                room: Semaphore::new(MAX_QUEUED_BYTES),
+            });
+
+            tokio::spawn(outbox.clone().drain(SymmetricallyFramed::new(
+                FramedWrite::new(tx, length_delimited()),
+                SymmetricalBincode::<Envelope>::default(),
+            )));
+
+            outboxes.push(outbox);
+            readers.push(
+                SymmetricallyFramed::new(
+                    FramedRead::new(rx, length_delimited()),
+                    SymmetricalBincode::<Envelope>::default(),
+                )
+                .map(move |envelope| (index, envelope)),
+            );
+        }
+
         (
             WriteConnection {
-                writter: SymmetricallyFramed::new(
-                    FramedWrite::new(tx, LengthDelimitedCodec::new()),
-                    SymmetricalBincode::<Frame>::default(),
-                ),
+                outboxes,
+                protocol_version: PROTOCOL_VERSION,
+                codec: Codec::Bincode,
+                next_message_id: 0,
+                traffic: traffic.clone(),
+                peer_address,
             },
             ReadConnection {
-                reader: SymmetricallyFramed::new(
-                    FramedRead::new(rx, LengthDelimitedCodec::new()),
-                    SymmetricalBincode::<Frame>::default(),
-                ),
+                reader: Box::pin(futures::stream::select_all(readers)),
+                codec: Codec::Bincode,
+                partials: (0..count).map(|_| None).collect(),
+                traffic,
+                pending_bytes: 0,
             },
         )
     }
+
+    // Exchanges `Frame::Version` (and whatever the agreed protocol version
+    // adds to the handshake) with the other side. Fails when neither can
+    // talk a version the other still supports, or when both follow a
+    // network and it is not the same one. `relay` tells the other side
+    // whether to send us unconfirmed transactions.
+    pub async fn handshake(
+        writter: &mut WriteConnection,
+        reader: &mut ReadConnection,
+        user_agent: &str,
+        network: Option<blockchain::Hash>,
+        relay: RelayPolicy,
+    ) -> Result<Handshake, String> {
+        writter
+            .write(Frame::Version(Version {
+                protocol_version: PROTOCOL_VERSION,
+                min_protocol_version: MIN_PROTOCOL_VERSION,
+                user_agent: user_agent.to_string(),
+            }))
+            .await;
+
+        let theirs = match reader.read().await {
+            Some(Frame::Version(version)) => version,
+            Some(_) => return Err("Expected a version message first".to_string()),
+            None => return Err("Connection closed during the handshake".to_string()),
+        };
+
+        let negotiated = PROTOCOL_VERSION.min(theirs.protocol_version);
+
+        if negotiated < MIN_PROTOCOL_VERSION || negotiated < theirs.min_protocol_version {
+            return Err(format!(
+                "Incompatible protocol versions: we talk {} to {}, {} talks {} to {}",
+                MIN_PROTOCOL_VERSION,
+                PROTOCOL_VERSION,
+                theirs.user_agent,
+                theirs.min_protocol_version,
+                theirs.protocol_version
+            ));
+        }
+
+        writter.protocol_version = negotiated;
+
+        if negotiated >= CODECS_PROTOCOL_VERSION {
+            writter
+                .write(Frame::Codecs(SUPPORTED_CODECS.to_vec()))
+                .await;
+
+            let codec = match reader.read().await {
+                Some(Frame::Codecs(codecs)) => codecs
+                    .into_iter()
+                    .filter(|codec| SUPPORTED_CODECS.contains(codec))
+                    .max()
+                    .unwrap_or(Codec::Bincode),
+                Some(_) => return Err("Expected the supported codecs".to_string()),
+                None => return Err("Connection closed during the handshake".to_string()),
+            };
+
+            writter.codec = codec;
+            reader.codec = codec;
+        }
+
+        let mut time = None;
+
+        if negotiated >= TIME_PROTOCOL_VERSION {
+            writter.write(Frame::Time(unix_time())).await;
+
+            time = match reader.read().await {
+                Some(Frame::Time(time)) => Some(time),
+                Some(_) => return Err("Expected the time".to_string()),
+                None => return Err("Connection closed during the handshake".to_string()),
+            };
+        }
+
+        let mut their_network = None;
+
+        if negotiated >= NETWORK_PROTOCOL_VERSION {
+            writter.write(Frame::Network(network)).await;
+
+            their_network = match reader.read().await {
+                Some(Frame::Network(network)) => network,
+                Some(_) => return Err("Expected the network".to_string()),
+                None => return Err("Connection closed during the handshake".to_string()),
+            };
+
+            if let (Some(ours), Some(theirs)) = (network, their_network) {
+                if ours != theirs {
+                    return Err(format!(
+                        "Peer is on another network ({} instead of {})",
+                        blockchain::HashFmt(theirs),
+                        blockchain::HashFmt(ours)
+                    ));
+                }
+            }
+        }
+
+        let mut their_relay = RelayPolicy::Full;
+
+        if negotiated >= RELAY_PROTOCOL_VERSION {
+            writter.write(Frame::Relay(relay)).await;
+
+            their_relay = match reader.read().await {
+                Some(Frame::Relay(relay)) => relay,
+                Some(_) => return Err("Expected the relay policy".to_string()),
+                None => return Err("Connection closed during the handshake".to_string()),
+            };
+        }
+
+        let mut observed_address = None;
+
+        if negotiated >= OBSERVED_ADDRESS_PROTOCOL_VERSION {
+            writter
+                .write(Frame::ObservedAddress(writter.peer_address))
+                .await;
+
+            observed_address = match reader.read().await {
+                Some(Frame::ObservedAddress(address)) => address,
+                Some(_) => return Err("Expected the observed address".to_string()),
+                None => return Err("Connection closed during the handshake".to_string()),
+            };
+        }
+
+        Ok(Handshake {
+            version: theirs,
+            time,
+            relay: their_relay,
+            observed_address,
+            network: their_network,
+        })
+    }
 }
 
 impl ReadConnection {
+    // Skips over messages we don't know how to decode, they were most likely
+    // added in a protocol version newer than ours
     pub async fn read(&mut self) -> Option<Frame> {
-        self.reader.try_next().await.unwrap()
+        self.read_payload().await.map(|(frame, _)| frame)
+    }
+
+    // Like `read`, also returning the payload the frame was decoded from
+    pub async fn read_payload(&mut self) -> Option<(Frame, Payload)> {
+        loop {
+            // A broken stream ends the connection, like a closed one
+            let (stream, envelope) = self.reader.next().await?;
+            let envelope = envelope.ok()?;
+
+            self.pending_bytes += envelope.payload.len() as u64 + ENVELOPE_OVERHEAD;
+
+            let (frame, bytes) = match self.codec.decode(&envelope.payload) {
+                Ok(Frame::Chunk(chunk)) => match self.reassemble(stream, chunk) {
+                    Some(payload) => (self.codec.decode(&payload), payload.into()),
+                    None => continue,
+                },
+                decoded => (decoded, envelope.payload),
+            };
+
+            let name = match &frame {
+                Ok(frame) => frame.name(),
+                Err(_) => "Unknown",
+            };
+
+            self.traffic
+                .lock()
+                .unwrap()
+                .received(name, self.pending_bytes);
+            self.pending_bytes = 0;
+
+            match frame {
+                Ok(frame) => {
+                    let payload = Payload {
+                        codec: self.codec,
+                        bytes,
+                    };
+
+                    return Some((frame, payload));
+                }
+                Err(_) => println!(
+                    "SKIPPING UNKNOWN MESSAGE TYPE {} (VERSION {})",
+                    envelope.message_type, envelope.version
+                ),
+            }
+        }
+    }
+
+    // Adds a chunk to the message being received, returning its payload
+    // once all the chunks are in
+    fn reassemble(&mut self, stream: usize, chunk: Chunk) -> Option<Vec<u8>> {
+        let mut partial = match self.partials[stream].take() {
+            Some(partial) if partial.message_id == chunk.message_id => partial,
+            _ if chunk.sequence == 0 => PartialMessage {
+                message_id: chunk.message_id,
+                next_sequence: 0,
+                data: Vec::new(),
+            },
+            _ => {
+                println!("DROPPING CHUNK OF UNKNOWN MESSAGE {}", chunk.message_id);
+
+                return None;
+            }
+        };
+
+        if chunk.sequence != partial.next_sequence
+            || partial.data.len() + chunk.data.len() > MAX_MESSAGE_SIZE
+        {
+            println!("DROPPING MESSAGE {}: BAD CHUNK", chunk.message_id);
+
+            return None;
+        }
+
+        partial.data.extend_from_slice(&chunk.data);
+        partial.next_sequence += 1;
+
+        if partial.next_sequence == chunk.total {
+            Some(partial.data)
+        } else {
+            self.partials[stream] = Some(partial);
+
+            None
+        }
     }
 }
 
 impl WriteConnection {
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn traffic(&self) -> TrafficStats {
+        self.traffic.lock().unwrap().clone()
+    }
+
     pub async fn write(&mut self, frame: Frame) {
-        self.writter.send(frame).await.unwrap();
+        let payload = self.codec.encode(&frame).into();
+
+        self.write_encoded(&frame, payload).await;
+    }
+
+    // Sends `frame` as `payload` when it is in the codec of this connection,
+    // which is then not encoded again
+    pub async fn write_payload(&mut self, frame: &Frame, payload: &Payload) {
+        let bytes = if payload.codec == self.codec {
+            payload.bytes.clone()
+        } else {
+            self.codec.encode(frame).into()
+        };
+
+        self.write_encoded(frame, bytes).await;
+    }
+
+    // Waits for everything written so far to be sent, or for the
+    // connection to break
+    pub async fn flush(&self) {
+        for outbox in &self.outboxes {
+            let _ = outbox.room.acquire_many(MAX_QUEUED_BYTES as u32).await;
+        }
+    }
+
+    // Large messages are split in chunks for peers that can put them back
+    // together
+    async fn write_encoded(&mut self, frame: &Frame, payload: Bytes) {
+        let mut envelopes = VecDeque::new();
+
+        if payload.len() <= MAX_CHUNK_SIZE || self.protocol_version < CHUNKS_PROTOCOL_VERSION {
+            envelopes.push_back(self.envelope(frame.message_type(), payload));
+        } else {
+            let message_id = self.next_message_id;
+            let chunks = payload.chunks(MAX_CHUNK_SIZE);
+            let total = chunks.len() as u32;
+
+            self.next_message_id = self.next_message_id.wrapping_add(1);
+
+            for (sequence, data) in chunks.enumerate() {
+                let chunk = Frame::Chunk(Chunk {
+                    message_id,
+                    message_type: frame.message_type(),
+                    sequence: sequence as u32,
+                    total,
+                    data: data.to_vec(),
+                });
+
+                envelopes.push_back(
+                    self.envelope(chunk.message_type(), self.codec.encode(&chunk).into()),
+                );
+            }
+        }
+
+        let bytes: u64 = envelopes
+            .iter()
+            .map(|envelope| envelope.payload.len() as u64 + ENVELOPE_OVERHEAD)
+            .sum();
+        let stream = (frame.lane() as usize).min(self.outboxes.len() - 1);
+
+        self.outboxes[stream]
+            .push(frame.priority(), envelopes, bytes)
+            .await;
+        self.traffic.lock().unwrap().sent(frame.name(), bytes);
+    }
+
+    fn envelope(&self, message_type: u32, payload: Bytes) -> Envelope {
+        Envelope {
+            message_type,
+            version: self.protocol_version,
+            payload,
+        }
+    }
+}
+
+// What was already written still goes out
+impl Drop for WriteConnection {
+    fn drop(&mut self) {
+        for outbox in &self.outboxes {
+            outbox.queue.lock().unwrap().closed = true;
+            outbox.queued.notify_one();
+        }
+    }
+}
+
+impl Outbox {
+    // Waits for room when the peer doesn't take what we send as fast, like
+    // writing to the stream would. Messages larger than all the room wait
+    // for the queue to be empty.
+    async fn push(&self, priority: Priority, envelopes: VecDeque<Envelope>, bytes: u64) {
+        let room = bytes.min(MAX_QUEUED_BYTES as u64) as u32;
+
+        // Closed once sending fails, then the frame is dropped
+        match self.room.acquire_many(room).await {
+            Ok(permit) => permit.forget(),
+            Err(_) => return,
+        }
+
+        self.queue.lock().unwrap().messages[priority as usize].push_back((room, envelopes));
+        self.queued.notify_one();
+    }
+
+    async fn drain(self: Arc<Self>, mut writter: EnvelopeWriter) {
+        loop {
+            let next = {
+                let mut queue = self.queue.lock().unwrap();
+
+                match queue.next() {
+                    Some(next) => Some(next),
+                    None if queue.closed => break,
+                    None => None,
+                }
+            };
+
+            let (envelope, room) = match next {
+                Some(next) => next,
+                None => {
+                    self.queued.notified().await;
+
+                    continue;
+                }
+            };
+
+            if writter.send(envelope).await.is_err() {
+                break;
+            }
+
+            self.room.add_permits(room as usize);
+        }
+
+        self.queue.lock().unwrap().closed = true;
+        self.room.close();
+    }
+}
+
+impl Queue {
+    // The next envelope to send, with the room to give back once it is,
+    // which is all the room its message took after the last envelope
+    fn next(&mut self) -> Option<(Envelope, u32)> {
+        for priority in PRIORITIES {
+            if let Some((started, room, envelopes)) = &mut self.chunked {
+                if *started == priority {
+                    let envelope = envelopes.pop_front()?;
+
+                    if !envelopes.is_empty() {
+                        return Some((envelope, 0));
+                    }
+
+                    let room = *room;
+
+                    self.chunked = None;
+
+                    return Some((envelope, room));
+                }
+            }
+
+            let messages = &mut self.messages[priority as usize];
+
+            match messages.front() {
+                Some((_, envelopes)) if envelopes.len() == 1 || self.chunked.is_none() => {
+                    let (room, mut envelopes) = messages.pop_front()?;
+                    let envelope = envelopes.pop_front()?;
+
+                    if envelopes.is_empty() {
+                        return Some((envelope, room));
+                    }
+
+                    self.chunked = Some((priority, room, envelopes));
+
+                    return Some((envelope, 0));
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reading from two streams that never send anything
+    fn reader() -> ReadConnection {
+        ReadConnection {
+            reader: Box::pin(futures::stream::empty()),
+            codec: Codec::Bincode,
+            partials: vec![None, None],
+            traffic: Arc::new(Mutex::new(TrafficStats::default())),
+            pending_bytes: 0,
+        }
+    }
+
+    fn chunk(message_id: u32, sequence: u32, total: u32, data: &[u8]) -> Chunk {
+        Chunk {
+            message_id,
+            message_type: 10,
+            sequence,
+            total,
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn large_messages_go_in_chunks() {
+        let (ours, theirs) = tokio::io::duplex(64 * 1024);
+        let streams = [ours, theirs].map(|stream| {
+            let (rx, tx) = tokio::io::split(stream);
+
+            vec![(Box::new(tx) as StreamWriter, Box::new(rx) as StreamReader)]
+        });
+        let [ours, theirs] = streams;
+        let (mut writter, _reader) = Connection::from_streams(ours, None);
+        let (_writter, mut reader) = Connection::from_streams(theirs, None);
+        let addresses = vec![SocketAddr::from(([127, 0, 0, 1], 8000)); 200_000];
+
+        writter.write(Frame::Addr(addresses.clone())).await;
+        writter.write(Frame::GetAddr).await;
+
+        assert!(matches!(reader.read().await, Some(Frame::Addr(read)) if read == addresses));
+        assert!(matches!(reader.read().await, Some(Frame::GetAddr)));
+        assert!(writter.traffic().by_message["Addr"].bytes_sent > MAX_CHUNK_SIZE as u64);
+    }
+
+    #[test]
+    fn chunks_of_two_streams_are_put_back_together_apart() {
+        let mut reader = reader();
+
+        assert_eq!(reader.reassemble(0, chunk(1, 0, 2, b"ab")), None);
+        assert_eq!(reader.reassemble(1, chunk(7, 0, 3, b"x")), None);
+        assert_eq!(reader.reassemble(1, chunk(7, 1, 3, b"y")), None);
+        assert_eq!(
+            reader.reassemble(0, chunk(1, 1, 2, b"cd")),
+            Some(b"abcd".to_vec())
+        );
+        assert_eq!(
+            reader.reassemble(1, chunk(7, 2, 3, b"z")),
+            Some(b"xyz".to_vec())
+        );
+    }
+
+    #[test]
+    fn chunks_out_of_order_drop_their_message() {
+        let mut reader = reader();
+
+        // A message can't start in the middle
+        assert_eq!(reader.reassemble(0, chunk(1, 1, 2, b"cd")), None);
+        assert_eq!(reader.reassemble(0, chunk(1, 0, 2, b"ab")), None);
+
+        // Missing a chunk drops what came before, and what comes after
+        assert_eq!(reader.reassemble(0, chunk(1, 2, 3, b"ef")), None);
+        assert_eq!(reader.reassemble(0, chunk(1, 1, 3, b"cd")), None);
+        assert!(reader.partials[0].is_none());
+
+        // So does getting one twice
+        assert_eq!(reader.reassemble(0, chunk(2, 0, 2, b"ab")), None);
+        assert_eq!(reader.reassemble(0, chunk(2, 0, 2, b"ab")), None);
+        assert_eq!(reader.reassemble(0, chunk(2, 1, 2, b"cd")), None);
+
+        // The next message starts over
+        assert_eq!(reader.reassemble(0, chunk(3, 0, 2, b"ab")), None);
+        assert_eq!(
+            reader.reassemble(0, chunk(3, 1, 2, b"cd")),
+            Some(b"abcd".to_vec())
+        );
+    }
+
+    // Queues `frame` as a message of `envelopes`, more than one for chunks
+    fn push(queue: &mut Queue, frame: Frame, envelopes: usize) {
+        let envelopes = (0..envelopes)
+            .map(|_| Envelope {
+                message_type: frame.message_type(),
+                version: PROTOCOL_VERSION,
+                payload: Codec::Bincode.encode(&frame).into(),
+            })
+            .collect();
+
+        queue.messages[frame.priority() as usize].push_back((1, envelopes));
+    }
+
+    // Message types of what is sent until the queue is empty
+    fn sent(queue: &mut Queue) -> Vec<u32> {
+        std::iter::from_fn(|| queue.next())
+            .map(|(envelope, _)| envelope.message_type)
+            .collect()
+    }
+
+    #[test]
+    fn new_blocks_and_the_handshake_go_first() {
+        let header = blockchain::BlockHeader::new(0, [0; 32], [0; 32], [0; 32], [0; 32]);
+        let block = blockchain::Block::new(header, Vec::new());
+        let mut queue = Queue::default();
+
+        push(&mut queue, Frame::Blocks(Vec::new()), 1);
+        push(&mut queue, Frame::TransactionExpired([0; 32]), 1);
+        push(&mut queue, Frame::Headers(Vec::new()), 1);
+        push(&mut queue, Frame::Block(block), 1);
+        push(&mut queue, Frame::GetAddr, 1);
+        push(&mut queue, Frame::Ping(1), 1);
+
+        // Then the rest, by priority and in the order they were queued
+        assert_eq!(sent(&mut queue), [0, 26, 13, 21, 10, 3]);
+    }
+
+    #[test]
+    fn single_envelopes_go_in_between_chunks() {
+        let mut queue = Queue::default();
+
+        push(&mut queue, Frame::Blocks(Vec::new()), 3);
+        assert_eq!(
+            queue
+                .next()
+                .map(|(envelope, room)| (envelope.message_type, room)),
+            Some((10, 0))
+        );
+
+        push(&mut queue, Frame::Ping(1), 1);
+        push(&mut queue, Frame::Addr(Vec::new()), 2);
+        push(&mut queue, Frame::GetAddr, 1);
+
+        // Chunks of another message wait for the first to be sent
+        assert_eq!(sent(&mut queue), [26, 10, 10, 22, 22, 21]);
+    }
+
+    #[test]
+    fn messages_over_the_size_limit_are_dropped() {
+        let mut reader = reader();
+
+        reader.partials[1] = Some(PartialMessage {
+            message_id: 1,
+            next_sequence: 1,
+            data: vec![0; MAX_MESSAGE_SIZE - 1],
+        });
+
+        assert_eq!(reader.reassemble(1, chunk(1, 1, 3, b"ab")), None);
+        assert!(reader.partials[1].is_none());
+        assert_eq!(reader.reassemble(1, chunk(1, 2, 3, b"c")), None);
     }
 }
@@ -0,0 +1,361 @@
+use super::address_index::AddressIndex;
+use super::blockchain::{self, Block, Hash, HashFmt, Node, Output, PublicKey, Transaction};
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema};
+use fcoin_core::{address, hex};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Most entries a single page can have
+const MAX_PAGE_SIZE: usize = 100;
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+pub type ChainSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+// What the resolvers read from
+struct ChainData {
+    node: Arc<Mutex<Node>>,
+    addresses: Arc<Mutex<AddressIndex>>,
+}
+
+pub fn schema(node: Arc<Mutex<Node>>, addresses: Arc<Mutex<AddressIndex>>) -> ChainSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(ChainData { node, addresses })
+        .finish()
+}
+
+fn chain<'a>(context: &Context<'a>) -> &'a ChainData {
+    context.data_unchecked::<ChainData>()
+}
+
+fn page_size(first: Option<i32>) -> usize {
+    first
+        .map(|first| first.max(0) as usize)
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .min(MAX_PAGE_SIZE)
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Blocks of the main chain, newest first. Cursors are block hashes.
+    async fn blocks(
+        &self,
+        context: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, BlockObject>> {
+        let node = chain(context).node.lock().await;
+        let limit = page_size(first);
+
+        let mut hash = match after {
+            Some(cursor) => {
+                let after = hex::decode_array::<32>(&cursor)?;
+
+                match node.block(&after) {
+                    Some(block) => *block.header().previous_hash(),
+                    None => return Err(format!("Block {} not found", cursor).into()),
+                }
+            }
+            None => node.tip_hash(),
+        };
+
+        let mut blocks = Vec::new();
+
+        while let Some(block) = node.block(&hash) {
+            if blocks.len() == limit {
+                break;
+            }
+
+            blocks.push(block.clone());
+            hash = *block.header().previous_hash();
+        }
+
+        let mut connection = Connection::new(false, node.block(&hash).is_some());
+
+        connection.edges.extend(
+            blocks
+                .into_iter()
+                .map(|block| Edge::new(HashFmt(block.hash()).to_string(), BlockObject(block))),
+        );
+
+        Ok(connection)
+    }
+
+    async fn block(&self, context: &Context<'_>, hash: String) -> Result<Option<BlockObject>> {
+        let hash = hex::decode_array::<32>(&hash)?;
+        let node = chain(context).node.lock().await;
+
+        Ok(node.block(&hash).cloned().map(BlockObject))
+    }
+
+    /// A transaction waiting in the mempool, or else one mined in the main
+    /// chain, which needs the transaction index (txindex)
+    async fn transaction(
+        &self,
+        context: &Context<'_>,
+        hash: String,
+    ) -> Result<Option<TransactionObject>> {
+        let hash = hex::decode_array::<32>(&hash)?;
+        let node = chain(context).node.lock().await;
+
+        if let Some(transaction) = node.mempool().get(&hash) {
+            return Ok(Some(TransactionObject {
+                transaction: transaction.clone(),
+                block_hash: None,
+            }));
+        }
+
+        Ok(node
+            .transaction(&hash)?
+            .map(|(transaction, location)| TransactionObject {
+                transaction: transaction.clone(),
+                block_hash: Some(location.block_hash),
+            }))
+    }
+
+    async fn address(&self, address: String) -> Result<AddressObject> {
+        Ok(AddressObject(address::decode(&address)?))
+    }
+
+    /// Transactions waiting to be mined, highest fee rate first. Cursors are
+    /// transaction hashes.
+    async fn mempool(
+        &self,
+        context: &Context<'_>,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> Result<Connection<String, MempoolEntryObject>> {
+        let node = chain(context).node.lock().await;
+        let now = blockchain::timestamp();
+
+        let mut entries: Vec<MempoolEntryObject> = node
+            .mempool()
+            .entries()
+            .map(|(hash, entry)| MempoolEntryObject {
+                hash: *hash,
+                transaction: entry.transaction().clone(),
+                age: now.saturating_sub(entry.received()),
+            })
+            .collect();
+
+        // Ties go to whoever has been waiting longer, like in getrawmempool
+        entries.sort_by(|a, b| {
+            b.transaction
+                .fee_rate()
+                .cmp(&a.transaction.fee_rate())
+                .then(b.age.cmp(&a.age))
+        });
+
+        let start = match after {
+            Some(cursor) => {
+                let after = hex::decode_array::<32>(&cursor)?;
+
+                match entries.iter().position(|entry| entry.hash == after) {
+                    Some(position) => position + 1,
+                    None => return Err(format!("{} is not in the mempool", cursor).into()),
+                }
+            }
+            None => 0,
+        };
+
+        let end = (start + page_size(first)).min(entries.len());
+        let mut connection = Connection::new(start > 0, end < entries.len());
+
+        connection.edges.extend(
+            entries
+                .drain(start..end)
+                .map(|entry| Edge::new(HashFmt(entry.hash).to_string(), entry)),
+        );
+
+        Ok(connection)
+    }
+}
+
+pub struct BlockObject(Block);
+
+#[Object(name = "Block")]
+impl BlockObject {
+    async fn hash(&self) -> String {
+        HashFmt(self.0.hash()).to_string()
+    }
+
+    /// Unset for blocks out of the main chain
+    async fn height(&self, context: &Context<'_>) -> Option<u64> {
+        chain(context)
+            .node
+            .lock()
+            .await
+            .block_height(&self.0.hash())
+    }
+
+    async fn confirmations(&self, context: &Context<'_>) -> u64 {
+        chain(context)
+            .node
+            .lock()
+            .await
+            .confirmations(&self.0.hash())
+    }
+
+    async fn time(&self) -> u64 {
+        self.0.header().time()
+    }
+
+    async fn size(&self) -> u64 {
+        self.0.size()
+    }
+
+    async fn producer(&self) -> AddressObject {
+        AddressObject(*self.0.header().node_public_key())
+    }
+
+    async fn previous_block(&self, context: &Context<'_>) -> Option<BlockObject> {
+        let node = chain(context).node.lock().await;
+
+        node.block(self.0.header().previous_hash())
+            .cloned()
+            .map(BlockObject)
+    }
+
+    async fn transactions(&self) -> Vec<TransactionObject> {
+        let block_hash = self.0.hash();
+
+        self.0
+            .transactions()
+            .iter()
+            .map(|transaction| TransactionObject {
+                transaction: transaction.clone(),
+                block_hash: Some(block_hash),
+            })
+            .collect()
+    }
+}
+
+pub struct TransactionObject {
+    transaction: Transaction,
+    // None while in the mempool
+    block_hash: Option<Hash>,
+}
+
+#[Object(name = "Transaction")]
+impl TransactionObject {
+    async fn hash(&self) -> String {
+        HashFmt(self.transaction.hash()).to_string()
+    }
+
+    async fn source(&self) -> AddressObject {
+        AddressObject(*self.transaction.source())
+    }
+
+    /// Of the first output
+    async fn destination(&self) -> AddressObject {
+        AddressObject(*self.transaction.destination())
+    }
+
+    /// Of all the outputs
+    async fn amount(&self) -> u64 {
+        self.transaction.amount()
+    }
+
+    async fn outputs(&self) -> Vec<OutputObject> {
+        self.transaction
+            .outputs()
+            .into_iter()
+            .map(OutputObject)
+            .collect()
+    }
+
+    async fn fee(&self) -> u64 {
+        self.transaction.fee()
+    }
+
+    async fn size(&self) -> u64 {
+        self.transaction.size()
+    }
+
+    /// Where the transaction was mined, unset while in the mempool
+    async fn block(&self, context: &Context<'_>) -> Option<BlockObject> {
+        let node = chain(context).node.lock().await;
+
+        self.block_hash
+            .and_then(|hash| node.block(&hash).cloned())
+            .map(BlockObject)
+    }
+}
+
+pub struct OutputObject(Output);
+
+#[Object(name = "Output")]
+impl OutputObject {
+    async fn destination(&self) -> AddressObject {
+        AddressObject(self.0.destination)
+    }
+
+    async fn amount(&self) -> u64 {
+        self.0.amount
+    }
+}
+
+pub struct AddressObject(PublicKey);
+
+#[Object(name = "Address")]
+impl AddressObject {
+    async fn address(&self) -> String {
+        address::encode(&self.0)
+    }
+
+    /// As of our tip, not counting what is in the mempool
+    async fn balance(&self, context: &Context<'_>) -> u64 {
+        let addresses = chain(context).addresses.lock().await;
+
+        addresses.get(&self.0).map_or(0, |entry| entry.balance())
+    }
+
+    async fn transaction_count(&self, context: &Context<'_>) -> u64 {
+        let addresses = chain(context).addresses.lock().await;
+
+        addresses
+            .get(&self.0)
+            .map_or(0, |entry| entry.transactions())
+    }
+
+    /// Height of the first block the address appears in
+    async fn first_seen(&self, context: &Context<'_>) -> Option<u64> {
+        let addresses = chain(context).addresses.lock().await;
+
+        addresses.get(&self.0).and_then(|entry| entry.first_seen())
+    }
+
+    async fn last_seen(&self, context: &Context<'_>) -> Option<u64> {
+        let addresses = chain(context).addresses.lock().await;
+
+        addresses.get(&self.0).and_then(|entry| entry.last_seen())
+    }
+}
+
+pub struct MempoolEntryObject {
+    hash: Hash,
+    transaction: Transaction,
+    age: u64,
+}
+
+#[Object(name = "MempoolEntry")]
+impl MempoolEntryObject {
+    /// Fee per 1000 bytes
+    async fn fee_rate(&self) -> u64 {
+        self.transaction.fee_rate()
+    }
+
+    /// Seconds since we received it
+    async fn age(&self) -> u64 {
+        self.age
+    }
+
+    async fn transaction(&self) -> TransactionObject {
+        TransactionObject {
+            transaction: self.transaction.clone(),
+            block_hash: None,
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::keystore::Keystore;
+use super::paper;
+use fcoin_core::{address, SecretKey};
+use std::io::Write;
+use zeroize::Zeroizing;
+
+// Key files hold the hex encoded 32 byte ed25519 secret key. Encrypted ones
+// hold it as `paper::encrypt` writes it instead, followed by its address on
+// a second line, so the node knows who to mine to before it is unlocked.
+// What is read from and written to them is wiped once done with.
+
+pub fn load(path: &str) -> Result<SecretKey, String> {
+    let content = read(path)?;
+
+    if paper::is_encrypted(content.lines().next().unwrap_or_default()) {
+        return Err(format!("Key file {} is encrypted", path));
+    }
+
+    decode_plain(&content).map_err(|issue| format!("Invalid key file {}: {}", path, issue))
+}
+
+pub fn load_keystore(path: &str) -> Result<Keystore, String> {
+    let content = read(path)?;
+
+    decode(&content).map_err(|issue| format!("Invalid key file {}: {}", path, issue))
+}
+
+// The contents of a key file, encrypted or not
+pub fn decode(content: &str) -> Result<Keystore, String> {
+    let mut lines = content.lines();
+
+    match (lines.next(), lines.next()) {
+        (Some(encrypted), Some(address)) if paper::is_encrypted(encrypted) => Ok(
+            Keystore::encrypted(encrypted.to_string(), address::decode(address.trim())?),
+        ),
+        _ => decode_plain(content).map(Keystore::plain),
+    }
+}
+
+fn decode_plain(content: &str) -> Result<SecretKey, String> {
+    let mut bytes = Zeroizing::new(fcoin_core::hex::decode_array(content.trim())?);
+
+    Ok(SecretKey::from_bytes(&mut bytes))
+}
+
+fn read(path: &str) -> Result<Zeroizing<String>, String> {
+    std::fs::read_to_string(path)
+        .map(Zeroizing::new)
+        .map_err(|issue| format!("Could not read key file {}: {}", path, issue))
+}
+
+// Replaces a key file in the clear with an encrypted one
+pub fn encrypt(path: &str, passphrase: &str) -> Result<(), String> {
+    let secret_key = load(path)?;
+    let temporary = format!("{}.tmp", path);
+
+    save_encrypted(&temporary, &secret_key, passphrase)?;
+
+    std::fs::rename(&temporary, path)
+        .map_err(|issue| format!("Could not write key file {}: {}", path, issue))
+}
+
+pub fn generate() -> SecretKey {
+    let mut secret_key = SecretKey::zeroed();
+
+    getrandom::getrandom(secret_key.as_mut_bytes()).expect("No randomness source available");
+
+    secret_key
+}
+
+pub fn save(path: &str, secret_key: &SecretKey) -> Result<(), String> {
+    let content = Zeroizing::new(format!(
+        "{}\n",
+        fcoin_core::hex::encode(secret_key.as_bytes())
+    ));
+
+    write_private(path, &content)
+}
+
+pub fn save_encrypted(path: &str, secret_key: &SecretKey, passphrase: &str) -> Result<(), String> {
+    let content = format!(
+        "{}\n{}\n",
+        paper::encrypt(secret_key, passphrase),
+        address::encode(&fcoin_core::keys::public_key(secret_key))
+    );
+
+    write_private(path, &content)
+}
+
+pub fn load_or_create(path: &str) -> Result<Keystore, String> {
+    if std::path::Path::new(path).exists() {
+        load_keystore(path)
+    } else {
+        println!("Creating a new key in {}", path);
+
+        let secret_key = generate();
+        save(path, &secret_key)?;
+
+        Ok(Keystore::plain(secret_key))
+    }
+}
+
+fn write_private(path: &str, content: &str) -> Result<(), String> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    // Nobody else should be able to read our keys
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|issue| format!("Could not write key file {}: {}", path, issue))
+}
@@ -0,0 +1,178 @@
+use super::paper;
+use super::policy::SpendingLog;
+use fcoin_core::{keys, PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use zeroize::Zeroize;
+
+// A passphrase sent over RPC, wiped once dropped. Requests are logged, so it
+// never shows in Debug output.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    pub fn new(passphrase: &str) -> Self {
+        Passphrase(passphrase.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Passphrase(..)")
+    }
+}
+
+impl Drop for Passphrase {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+// A key locked in memory, which keeps it from being swapped to disk where
+// the system allows it. Boxed, so it stays where it was locked.
+pub struct LockedKey(Box<SecretKey>);
+
+impl LockedKey {
+    pub fn new(secret_key: SecretKey) -> Self {
+        let locked = LockedKey(Box::new(secret_key));
+
+        if unsafe { libc::mlock(locked.address(), locked.size()) } != 0 {
+            println!("COULD NOT LOCK THE KEY IN MEMORY, IT MAY BE SWAPPED TO DISK");
+        }
+
+        locked
+    }
+
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.0
+    }
+
+    fn address(&self) -> *const libc::c_void {
+        self.0.as_bytes().as_ptr() as *const libc::c_void
+    }
+
+    fn size(&self) -> usize {
+        self.0.as_bytes().len()
+    }
+}
+
+impl Drop for LockedKey {
+    // Wiped before the memory is unlocked
+    fn drop(&mut self) {
+        self.0.as_mut_bytes().zeroize();
+
+        unsafe {
+            libc::munlock(self.address(), self.size());
+        }
+    }
+}
+
+// The key of the node. When its key file is encrypted it starts locked, and
+// is only kept decrypted for as long as it was unlocked for. Blocks are
+// still mined to its public key while it is locked.
+pub struct Keystore {
+    public_key: PublicKey,
+    // As written by `paper::encrypt`, None when the key file is in the clear
+    encrypted: Option<String>,
+    secret_key: Option<LockedKey>,
+    // When it locks again by itself
+    relock_at: Option<Instant>,
+    // For the daily limit of the spending policy, since the node started
+    spending: SpendingLog,
+}
+
+impl Keystore {
+    pub fn plain(secret_key: SecretKey) -> Self {
+        Keystore {
+            public_key: keys::public_key(&secret_key),
+            encrypted: None,
+            secret_key: Some(LockedKey::new(secret_key)),
+            relock_at: None,
+            spending: SpendingLog::default(),
+        }
+    }
+
+    pub fn encrypted(encrypted: String, public_key: PublicKey) -> Self {
+        Keystore {
+            public_key,
+            encrypted: Some(encrypted),
+            secret_key: None,
+            relock_at: None,
+            spending: SpendingLog::default(),
+        }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    // What `unlock` needs the passphrase to decrypt
+    pub fn encrypted_key(&self) -> Option<&str> {
+        self.encrypted.as_deref()
+    }
+
+    pub fn spending_mut(&mut self) -> &mut SpendingLog {
+        &mut self.spending
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.secret_key.is_none()
+    }
+
+    pub fn secret_key(&self) -> Result<&SecretKey, String> {
+        self.secret_key
+            .as_ref()
+            .map(LockedKey::secret_key)
+            .ok_or_else(|| "The wallet is locked".to_string())
+    }
+
+    // Takes the key `passphrase` decrypts `encrypted_key` to, which is slow
+    // on purpose and so left to the caller
+    pub fn unlock(&mut self, secret_key: SecretKey, timeout: Duration) -> Result<(), String> {
+        if self.encrypted.is_none() {
+            return Err("The wallet is not encrypted".to_string());
+        }
+
+        if keys::public_key(&secret_key) != self.public_key {
+            return Err("That is not the key of the wallet".to_string());
+        }
+
+        self.secret_key = Some(LockedKey::new(secret_key));
+        self.relock_at = Some(Instant::now() + timeout);
+
+        Ok(())
+    }
+
+    pub fn lock(&mut self) -> Result<(), String> {
+        if self.encrypted.is_none() {
+            return Err("The wallet is not encrypted".to_string());
+        }
+
+        self.secret_key = None;
+        self.relock_at = None;
+
+        Ok(())
+    }
+
+    // Called when an unlock may have run out, an unlock for longer since
+    // then keeps it unlocked
+    pub fn relock_if_expired(&mut self) -> bool {
+        match self.relock_at {
+            Some(relock_at) if relock_at <= Instant::now() => self.lock().is_ok(),
+            _ => false,
+        }
+    }
+}
+
+// Decrypts a key written by `paper::encrypt`, on the blocking thread pool as
+// it takes a while
+pub async fn decrypt(encrypted: String, passphrase: Passphrase) -> Result<SecretKey, String> {
+    tokio::task::spawn_blocking(move || paper::decode(&encrypted, Some(passphrase.as_str())))
+        .await
+        .map_err(|issue| issue.to_string())?
+}
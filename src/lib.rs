@@ -0,0 +1,51 @@
+pub mod address_book;
+pub mod address_index;
+pub mod alerts;
+pub mod audit;
+pub mod backup;
+pub mod blockchain;
+pub mod bloom;
+pub mod channel;
+pub mod checkpoint;
+pub mod client;
+pub mod compact_filter;
+pub mod consensus;
+pub mod contacts;
+pub mod deployment;
+pub mod escrow;
+pub mod events;
+pub mod faucet;
+pub mod framing;
+pub mod graphql;
+pub mod keyfile;
+pub mod keystore;
+pub mod light;
+pub mod lru;
+pub mod memory;
+pub mod mempool;
+pub mod migrations;
+pub mod miner;
+pub mod nat;
+pub mod network;
+pub mod network_time;
+pub mod notary;
+pub mod paper;
+pub mod peer;
+pub mod policy;
+pub mod pool;
+pub mod pow;
+pub mod quic;
+pub mod ratelimit;
+pub mod replay;
+pub mod rest;
+pub mod rpc;
+pub mod signer;
+pub mod stats;
+pub mod store;
+pub mod swap;
+pub mod vanity;
+pub mod verify;
+pub mod view;
+pub mod wallets;
+
+pub use fcoin_core::merkle;
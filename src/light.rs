@@ -0,0 +1,354 @@
+use super::blockchain;
+use super::blockchain::{
+    Block, BlockHeader, Hash, MerkleBlock, PublicKey, Transaction, MAX_BLOCKS, MAX_HEADERS,
+};
+use super::bloom::BloomFilter;
+use super::framing::{Frame, ReadConnection, WriteConnection};
+use super::network::Emission;
+use super::rpc::{Request, Response};
+
+// A false positive rate this high makes the filter match plenty of
+// unrelated transactions, which is what keeps our keys private
+const FILTER_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+// How the light client finds the transactions it cares about
+pub enum FilterMode {
+    // Loads a bloom filter into the full node, which replies with only the
+    // matching transactions and their Merkle proofs
+    Bloom,
+    // Downloads the compact filter of every block, matches them locally and
+    // fetches the full blocks that match, revealing nothing to the node
+    Compact,
+}
+
+// A client that only keeps block headers around and learns about the
+// transactions it cares about through filters served by a full node
+pub struct LightClient {
+    public_key: PublicKey,
+    mode: FilterMode,
+    // Main chain headers, oldest first
+    headers: Vec<BlockHeader>,
+    // Verified transactions that involve us, along with their block hash
+    transactions: Vec<(Hash, Transaction)>,
+    // Fees collected in the blocks we mined. Only known for full blocks, so
+    // it stays at zero in bloom mode.
+    mined_fees: i128,
+}
+
+impl LightClient {
+    pub fn new(public_key: PublicKey, mode: FilterMode) -> Self {
+        LightClient {
+            public_key,
+            mode,
+            headers: Vec::new(),
+            transactions: Vec::new(),
+            mined_fees: 0,
+        }
+    }
+
+    pub fn tip(&self) -> Hash {
+        match self.headers.last() {
+            Some(header) => header.hash(),
+            None => [0; 32],
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn filter(&self) -> BloomFilter {
+        let mut filter = BloomFilter::new(1, FILTER_FALSE_POSITIVE_RATE, rand_tweak());
+
+        filter.insert(&self.public_key);
+
+        filter
+    }
+
+    // Headers must link to one of ours (or start a new chain from genesis),
+    // anything we had after the link point is replaced.
+    fn add_headers(&mut self, headers: Vec<BlockHeader>) -> Result<(), String> {
+        let first = match headers.first() {
+            Some(header) => header,
+            None => return Ok(()),
+        };
+
+        let fork_point = if first.previous_hash() == &[0; 32] {
+            0
+        } else {
+            match self
+                .headers
+                .iter()
+                .position(|header| &header.hash() == first.previous_hash())
+            {
+                Some(position) => position + 1,
+                None => return Err("Headers do not link to our chain".to_string()),
+            }
+        };
+
+        let mut previous_hash = *first.previous_hash();
+
+        for header in &headers {
+            let hash = header.hash();
+
+            if header.previous_hash() != &previous_hash {
+                return Err(format!(
+                    "Header {} does not link to the previous one",
+                    blockchain::HashFmt(hash)
+                ));
+            }
+
+            if !blockchain::meets_target(&hash) {
+                return Err(format!(
+                    "Header {} does not meet the proof-of-work target",
+                    blockchain::HashFmt(hash)
+                ));
+            }
+
+            previous_hash = hash;
+        }
+
+        self.headers.truncate(fork_point);
+        self.headers.extend(headers);
+
+        let hashes: Vec<Hash> = self.headers.iter().map(BlockHeader::hash).collect();
+        self.transactions
+            .retain(|(block_hash, _)| hashes.contains(block_hash));
+
+        Ok(())
+    }
+
+    fn add_merkle_block(&mut self, merkle_block: MerkleBlock) -> Result<(), String> {
+        let hash = merkle_block.header().hash();
+
+        if !self.headers.iter().any(|header| header.hash() == hash) {
+            return Err(format!(
+                "Merkle block {} is not in our header chain",
+                blockchain::HashFmt(hash)
+            ));
+        }
+
+        if !merkle_block.verify() {
+            return Err(format!(
+                "Merkle block {} has invalid proofs",
+                blockchain::HashFmt(hash)
+            ));
+        }
+
+        self.add_transactions(hash, merkle_block.transactions());
+
+        Ok(())
+    }
+
+    fn add_block(&mut self, block: Block) -> Result<(), String> {
+        let hash = block.hash();
+
+        if !self.headers.iter().any(|header| header.hash() == hash) {
+            return Err(format!(
+                "Block {} is not in our header chain",
+                blockchain::HashFmt(hash)
+            ));
+        }
+
+        if !block.has_valid_merkle_root() {
+            return Err(format!(
+                "Block {} does not match its Merkle root",
+                blockchain::HashFmt(hash)
+            ));
+        }
+
+        if block.header().node_public_key() == &self.public_key {
+            self.mined_fees += block
+                .transactions()
+                .iter()
+                .map(|transaction| transaction.fee() as i128)
+                .sum::<i128>();
+        }
+
+        self.add_transactions(hash, block.transactions().iter());
+
+        Ok(())
+    }
+
+    // Filters match more than we need, keep only what is ours
+    fn add_transactions<'a>(
+        &mut self,
+        block_hash: Hash,
+        transactions: impl Iterator<Item = &'a Transaction>,
+    ) {
+        for transaction in transactions {
+            if transaction.source() == &self.public_key
+                || transaction
+                    .destinations()
+                    .any(|destination| destination == &self.public_key)
+            {
+                self.transactions.push((block_hash, transaction.clone()));
+            }
+        }
+    }
+
+    pub fn balance(&self) -> i128 {
+        // Light clients don't know the network profile, so they assume the
+        // default emission
+        let emission = Emission::default();
+        let mined = self
+            .headers
+            .iter()
+            .zip(1..)
+            .filter(|(header, _)| header.node_public_key() == &self.public_key)
+            .map(|(_, height)| emission.reward(height) as i128)
+            .sum::<i128>()
+            + self.mined_fees;
+
+        self.transactions
+            .iter()
+            .fold(mined, |balance, (_, transaction)| {
+                let mut balance = balance;
+
+                if transaction.source() == &self.public_key {
+                    balance -= (transaction.amount() + transaction.fee()) as i128;
+                }
+
+                balance + transaction.amount_to(&self.public_key) as i128
+            })
+    }
+
+    // Downloads all headers from the full node, then finds and verifies the
+    // transactions that involve us.
+    pub async fn sync(
+        &mut self,
+        writter: &mut WriteConnection,
+        reader: &mut ReadConnection,
+    ) -> Result<(), String> {
+        self.sync_headers(writter, reader).await?;
+
+        self.transactions.clear();
+        self.mined_fees = 0;
+
+        match self.mode {
+            FilterMode::Bloom => self.sync_merkle_blocks(writter, reader).await,
+            FilterMode::Compact => self.sync_compact_filters(writter, reader).await,
+        }
+    }
+
+    async fn sync_headers(
+        &mut self,
+        writter: &mut WriteConnection,
+        reader: &mut ReadConnection,
+    ) -> Result<(), String> {
+        loop {
+            writter.write(Frame::GetHeaders(self.tip())).await;
+
+            match reader.read().await {
+                Some(Frame::Headers(headers)) => {
+                    let count = headers.len();
+
+                    self.add_headers(headers)?;
+
+                    if count < MAX_HEADERS {
+                        break;
+                    }
+                }
+                Some(_) => return Err("Unexpected frame while syncing headers".to_string()),
+                None => return Err("Connection closed while syncing headers".to_string()),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn sync_merkle_blocks(
+        &mut self,
+        writter: &mut WriteConnection,
+        reader: &mut ReadConnection,
+    ) -> Result<(), String> {
+        writter.write(Frame::FilterLoad(self.filter())).await;
+
+        let hashes: Vec<Hash> = self.headers.iter().map(BlockHeader::hash).collect();
+
+        for chunk in hashes.chunks(MAX_HEADERS) {
+            writter.write(Frame::GetMerkleBlocks(chunk.to_vec())).await;
+
+            match reader.read().await {
+                Some(Frame::MerkleBlocks(merkle_blocks)) => {
+                    for merkle_block in merkle_blocks {
+                        self.add_merkle_block(merkle_block)?;
+                    }
+                }
+                Some(_) => return Err("Unexpected frame while syncing blocks".to_string()),
+                None => return Err("Connection closed while syncing blocks".to_string()),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn sync_compact_filters(
+        &mut self,
+        writter: &mut WriteConnection,
+        reader: &mut ReadConnection,
+    ) -> Result<(), String> {
+        let hashes: Vec<Hash> = self.headers.iter().map(BlockHeader::hash).collect();
+        let mut matching = Vec::new();
+
+        for chunk in hashes.chunks(MAX_HEADERS) {
+            writter
+                .write(Frame::GetCompactFilters(chunk.to_vec()))
+                .await;
+
+            match reader.read().await {
+                Some(Frame::CompactFilters(filters)) => {
+                    for (hash, filter) in filters {
+                        if filter.matches_any(&hash, &[&self.public_key]) {
+                            matching.push(hash);
+                        }
+                    }
+                }
+                Some(_) => return Err("Unexpected frame while syncing filters".to_string()),
+                None => return Err("Connection closed while syncing filters".to_string()),
+            }
+        }
+
+        for chunk in matching.chunks(MAX_BLOCKS) {
+            writter.write(Frame::GetBlocks(chunk.to_vec())).await;
+
+            match reader.read().await {
+                Some(Frame::Blocks(blocks)) => {
+                    for block in blocks {
+                        self.add_block(block)?;
+                    }
+                }
+                Some(_) => return Err("Unexpected frame while syncing blocks".to_string()),
+                None => return Err("Connection closed while syncing blocks".to_string()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Asks the full node for a fee rate (per 1000 bytes) to be confirmed within
+// `target_blocks`
+pub async fn estimate_fee(
+    writter: &mut WriteConnection,
+    reader: &mut ReadConnection,
+    target_blocks: u32,
+) -> Result<u64, String> {
+    writter
+        .write(Frame::Request(Request::EstimateFee(target_blocks)))
+        .await;
+
+    match reader.read().await {
+        Some(Frame::Response(Response::FeeRate(rate))) => Ok(rate),
+        Some(_) => Err("Unexpected frame while estimating the fee".to_string()),
+        None => Err("Connection closed while estimating the fee".to_string()),
+    }
+}
+
+// Varies the filter between sessions so full nodes can't link them
+fn rand_tweak() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos()
+}
@@ -0,0 +1,107 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::Mutex;
+
+// Map keeping at most `capacity` entries, dropping the least recently used
+// ones to make room. Locked inside, so it can be shared between threads.
+pub struct Lru<K, V> {
+    capacity: usize,
+    inner: Mutex<Entries<K, V>>,
+}
+
+struct Entries<K, V> {
+    // Each value with when it was last used
+    values: HashMap<K, (V, u64)>,
+    // Keys in the order they were used, a key being there again each time
+    // it is. Only the latest of those, with the same time as in `values`,
+    // counts.
+    uses: VecDeque<(K, u64)>,
+    clock: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            inner: Mutex::new(Entries {
+                values: HashMap::new(),
+                uses: VecDeque::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut entries = self.inner.lock().unwrap();
+
+        entries.clock += 1;
+
+        let clock = entries.clock;
+        let value = match entries.values.get_mut(key) {
+            Some((value, used)) => {
+                *used = clock;
+
+                value.clone()
+            }
+            None => return None,
+        };
+
+        entries.uses.push_back((key.clone(), clock));
+        entries.trim(self.capacity);
+
+        Some(value)
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut entries = self.inner.lock().unwrap();
+
+        entries.clock += 1;
+
+        let clock = entries.clock;
+
+        entries.values.insert(key.clone(), (value, clock));
+        entries.uses.push_back((key, clock));
+        entries.trim(self.capacity);
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Entries<K, V> {
+    fn trim(&mut self, capacity: usize) {
+        while self.values.len() > capacity {
+            let (key, time) = match self.uses.pop_front() {
+                Some(used) => used,
+                None => break,
+            };
+
+            if self.values.get(&key).map(|(_, used)| *used) == Some(time) {
+                self.values.remove(&key);
+            }
+        }
+
+        // Outdated uses pile up as entries are used again, so they are
+        // rebuilt from `values` once most of them are
+        if self.uses.len() > 2 * capacity.max(1) {
+            let mut uses: Vec<(K, u64)> = self
+                .values
+                .iter()
+                .map(|(key, (_, used))| (key.clone(), *used))
+                .collect();
+
+            uses.sort_unstable_by_key(|(_, used)| *used);
+
+            self.uses = uses.into();
+        }
+    }
+}
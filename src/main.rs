@@ -1,34 +1,373 @@
-#[macro_use]
-extern crate serde_big_array;
-big_array! { BigArray; }
-
-mod blockchain;
-mod framing;
-
+use clap::{Parser, Subcommand};
+use fcoin::address_book::{self, AddressBook, ADDRESS_BOOK_FORMAT, ANCHORS_FORMAT};
+use fcoin::address_index::{self, AddressIndex, ADDRESS_INDEX_FORMAT};
+use fcoin::alerts::{self, AlertSettings};
+use fcoin::audit::{self, Decision};
+use fcoin::backup;
+use fcoin::blockchain;
+use fcoin::blockchain::{MAX_BLOCKS, MAX_HEADERS};
+use fcoin::channel;
+use fcoin::client;
+use fcoin::contacts;
+use fcoin::events::{self, ChainEvent};
+use fcoin::faucet::{Faucet, FaucetSettings};
+use fcoin::keyfile;
+use fcoin::memory::{MemorySettings, MemoryUsage};
+use fcoin::miner::{self, MiningSettings};
+use fcoin::nat;
+use fcoin::network::{ConsensusKind, NetworkProfile};
+use fcoin::notary;
+use fcoin::peer::{self, PeerProtocol, PeerState};
+use fcoin::policy::SpendingPolicy;
+use fcoin::pool::{self, PoolSettings};
+use fcoin::quic;
+use fcoin::ratelimit::RestLimits;
+use fcoin::replay::{self, Recorder};
+use fcoin::rest;
+use fcoin::rpc::{self, MethodClass, RpcSettings};
+use fcoin::vanity;
+use fcoin::verify::VERIFY_LINKAGE;
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::future;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::Mutex;
+use tokio::time::timeout;
+use zeroize::Zeroizing;
 
-use framing::{Connection, Frame};
+use fcoin::framing::{Connection, Frame, ReadConnection, RejectKind, RelayPolicy, WriteConnection};
 
 #[derive(Deserialize)]
 struct Configuration {
     port: u16,
+    // When false we don't accept connections, only connect to the seeds.
+    // Clients (send-fcoin, fcoin mempool, ...) can't reach the node then.
+    #[serde(default = "default_listen")]
+    listen: bool,
+    // Asks the router (with UPnP or NAT-PMP) to forward the port to us, and
+    // tells our peers the address it gives us
+    #[serde(default)]
+    port_mapping: bool,
     seeds: Vec<SocketAddr>,
+    // Seconds before an unmined transaction is dropped from the mempool
+    #[serde(default = "default_mempool_ttl")]
+    mempool_ttl: u64,
+    // Serves the explorer REST API on this port when set
+    rest_port: Option<u16>,
+    // And on this Unix socket, for a reverse proxy
+    rest_socket: Option<PathBuf>,
+    // Origins of the web pages that can call the REST API, "*" for any
+    #[serde(default)]
+    cors_origins: Vec<String>,
+    // Also serves a block explorer web page on the REST port
+    #[serde(default)]
+    explorer: bool,
+    // Rate and size limits of the REST API
+    #[serde(default)]
+    rest_limits: RestLimits,
+    // Gives coins away on the REST port, only on test networks
+    faucet: Option<FaucetSettings>,
+    // Mines with workers connecting to its port instead of by ourselves,
+    // only with proof of work
+    pool: Option<PoolSettings>,
+    // When false we leave mining to `fcoin-miner`, or don't mine at all
+    #[serde(default = "default_mine")]
+    mine: bool,
+    // When to raise alerts, and where to send them besides the log
+    #[serde(default)]
+    alerts: AlertSettings,
+    // Indexes transactions by hash so they can be looked up
+    #[serde(default)]
+    txindex: bool,
+    // How many blocks behind our peers we can be while still mining and
+    // accepting transactions
+    #[serde(default = "default_sync_tolerance")]
+    sync_tolerance: u64,
+    #[serde(default)]
+    network: NetworkProfile,
+    // Once we upload this many megabytes in a day, we stop sending blocks
+    // far from the tip
+    daily_upload_cap_mb: Option<u64>,
+    // "blocksonly" to not take unconfirmed transactions from peers, which
+    // saves bandwidth. Transactions from our own clients are still relayed.
+    #[serde(default)]
+    relay: RelayPolicy,
+    // How much memory the mempool and caches may take
+    #[serde(default)]
+    memory: MemorySettings,
+    // Also accepts QUIC connections on this UDP port when set
+    quic_port: Option<u16>,
+    // Peers we always stay connected to over QUIC instead of TCP
+    #[serde(default)]
+    quic_peers: Vec<SocketAddr>,
+    // Deployments of the network our blocks signal for, by name
+    #[serde(default)]
+    signal: Vec<String>,
+    // How hard our own miner works
+    #[serde(default)]
+    mining: MiningSettings,
+    // IPs of peers, such as our own mining rigs, that can push us blocks
+    // at any point of the connection, which are checked ahead of other work
+    #[serde(default)]
+    trusted_peers: Vec<IpAddr>,
+    // Named wallets loaded on start, see `fcoin wallet`
+    #[serde(default)]
+    wallets: Vec<String>,
+    // Limits what the node signs for RPC clients
+    #[serde(default)]
+    policy: SpendingPolicy,
+    // Which RPC methods each listener allows
+    #[serde(default)]
+    rpc: RpcSettings,
+}
+
+fn default_listen() -> bool {
+    true
+}
+
+fn default_mine() -> bool {
+    true
+}
+
+fn default_sync_tolerance() -> u64 {
+    6
+}
+
+fn default_mempool_ttl() -> u64 {
+    72 * 60 * 60
 }
 
 const CONFIGURATION_FILE_PATH: &str = "fcoin.toml";
+const KEY_FILE_PATH: &str = "fcoin.key";
+const USER_AGENT: &str = concat!("fcoin/", env!("CARGO_PKG_VERSION"));
+const MEMPOOL_EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+const ADDRESS_BOOK_PATH: &str = "peers.dat";
+const ANCHORS_PATH: &str = "anchors.dat";
+const ADDRESS_INDEX_PATH: &str = "addresses.dat";
+// Kept by `fcoin-wallet channel` and `fcoin-wallet contact`
+const CHANNELS_PATH: &str = "channels.json";
+const CONTACTS_PATH: &str = "contacts.json";
+const AUDIT_LOG_PATH: &str = "audit.jsonl";
+// Blocks `fcoin anchor` is willing to wait for when estimating the fee
+const ANCHOR_CONFIRMATION_TARGET: u32 = 6;
+// What `fcoin backup` saves. The chain is only kept in memory, and synced
+// again on each run.
+const BACKUP_FILES: [&str; 7] = [
+    CONFIGURATION_FILE_PATH,
+    KEY_FILE_PATH,
+    ADDRESS_BOOK_PATH,
+    ANCHORS_PATH,
+    ADDRESS_INDEX_PATH,
+    CHANNELS_PATH,
+    CONTACTS_PATH,
+];
+const ADDRESS_BOOK_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+// Connections we keep to other nodes, besides those they make to us
+const MAX_OUTBOUND_PEERS: usize = 8;
+// How often we look for more peers to connect to
+const OUTBOUND_INTERVAL: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const FEELER_INTERVAL: Duration = Duration::from_secs(2 * 60);
+// How often we check that we are still connected to the QUIC peers
+const QUIC_PEERS_INTERVAL: Duration = Duration::from_secs(30);
+// How often we check whether we synced, to verify the chain we got
+const VERIFY_SYNC_INTERVAL: Duration = Duration::from_secs(10);
+// How often the nodes we relay blocks to are pinged, to order them by round
+// trip
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+// Without a command, runs the node configured in the current directory
+#[derive(Parser)]
+#[command(name = "fcoin", about = "Runs an fcoin node")]
+struct Arguments {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Records every frame received, and every block mined, to this
+    /// directory, for `fcoin replay`
+    #[arg(long, value_name = "DIR")]
+    record: Option<PathBuf>,
+    /// Runs without any key, only syncing, relaying and answering read-only
+    /// requests, so there are no funds to lose if the node is compromised
+    #[arg(long)]
+    no_wallet: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows the transactions the node in this directory is waiting to mine
+    Mempool,
+    /// Shows the traffic of the node in this directory, in total and by peer
+    Peers,
+    /// Shows the latest alerts raised by the node in this directory
+    Alerts,
+    /// Shows how much memory the node in this directory uses, by part
+    Memory,
+    /// Shows the coins issued so far, how many were burned or slashed, and
+    /// how many are left in circulation
+    Supply,
+    /// Shows the rule changes of the network and how close each is to
+    /// activating, from the signals of the latest blocks
+    Deployments,
+    /// Shows the hashrate of the miner of the node in this directory, how
+    /// many of its blocks were orphaned and how fast blocks reach it
+    Mining,
+    /// Feeds the frames recorded with `--record` to a new node, without
+    /// connecting to anyone, showing what it makes of each
+    Replay { dir: PathBuf },
+    /// Checks the chain of the node in this directory again, showing the
+    /// first block that breaks the rules. Level 0 checks the blocks link
+    /// up, 1 their seals too, 2 their transactions too.
+    VerifyChain {
+        #[arg(long, default_value_t = 1)]
+        level: u8,
+    },
+    /// Saves the configuration, key, address book and address index of the
+    /// node in this directory to a new directory, even while it runs
+    Backup { path: PathBuf },
+    /// Replaces the files of the node in this directory with those of a
+    /// backup, once they are all checked. The node must not be running.
+    Restore { path: PathBuf },
+    /// Reads the audit log of the node in this directory
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+    /// Generates a key whose address starts with the given prefix
+    Vanity {
+        #[arg(long)]
+        prefix: String,
+        /// Defaults to one per CPU
+        #[arg(long)]
+        threads: Option<usize>,
+        /// Where to save the key, must not exist yet
+        #[arg(long, default_value = "vanity.key")]
+        key_file: String,
+    },
+    /// Timestamps a file with the key of the node in this directory. The
+    /// first run commits the hash of the file to the chain, the next ones
+    /// complete `<file>.anchor` with the proof it was mined.
+    Anchor { file: String },
+    /// Checks the `<file>.anchor` certificate of a file, showing the block
+    /// that timestamps it
+    VerifyAnchor { file: String },
+    /// Encrypts the key of the node in this directory with a passphrase.
+    /// The node then starts locked, and can't sign until it is unlocked.
+    EncryptKey,
+    /// Unlocks the encrypted key of the node in this directory for a while
+    Unlock {
+        /// Seconds before it locks again
+        #[arg(long, default_value_t = 300)]
+        timeout: u64,
+        /// A loaded wallet instead of the key of the node
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Locks the key of the node in this directory again right away
+    Lock {
+        /// A loaded wallet instead of the key of the node
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Has the node in this directory sign an unsigned raw transaction,
+    /// within its spending policy
+    SignTransaction {
+        transaction: String,
+        /// A loaded wallet instead of the key of the node
+        #[arg(long)]
+        wallet: Option<String>,
+        /// Signature of an approver on the transaction, from `fcoin-wallet
+        /// sign-message`, to sign beyond the policy
+        #[arg(long)]
+        approval: Option<String>,
+    },
+    /// Manages the named wallets of the node in this directory
+    Wallet {
+        #[command(subcommand)]
+        command: WalletCommand,
+    },
+    /// Checks a message was signed by whoever controls an address
+    VerifyMessage {
+        address: String,
+        signature: String,
+        message: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditCommand {
+    /// Shows the latest blocks accepted and rejected, reorganizations and
+    /// dropped peers
+    Tail {
+        #[arg(long, short = 'n', default_value_t = 20)]
+        lines: usize,
+        /// Keeps showing new records as they are written
+        #[arg(long, short)]
+        follow: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum WalletCommand {
+    /// Generates the key of a new wallet and loads it
+    Create {
+        name: String,
+        /// Asks for a passphrase to encrypt its key with
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Loads a wallet created before, locked if it is encrypted
+    Load { name: String },
+    /// Wipes the key of a wallet from memory, keeping its key file
+    Unload { name: String },
+    /// Shows the loaded wallets
+    List,
+}
 
 #[tokio::main]
 async fn main() {
-    println!("Starting fcoin server...");
+    let arguments = Arguments::parse();
+    let command = arguments.command;
+
+    // These don't need a node
+    let result = match &command {
+        Some(Command::Vanity {
+            prefix,
+            threads,
+            key_file,
+        }) => Some(generate_vanity(prefix, *threads, key_file)),
+        Some(Command::VerifyMessage {
+            address,
+            signature,
+            message,
+        }) => Some(verify_message(address, signature, message)),
+        Some(Command::VerifyAnchor { file }) => Some(verify_anchor(file)),
+        Some(Command::Audit {
+            command: AuditCommand::Tail { lines, follow },
+        }) => Some(audit::tail(AUDIT_LOG_PATH, *lines, *follow).await),
+        Some(Command::Backup { path }) => Some(save_backup(path)),
+        Some(Command::EncryptKey) => Some(encrypt_key()),
+        Some(Command::Restore { path }) => Some(restore_backup(path).await),
+        _ => None,
+    };
+
+    if let Some(result) = result {
+        if let Err(issue) = result {
+            println!("{}", issue);
+            std::process::exit(1);
+        }
+
+        return;
+    }
 
-    match std::fs::read_to_string(CONFIGURATION_FILE_PATH) {
+    let configuration: Configuration = match std::fs::read_to_string(CONFIGURATION_FILE_PATH) {
         Ok(content) => match toml::from_str(&content) {
-            Ok(configuration) => accept_connections_loop(configuration).await,
+            Ok(configuration) => configuration,
             Err(issue) => panic!(
                 "Could not parse {}. Error: {}",
                 CONFIGURATION_FILE_PATH, issue
@@ -38,78 +377,1508 @@ async fn main() {
             "Could not find the {} configuration file. Error: {}",
             CONFIGURATION_FILE_PATH, issue
         ),
+    };
+
+    let node_address = format!(
+        "localhost:{}",
+        configuration.rpc.port.unwrap_or(configuration.port)
+    );
+    let result = match command {
+        Some(Command::Mempool) => Some(show_mempool(&node_address).await),
+        Some(Command::Peers) => Some(show_peers(&node_address).await),
+        Some(Command::Alerts) => Some(show_alerts(&node_address).await),
+        Some(Command::Memory) => Some(show_memory(&node_address).await),
+        Some(Command::Supply) => Some(show_supply(&node_address).await),
+        Some(Command::Deployments) => Some(show_deployments(&node_address).await),
+        Some(Command::Mining) => Some(show_mining(&node_address).await),
+        Some(Command::Anchor { file }) => Some(anchor(&node_address, &file).await),
+        Some(Command::Replay { dir }) => Some(replay(&configuration, &dir).await),
+        Some(Command::VerifyChain { level }) => Some(verify_chain(&node_address, level).await),
+        Some(Command::Unlock { timeout, wallet }) => {
+            Some(unlock(&node_address, timeout, wallet.as_deref()).await)
+        }
+        Some(Command::Lock { wallet }) => {
+            Some(client::wallet_lock(&node_address, wallet.as_deref()).await)
+        }
+        Some(Command::SignTransaction {
+            transaction,
+            wallet,
+            approval,
+        }) => Some(
+            client::sign_raw_transaction(
+                &node_address,
+                &transaction,
+                wallet.as_deref(),
+                approval.as_deref(),
+            )
+            .await
+            .map(|signed| println!("{}", signed)),
+        ),
+        Some(Command::Wallet { command }) => Some(manage_wallets(&node_address, command).await),
+        _ => None,
+    };
+
+    if let Some(result) = result {
+        if let Err(issue) = result {
+            println!("{}", issue);
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    println!("Starting fcoin server...");
+
+    let recorder = match arguments.record {
+        Some(dir) => match Recorder::new(&dir) {
+            Ok(recorder) => Some(Arc::new(recorder)),
+            Err(issue) => panic!("{}", issue),
+        },
+        None => None,
+    };
+
+    accept_connections_loop(configuration, recorder, arguments.no_wallet).await
+}
+
+fn encrypt_key() -> Result<(), String> {
+    let passphrase = read_passphrase("Passphrase")?;
+
+    if passphrase.is_empty() {
+        return Err("The passphrase can't be empty".to_string());
+    }
+
+    if read_passphrase("Repeat the passphrase")? != passphrase {
+        return Err("The passphrases don't match".to_string());
+    }
+
+    keyfile::encrypt(KEY_FILE_PATH, &passphrase)?;
+
+    println!(
+        "Encrypted {}, unlock the node with `fcoin unlock` to sign",
+        KEY_FILE_PATH
+    );
+
+    Ok(())
+}
+
+async fn unlock(node_address: &str, timeout: u64, wallet: Option<&str>) -> Result<(), String> {
+    let passphrase = read_passphrase("Passphrase")?;
+
+    client::wallet_unlock(node_address, &passphrase, timeout, wallet).await?;
+
+    println!("Unlocked for {} seconds", timeout);
+
+    Ok(())
+}
+
+async fn manage_wallets(node_address: &str, command: WalletCommand) -> Result<(), String> {
+    let wallets = match command {
+        WalletCommand::Create { name, encrypt } => {
+            let passphrase = if encrypt {
+                let passphrase = read_passphrase("Passphrase")?;
+
+                if read_passphrase("Repeat the passphrase")? != passphrase {
+                    return Err("The passphrases don't match".to_string());
+                }
+
+                Some(passphrase)
+            } else {
+                None
+            };
+
+            vec![
+                client::create_wallet(
+                    node_address,
+                    &name,
+                    passphrase.as_deref().map(String::as_str),
+                )
+                .await?,
+            ]
+        }
+        WalletCommand::Load { name } => vec![client::load_wallet(node_address, &name).await?],
+        WalletCommand::Unload { name } => {
+            client::unload_wallet(node_address, &name).await?;
+
+            println!("Unloaded {}", name);
+
+            return Ok(());
+        }
+        WalletCommand::List => client::list_wallets(node_address).await?,
+    };
+
+    for wallet in wallets {
+        let state = match (wallet.encrypted, wallet.locked) {
+            (false, _) => "not encrypted",
+            (true, true) => "locked",
+            (true, false) => "unlocked",
+        };
+
+        println!(
+            "{:<20} {} ({})",
+            wallet.name,
+            fcoin_core::address::encode(&wallet.address),
+            state
+        );
     }
+
+    Ok(())
+}
+
+// Wiped once dropped, like the keys it decrypts
+fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>, String> {
+    print!("{}: ", prompt);
+    std::io::stdout().flush().unwrap();
+
+    let mut passphrase = Zeroizing::new(String::new());
+
+    std::io::stdin()
+        .read_line(&mut passphrase)
+        .map_err(|issue| format!("Could not read the passphrase: {}", issue))?;
+
+    let length = passphrase.trim_end_matches(&['\r', '\n'][..]).len();
+
+    passphrase.truncate(length);
+
+    Ok(passphrase)
+}
+
+// Like the node would with the same configuration, starting from an empty
+// chain as it does. Nothing is mined, and our blocks in the recording are
+// replayed like our peers' are.
+async fn replay(conf: &Configuration, dir: &Path) -> Result<(), String> {
+    let records = replay::load(dir)?;
+
+    println!("REPLAYING {} FRAMES FROM {}", records.len(), dir.display());
+
+    let node = Arc::new(Mutex::new(blockchain::Node::new(
+        keyfile::generate(),
+        node_settings(conf),
+        AddressBook::default(),
+    )));
+
+    replay::replay(node, records).await;
+
+    Ok(())
+}
+
+fn node_settings(conf: &Configuration) -> blockchain::NodeSettings {
+    blockchain::NodeSettings {
+        mempool_ttl: conf.mempool_ttl,
+        transaction_index: conf.txindex,
+        sync_tolerance: conf.sync_tolerance,
+        network: conf.network.clone(),
+        daily_upload_cap: conf
+            .daily_upload_cap_mb
+            .map(|megabytes| megabytes * 1024 * 1024),
+        relay: conf.relay,
+        listen_port: if conf.listen { Some(conf.port) } else { None },
+        memory: conf.memory.clone(),
+        signal: conf.signal.clone(),
+        mining: conf.mining.clone(),
+        trusted_peers: conf.trusted_peers.clone(),
+        policy: conf.policy.clone(),
+    }
+}
+
+// Lists the transactions in the order the fee rates put them in the queue
+async fn show_mempool(node_address: &str) -> Result<(), String> {
+    let info = client::mempool_info(node_address).await?;
+    let transactions = client::raw_mempool(node_address).await?;
+
+    println!(
+        "{} transactions, {} bytes, ${} in fees (${} to ${} per kB)",
+        info.transactions, info.size, info.fees, info.min_fee_rate, info.max_fee_rate
+    );
+
+    for (position, transaction) in transactions.iter().enumerate() {
+        println!(
+            "{:>4} {} from {} ${} (${} per kB, {} bytes) waiting {}s",
+            position + 1,
+            blockchain::HashFmt(transaction.hash),
+            fcoin_core::address::encode(&transaction.source),
+            transaction.fee,
+            transaction.fee_rate,
+            transaction.size,
+            transaction.age
+        );
+
+        if transaction.ancestors > 0 {
+            println!(
+                "     depends on {} unconfirmed transactions, ${} per kB with them",
+                transaction.ancestors, transaction.package_fee_rate
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn show_peers(node_address: &str) -> Result<(), String> {
+    let totals = client::net_totals(node_address).await?;
+
+    println!(
+        "Sent {} bytes in {} frames, received {} bytes in {} frames",
+        totals.bytes_sent, totals.frames_sent, totals.bytes_received, totals.frames_received
+    );
+
+    for peer in client::peer_info(node_address).await? {
+        println!(
+            "{} (protocol version {}, height {}, round trip {}): sent {} bytes, received {} bytes",
+            peer.address,
+            peer.protocol_version,
+            peer.height
+                .map_or_else(|| "unknown".to_string(), |height| height.to_string()),
+            peer.round_trip_ms
+                .map_or_else(|| "unknown".to_string(), |ms| format!("{} ms", ms)),
+            peer.traffic.total.bytes_sent,
+            peer.traffic.total.bytes_received
+        );
+
+        for (message, traffic) in &peer.traffic.by_message {
+            println!(
+                "    {:<20} sent {} in {} bytes, received {} in {} bytes",
+                message,
+                traffic.frames_sent,
+                traffic.bytes_sent,
+                traffic.frames_received,
+                traffic.bytes_received
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn show_alerts(node_address: &str) -> Result<(), String> {
+    let alerts = client::alerts(node_address).await?;
+
+    if alerts.is_empty() {
+        println!("No alerts");
+    }
+
+    for alert in alerts {
+        println!("{} {:?}: {}", alert.time, alert.kind, alert.message);
+    }
+
+    Ok(())
+}
+
+async fn show_memory(node_address: &str) -> Result<(), String> {
+    let info = client::memory_info(node_address).await?;
+    let show = |name: &str, usage: &MemoryUsage| {
+        println!(
+            "{:<20} {} entries, {} of {} bytes",
+            name, usage.entries, usage.bytes, usage.budget
+        )
+    };
+
+    show("mempool", &info.mempool);
+    show("verification cache", &info.verification_cache);
+    show("rejections", &info.rejections);
+
+    Ok(())
+}
+
+async fn show_supply(node_address: &str) -> Result<(), String> {
+    let supply = client::supply(node_address).await?;
+
+    println!("{:<12} ${}", "issued", supply.issued);
+    println!("{:<12} ${}", "burned", supply.burned);
+    println!("{:<12} ${}", "slashed", supply.slashed);
+    println!("{:<12} ${}", "circulating", supply.circulating);
+
+    Ok(())
+}
+
+async fn show_mining(node_address: &str) -> Result<(), String> {
+    let info = client::mining_info(node_address).await?;
+
+    println!(
+        "{:.1} hashes/s, using up to {}% of a core",
+        info.hashrate, info.cpu_percent
+    );
+    println!(
+        "{} blocks mined, {} orphaned ({:.1}%)",
+        info.blocks_mined,
+        info.blocks_orphaned,
+        info.orphan_rate * 100.0
+    );
+
+    for peer in &info.propagation {
+        println!(
+            "{:<24} {} blocks, {} ms on average, {} ms at most",
+            peer.address, peer.blocks, peer.average_ms, peer.max_ms
+        );
+    }
+
+    Ok(())
+}
+
+async fn show_deployments(node_address: &str) -> Result<(), String> {
+    let deployments = client::deployment_info(node_address).await?;
+
+    if deployments.is_empty() {
+        println!("The network has no deployments");
+    }
+
+    for info in deployments {
+        println!(
+            "{:<16} bit {:<2} {:?}, {} of {} blocks signaling ({} to lock in), from {} to {}",
+            info.name,
+            info.bit,
+            info.state,
+            info.signals,
+            info.window,
+            info.threshold,
+            info.start_height,
+            info.timeout_height
+        );
+    }
+
+    Ok(())
 }
 
-// Binds to the port in the configuration file and spawns a `peer_loop` for each
-// of the connections created.
-async fn accept_connections_loop(conf: Configuration) {
+async fn verify_chain(node_address: &str, level: u8) -> Result<(), String> {
+    let verification = client::verify_chain(node_address, level).await?;
+
+    match verification.violation {
+        Some(violation) => Err(format!(
+            "BLOCK {} AT HEIGHT {} BREAKS LEVEL {}: {}",
+            blockchain::HashFmt(violation.hash),
+            violation.height,
+            verification.level,
+            violation.issue
+        )),
+        None => {
+            println!(
+                "{} BLOCKS VERIFIED AT LEVEL {}",
+                verification.blocks, verification.level
+            );
+
+            Ok(())
+        }
+    }
+}
+
+// The chain is only in memory, so there is nothing to check when starting.
+// This checks the linkage of what we synced once we are done instead.
+async fn verify_after_sync(node: Arc<Mutex<blockchain::Node>>) {
+    loop {
+        tokio::time::sleep(VERIFY_SYNC_INTERVAL).await;
+
+        let node = node.lock().await;
+
+        if !node.is_synced() || node.height() == 0 {
+            continue;
+        }
+
+        let verification = node.verify_chain(VERIFY_LINKAGE);
+
+        match verification.violation {
+            Some(violation) => println!(
+                "CHAIN VERIFICATION FAILED AT {} (HEIGHT {}): {}",
+                blockchain::HashFmt(violation.hash),
+                violation.height,
+                violation.issue
+            ),
+            None => println!("CHAIN VERIFIED, {} BLOCKS", verification.blocks),
+        }
+
+        return;
+    }
+}
+
+fn save_backup(path: &Path) -> Result<(), String> {
+    let manifest = backup::backup(&BACKUP_FILES, path)?;
+
+    for file in &manifest.files {
+        println!("SAVED {} ({} BYTES)", file.name, file.size);
+    }
+
+    Ok(())
+}
+
+async fn restore_backup(path: &Path) -> Result<(), String> {
+    // The node would overwrite what we restore with what it has in memory
+    if let Ok(content) = std::fs::read_to_string(CONFIGURATION_FILE_PATH) {
+        if let Ok(configuration) = toml::from_str::<Configuration>(&content) {
+            let address = format!("localhost:{}", configuration.port);
+
+            if TcpStream::connect(address).await.is_ok() {
+                return Err("Stop the node before restoring a backup".to_string());
+            }
+        }
+    }
+
+    let manifest = backup::restore(&BACKUP_FILES, path, |name, content| {
+        let valid = match name {
+            CONFIGURATION_FILE_PATH => std::str::from_utf8(content)
+                .map_err(|issue| issue.to_string())
+                .and_then(|content| {
+                    toml::from_str::<Configuration>(content)
+                        .map(|_| ())
+                        .map_err(|issue| issue.to_string())
+                }),
+            KEY_FILE_PATH => std::str::from_utf8(content)
+                .map_err(|issue| issue.to_string())
+                .and_then(|content| keyfile::decode(content).map(|_| ())),
+            ADDRESS_BOOK_PATH => AddressBook::decode(content.to_vec()).map(|_| ()),
+            ANCHORS_PATH => address_book::decode_anchors(content.to_vec()).map(|_| ()),
+            CHANNELS_PATH => channel::decode(content).map(|_| ()),
+            CONTACTS_PATH => contacts::decode(content).map(|_| ()),
+            // Checks its own snapshots when loaded
+            _ => ADDRESS_INDEX_FORMAT.decode(content.to_vec()).map(|_| ()),
+        };
+
+        valid.map_err(|issue| format!("{} is invalid: {}", name, issue))
+    })?;
+
+    for file in &manifest.files {
+        println!("RESTORED {} ({} BYTES)", file.name, file.size);
+    }
+
+    Ok(())
+}
+
+fn verify_message(address: &str, signature: &str, message: &str) -> Result<(), String> {
+    let public_key = fcoin_core::address::decode(address)?;
+    let signature = fcoin_core::hex::decode_array::<64>(signature)?;
+
+    if !fcoin_core::message::verify(&public_key, message, &signature) {
+        return Err(format!("The message was not signed by {}", address));
+    }
+
+    println!("The message was signed by {}", address);
+
+    Ok(())
+}
+
+// Commits the hash of the file on the first run, and proves it was mined
+// once it is
+async fn anchor(node_address: &str, file: &str) -> Result<(), String> {
+    let file_hash = notary::hash_file(file)?;
+    let mut certificate = match notary::load(file)? {
+        Some(certificate) if certificate.file_hash != file_hash => {
+            return Err(format!(
+                "{} changed since it was anchored, remove {} to anchor it again",
+                file,
+                notary::certificate_path(file)
+            ));
+        }
+        Some(certificate) => certificate,
+        None => {
+            let secret_key = keyfile::load(KEY_FILE_PATH)?;
+            let certificate = notary::commit(
+                node_address,
+                &secret_key,
+                file_hash,
+                ANCHOR_CONFIRMATION_TARGET,
+            )
+            .await?;
+
+            notary::save(file, &certificate)?;
+            println!(
+                "COMMITTED {} IN TRANSACTION {}, RUN AGAIN ONCE IT IS MINED",
+                file,
+                blockchain::HashFmt(certificate.transaction.hash())
+            );
+
+            return Ok(());
+        }
+    };
+
+    if certificate.inclusion.is_none() {
+        if !notary::prove(node_address, &mut certificate).await? {
+            println!(
+                "TRANSACTION {} IS NOT MINED YET",
+                blockchain::HashFmt(certificate.transaction.hash())
+            );
+
+            return Ok(());
+        }
+
+        notary::save(file, &certificate)?;
+    }
+
+    verify_anchor(file)
+}
+
+fn verify_anchor(file: &str) -> Result<(), String> {
+    let certificate = notary::load(file)?.ok_or_else(|| format!("{} was never anchored", file))?;
+    let inclusion = notary::verify(&certificate, &notary::hash_file(file)?)?;
+
+    println!(
+        "{} EXISTED BY BLOCK {} AT HEIGHT {}, MINED AT {}",
+        file,
+        blockchain::HashFmt(inclusion.header.hash()),
+        inclusion.height,
+        chrono::DateTime::from_timestamp(inclusion.header.time() as i64, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+fn generate_vanity(prefix: &str, threads: Option<usize>, key_file: &str) -> Result<(), String> {
+    if std::path::Path::new(key_file).exists() {
+        return Err(format!("{} already exists", key_file));
+    }
+
+    let probability = vanity::prefix_probability(prefix)?;
+    let expected = 1.0 / probability;
+    let threads = threads.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1)
+    });
+
+    println!(
+        "Searching for an address starting with {} on {} threads, about {:.0} keys to try",
+        prefix, threads, expected
+    );
+
+    let start = Instant::now();
+
+    let secret_key = vanity::search(prefix, threads, |attempts| {
+        let rate = attempts as f64 / start.elapsed().as_secs_f64();
+
+        println!(
+            "Tried {} keys ({:.0}/s), {:.0}s expected in total",
+            attempts,
+            rate,
+            expected / rate.max(1.0)
+        );
+    });
+
+    keyfile::save(key_file, &secret_key)?;
+
+    println!(
+        "Found {} in {:.1}s, key saved to {}",
+        fcoin_core::address::encode(&fcoin_core::keys::public_key(&secret_key)),
+        start.elapsed().as_secs_f64(),
+        key_file
+    );
+
+    Ok(())
+}
+
+// Binds to the port in the configuration file (unless `listen` is off) and
+// spawns a `peer_loop` for each of the connections created.
+async fn accept_connections_loop(
+    conf: Configuration,
+    recorder: Option<Arc<Recorder>>,
+    no_wallet: bool,
+) {
     println!("{:?}", conf.seeds);
 
-    let listener = TcpListener::bind(format!("localhost:{}", conf.port))
-        .await
-        .unwrap();
+    // Connections forwarded by the router come from outside
+    let host = if conf.port_mapping {
+        "0.0.0.0"
+    } else {
+        "localhost"
+    };
+    let listener = if conf.listen {
+        Some(
+            TcpListener::bind(format!("{}:{}", host, conf.port))
+                .await
+                .unwrap(),
+        )
+    } else {
+        None
+    };
 
     let (tx, rx) = mpsc::channel(1);
-    let node = Arc::new(Mutex::new(blockchain::Node::new()));
+    // Without a wallet the key file isn't even read
+    let keystore = if no_wallet {
+        if conf.faucet.is_some() || conf.pool.is_some() || !conf.wallets.is_empty() {
+            panic!("The faucet, pool and wallets need a key, so can't be run with --no-wallet");
+        }
+
+        println!("RUNNING WITHOUT A WALLET, NOT MINING NOR SIGNING");
+
+        None
+    } else {
+        let keystore = match keyfile::load_or_create(KEY_FILE_PATH) {
+            Ok(keystore) => keystore,
+            Err(issue) => panic!("{}", issue),
+        };
+        println!(
+            "Mining to {}",
+            fcoin_core::address::encode(keystore.public_key())
+        );
+
+        if keystore.is_locked() {
+            println!("WALLET IS LOCKED, UNLOCK IT WITH `fcoin unlock` TO SIGN");
+        }
+
+        Some(keystore)
+    };
+
+    for (format, path) in [
+        (ADDRESS_BOOK_FORMAT, ADDRESS_BOOK_PATH),
+        (ANCHORS_FORMAT, ANCHORS_PATH),
+        (ADDRESS_INDEX_FORMAT, ADDRESS_INDEX_PATH),
+    ] {
+        if let Err(issue) = format.migrate(path) {
+            panic!("{}", issue);
+        }
+    }
+
+    let address_book = AddressBook::load(ADDRESS_BOOK_PATH).unwrap_or_else(|issue| {
+        println!("{}. STARTING WITH AN EMPTY ADDRESS BOOK.", issue);
+
+        AddressBook::default()
+    });
+
+    if conf.faucet.is_some() && !conf.network.testnet {
+        panic!("The faucet can only be enabled on test networks ([network] testnet = true)");
+    }
+
+    if conf.faucet.is_some() && !conf.rpc.rest_methods.contains(&MethodClass::Wallet) {
+        panic!("The faucet pays from the key of the node, so needs rest_methods = [\"read\", \"wallet\"] in [rpc]");
+    }
+
+    if conf.pool.is_some() && conf.network.consensus != ConsensusKind::ProofOfWork {
+        panic!("A pool can only be run on proof of work networks");
+    }
+
+    let emission = conf.network.emission;
+    let mine = conf.mine && keystore.is_some();
+    let node = Arc::new(Mutex::new(match keystore {
+        Some(keystore) => {
+            blockchain::Node::with_keystore(keystore, node_settings(&conf), address_book)
+        }
+        None => blockchain::Node::without_wallet(node_settings(&conf), address_book),
+    }));
+
+    for name in &conf.wallets {
+        match node.lock().await.wallets_mut().load(name) {
+            Ok(wallet) if wallet.locked => println!("WALLET {} LOADED, LOCKED", name),
+            Ok(_) => println!("WALLET {} LOADED", name),
+            Err(issue) => println!("COULD NOT LOAD WALLET {}: {}", name, issue),
+        }
+    }
+
+    if let Some(recorder) = recorder {
+        println!("RECORDING FRAMES TO REPLAY");
+
+        node.lock().await.record_to(recorder);
+    }
+
+    if let Some(records) = node.lock().await.audit().records() {
+        tokio::spawn(async move {
+            audit::append(records, AUDIT_LOG_PATH).await;
+        });
+    }
+
+    if let Some(settings) = conf.pool.clone() {
+        let node_clone = node.clone();
+        let network = conf.network.clone();
+
+        tokio::spawn(async move {
+            if let Err(issue) = pool::serve(node_clone, settings, network).await {
+                println!("{}", issue);
+            }
+        });
+    } else if mine {
+        let node_clone = node.clone();
+
+        if conf.mining.is_throttled() {
+            // On a thread of its own, so only the miner runs at a lower
+            // priority
+            std::thread::spawn(move || {
+                miner::lower_priority();
+
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap()
+                    .block_on(blockchain::block_generator(node_clone, rx));
+            });
+        } else {
+            tokio::spawn(async move {
+                blockchain::block_generator(node_clone, rx).await;
+            });
+        }
+
+        {
+            let node_clone = node.clone();
+            let events = node.lock().await.subscribe();
+
+            tokio::spawn(async move {
+                blockchain::feed_miner(node_clone, events, tx).await;
+            });
+        }
+    }
+
+    let addresses = Arc::new(Mutex::new(AddressIndex::load(emission, ADDRESS_INDEX_PATH)));
+
+    {
+        let addresses_clone = addresses.clone();
+        let events = node.lock().await.subscribe();
+
+        tokio::spawn(async move {
+            address_index::follow(addresses_clone, events, ADDRESS_INDEX_PATH).await;
+        });
+    }
 
     {
         let node_clone = node.clone();
-        let tx_clone = tx.clone();
 
         tokio::spawn(async move {
-            blockchain::block_generator(node_clone, rx, tx_clone).await;
+            blockchain::mempool_expiry_loop(node_clone, MEMPOOL_EXPIRY_INTERVAL).await;
         });
     }
 
-    for seed in conf.seeds {
-        match TcpStream::connect(seed).await {
-            Ok(stream) => {
-                let node_clone = node.clone();
-                let tx_clone = tx.clone();
+    {
+        let node_clone = node.clone();
+        let events = node.lock().await.subscribe();
 
-                tokio::spawn(async move {
-                    peer_loop(node_clone, tx_clone, stream, seed).await;
-                });
+        tokio::spawn(async move {
+            notify_wallets(node_clone, events).await;
+        });
+    }
+
+    {
+        let node_clone = node.clone();
+        let events = node.lock().await.subscribe();
+        let settings = conf.alerts.clone();
+
+        tokio::spawn(async move {
+            alerts::monitor(node_clone, settings, events).await;
+        });
+    }
+
+    {
+        let alerts = node.lock().await.alerts().subscribe();
+        let webhook = conf.alerts.webhook.clone();
+
+        tokio::spawn(async move {
+            alerts::deliver(alerts, webhook).await;
+        });
+    }
+
+    if conf.listen && conf.port_mapping {
+        let node_clone = node.clone();
+        let port = conf.port;
+
+        tokio::spawn(async move {
+            port_mapping_loop(node_clone, port).await;
+        });
+    }
+
+    if conf.rest_port.is_some() || conf.rest_socket.is_some() {
+        let node_clone = node.clone();
+        let addresses_clone = addresses.clone();
+        let settings = rest::ServeSettings {
+            port: conf.rest_port,
+            socket: conf.rest_socket.clone(),
+            explorer: conf.explorer,
+            limits: conf.rest_limits.clone(),
+            cors_origins: conf.cors_origins.clone(),
+        };
+        let faucet = conf.faucet.clone().map(Faucet::new);
+
+        tokio::spawn(async move {
+            if let Err(issue) = rest::serve(node_clone, addresses_clone, settings, faucet).await {
+                println!("{}", issue);
             }
-            Err(issue) => println!(
-                "Could not connect to the {} hardcoded seed node: {}",
-                seed, issue
-            ),
+        });
+    }
+
+    // Not reachable from outside, whatever `port_mapping` says
+    if let Some(port) = conf.rpc.port {
+        let listener = TcpListener::bind(format!("localhost:{}", port))
+            .await
+            .unwrap();
+        let node_clone = node.clone();
+        let addresses_clone = addresses.clone();
+        let methods = conf.rpc.methods.clone();
+
+        tokio::spawn(async move {
+            accept_loop(
+                node_clone,
+                addresses_clone,
+                listener,
+                methods.clone(),
+                methods,
+            )
+            .await;
+        });
+    }
+
+    {
+        let node_clone = node.clone();
+        let addresses_clone = addresses.clone();
+        let seeds = conf.seeds.clone();
+        let anchors = address_book::load_anchors(ANCHORS_PATH);
+        let methods = conf.rpc.peer_methods.clone();
+
+        tokio::spawn(async move {
+            outbound_connections_loop(node_clone, addresses_clone, seeds, anchors, methods).await;
+        });
+    }
+
+    {
+        let node_clone = node.clone();
+
+        tokio::spawn(async move {
+            feeler_loop(node_clone).await;
+        });
+    }
+
+    {
+        let node_clone = node.clone();
+
+        tokio::spawn(async move {
+            address_book_save_loop(node_clone).await;
+        });
+    }
+
+    {
+        let node_clone = node.clone();
+
+        tokio::spawn(async move {
+            ping_loop(node_clone).await;
+        });
+    }
+
+    {
+        let node_clone = node.clone();
+
+        tokio::spawn(async move {
+            verify_after_sync(node_clone).await;
+        });
+    }
+
+    if conf.quic_port.is_some() || !conf.quic_peers.is_empty() {
+        let endpoint = quic::endpoint(conf.quic_port).unwrap();
+
+        if conf.quic_port.is_some() {
+            let node_clone = node.clone();
+            let addresses_clone = addresses.clone();
+            let endpoint = endpoint.clone();
+            let methods = conf.rpc.peer_methods.clone();
+
+            tokio::spawn(async move {
+                quic_accept_loop(node_clone, addresses_clone, endpoint, methods).await;
+            });
         }
+
+        let node_clone = node.clone();
+        let addresses_clone = addresses.clone();
+        let peers = conf.quic_peers.clone();
+        let methods = conf.rpc.peer_methods.clone();
+
+        tokio::spawn(async move {
+            quic_peers_loop(node_clone, addresses_clone, endpoint, peers, methods).await;
+        });
     }
 
+    let listener = match listener {
+        Some(listener) => listener,
+        None => {
+            println!("NOT LISTENING. ONLY CONNECTING OUT.");
+
+            // Everything else runs in its own task
+            return future::pending().await;
+        }
+    };
+
+    accept_loop(
+        node,
+        addresses,
+        listener,
+        conf.rpc.peer_methods,
+        conf.rpc.methods,
+    )
+    .await
+}
+
+// Spawns a `peer_loop` for each connection made to `listener`, whose RPC
+// clients can call `methods`, or `local_methods` from this machine, as the
+// tools of the operator do without an RPC port of their own
+async fn accept_loop(
+    node: Arc<Mutex<blockchain::Node>>,
+    address_index: Arc<Mutex<AddressIndex>>,
+    listener: TcpListener,
+    methods: Vec<MethodClass>,
+    local_methods: Vec<MethodClass>,
+) {
     loop {
         let (stream, address) = listener.accept().await.unwrap();
         let node_clone = node.clone();
-        let tx_clone = tx.clone();
+        let address_index = address_index.clone();
+        let methods = if address.ip().is_loopback() {
+            local_methods.clone()
+        } else {
+            methods.clone()
+        };
 
         tokio::spawn(async move {
-            peer_loop(node_clone, tx_clone, stream, address).await;
+            let connection = Connection::new(stream);
+
+            peer_loop(
+                node_clone,
+                address_index,
+                connection,
+                address,
+                false,
+                methods,
+            )
+            .await;
         });
     }
 }
 
-// await is spanwed for each connected peer
+// await is spanwed for each connected peer. We sync from the peers we
+// connect to (`outbound`), the others sync from us.
 async fn peer_loop(
     node: Arc<Mutex<blockchain::Node>>,
-    tx: mpsc::Sender<blockchain::ProtoBlock>,
-    stream: TcpStream,
+    address_index: Arc<Mutex<AddressIndex>>,
+    (mut writter, mut reader): (WriteConnection, ReadConnection),
     address: SocketAddr,
+    outbound: bool,
+    methods: Vec<MethodClass>,
 ) {
     println!("Connected with {}.", address);
 
-    let (writter, mut reader) = Connection::new(stream);
+    let mut protocol = PeerProtocol::new();
+    let (network, relay, views, recorder) = {
+        let node = node.lock().await;
+
+        (
+            node.network_id(),
+            node.relay_policy(),
+            node.chain_views(),
+            node.recorder(),
+        )
+    };
+    let peer_relay;
+    // Clients follow no network
+    let peer_is_node;
 
-    node.lock().await.add_peer(address, writter);
+    match Connection::handshake(&mut writter, &mut reader, USER_AGENT, Some(network), relay).await {
+        Ok(handshake) => {
+            println!(
+                "{} IS {} (PROTOCOL VERSION {}, {:?})",
+                address,
+                handshake.version.user_agent,
+                writter.protocol_version(),
+                writter.codec()
+            );
+
+            if let (true, Some(time)) = (outbound, handshake.time) {
+                node.lock().await.add_time_sample(address, time);
+            }
+
+            // Like with clocks, only peers we connected to get a say
+            if let (true, Some(observed)) = (outbound, handshake.observed_address) {
+                node.lock()
+                    .await
+                    .add_address_vote(address, observed.ip())
+                    .await;
+            }
+
+            peer_relay = handshake.relay;
+            peer_is_node = handshake.network.is_some();
+        }
+        Err(issue) => {
+            println!("HANDSHAKE WITH {} FAILED: {}", address, issue);
+
+            return;
+        }
+    }
+
+    protocol.handshake_completed();
+
+    let trusted = node.lock().await.is_trusted(&address);
+
+    if trusted {
+        println!("{} IS TRUSTED TO PUSH BLOCKS", address);
+
+        protocol.trust();
+    }
+
+    // Whether we relay new blocks to the peer, once one of us caught up with
+    // the other
+    let mut relaying_blocks = false;
+
+    if outbound {
+        let tip_hash = node.lock().await.tip_hash();
+
+        writter.write(Frame::GetHeaders(tip_hash)).await;
+        protocol.headers_requested();
+    }
+
+    if outbound {
+        writter.write(Frame::GetAddr).await;
+    }
+
+    if let Some(external_address) = node.lock().await.external_address() {
+        writter.write(Frame::Addr(vec![external_address])).await;
+    }
+
+    if let Some(checkpoint) = node.lock().await.checkpoint().cloned() {
+        writter.write(Frame::Checkpoint(checkpoint)).await;
+    }
+
+    node.lock()
+        .await
+        .add_peer(address, writter, peer_relay, outbound);
 
     loop {
-        match reader.read().await {
-            Some(Frame::Block(block)) => blockchain::block_received(node.clone(), block).await,
+        // Transactions are relayed as the bytes they came in
+        let (frame, payload) = match reader.read_payload().await {
+            Some((frame, payload)) => (Some(frame), Some(payload)),
+            None => (None, None),
+        };
+        let received = Instant::now();
+
+        if let (Some(recorder), Some(payload)) = (&recorder, &payload) {
+            recorder.record(Some(address), payload);
+        }
+
+        if let Some(frame) = &frame {
+            if let Err(issue) = protocol.receive(frame) {
+                println!("MISBEHAVIOR FROM {}: {}", address, issue);
+
+                node.lock().await.audit().record(Decision::PeerDropped {
+                    peer: address,
+                    reason: issue.to_string(),
+                });
+
+                break;
+            }
+        }
+
+        match frame {
+            Some(Frame::Block(block)) if trusted => {
+                let hash = block.hash();
+
+                match blockchain::block_pushed(node.clone(), block.clone()).await {
+                    Ok(()) => blockchain::relay_block(node.clone(), block, Some(address)),
+                    Err(reason) => reject(&node, address, RejectKind::Block, hash, reason).await,
+                }
+            }
+            Some(Frame::Block(block)) => {
+                let hash = block.hash();
+                let first = node.lock().await.block(&hash).is_none();
+
+                match blockchain::block_received(node.clone(), block.clone()).await {
+                    Ok(()) if first => {
+                        node.lock()
+                            .await
+                            .block_propagated(address, received.elapsed());
+                        blockchain::relay_block(node.clone(), block, Some(address));
+                    }
+                    Ok(()) => {}
+                    Err(reason) => reject(&node, address, RejectKind::Block, hash, reason).await,
+                }
+            }
+            Some(Frame::Transaction(_)) if relay == RelayPolicy::BlocksOnly => {
+                println!("BLOCKSONLY. DROPPING TRANSACTION FROM {}", address);
+            }
             Some(Frame::Transaction(trx)) => {
-                blockchain::transaction_received(trx, tx.clone()).await
+                let hash = trx.hash();
+
+                if let Err(reason) =
+                    blockchain::transaction_received(node.clone(), trx, Some(address), payload)
+                        .await
+                {
+                    reject(&node, address, RejectKind::Transaction, hash, reason).await;
+                }
+            }
+            Some(Frame::GetHeaders(locator)) => {
+                let count = blockchain::headers_requested(node.clone(), address, locator).await;
+
+                protocol.headers_sent(count);
             }
+            Some(Frame::GetAddr) => {
+                let mut node = node.lock().await;
+                let addresses = node.addresses();
+
+                node.send(&address, Frame::Addr(addresses)).await;
+            }
+            Some(Frame::Addr(addresses)) => {
+                let added = node.lock().await.add_addresses(addresses);
+
+                if added > 0 {
+                    println!("LEARNED {} ADDRESSES FROM {}", added, address);
+                }
+            }
+            Some(Frame::Ping(nonce)) => node.lock().await.send(&address, Frame::Pong(nonce)).await,
+            Some(Frame::Pong(nonce)) => node.lock().await.pong_received(address, nonce),
+            Some(Frame::Checkpoint(checkpoint)) => {
+                let result = node
+                    .lock()
+                    .await
+                    .checkpoint_received(checkpoint, Some(address))
+                    .await;
+
+                if let Err(issue) = result {
+                    println!("REJECTED CHECKPOINT FROM {}: {}", address, issue);
+                }
+            }
+            Some(Frame::Headers(headers)) => {
+                println!("{} HAS {} HEADERS AFTER OURS", address, headers.len());
+
+                let mut node = node.lock().await;
+                let (valid, height) = node.valid_headers(&headers);
+
+                if valid < headers.len() {
+                    println!(
+                        "{} SENT {} HEADERS THAT DON'T FOLLOW ON OR AREN'T SEALED",
+                        address,
+                        headers.len() - valid
+                    );
+                }
+
+                if valid > 0 {
+                    node.set_peer_height(address, height);
+                }
+
+                let hashes: Vec<blockchain::Hash> = headers[..valid]
+                    .iter()
+                    .map(|header| header.hash())
+                    .collect();
+
+                for chunk in hashes.chunks(MAX_BLOCKS) {
+                    node.send(&address, Frame::GetBlocks(chunk.to_vec())).await;
+                    protocol.blocks_requested();
+                }
+
+                // Keep asking until we get to their tip
+                if let (true, Some(last)) = (valid == MAX_HEADERS, hashes.last()) {
+                    node.send(&address, Frame::GetHeaders(*last)).await;
+                    protocol.headers_requested();
+                }
+            }
+            Some(Frame::Blocks(blocks)) => {
+                for (hash, result) in blockchain::blocks_received(node.clone(), blocks).await {
+                    if let Err(reason) = result {
+                        println!(
+                            "SYNCED BLOCK {} FROM {} REJECTED: {}",
+                            blockchain::HashFmt(hash),
+                            address,
+                            reason
+                        );
+                    }
+                }
+            }
+            Some(Frame::FilterLoad(filter)) => node.lock().await.set_filter(address, filter),
+            Some(Frame::GetMerkleBlocks(hashes)) => {
+                blockchain::merkle_blocks_requested(node.clone(), address, hashes).await
+            }
+            Some(Frame::GetCompactFilters(hashes)) => {
+                blockchain::compact_filters_requested(node.clone(), address, hashes).await
+            }
+            Some(Frame::GetBlocks(hashes)) => {
+                blockchain::blocks_requested(node.clone(), address, hashes).await
+            }
+            Some(Frame::Request(request)) => {
+                rpc::request_received(
+                    node.clone(),
+                    address_index.clone(),
+                    &views,
+                    address,
+                    request,
+                    &methods,
+                )
+                .await
+            }
+            Some(Frame::Reject { kind, hash, reason }) => println!(
+                "{} REJECTED {:?} {}: {}",
+                address,
+                kind,
+                blockchain::HashFmt(hash),
+                reason
+            ),
+            Some(_) => println!("IGNORING UNEXPECTED FRAME FROM {}", address),
             None => break,
         }
+
+        if peer_is_node && !relaying_blocks && protocol.state() == PeerState::Steady {
+            relaying_blocks = true;
+
+            let mut node = node.lock().await;
+
+            node.relay_blocks_to(address);
+            node.ping(address).await;
+        }
+    }
+
+    node.lock().await.remove_peer(&address);
+
+    println!("Disconnected from {}.", address);
+}
+
+// Keeps `MAX_OUTBOUND_PEERS` connections to nodes from the address book. The
+// seeds are only dialed until we connected to some node, usually on the first
+// start, or when the address book has nothing else left to try.
+async fn outbound_connections_loop(
+    node: Arc<Mutex<blockchain::Node>>,
+    address_index: Arc<Mutex<AddressIndex>>,
+    seeds: Vec<SocketAddr>,
+    mut anchors: Vec<SocketAddr>,
+    methods: Vec<MethodClass>,
+) {
+    loop {
+        let addresses = {
+            let mut node = node.lock().await;
+            // Only on the first round
+            let mut addresses = std::mem::take(&mut anchors);
+
+            for anchor in &addresses {
+                println!("RECONNECTING TO ANCHOR {}", anchor);
+
+                node.address_attempted(anchor);
+            }
+
+            let missing =
+                MAX_OUTBOUND_PEERS.saturating_sub(node.outbound_count() + addresses.len());
+            let selected = node.select_outbound(missing, &addresses);
+
+            addresses.extend(selected);
+
+            if node.address_book().tried_len() == 0
+                || (node.outbound_count() == 0 && addresses.is_empty())
+            {
+                for seed in &seeds {
+                    if !addresses.contains(seed) && !node.is_connected(seed) {
+                        node.address_attempted(seed);
+                        addresses.push(*seed);
+                    }
+                }
+            }
+
+            addresses
+        };
+
+        for address in addresses {
+            let node_clone = node.clone();
+            let address_index = address_index.clone();
+            let methods = methods.clone();
+
+            tokio::spawn(async move {
+                match timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+                    Ok(Ok(stream)) => {
+                        let connection = Connection::new(stream);
+
+                        peer_loop(
+                            node_clone,
+                            address_index,
+                            connection,
+                            address,
+                            true,
+                            methods,
+                        )
+                        .await
+                    }
+                    Ok(Err(issue)) => println!("COULD NOT CONNECT TO {}: {}", address, issue),
+                    Err(_) => println!("COULD NOT CONNECT TO {}: TIMED OUT", address),
+                }
+            });
+        }
+
+        tokio::time::sleep(OUTBOUND_INTERVAL).await;
+    }
+}
+
+// Spawns a `peer_loop` for each QUIC connection made to us
+async fn quic_accept_loop(
+    node: Arc<Mutex<blockchain::Node>>,
+    address_index: Arc<Mutex<AddressIndex>>,
+    endpoint: quinn::Endpoint,
+    methods: Vec<MethodClass>,
+) {
+    while let Some(incoming) = endpoint.accept().await {
+        let node_clone = node.clone();
+        let address_index = address_index.clone();
+        let methods = methods.clone();
+
+        tokio::spawn(async move {
+            match timeout(CONNECT_TIMEOUT, quic::accept(incoming)).await {
+                Ok(Ok((address, writter, reader))) => {
+                    peer_loop(
+                        node_clone,
+                        address_index,
+                        (writter, reader),
+                        address,
+                        false,
+                        methods,
+                    )
+                    .await
+                }
+                Ok(Err(issue)) => println!("QUIC CONNECTION FAILED: {}", issue),
+                Err(_) => println!("QUIC CONNECTION FAILED: TIMED OUT"),
+            }
+        });
+    }
+}
+
+// Connects to the QUIC peers we aren't connected to, every
+// `QUIC_PEERS_INTERVAL`. They don't count towards `MAX_OUTBOUND_PEERS`.
+async fn quic_peers_loop(
+    node: Arc<Mutex<blockchain::Node>>,
+    address_index: Arc<Mutex<AddressIndex>>,
+    endpoint: quinn::Endpoint,
+    peers: Vec<SocketAddr>,
+    methods: Vec<MethodClass>,
+) {
+    loop {
+        for address in peers.clone() {
+            if node.lock().await.is_connected(&address) {
+                continue;
+            }
+
+            let node_clone = node.clone();
+            let address_index = address_index.clone();
+            let endpoint = endpoint.clone();
+            let methods = methods.clone();
+
+            tokio::spawn(async move {
+                match timeout(CONNECT_TIMEOUT, quic::connect(&endpoint, address)).await {
+                    Ok(Ok(connection)) => {
+                        peer_loop(
+                            node_clone,
+                            address_index,
+                            connection,
+                            address,
+                            true,
+                            methods,
+                        )
+                        .await
+                    }
+                    Ok(Err(issue)) => println!("COULD NOT CONNECT TO {}: {}", address, issue),
+                    Err(_) => println!("COULD NOT CONNECT TO {}: TIMED OUT", address),
+                }
+            });
+        }
+
+        tokio::time::sleep(QUIC_PEERS_INTERVAL).await;
     }
 }
+
+// Every `FEELER_INTERVAL` connects to an address we couldn't connect to yet,
+// only to do the handshake. Those that answer move to the tried addresses,
+// which we prefer when picking outbound peers, and those that don't are
+// eventually forgotten.
+async fn feeler_loop(node: Arc<Mutex<blockchain::Node>>) {
+    loop {
+        tokio::time::sleep(FEELER_INTERVAL).await;
+
+        let (address, network) = {
+            let mut node = node.lock().await;
+
+            match node.select_feeler() {
+                Some(address) => (address, node.network_id()),
+                None => continue,
+            }
+        };
+
+        let result = match timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+            Ok(Ok(stream)) => {
+                let (mut writter, mut reader) = Connection::new(stream);
+
+                Connection::handshake(
+                    &mut writter,
+                    &mut reader,
+                    USER_AGENT,
+                    Some(network),
+                    RelayPolicy::BlocksOnly,
+                )
+                .await
+                .map(|_| ())
+            }
+            Ok(Err(issue)) => Err(issue.to_string()),
+            Err(_) => Err("Timed out".to_string()),
+        };
+
+        match result {
+            Ok(()) => {
+                println!("FEELER {} IS REACHABLE", address);
+
+                node.lock().await.address_reachable(&address);
+            }
+            Err(issue) => println!("FEELER {} IS UNREACHABLE: {}", address, issue),
+        }
+    }
+}
+
+async fn ping_loop(node: Arc<Mutex<blockchain::Node>>) {
+    loop {
+        tokio::time::sleep(PING_INTERVAL).await;
+
+        node.lock().await.ping_peers().await;
+    }
+}
+
+async fn address_book_save_loop(node: Arc<Mutex<blockchain::Node>>) {
+    loop {
+        tokio::time::sleep(ADDRESS_BOOK_SAVE_INTERVAL).await;
+
+        let node = node.lock().await;
+
+        if let Err(issue) = node.address_book().save(ADDRESS_BOOK_PATH) {
+            println!("{}", issue);
+        }
+
+        if let Err(issue) = address_book::save_anchors(ANCHORS_PATH, &node.anchors()) {
+            println!("{}", issue);
+        }
+    }
+}
+
+// Maps the port, and renews the mapping halfway through each lease
+async fn port_mapping_loop(node: Arc<Mutex<blockchain::Node>>, port: u16) {
+    loop {
+        match nat::map_port(port).await {
+            Ok(address) => {
+                println!("PORT {} MAPPED. REACHABLE AT {}", port, address);
+
+                node.lock().await.set_mapped_address(address).await;
+            }
+            Err(issue) => println!("COULD NOT MAP PORT {}: {}", port, issue),
+        }
+
+        tokio::time::sleep(nat::LEASE / 2).await;
+    }
+}
+
+// Tells wallets about the transactions they sent that were dropped
+async fn notify_wallets(
+    node: Arc<Mutex<blockchain::Node>>,
+    mut events: broadcast::Receiver<ChainEvent>,
+) {
+    while let Some(event) = events::next(&mut events).await {
+        if let ChainEvent::MempoolExpired {
+            hash,
+            origin: Some(origin),
+        } = event
+        {
+            node.lock()
+                .await
+                .send(&origin, Frame::TransactionExpired(hash))
+                .await;
+        }
+    }
+}
+
+async fn reject(
+    node: &Arc<Mutex<blockchain::Node>>,
+    address: SocketAddr,
+    kind: RejectKind,
+    hash: blockchain::Hash,
+    reason: blockchain::RejectReason,
+) {
+    let frame = match peer::reject_frame(kind, hash, reason) {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    println!("REJECTING {:?} FROM {}: {}", kind, address, reason);
+
+    node.lock().await.send(&address, frame).await;
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+const MEGABYTE: u64 = 1024 * 1024;
+
+// The `[memory]` table of the configuration file, in megabytes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MemorySettings {
+    // Transactions waiting to be mined. Over it, those paying the lowest
+    // fee rate are dropped.
+    pub mempool_mb: u64,
+    // Blocks and transactions remembered as already checked
+    pub verification_cache_mb: u64,
+}
+
+impl MemorySettings {
+    pub fn mempool_budget(&self) -> u64 {
+        self.mempool_mb * MEGABYTE
+    }
+
+    pub fn verification_cache_budget(&self) -> u64 {
+        self.verification_cache_mb * MEGABYTE
+    }
+}
+
+impl Default for MemorySettings {
+    fn default() -> Self {
+        MemorySettings {
+            mempool_mb: 300,
+            verification_cache_mb: 16,
+        }
+    }
+}
+
+// Answer to `GetMemoryInfo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryInfo {
+    pub mempool: MemoryUsage,
+    pub verification_cache: MemoryUsage,
+    // Why the latest transactions were turned down
+    pub rejections: MemoryUsage,
+}
+
+// Sizes are estimates of what the entries take, not what was allocated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    pub entries: usize,
+    pub bytes: u64,
+    pub budget: u64,
+}
@@ -0,0 +1,539 @@
+use super::blockchain::{Block, Hash, PublicKey, Transaction};
+use fcoin_core::asset::AssetAction;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+
+// What an entry takes besides the transaction itself, the maps and sets
+// it is kept in included. A rough estimate, for the memory budget.
+const ENTRY_OVERHEAD: u64 = 256;
+
+// A transaction waiting to be included in a block
+pub struct MempoolEntry {
+    transaction: Transaction,
+    // When we first saw it, in seconds since the UNIX epoch
+    received: u64,
+    // Who sent it to us, so they can be told if it never gets mined
+    origin: Option<SocketAddr>,
+    // Transactions also waiting that pay its source what it spends beyond
+    // its confirmed balance, which have to be mined before it
+    parents: HashSet<Hash>,
+    // Transactions also waiting that spend what it pays
+    children: HashSet<Hash>,
+}
+
+// A transaction along with its ancestors not in the block yet, which are
+// selected together for the fee rate of all of them: a child paying a high
+// fee gets its parents mined
+struct Package {
+    hashes: Vec<Hash>,
+    fee: u64,
+    size: u64,
+}
+
+impl Package {
+    // Fee per 1000 bytes, like `Transaction::fee_rate`
+    fn fee_rate(&self) -> u64 {
+        self.fee * 1000 / self.size.max(1)
+    }
+}
+
+// Transactions we know about that are not in the chain yet
+pub struct Mempool {
+    entries: HashMap<Hash, MempoolEntry>,
+    // Estimated memory the entries take
+    bytes: u64,
+}
+
+impl MempoolEntry {
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn origin(&self) -> Option<SocketAddr> {
+        self.origin
+    }
+
+    pub fn parents(&self) -> &HashSet<Hash> {
+        &self.parents
+    }
+
+    pub fn children(&self) -> &HashSet<Hash> {
+        &self.children
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Mempool {
+            entries: HashMap::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&Transaction> {
+        self.entries.get(hash).map(|entry| &entry.transaction)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&Hash, &MempoolEntry)> {
+        self.entries.iter()
+    }
+
+    // What the transactions waiting spend from `id` (amounts and fees), and
+    // the ones paying it with how much
+    pub fn pending(&self, id: &PublicKey) -> (u64, Vec<(Hash, u64)>) {
+        let mut spent = 0;
+        let mut credits = Vec::new();
+
+        for (hash, entry) in &self.entries {
+            let transaction = &entry.transaction;
+
+            if transaction.source() == id {
+                spent += transaction.amount() + transaction.fee();
+            }
+
+            // Registering a name, moving tokens or anchoring data pays
+            // nothing to its source
+            if transaction
+                .destinations()
+                .any(|destination| destination == id)
+                && !transaction.moves_no_coins()
+            {
+                credits.push((*hash, transaction.amount_to(id)));
+            }
+        }
+
+        (spent, credits)
+    }
+
+    // Who registers `name` in a transaction waiting, if anyone
+    pub fn name_registrant(&self, name: &str) -> Option<&PublicKey> {
+        self.entries
+            .values()
+            .map(|entry| &entry.transaction)
+            .find(|transaction| transaction.name() == Some(name))
+            .map(|transaction| transaction.source())
+    }
+
+    // Who issues `asset` in a transaction waiting, if anyone
+    pub fn asset_issuer(&self, asset: &str) -> Option<&PublicKey> {
+        self.entries
+            .values()
+            .map(|entry| &entry.transaction)
+            .find(|transaction| {
+                matches!(transaction.asset(), Some(action @ AssetAction::Issue { .. }) if action.asset() == asset)
+            })
+            .map(|transaction| transaction.source())
+    }
+
+    // Tokens of `asset` the transactions waiting take from `id`
+    pub fn pending_tokens(&self, id: &PublicKey, asset: &str) -> u64 {
+        self.entries
+            .values()
+            .map(|entry| &entry.transaction)
+            .filter(|transaction| transaction.source() == id)
+            .filter_map(|transaction| transaction.asset())
+            .filter(|action| action.asset() == asset)
+            .map(|action| action.spent().unwrap_or(0))
+            .sum()
+    }
+
+    // Returns false if the transaction was already there. `parents` are the
+    // transactions it depends on, which must be waiting too.
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        now: u64,
+        origin: Option<SocketAddr>,
+        parents: HashSet<Hash>,
+    ) -> bool {
+        let hash = transaction.hash();
+
+        if self.entries.contains_key(&hash) {
+            return false;
+        }
+
+        for parent in &parents {
+            if let Some(entry) = self.entries.get_mut(parent) {
+                entry.children.insert(hash);
+            }
+        }
+
+        self.bytes += transaction.size() + ENTRY_OVERHEAD;
+
+        self.entries.insert(
+            hash,
+            MempoolEntry {
+                transaction,
+                received: now,
+                origin,
+                parents,
+                children: HashSet::new(),
+            },
+        );
+
+        true
+    }
+
+    // Takes a transaction out, its children no longer depending on it
+    fn remove(&mut self, hash: &Hash) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(hash)?;
+
+        self.bytes -= entry.transaction.size() + ENTRY_OVERHEAD;
+
+        for parent in &entry.parents {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.remove(hash);
+            }
+        }
+
+        for child in &entry.children {
+            if let Some(child) = self.entries.get_mut(child) {
+                child.parents.remove(hash);
+            }
+        }
+
+        Some(entry)
+    }
+
+    // The transactions that have to be mined before `hash`, following
+    // parents of parents
+    pub fn ancestors(&self, hash: &Hash) -> HashSet<Hash> {
+        self.related(hash, |entry| &entry.parents)
+    }
+
+    // The transactions that can only be mined after `hash`
+    pub fn descendants(&self, hash: &Hash) -> HashSet<Hash> {
+        self.related(hash, |entry| &entry.children)
+    }
+
+    fn related(
+        &self,
+        hash: &Hash,
+        next: impl Fn(&MempoolEntry) -> &HashSet<Hash>,
+    ) -> HashSet<Hash> {
+        let mut found = HashSet::new();
+        let mut queue = vec![*hash];
+
+        while let Some(hash) = queue.pop() {
+            if let Some(entry) = self.entries.get(&hash) {
+                for related in next(entry) {
+                    if found.insert(*related) {
+                        queue.push(*related);
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    // Forgets about the transactions that were just confirmed. The ones
+    // depending on them now depend on the chain.
+    pub fn remove_confirmed(&mut self, block: &Block) {
+        for transaction in block.transactions() {
+            self.remove(&transaction.hash());
+        }
+    }
+
+    // Evicts what has been waiting for longer than `ttl` seconds and what
+    // can no longer be included in the next block, at `height`, along with
+    // whatever depended on them
+    pub fn remove_expired(&mut self, now: u64, ttl: u64, height: u64) -> Vec<(Hash, MempoolEntry)> {
+        let mut expired: HashSet<Hash> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                now.saturating_sub(entry.received) > ttl || entry.transaction.is_expired_at(height)
+            })
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        for hash in expired.clone() {
+            expired.extend(self.descendants(&hash));
+        }
+
+        expired
+            .into_iter()
+            .filter_map(|hash| self.remove(&hash).map(|entry| (hash, entry)))
+            .collect()
+    }
+
+    // Evicts the transactions paying the lowest fee rate, along with
+    // whatever depended on them, until the mempool takes no more than
+    // `budget` bytes. Ties go to whoever has been waiting longer.
+    pub fn trim(&mut self, budget: u64) -> Vec<(Hash, MempoolEntry)> {
+        let mut evicted = Vec::new();
+
+        while self.bytes > budget {
+            let cheapest = self
+                .entries
+                .iter()
+                .min_by(|(_, a), (_, b)| {
+                    a.transaction
+                        .fee_rate()
+                        .cmp(&b.transaction.fee_rate())
+                        .then(b.received.cmp(&a.received))
+                })
+                .map(|(hash, _)| *hash);
+
+            let cheapest = match cheapest {
+                Some(hash) => hash,
+                None => break,
+            };
+
+            let mut hashes = self.descendants(&cheapest);
+
+            hashes.insert(cheapest);
+
+            for hash in hashes {
+                if let Some(entry) = self.remove(&hash) {
+                    evicted.push((hash, entry));
+                }
+            }
+        }
+
+        evicted
+    }
+
+    // Fee rate of `hash` together with its ancestors, which is what it is
+    // picked for when building blocks
+    pub fn package_fee_rate(&self, hash: &Hash) -> u64 {
+        self.package(hash, &HashSet::new())
+            .map_or(0, |package| package.fee_rate())
+    }
+
+    // `hash` and the ancestors of it not in `selected`, parents first
+    fn package(&self, hash: &Hash, selected: &HashSet<Hash>) -> Option<Package> {
+        let mut hashes: Vec<Hash> = self
+            .ancestors(hash)
+            .into_iter()
+            .filter(|ancestor| !selected.contains(ancestor))
+            .collect();
+
+        hashes.push(*hash);
+
+        // Ancestors always have fewer ancestors than their descendants
+        hashes.sort_by_cached_key(|hash| self.ancestors(hash).len());
+
+        let mut fee = 0;
+        let mut size = 0;
+
+        for hash in &hashes {
+            let transaction = &self.entries.get(hash)?.transaction;
+
+            fee += transaction.fee();
+            size += transaction.size();
+        }
+
+        Some(Package { hashes, fee, size })
+    }
+
+    // Transactions for a block of up to `max_size` bytes of transactions,
+    // picked by the fee rate of their packages so children can pay for their
+    // parents. Parents always come before their children.
+    pub fn block_template(&self, max_size: u64) -> Vec<Transaction> {
+        let mut selected = HashSet::new();
+        let mut too_large = HashSet::new();
+        let mut transactions = Vec::new();
+        let mut size = 0;
+
+        loop {
+            let best = self
+                .entries
+                .iter()
+                .filter(|(hash, _)| !selected.contains(*hash) && !too_large.contains(*hash))
+                .filter_map(|(hash, entry)| {
+                    self.package(hash, &selected)
+                        .map(|package| (package, entry.received))
+                })
+                // Ties go to whoever has been waiting longer
+                .max_by(|(a, a_received), (b, b_received)| {
+                    a.fee_rate()
+                        .cmp(&b.fee_rate())
+                        .then(b_received.cmp(a_received))
+                });
+
+            let package = match best {
+                Some((package, _)) => package,
+                None => return transactions,
+            };
+
+            if size + package.size > max_size {
+                too_large.insert(*package.hashes.last().unwrap());
+
+                continue;
+            }
+
+            size += package.size;
+
+            for hash in package.hashes {
+                transactions.push(self.entries[&hash].transaction.clone());
+                selected.insert(hash);
+            }
+        }
+    }
+
+    // Fee rates of everything waiting, highest first
+    pub fn fee_rates(&self) -> Vec<u64> {
+        let mut rates: Vec<u64> = self
+            .entries
+            .values()
+            .map(|entry| entry.transaction.fee_rate())
+            .collect();
+
+        rates.sort_unstable_by(|a, b| b.cmp(a));
+
+        rates
+    }
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::TransactionDetails;
+    use crate::keyfile;
+    use fcoin_core::keys;
+
+    // Paid from a new key, which the mempool doesn't check
+    fn transaction(amount: u64, fee: u64) -> Transaction {
+        expiring(amount, fee, None)
+    }
+
+    fn expiring(amount: u64, fee: u64, expires_at: Option<u64>) -> Transaction {
+        let secret_key = keyfile::generate();
+        let details = TransactionDetails::new(keys::public_key(&secret_key), [2; 32], amount, fee)
+            .with_expiry(expires_at);
+        let signature = fcoin_core::transaction::sign(&details, &secret_key);
+
+        Transaction::new(details, signature)
+    }
+
+    #[test]
+    fn transactions_are_only_kept_once() {
+        let mut mempool = Mempool::new();
+        let transaction = transaction(10, 1);
+
+        assert!(mempool.insert(transaction.clone(), 0, None, HashSet::new()));
+        assert!(!mempool.insert(transaction.clone(), 5, None, HashSet::new()));
+
+        assert_eq!(mempool.len(), 1);
+        assert_eq!(mempool.bytes(), transaction.size() + ENTRY_OVERHEAD);
+        // As first received
+        assert_eq!(mempool.entries().next().unwrap().1.received(), 0);
+    }
+
+    #[test]
+    fn fee_rates_are_highest_first() {
+        let mut mempool = Mempool::new();
+
+        for fee in [3, 9, 1] {
+            mempool.insert(transaction(10, fee), 0, None, HashSet::new());
+        }
+
+        let rates = mempool.fee_rates();
+
+        assert_eq!(rates.len(), 3);
+        assert!(rates.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn transactions_expire_with_their_descendants() {
+        let mut mempool = Mempool::new();
+        let old = transaction(10, 1);
+        let child = transaction(11, 1);
+        let until_five = expiring(12, 1, Some(5));
+        let fresh = transaction(13, 1);
+
+        mempool.insert(old.clone(), 0, None, HashSet::new());
+        mempool.insert(child.clone(), 100, None, HashSet::from([old.hash()]));
+        mempool.insert(until_five.clone(), 100, None, HashSet::new());
+        mempool.insert(fresh.clone(), 100, None, HashSet::new());
+
+        // Waiting for longer than a minute, or still includable at 5
+        let expired: HashSet<Hash> = mempool
+            .remove_expired(100, 60, 5)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+
+        assert_eq!(expired, HashSet::from([old.hash(), child.hash()]));
+
+        let expired: Vec<Hash> = mempool
+            .remove_expired(100, 60, 6)
+            .into_iter()
+            .map(|(hash, _)| hash)
+            .collect();
+
+        assert_eq!(expired, vec![until_five.hash()]);
+        assert!(mempool.contains(&fresh.hash()));
+        assert_eq!(mempool.bytes(), fresh.size() + ENTRY_OVERHEAD);
+    }
+
+    #[test]
+    fn children_pay_for_their_parents() {
+        let mut mempool = Mempool::new();
+        let parent = transaction(10, 1);
+        let child = transaction(11, 100);
+        let other = transaction(12, 20);
+
+        mempool.insert(parent.clone(), 0, None, HashSet::new());
+        mempool.insert(child.clone(), 0, None, HashSet::from([parent.hash()]));
+        mempool.insert(other.clone(), 0, None, HashSet::new());
+
+        assert_eq!(
+            mempool.ancestors(&child.hash()),
+            HashSet::from([parent.hash()])
+        );
+        assert_eq!(
+            mempool.descendants(&parent.hash()),
+            HashSet::from([child.hash()])
+        );
+        assert!(mempool.package_fee_rate(&child.hash()) > other.fee_rate());
+        assert!(mempool.package_fee_rate(&child.hash()) < child.fee_rate());
+
+        // Room for two, which the package pays more for, parent first
+        let hashes: Vec<Hash> = mempool
+            .block_template(parent.size() + child.size())
+            .iter()
+            .map(Transaction::hash)
+            .collect();
+
+        assert_eq!(hashes, vec![parent.hash(), child.hash()]);
+
+        // Mined without the child, which then depends on the chain
+        let block = Block::new(
+            crate::blockchain::BlockHeader::new(0, [0; 32], [0; 32], [0; 32], [0; 32]),
+            vec![parent.clone()],
+        );
+        mempool.remove_confirmed(&block);
+
+        assert!(mempool.ancestors(&child.hash()).is_empty());
+        assert_eq!(mempool.package_fee_rate(&child.hash()), child.fee_rate());
+    }
+}
@@ -0,0 +1,99 @@
+use std::convert::TryInto;
+
+// Starts every versioned file, followed by the version as a little endian
+// u32. Files from before versions can't start with it, as bincode would read
+// it as the length of a map larger than any file.
+const MAGIC: &[u8; 8] = b"FCOINDAT";
+const HEADER_SIZE: usize = MAGIC.len() + 4;
+
+// Upgrades the content of a file, without its header, to the next version
+pub type Migration = fn(Vec<u8>) -> Result<Vec<u8>, String>;
+
+// A file format the node writes. Each change to it adds a migration, which
+// bumps its version.
+pub struct Format {
+    // For messages
+    pub name: &'static str,
+    // `migrations[n]` upgrades version `n` to `n + 1`. Version 0 is the
+    // content of files from before versions.
+    pub migrations: &'static [Migration],
+}
+
+impl Format {
+    pub fn version(&self) -> u32 {
+        self.migrations.len() as u32
+    }
+
+    // The content to write, at the current version
+    pub fn encode(&self, content: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HEADER_SIZE + content.len());
+
+        encoded.extend_from_slice(MAGIC);
+        encoded.extend_from_slice(&self.version().to_le_bytes());
+        encoded.extend_from_slice(content);
+
+        encoded
+    }
+
+    // The content of `file`, upgraded to the current version, along with the
+    // version it was at
+    pub fn decode(&self, file: Vec<u8>) -> Result<(u32, Vec<u8>), String> {
+        let (version, mut content) = if file.starts_with(MAGIC) && file.len() >= HEADER_SIZE {
+            let version = u32::from_le_bytes(file[MAGIC.len()..HEADER_SIZE].try_into().unwrap());
+
+            (version, file[HEADER_SIZE..].to_vec())
+        } else {
+            (0, file)
+        };
+
+        if version > self.version() {
+            return Err(format!(
+                "The {} is at version {}, from a newer fcoin. This one only knows up to version {}",
+                self.name,
+                version,
+                self.version()
+            ));
+        }
+
+        for (from, migration) in self.migrations.iter().enumerate().skip(version as usize) {
+            content = migration(content).map_err(|issue| {
+                format!(
+                    "Could not upgrade the {} from version {}: {}",
+                    self.name, from, issue
+                )
+            })?;
+        }
+
+        Ok((version, content))
+    }
+
+    // Upgrades the file at `path`, if there is one, in place. Run on startup,
+    // so the node refuses to start on files it doesn't know rather than
+    // overwriting them.
+    pub fn migrate(&self, path: &str) -> Result<(), String> {
+        let file = match std::fs::read(path) {
+            Ok(file) => file,
+            Err(issue) if issue.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(issue) => return Err(format!("Could not read {}: {}", path, issue)),
+        };
+        let (version, content) = self.decode(file)?;
+
+        if version < self.version() {
+            super::backup::write_atomically(path, &self.encode(&content))?;
+
+            println!(
+                "UPGRADED {} FROM VERSION {} TO {}",
+                path,
+                version,
+                self.version()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// For the first version of each format, which only added the header
+pub fn unversioned(content: Vec<u8>) -> Result<Vec<u8>, String> {
+    Ok(content)
+}
@@ -0,0 +1,364 @@
+use super::blockchain::Block;
+use super::network::PowHash;
+use super::pow::PowHasher;
+use super::rpc::BlockTemplate;
+use fcoin_core::Hash;
+use num::BigUint;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+// How often the threads are checked on
+const CHECK_INTERVAL: Duration = Duration::from_millis(200);
+// Nonces each thread tries between checks of whether to stop
+const BATCH_SIZE: u64 = 64;
+
+// Rests shorter than this add up until they are worth sleeping for
+const MIN_REST: Duration = Duration::from_millis(10);
+// Hashrates are averaged over this long
+const HASHRATE_WINDOW: Duration = Duration::from_secs(60);
+// Niceness of a throttled miner, the lowest priority there is
+const MINER_NICENESS: libc::c_int = 19;
+
+// The `[mining]` table of the configuration file, for the miner built into
+// the node
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MiningSettings {
+    // Share of a core mining takes, from 1 to 100. Below 100 the miner
+    // sleeps between hashes, and runs at a lower priority.
+    pub cpu_percent: u64,
+}
+
+impl Default for MiningSettings {
+    fn default() -> Self {
+        MiningSettings { cpu_percent: 100 }
+    }
+}
+
+impl MiningSettings {
+    pub fn is_throttled(&self) -> bool {
+        self.cpu_percent < 100
+    }
+}
+
+// See `Request::GetMiningInfo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MiningInfo {
+    pub cpu_percent: u64,
+    // Hashes per second over the last minute, counting the rests
+    pub hashrate: f64,
+    // Blocks we mined since we started, by ourselves or with our workers,
+    // and how many of them are now off the main chain
+    pub blocks_mined: u64,
+    pub blocks_orphaned: u64,
+    pub orphan_rate: f64,
+    pub propagation: Vec<PeerPropagation>,
+}
+
+// How long the blocks a peer sent us first took from arriving to being
+// validated
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerPropagation {
+    pub address: SocketAddr,
+    pub blocks: u64,
+    pub average_ms: u64,
+    pub max_ms: u64,
+}
+
+// Our own blocks, and those of them off the main chain, be it because
+// another block made it there first or because a reorganization took them
+// off it
+#[derive(Default)]
+pub struct OrphanTracker {
+    mined: u64,
+    off_main_chain: HashSet<Hash>,
+}
+
+impl OrphanTracker {
+    // Before it is connected, if it ever is
+    pub fn mined(&mut self, hash: Hash) {
+        self.mined += 1;
+        self.off_main_chain.insert(hash);
+    }
+
+    pub fn connected(&mut self, hash: &Hash) {
+        self.off_main_chain.remove(hash);
+    }
+
+    pub fn disconnected(&mut self, hash: Hash) {
+        self.off_main_chain.insert(hash);
+    }
+
+    pub fn mined_count(&self) -> u64 {
+        self.mined
+    }
+
+    pub fn orphaned_count(&self) -> u64 {
+        self.off_main_chain.len() as u64
+    }
+
+    pub fn orphan_rate(&self) -> f64 {
+        self.orphaned_count() as f64 / self.mined.max(1) as f64
+    }
+}
+
+// Propagation delays of the blocks each peer sent us first
+#[derive(Default)]
+pub struct PropagationTracker {
+    peers: HashMap<SocketAddr, (u64, Duration, Duration)>,
+}
+
+impl PropagationTracker {
+    pub fn record(&mut self, peer: SocketAddr, delay: Duration) {
+        let (blocks, total, max) = self.peers.entry(peer).or_default();
+
+        *blocks += 1;
+        *total += delay;
+        *max = (*max).max(delay);
+    }
+
+    pub fn remove(&mut self, peer: &SocketAddr) {
+        self.peers.remove(peer);
+    }
+
+    pub fn peers(&self) -> Vec<PeerPropagation> {
+        let mut peers: Vec<PeerPropagation> = self
+            .peers
+            .iter()
+            .map(|(address, (blocks, total, max))| PeerPropagation {
+                address: *address,
+                blocks: *blocks,
+                average_ms: (*total / *blocks as u32).as_millis() as u64,
+                max_ms: max.as_millis() as u64,
+            })
+            .collect();
+
+        peers.sort_by_key(|peer| peer.address);
+        peers
+    }
+}
+
+// Keeps the miner to its share of the CPU, by resting in proportion to the
+// time it spends hashing
+pub struct Throttle {
+    cpu_percent: u64,
+    owed: Duration,
+}
+
+impl Throttle {
+    pub fn new(settings: &MiningSettings) -> Self {
+        Throttle {
+            cpu_percent: settings.cpu_percent.clamp(1, 100),
+            owed: Duration::ZERO,
+        }
+    }
+
+    // How long to rest after `worked`, once it is worth it
+    pub fn rest(&mut self, worked: Duration) -> Option<Duration> {
+        self.owed += worked * (100 - self.cpu_percent) as u32 / self.cpu_percent as u32;
+
+        if self.owed < MIN_REST {
+            return None;
+        }
+
+        Some(std::mem::take(&mut self.owed))
+    }
+}
+
+// Hashes tried recently, by when
+#[derive(Default)]
+pub struct HashrateMeter {
+    hashes: VecDeque<(Instant, u64)>,
+}
+
+impl HashrateMeter {
+    pub fn record(&mut self, hashes: u64) {
+        let now = Instant::now();
+
+        self.hashes.push_back((now, hashes));
+
+        while let Some((time, _)) = self.hashes.front() {
+            if now.duration_since(*time) <= HASHRATE_WINDOW {
+                break;
+            }
+
+            self.hashes.pop_front();
+        }
+    }
+
+    // Per second, over the last minute or since the first hash
+    pub fn hashrate(&self) -> f64 {
+        let since = match self.hashes.front() {
+            Some((time, _)) => time.elapsed(),
+            None => return 0.0,
+        };
+        let hashes: u64 = self.hashes.iter().map(|(_, hashes)| hashes).sum();
+
+        hashes as f64 / since.as_secs_f64().max(1.0)
+    }
+}
+
+// Lowers the priority of the calling thread, so the rest of the machine goes
+// first. On Linux only that thread is affected.
+pub fn lower_priority() {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, MINER_NICENESS) } != 0 {
+        println!("COULD NOT LOWER THE PRIORITY OF THE MINER");
+    }
+}
+
+// Bytes at the start of the nonce counted up while grinding. The other 24
+// are an extra-nonce, rolled when the counter runs out. fcoin blocks have
+// no coinbase transaction, so it lives in the header nonce instead.
+const COUNTER_SIZE: usize = 8;
+
+// Tries nonces for one block, without encoding its header for each
+pub struct Grinder {
+    hasher: Box<dyn PowHasher>,
+    target: BigUint,
+    // Encoded header, whose nonce is overwritten for each try
+    header: Vec<u8>,
+    start: usize,
+    // Tried so far
+    hashes: u64,
+}
+
+impl Grinder {
+    pub fn new(block: &Block, target: BigUint, pow_hash: PowHash) -> Self {
+        let (mut header, after) = block.header().encoded_around_nonce();
+        let start = header.len();
+
+        header.extend_from_slice(block.header().nonce());
+        header.extend_from_slice(&after);
+
+        Grinder {
+            hasher: pow_hash.hasher(),
+            target,
+            header,
+            start,
+            hashes: 0,
+        }
+    }
+
+    // Tries `batch` nonces from `nonce` on, leaving it at the next one to
+    // try. Returns the first whose proof of work hash is below the target.
+    pub fn grind(&mut self, nonce: &mut [u8; 32], batch: u64) -> Option<[u8; 32]> {
+        for _ in 0..batch {
+            let tried = *nonce;
+
+            self.header[self.start..self.start + 32].copy_from_slice(&tried);
+            self.hashes += 1;
+            next_nonce(nonce);
+
+            if BigUint::from_bytes_le(&self.hasher.hash(&self.header)) < self.target {
+                return Some(tried);
+            }
+        }
+
+        None
+    }
+
+    pub fn hashes(&self) -> u64 {
+        self.hashes
+    }
+}
+
+// A nonce to start from, so threads and nodes grinding the same template
+// don't try the same ones
+pub fn random_nonce() -> [u8; 32] {
+    let mut nonce = [0; 32];
+
+    getrandom::getrandom(&mut nonce).expect("No randomness source available");
+
+    nonce
+}
+
+// Tries nonces for the block of `template` on `threads` threads, for at
+// most `duration`, after which the template is likely stale. Each thread
+// starts from a random nonce, so they don't try the same ones. Returns the
+// block when one is found, and how many hashes were tried.
+pub fn search(
+    template: &BlockTemplate,
+    threads: usize,
+    duration: Duration,
+) -> (Option<Block>, u64) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let hashes = Arc::new(AtomicU64::new(0));
+    let target = BigUint::from_bytes_le(&template.target);
+    let (tx, rx) = mpsc::channel();
+    let mut handles = Vec::new();
+
+    for _ in 0..threads.max(1) {
+        let stop = stop.clone();
+        let hashes = hashes.clone();
+        let mut grinder = Grinder::new(&template.block, target.clone(), template.pow_hash);
+        let tx = tx.clone();
+
+        handles.push(thread::spawn(move || {
+            let mut nonce = random_nonce();
+
+            while !stop.load(Ordering::Relaxed) {
+                let found = grinder.grind(&mut nonce, BATCH_SIZE);
+
+                hashes.fetch_add(grinder.hashes(), Ordering::Relaxed);
+                grinder.hashes = 0;
+
+                if let Some(found) = found {
+                    stop.store(true, Ordering::Relaxed);
+                    let _ = tx.send(found);
+
+                    return;
+                }
+            }
+        }));
+    }
+
+    let started = Instant::now();
+
+    let found = loop {
+        match rx.recv_timeout(CHECK_INTERVAL) {
+            Ok(nonce) => break Some(nonce),
+            Err(_) if started.elapsed() >= duration => break None,
+            Err(_) => {}
+        }
+    };
+
+    stop.store(true, Ordering::Relaxed);
+
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    let block = found.map(|nonce| {
+        let mut block = template.block.clone();
+
+        block.header_mut().set_nonce(nonce);
+        block
+    });
+
+    (block, hashes.load(Ordering::Relaxed))
+}
+
+// Counts up in the counter, rolling the extra-nonce when it wraps around
+fn next_nonce(nonce: &mut [u8; 32]) {
+    let (counter, extra_nonce) = nonce.split_at_mut(COUNTER_SIZE);
+    let next = u64::from_le_bytes((&*counter).try_into().unwrap()).wrapping_add(1);
+
+    counter.copy_from_slice(&next.to_le_bytes());
+
+    if next == 0 {
+        for byte in extra_nonce {
+            *byte = byte.wrapping_add(1);
+
+            if *byte != 0 {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use igd_next::aio::tokio::search_gateway;
+use igd_next::{PortMappingProtocol, SearchOptions};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+use tokio::time::timeout;
+
+// How long routers keep our mappings, they are renewed before that
+pub const LEASE: Duration = Duration::from_secs(60 * 60);
+
+const UPNP_SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+const NATPMP_PORT: u16 = 5351;
+// NAT-PMP clients wait 250ms for an answer, doubling it after each attempt
+const NATPMP_FIRST_WAIT: Duration = Duration::from_millis(250);
+const NATPMP_ATTEMPTS: usize = 5;
+const NATPMP_EXTERNAL_ADDRESS: u8 = 0;
+const NATPMP_MAP_TCP: u8 = 2;
+
+// Asks the router to forward `port` to us, with UPnP or else NAT-PMP.
+// Returns the address other nodes can reach us at.
+pub async fn map_port(port: u16) -> Result<SocketAddr, String> {
+    match upnp_map_port(port).await {
+        Ok(address) => Ok(address),
+        Err(upnp_issue) => natpmp_map_port(port)
+            .await
+            .map_err(|natpmp_issue| format!("UPnP: {}. NAT-PMP: {}", upnp_issue, natpmp_issue)),
+    }
+}
+
+async fn upnp_map_port(port: u16) -> Result<SocketAddr, String> {
+    let mut options = SearchOptions::default();
+    options.timeout = Some(UPNP_SEARCH_TIMEOUT);
+
+    let gateway = search_gateway(options)
+        .await
+        .map_err(|issue| issue.to_string())?;
+
+    let local_address = SocketAddr::new(local_ip_towards(gateway.addr)?, port);
+
+    gateway
+        .add_port(
+            PortMappingProtocol::TCP,
+            port,
+            local_address,
+            LEASE.as_secs() as u32,
+            "fcoin",
+        )
+        .await
+        .map_err(|issue| issue.to_string())?;
+
+    let ip = gateway
+        .get_external_ip()
+        .await
+        .map_err(|issue| issue.to_string())?;
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+// The router maps the port of whoever sends the request, so unlike with
+// UPnP we don't need to know our own address
+async fn natpmp_map_port(port: u16) -> Result<SocketAddr, String> {
+    let gateway = default_gateway()?;
+
+    let response = natpmp_request(gateway, &[0, NATPMP_EXTERNAL_ADDRESS], 12).await?;
+    let ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let mut request = vec![0, NATPMP_MAP_TCP, 0, 0];
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    request.extend_from_slice(&(LEASE.as_secs() as u32).to_be_bytes());
+
+    let response = natpmp_request(gateway, &request, 16).await?;
+    // The router may pick another port when ours is taken
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+
+    Ok(SocketAddr::new(IpAddr::V4(ip), external_port))
+}
+
+// Sends `request` until the gateway answers it, returning the response
+// once it checked it was successful
+async fn natpmp_request(
+    gateway: Ipv4Addr,
+    request: &[u8],
+    response_length: usize,
+) -> Result<Vec<u8>, String> {
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|issue| issue.to_string())?;
+
+    socket
+        .connect((gateway, NATPMP_PORT))
+        .await
+        .map_err(|issue| issue.to_string())?;
+
+    let mut wait = NATPMP_FIRST_WAIT;
+
+    for _ in 0..NATPMP_ATTEMPTS {
+        socket
+            .send(request)
+            .await
+            .map_err(|issue| issue.to_string())?;
+
+        let mut response = [0; 16];
+
+        match timeout(wait, socket.recv(&mut response)).await {
+            Ok(Ok(length)) if length >= response_length => {
+                if response[1] != request[1] + 128 {
+                    return Err("Unexpected response from the gateway".to_string());
+                }
+
+                match u16::from_be_bytes([response[2], response[3]]) {
+                    0 => return Ok(response[..response_length].to_vec()),
+                    code => return Err(format!("The gateway refused with code {}", code)),
+                }
+            }
+            Ok(Ok(_)) => return Err("Truncated response from the gateway".to_string()),
+            Ok(Err(issue)) => return Err(issue.to_string()),
+            Err(_) => wait *= 2,
+        }
+    }
+
+    Err(format!("{} did not answer", gateway))
+}
+
+// Router of the default route, from the Linux routing table
+fn default_gateway() -> Result<Ipv4Addr, String> {
+    let routes = fs::read_to_string("/proc/net/route")
+        .map_err(|issue| format!("Could not read the routing table: {}", issue))?;
+
+    for route in routes.lines().skip(1) {
+        let fields: Vec<&str> = route.split_whitespace().collect();
+
+        if let [_, "00000000", gateway, ..] = fields.as_slice() {
+            // Written in the byte order of the host
+            let gateway = u32::from_str_radix(gateway, 16).map_err(|issue| issue.to_string())?;
+
+            return Ok(Ipv4Addr::from(gateway.to_ne_bytes()));
+        }
+    }
+
+    Err("No default route".to_string())
+}
+
+// Our address on the network `gateway` is in. Connecting a UDP socket sends
+// nothing, it just picks the interface.
+fn local_ip_towards(gateway: SocketAddr) -> Result<IpAddr, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|issue| issue.to_string())?;
+
+    socket.connect(gateway).map_err(|issue| issue.to_string())?;
+
+    socket
+        .local_addr()
+        .map(|address| address.ip())
+        .map_err(|issue| issue.to_string())
+}
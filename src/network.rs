@@ -0,0 +1,246 @@
+use super::consensus::{Consensus, ProofOfStake, ProofOfWork};
+use super::deployment::{Deployment, Deployments};
+use super::pow::{Argon2Hasher, PowHasher, Sha256Hasher};
+use fcoin_core::encoding::Encode;
+use fcoin_core::{address, Hash, PublicKey};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+// Rules every node of a network has to agree on, from the `[network]`
+// table of the configuration file. Peers compare them in the handshake.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NetworkProfile {
+    pub consensus: ConsensusKind,
+    // Only used with proof of work
+    pub pow_hash: PowHash,
+    // Seconds blocks should be apart on average. Proof of work adjusts its
+    // target to it, proof of stake uses it as the length of its slots.
+    pub block_time: u64,
+    // How many blocks go by between proof of work target adjustments
+    pub retarget_window: u64,
+    // Most blocks we disconnect to switch to another branch, deeper forks
+    // are ignored
+    pub max_reorg_depth: u64,
+    pub emission: Emission,
+    // Coins of test networks are worth nothing, so nodes can give them away
+    // with a faucet
+    pub testnet: bool,
+    // Addresses whose signed checkpoints nodes never reorganize below, for
+    // small networks where a single miner could outpace everyone else
+    #[serde(deserialize_with = "addresses")]
+    pub checkpoint_signers: Vec<PublicKey>,
+    // Transactions paying less than this are dust, spam that would bloat
+    // the chain, and are never mined. Networks start with nobody having
+    // coins, so usually only raised once they took off.
+    pub dust_threshold: u64,
+    // Least fee of a transaction registering or renewing a name
+    pub name_fee: u64,
+    // Blocks a name stays registered for, unless renewed
+    pub name_lifetime: u64,
+    // Least fee of a transaction carrying data, for each byte of it, on
+    // top of what it pays to be mined
+    pub data_byte_fee: u64,
+    // Rule changes blocks signal for, see `deployment.rs`
+    pub deployments: Vec<Deployment>,
+    // Percent of the blocks of a retarget window that have to signal for a
+    // deployment to lock it in
+    pub activation_threshold: u64,
+    pub forks: Forks,
+}
+
+// Changes to the consensus rules, which validation only applies from the
+// height the network schedules them at, see `NetworkProfile::is_active`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fork {
+    // Transactions carrying data pay `data_byte_fee` for each byte of it
+    DataFee,
+}
+
+// Heights each fork activates at, from the `[network.forks]` table. Those
+// left out apply from the first block.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Forks {
+    pub data_fee: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConsensusKind {
+    #[default]
+    ProofOfWork,
+    ProofOfStake,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PowHash {
+    #[default]
+    Sha256,
+    Argon2,
+}
+
+impl PowHash {
+    pub fn hasher(self) -> Box<dyn PowHasher> {
+        match self {
+            PowHash::Sha256 => Box::new(Sha256Hasher),
+            PowHash::Argon2 => Box::new(Argon2Hasher::new()),
+        }
+    }
+}
+
+// How many coins each block creates for whoever produced it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Emission {
+    pub initial_reward: u64,
+    // The reward halves every this many blocks, never when 0
+    pub halving_interval: u64,
+}
+
+impl Default for NetworkProfile {
+    fn default() -> Self {
+        NetworkProfile {
+            consensus: ConsensusKind::default(),
+            pow_hash: PowHash::default(),
+            block_time: 10,
+            retarget_window: 100,
+            max_reorg_depth: 100,
+            emission: Emission::default(),
+            testnet: false,
+            checkpoint_signers: Vec::new(),
+            dust_threshold: 0,
+            name_fee: 10,
+            name_lifetime: 100_000,
+            data_byte_fee: 1,
+            deployments: Vec::new(),
+            activation_threshold: 95,
+            forks: Forks::default(),
+        }
+    }
+}
+
+pub fn addresses<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PublicKey>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| address::decode(text).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+impl Default for Emission {
+    fn default() -> Self {
+        Emission {
+            initial_reward: 1,
+            halving_interval: 0,
+        }
+    }
+}
+
+impl Emission {
+    // Reward of the block at `height`, the first block being at 1
+    pub fn reward(&self, height: u64) -> u64 {
+        if self.halving_interval == 0 {
+            return self.initial_reward;
+        }
+
+        let halvings = height.saturating_sub(1) / self.halving_interval;
+
+        self.initial_reward
+            .checked_shr(halvings as u32)
+            .unwrap_or(0)
+    }
+
+    // Coins created by all the blocks up to `height`
+    pub fn issued(&self, height: u64) -> u64 {
+        if self.halving_interval == 0 {
+            return self.initial_reward * height;
+        }
+
+        let mut issued = 0;
+        let mut start = 1;
+
+        while start <= height && self.reward(start) > 0 {
+            let end = height.min(start + self.halving_interval - 1);
+
+            issued += self.reward(start) * (end - start + 1);
+            start = end + 1;
+        }
+
+        issued
+    }
+}
+
+impl NetworkProfile {
+    pub fn consensus(&self) -> Arc<dyn Consensus> {
+        match self.consensus {
+            ConsensusKind::ProofOfWork => Arc::new(ProofOfWork::new(
+                self.pow_hasher(),
+                self.block_time,
+                self.retarget_window,
+            )),
+            ConsensusKind::ProofOfStake => {
+                Arc::new(ProofOfStake::new(self.block_time, self.emission))
+            }
+        }
+    }
+
+    pub fn pow_hasher(&self) -> Box<dyn PowHasher> {
+        self.pow_hash.hasher()
+    }
+
+    // Whether the rules of `fork` apply to the block at `height`
+    pub fn is_active(&self, fork: Fork, height: u64) -> bool {
+        let activation = match fork {
+            Fork::DataFee => self.forks.data_fee,
+        };
+
+        height >= activation
+    }
+
+    pub fn deployments(&self) -> Deployments {
+        Deployments::new(
+            self.deployments.clone(),
+            self.retarget_window,
+            self.activation_threshold,
+        )
+    }
+
+    // Least fee of a transaction carrying `bytes` of data
+    pub fn data_fee(&self, bytes: usize) -> u64 {
+        self.data_byte_fee.saturating_mul(bytes as u64)
+    }
+
+    // Identifies the network, nodes with different profiles can't talk
+    pub fn id(&self) -> Hash {
+        Sha256::digest(&self.encoded()).into()
+    }
+}
+
+impl Encode for NetworkProfile {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.consensus as u64).encode(out);
+        (self.pow_hash as u64).encode(out);
+        self.block_time.encode(out);
+        self.retarget_window.encode(out);
+        self.max_reorg_depth.encode(out);
+        self.emission.initial_reward.encode(out);
+        self.emission.halving_interval.encode(out);
+        (self.testnet as u64).encode(out);
+        (self.checkpoint_signers.len() as u64).encode(out);
+
+        for signer in &self.checkpoint_signers {
+            out.extend_from_slice(signer);
+        }
+
+        self.dust_threshold.encode(out);
+        self.name_fee.encode(out);
+        self.name_lifetime.encode(out);
+        self.data_byte_fee.encode(out);
+        self.deployments[..].encode(out);
+        self.activation_threshold.encode(out);
+        self.forks.data_fee.encode(out);
+    }
+}
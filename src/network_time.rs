@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+// Peers further off than this (in seconds) are assumed to have a broken
+// clock and are left out of the median
+const MAX_TIME_OFFSET: i64 = 70 * 60;
+// Below this many peers our own clock is used as is
+const MIN_TIME_SAMPLES: usize = 3;
+// Warns when our clock is this far (in seconds) from the network's
+const CLOCK_SKEW_WARNING: i64 = 5 * 60;
+
+// Time adjusted by the median of how far the clocks of our peers are from
+// ours, so a single wrong clock (including ours) can't mess with block times
+pub struct NetworkTime {
+    // In seconds, theirs minus ours
+    offsets: HashMap<SocketAddr, i64>,
+}
+
+impl NetworkTime {
+    pub fn new() -> Self {
+        NetworkTime {
+            offsets: HashMap::new(),
+        }
+    }
+
+    // `time` is what the peer told us its clock was at, just now
+    pub fn add_sample(&mut self, peer: SocketAddr, time: u64) {
+        self.offsets.insert(peer, time as i64 - local_time() as i64);
+
+        let offset = self.offset();
+
+        if offset.abs() > CLOCK_SKEW_WARNING {
+            println!(
+                "WARNING: OUR CLOCK IS {} SECONDS OFF FROM THE NETWORK. PLEASE CHECK THE DATE AND TIME OF THIS COMPUTER!",
+                -offset
+            );
+        }
+    }
+
+    pub fn remove_sample(&mut self, peer: &SocketAddr) {
+        self.offsets.remove(peer);
+    }
+
+    // Median offset of the peers with sane clocks, in seconds
+    pub fn offset(&self) -> i64 {
+        let mut offsets: Vec<i64> = self
+            .offsets
+            .values()
+            .copied()
+            .filter(|offset| offset.abs() <= MAX_TIME_OFFSET)
+            .collect();
+
+        if offsets.len() < MIN_TIME_SAMPLES {
+            return 0;
+        }
+
+        offsets.sort_unstable();
+
+        offsets[offsets.len() / 2]
+    }
+
+    pub fn now(&self) -> u64 {
+        (local_time() as i64 + self.offset()) as u64
+    }
+}
+
+impl Default for NetworkTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn local_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -0,0 +1,140 @@
+use super::blockchain::{
+    BlockHeader, Hash, HashFmt, Transaction, TransactionDetails, TransactionStatus,
+};
+use super::client;
+use super::merkle::MerkleProof;
+use fcoin_core::{keys, raw, transaction, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Timestamps of documents. Anchoring a file commits its hash to the chain
+// in the data of a transaction paying nobody. Once mined, the header of its
+// block and the Merkle proof the transaction is part of it make a
+// certificate that the file existed by the time of the block, which anyone
+// can check against the chain without the node that made it.
+
+// Kept next to the file, as `<file>.anchor`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Certificate {
+    pub file_hash: Hash,
+    // Carries `file_hash` as its data
+    pub transaction: Transaction,
+    // `None` until the transaction is mined
+    pub inclusion: Option<Inclusion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inclusion {
+    pub header: BlockHeader,
+    pub height: u64,
+    pub proof: MerkleProof,
+}
+
+pub fn certificate_path(file: &str) -> String {
+    format!("{}.anchor", file)
+}
+
+pub fn hash_file(file: &str) -> Result<Hash, String> {
+    let content =
+        std::fs::read(file).map_err(|issue| format!("Could not read {}: {}", file, issue))?;
+
+    Ok(Sha256::digest(&content).into())
+}
+
+// `None` when the file was never anchored
+pub fn load(file: &str) -> Result<Option<Certificate>, String> {
+    let path = certificate_path(file);
+
+    match std::fs::read(&path) {
+        Ok(content) => serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|issue| format!("Invalid certificate {}: {}", path, issue)),
+        Err(issue) if issue.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(issue) => Err(format!("Could not read {}: {}", path, issue)),
+    }
+}
+
+pub fn save(file: &str, certificate: &Certificate) -> Result<(), String> {
+    let content = serde_json::to_vec_pretty(certificate).map_err(|issue| issue.to_string())?;
+
+    super::backup::write_atomically(&certificate_path(file), &content)
+}
+
+// Sends a transaction carrying the hash of the file, paying the fee for its
+// data and whatever more it takes to be mined within `confirm_within`
+// blocks
+pub async fn commit(
+    node: &str,
+    secret_key: &SecretKey,
+    file_hash: Hash,
+    confirm_within: u32,
+) -> Result<Certificate, String> {
+    let source = keys::public_key(secret_key);
+    let data_fee = client::node_info(node).await?.data_byte_fee * file_hash.len() as u64;
+    let rate = client::estimate_fee(node, confirm_within).await?;
+    let fee = TransactionDetails::anchor(source, file_hash.to_vec(), 0)
+        .fee_for_rate(rate)
+        .max(data_fee);
+    let details = TransactionDetails::anchor(source, file_hash.to_vec(), fee);
+    let signature = transaction::sign(&details, secret_key);
+    let signed = Transaction::new(details, signature);
+
+    client::broadcast_raw_transaction(node, &raw::encode_signed(&signed)).await?;
+
+    Ok(Certificate {
+        file_hash,
+        transaction: signed,
+        inclusion: None,
+    })
+}
+
+// Fills in where the transaction was mined, `false` when it wasn't yet.
+// Needs the node to index transactions.
+pub async fn prove(node: &str, certificate: &mut Certificate) -> Result<bool, String> {
+    let hash = certificate.transaction.hash();
+
+    let height = match client::transaction_status(node, hash).await? {
+        TransactionStatus::Confirmed { height, .. } => height,
+        _ => return Ok(false),
+    };
+    let (header, proof) = client::transaction_proof(node, hash).await?;
+
+    certificate.inclusion = Some(Inclusion {
+        header,
+        height,
+        proof,
+    });
+
+    Ok(true)
+}
+
+// Checks the certificate is for a file hashing to `file_hash`, and proves
+// the transaction carrying it is part of the block. Whether that block is
+// part of the chain is left to check against a node.
+pub fn verify<'a>(certificate: &'a Certificate, file_hash: &Hash) -> Result<&'a Inclusion, String> {
+    if &certificate.file_hash != file_hash {
+        return Err("The certificate is for another file, or the file changed".to_string());
+    }
+
+    let transaction = &certificate.transaction;
+
+    if transaction.data() != Some(&file_hash[..]) || !transaction.has_valid_signature() {
+        return Err("The transaction of the certificate doesn't carry the file hash".to_string());
+    }
+
+    let inclusion = certificate.inclusion.as_ref().ok_or_else(|| {
+        format!(
+            "Transaction {} isn't mined yet",
+            HashFmt(transaction.hash())
+        )
+    })?;
+
+    if !inclusion
+        .proof
+        .verify(&transaction.hash(), inclusion.header.merkle_root())
+    {
+        return Err("The Merkle proof doesn't lead to the block of the certificate".to_string());
+    }
+
+    Ok(inclusion)
+}
@@ -0,0 +1,132 @@
+use fcoin_core::address::{self, CHECKSUM_LENGTH};
+use fcoin_core::{keys, SecretKey};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use zeroize::Zeroizing;
+
+// Prepended to private keys written on paper, which are Base58Check encoded
+// like addresses
+const PLAIN_VERSION: u8 = 0x80;
+const ENCRYPTED_VERSION: u8 = 0x81;
+// Slow on purpose, as anyone holding the paper can try passphrases offline
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_SALT: &[u8] = b"fcoin paper wallet";
+
+// Encrypted keys carry this much of the checksum of their address, which
+// salts the passphrase and tells whether it was the right one
+const ADDRESS_HASH_LENGTH: usize = 4;
+
+pub fn encode(secret_key: &SecretKey) -> String {
+    base58check(PLAIN_VERSION, secret_key.as_bytes())
+}
+
+// Like BIP38: the key is XORed with what Argon2 derives from the
+// passphrase, salted with the address so every key needs its own search
+pub fn encrypt(secret_key: &SecretKey, passphrase: &str) -> String {
+    let address_hash = address_hash(secret_key);
+    let pad = derive_pad(passphrase, &address_hash);
+
+    let mut payload = address_hash.to_vec();
+    payload.extend(
+        secret_key
+            .as_bytes()
+            .iter()
+            .zip(pad.iter())
+            .map(|(a, b)| a ^ b),
+    );
+
+    base58check(ENCRYPTED_VERSION, &payload)
+}
+
+pub fn is_encrypted(encoded: &str) -> bool {
+    matches!(open(encoded), Ok((ENCRYPTED_VERSION, _)))
+}
+
+// The passphrase is only needed for encrypted keys
+pub fn decode(encoded: &str, passphrase: Option<&str>) -> Result<SecretKey, String> {
+    let (version, payload) = open(encoded)?;
+
+    match version {
+        PLAIN_VERSION if payload.len() == 32 => {
+            let mut secret_key = SecretKey::zeroed();
+            secret_key.as_mut_bytes().copy_from_slice(&payload);
+
+            Ok(secret_key)
+        }
+        ENCRYPTED_VERSION if payload.len() == ADDRESS_HASH_LENGTH + 32 => {
+            let passphrase =
+                passphrase.ok_or_else(|| "The private key is encrypted".to_string())?;
+            let (expected, encrypted) = payload.split_at(ADDRESS_HASH_LENGTH);
+            let pad = derive_pad(passphrase, expected);
+
+            let mut secret_key = SecretKey::zeroed();
+
+            for (byte, (a, b)) in secret_key
+                .as_mut_bytes()
+                .iter_mut()
+                .zip(encrypted.iter().zip(pad.iter()))
+            {
+                *byte = a ^ b;
+            }
+
+            if address_hash(&secret_key) != expected {
+                return Err("Wrong passphrase".to_string());
+            }
+
+            Ok(secret_key)
+        }
+        _ => Err("Not a paper wallet private key".to_string()),
+    }
+}
+
+// The payload is wiped once encoded, as it may hold a key
+fn base58check(version: u8, data: &[u8]) -> String {
+    let mut payload = Zeroizing::new(vec![version]);
+    payload.extend_from_slice(data);
+
+    let checksum = address::checksum(&payload);
+    payload.extend_from_slice(&checksum);
+
+    bs58::encode(&*payload).into_string()
+}
+
+// The version byte and what follows it, once the checksum is verified
+fn open(encoded: &str) -> Result<(u8, Zeroizing<Vec<u8>>), String> {
+    let payload = bs58::decode(encoded.trim())
+        .into_vec()
+        .map(Zeroizing::new)
+        .map_err(|_| "Not a paper wallet private key".to_string())?;
+
+    if payload.len() < 1 + CHECKSUM_LENGTH {
+        return Err("Not a paper wallet private key".to_string());
+    }
+
+    let (data, checksum) = payload.split_at(payload.len() - CHECKSUM_LENGTH);
+
+    if address::checksum(data) != checksum {
+        return Err("The private key has an invalid checksum".to_string());
+    }
+
+    Ok((data[0], Zeroizing::new(data[1..].to_vec())))
+}
+
+fn address_hash(secret_key: &SecretKey) -> [u8; ADDRESS_HASH_LENGTH] {
+    address::checksum(address::encode(&keys::public_key(secret_key)).as_bytes())
+}
+
+fn derive_pad(passphrase: &str, address_hash: &[u8]) -> Zeroizing<[u8; 32]> {
+    let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, 1, Some(32)).unwrap();
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+    let mut salt = ARGON2_SALT.to_vec();
+    salt.extend_from_slice(address_hash);
+
+    let mut pad = Zeroizing::new([0; 32]);
+
+    argon2
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut *pad)
+        .unwrap();
+
+    pad
+}
@@ -0,0 +1,254 @@
+use super::blockchain::{Hash, RejectReason, MAX_HEADERS};
+use super::framing::{Frame, RejectKind};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerState {
+    // Only the version and codecs can be exchanged
+    AwaitingHandshake,
+    // One of us is catching up with the chain of the other. The peer can
+    // ask for headers, blocks and filters, or make RPC calls.
+    Syncing,
+    // One of us got all the headers of the other, so the peer can also
+    // relay blocks and transactions to us
+    Steady,
+}
+
+// What each peer is allowed to send us at each point of the connection.
+// Anything else is misbehavior.
+pub struct PeerProtocol {
+    state: PeerState,
+    // Whether we asked the peer for headers and are waiting for them
+    awaiting_headers: bool,
+    // How many `Frame::GetBlocks` we sent that weren't answered yet
+    awaiting_blocks: usize,
+    // Trusted peers can push blocks as soon as the handshake is done
+    trusted: bool,
+}
+
+impl PeerProtocol {
+    pub fn new() -> Self {
+        PeerProtocol {
+            state: PeerState::AwaitingHandshake,
+            awaiting_headers: false,
+            awaiting_blocks: 0,
+            trusted: false,
+        }
+    }
+
+    pub fn state(&self) -> PeerState {
+        self.state
+    }
+
+    pub fn trust(&mut self) {
+        self.trusted = true;
+    }
+
+    pub fn handshake_completed(&mut self) {
+        self.state = PeerState::Syncing;
+    }
+
+    pub fn headers_requested(&mut self) {
+        self.awaiting_headers = true;
+    }
+
+    pub fn blocks_requested(&mut self) {
+        self.awaiting_blocks += 1;
+    }
+
+    // A batch shorter than the maximum means they got up to our tip
+    pub fn headers_sent(&mut self, count: usize) {
+        if self.state == PeerState::Syncing && count < MAX_HEADERS {
+            self.state = PeerState::Steady;
+        }
+    }
+
+    // Checks `frame` can be sent in the current state, moving to the next
+    // state when it completes a sync
+    pub fn receive(&mut self, frame: &Frame) -> Result<(), String> {
+        let allowed = match (self.state, frame) {
+            (
+                PeerState::AwaitingHandshake,
+                Frame::Version(_)
+                | Frame::Codecs(_)
+                | Frame::Time(_)
+                | Frame::Network(_)
+                | Frame::Relay(_)
+                | Frame::ObservedAddress(_),
+            ) => true,
+            (PeerState::AwaitingHandshake, _) => false,
+            (
+                _,
+                Frame::GetHeaders(_)
+                | Frame::GetBlocks(_)
+                | Frame::GetMerkleBlocks(_)
+                | Frame::GetCompactFilters(_)
+                | Frame::FilterLoad(_)
+                | Frame::Request(_)
+                | Frame::GetAddr,
+            ) => true,
+            // Nodes announce their addresses whenever they learn them
+            (_, Frame::Addr(_)) => true,
+            // And checkpoints are relayed as soon as they are signed
+            (_, Frame::Checkpoint(_)) => true,
+            (_, Frame::Ping(_) | Frame::Pong(_)) => true,
+            // Answers to what we relay, which honest peers send too
+            (_, Frame::Reject { .. } | Frame::TransactionExpired(_)) => true,
+            (_, Frame::ChannelUpdate(_)) => true,
+            (_, Frame::Headers(headers)) if self.awaiting_headers => {
+                self.awaiting_headers = false;
+
+                // We got up to their tip
+                if self.state == PeerState::Syncing && headers.len() < MAX_HEADERS {
+                    self.state = PeerState::Steady;
+                }
+
+                true
+            }
+            (_, Frame::Blocks(_)) if self.awaiting_blocks > 0 => {
+                self.awaiting_blocks -= 1;
+
+                true
+            }
+            (PeerState::Steady, Frame::Block(_) | Frame::Transaction(_)) => true,
+            (_, Frame::Block(_)) if self.trusted => true,
+            _ => false,
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} frame not allowed while {:?}",
+                frame.name(),
+                self.state
+            ))
+        }
+    }
+}
+
+impl Default for PeerProtocol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What to send back for a block or transaction we didn't accept. Peers relay
+// what they get to all their peers, so getting one we already have is no
+// fault of theirs, and isn't answered.
+pub fn reject_frame(kind: RejectKind, hash: Hash, reason: RejectReason) -> Option<Frame> {
+    if reason == RejectReason::Duplicate {
+        return None;
+    }
+
+    Some(Frame::Reject { kind, hash, reason })
+}
+
+// Round trips to our peers, measured with `Frame::Ping`
+#[derive(Default)]
+pub struct Latencies {
+    // Nonce of the ping each peer has yet to answer, and when it was sent
+    pending: HashMap<SocketAddr, (u64, Instant)>,
+    // Smoothed over the last few pings, so one slow answer doesn't reorder
+    // the peers
+    round_trips: HashMap<SocketAddr, Duration>,
+}
+
+impl Latencies {
+    // A ping that was never answered is replaced, the peer is then just
+    // slower to get a round trip
+    pub fn ping_sent(&mut self, peer: SocketAddr, nonce: u64) {
+        self.pending.insert(peer, (nonce, Instant::now()));
+    }
+
+    // Pongs that don't answer our last ping are ignored
+    pub fn pong_received(&mut self, peer: SocketAddr, nonce: u64) -> Option<Duration> {
+        match self.pending.get(&peer) {
+            Some((pending, _)) if *pending == nonce => {}
+            _ => return None,
+        }
+
+        let (_, sent) = self.pending.remove(&peer)?;
+        let sample = sent.elapsed();
+        let round_trip = match self.round_trips.get(&peer) {
+            Some(previous) => (*previous * 3 + sample) / 4,
+            None => sample,
+        };
+
+        self.round_trips.insert(peer, round_trip);
+
+        Some(round_trip)
+    }
+
+    pub fn round_trip(&self, peer: &SocketAddr) -> Option<Duration> {
+        self.round_trips.get(peer).copied()
+    }
+
+    pub fn remove(&mut self, peer: &SocketAddr) {
+        self.pending.remove(peer);
+        self.round_trips.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Transaction, TransactionDetails};
+    use crate::keyfile;
+    use fcoin_core::{keys, transaction};
+
+    fn steady() -> PeerProtocol {
+        let mut protocol = PeerProtocol::new();
+
+        protocol.handshake_completed();
+        protocol.headers_requested();
+        protocol.receive(&Frame::Headers(Vec::new())).unwrap();
+
+        assert_eq!(protocol.state(), PeerState::Steady);
+
+        protocol
+    }
+
+    fn transaction() -> Transaction {
+        let secret_key = keyfile::generate();
+        let details = TransactionDetails::anchor(keys::public_key(&secret_key), vec![1; 32], 1);
+        let signature = transaction::sign(&details, &secret_key);
+
+        Transaction::new(details, signature)
+    }
+
+    #[test]
+    fn relaying_a_duplicate_is_not_misbehavior() {
+        // Each side sees the other as a steady peer
+        let mut first = steady();
+        let mut second = steady();
+        let transaction = transaction();
+        let hash = transaction.hash();
+
+        // Both got the transaction elsewhere and relay it to each other
+        first
+            .receive(&Frame::Transaction(transaction.clone()))
+            .unwrap();
+        second.receive(&Frame::Transaction(transaction)).unwrap();
+
+        // Neither answers the duplicate
+        assert!(reject_frame(RejectKind::Transaction, hash, RejectReason::Duplicate).is_none());
+
+        // Other rejections, and expiries, are answered, and accepted
+        let reject = reject_frame(RejectKind::Transaction, hash, RejectReason::Expired).unwrap();
+
+        first.receive(&reject).unwrap();
+        second.receive(&Frame::TransactionExpired(hash)).unwrap();
+        assert_eq!(first.state(), PeerState::Steady);
+    }
+
+    #[test]
+    fn rejections_wait_for_the_handshake() {
+        let mut protocol = PeerProtocol::new();
+        let reject = reject_frame(RejectKind::Block, [0; 32], RejectReason::UnknownParent);
+
+        assert!(protocol.receive(&reject.unwrap()).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use fcoin_core::asset::AssetAction;
+use fcoin_core::transaction::TransactionDetails;
+use fcoin_core::{address, message, PublicKey, Signature};
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+// Spending limits are over the last day
+const DAY: u64 = 24 * 60 * 60;
+
+// The `[policy]` table of the configuration file, which limits what the node
+// signs with its key and those of its wallets. Coins count with their fee,
+// so a limit can't be worked around by overpaying miners. A transaction
+// breaking the policy is still signed when one of the `approvers` signed it
+// too, as a message.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SpendingPolicy {
+    pub max_per_transaction: Option<u64>,
+    pub max_per_day: Option<u64>,
+    // When not empty, coins and tokens can only go to these addresses, or
+    // back to the wallet
+    #[serde(deserialize_with = "super::network::addresses")]
+    pub allowed_destinations: Vec<PublicKey>,
+    #[serde(deserialize_with = "super::network::addresses")]
+    pub approvers: Vec<PublicKey>,
+}
+
+// What a wallet signed over the last day, by when
+#[derive(Debug, Clone, Default)]
+pub struct SpendingLog {
+    spent: VecDeque<(u64, u64)>,
+}
+
+impl SpendingLog {
+    pub fn record(&mut self, now: u64, amount: u64) {
+        self.spent.push_back((now, amount));
+    }
+
+    pub fn spent_since(&mut self, now: u64) -> u64 {
+        while let Some((time, _)) = self.spent.front() {
+            if time + DAY > now {
+                break;
+            }
+
+            self.spent.pop_front();
+        }
+
+        self.spent
+            .iter()
+            .fold(0, |total, (_, amount)| total.saturating_add(*amount))
+    }
+}
+
+impl SpendingPolicy {
+    // Why `details` can't be signed without approval, given what the
+    // wallet already spent today
+    pub fn check(&self, details: &TransactionDetails, spent_today: u64) -> Result<(), String> {
+        let amount = spent(details);
+
+        if let Some(max) = self.max_per_transaction {
+            if amount > max {
+                return Err(format!(
+                    "Moves ${}, more than the ${} allowed per transaction",
+                    amount, max
+                ));
+            }
+        }
+
+        if let Some(max) = self.max_per_day {
+            if spent_today.saturating_add(amount) > max {
+                return Err(format!(
+                    "Moves ${} on top of the ${} spent today, more than the ${} allowed per day",
+                    amount, spent_today, max
+                ));
+            }
+        }
+
+        if !self.allowed_destinations.is_empty() {
+            let source = details.source();
+
+            if let Some(destination) = destinations(details).into_iter().find(|destination| {
+                destination != source && !self.allowed_destinations.contains(destination)
+            }) {
+                return Err(format!(
+                    "Pays {}, which is not an allowed destination",
+                    address::encode(&destination)
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether an approver signed the unsigned raw transaction, as a message
+    pub fn is_approved(&self, raw: &str, approval: &Signature) -> bool {
+        self.approvers
+            .iter()
+            .any(|approver| message::verify(approver, raw, approval))
+    }
+}
+
+// Coins it moves, fee included
+pub fn spent(details: &TransactionDetails) -> u64 {
+    let paid = if details.moves_no_coins() {
+        0
+    } else {
+        details.amount()
+    };
+
+    paid.saturating_add(details.fee())
+}
+
+// Of coins and tokens
+fn destinations(details: &TransactionDetails) -> Vec<PublicKey> {
+    let mut destinations: Vec<PublicKey> = details.destinations().copied().collect();
+
+    if let Some(AssetAction::Transfer { outputs, .. }) = details.asset() {
+        destinations.extend(outputs.iter().map(|output| output.destination));
+    }
+
+    destinations
+}
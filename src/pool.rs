@@ -0,0 +1,699 @@
+use super::blockchain::{
+    self, Block, Node, Output, PublicKey, SecretKey, Transaction, TransactionDetails,
+};
+use super::events::{self, ChainEvent};
+use super::network::{NetworkProfile, PowHash};
+use super::pow::PowHasher;
+use fcoin_core::encoding::Encode;
+use fcoin_core::{address, hex, keys, transaction};
+use num::BigUint;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+// A node acting as a mining pool. Workers connect over a Stratum-like
+// protocol, one JSON object per line:
+//
+// * `{"id": 1, "method": "login", "params": ["<address>"]}` names the
+//   address the worker is paid to, and is answered with the 8 bytes every
+//   nonce of the worker starts with, as `{"extranonce": "<hex>"}`
+// * `{"id": null, "method": "job", "params": {...}}` is sent to workers
+//   whenever there is new work, see `Job`
+// * `{"id": 2, "method": "submit", "params": ["<job id>", "<hex>"]}` submits
+//   the other 24 bytes of a nonce that brings the proof of work hash of the
+//   job below its target
+//
+// Shares are counted for each worker until the pool finds a block, whose
+// reward and fees are then paid to the workers in proportion to their
+// shares, less the fee of the pool.
+
+// Jobs kept for late shares, older ones are dropped
+const MAX_JOBS: usize = 16;
+// How often workers get new work while the tip stays the same, with the
+// transactions that came in since
+const JOB_INTERVAL: Duration = Duration::from_secs(30);
+// Fee estimates for payouts aim to be confirmed within this many blocks
+const CONFIRMATION_TARGET: u32 = 6;
+// Bytes of the nonce the pool picks for each worker
+const EXTRANONCE_SIZE: usize = 8;
+
+// The `[pool]` table of the configuration file
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoolSettings {
+    // Workers connect to this TCP port
+    pub port: u16,
+    // How many times harder than a share a block is at the easiest target
+    #[serde(default = "default_share_difficulty")]
+    pub share_difficulty: u64,
+    // Percent of each block the pool keeps
+    #[serde(default = "default_fee_percent")]
+    pub fee_percent: u64,
+}
+
+fn default_share_difficulty() -> u64 {
+    1000
+}
+
+fn default_fee_percent() -> u64 {
+    1
+}
+
+// Work for the workers
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub job_id: String,
+    // Encoded header before and after the nonce, in hex. The proof of work
+    // hash is of `before`, the nonce and `after`.
+    pub before: String,
+    pub after: String,
+    // Shares have to hash below it, as a little-endian number in hex
+    pub target: String,
+    // "sha256" or "argon2"
+    pub algorithm: String,
+    // Whether the tip moved, which makes earlier jobs worthless
+    pub clean: bool,
+}
+
+pub struct Pool {
+    settings: PoolSettings,
+    network: NetworkProfile,
+    hasher: Box<dyn PowHasher>,
+    // Blocks being mined by job id, with the target each has to meet
+    jobs: HashMap<u64, (Block, BigUint)>,
+    next_job: u64,
+    current: Option<Job>,
+    next_extranonce: u64,
+    // Shares since the pool last paid, by worker address
+    round: HashMap<PublicKey, u64>,
+    // Shares ever, by worker address
+    totals: HashMap<PublicKey, u64>,
+    // Shares already counted, so none is counted twice
+    seen: HashSet<(u64, [u8; 32])>,
+    job_sender: broadcast::Sender<Job>,
+}
+
+#[derive(Deserialize)]
+struct Call {
+    id: serde_json::Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Reply {
+    id: serde_json::Value,
+    result: serde_json::Value,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    id: serde_json::Value,
+    method: &'a str,
+    params: &'a Job,
+}
+
+// A worker that logged in
+struct Worker {
+    address: PublicKey,
+    extranonce: [u8; EXTRANONCE_SIZE],
+}
+
+impl Pool {
+    pub fn new(settings: PoolSettings, network: NetworkProfile) -> Self {
+        let (job_sender, _) = broadcast::channel(MAX_JOBS);
+
+        Pool {
+            settings,
+            hasher: network.pow_hasher(),
+            network,
+            jobs: HashMap::new(),
+            next_job: 0,
+            current: None,
+            next_extranonce: 0,
+            round: HashMap::new(),
+            totals: HashMap::new(),
+            seen: HashSet::new(),
+            job_sender,
+        }
+    }
+
+    // Shares have to be below both this and the target of the block, so
+    // every block is a share too
+    fn share_target(&self, block_target: &BigUint) -> BigUint {
+        let target =
+            blockchain::max_target() / BigUint::from(self.settings.share_difficulty.max(1));
+
+        target.max(block_target.clone())
+    }
+
+    // Hands `block` out to the workers
+    fn add_job(&mut self, block: Block, target: BigUint, clean: bool) {
+        let share_target = self.share_target(&target);
+        let (before, after) = block.header().encoded_around_nonce();
+        let id = self.next_job;
+
+        if clean {
+            self.jobs.clear();
+            self.seen.clear();
+        }
+
+        self.next_job += 1;
+        self.jobs.insert(id, (block, target));
+        self.jobs.retain(|job, _| job + MAX_JOBS as u64 > id);
+
+        let job = Job {
+            job_id: id.to_string(),
+            before: hex::encode(&before),
+            after: hex::encode(&after),
+            target: hex::encode(&share_target.to_bytes_le()),
+            algorithm: match self.network.pow_hash {
+                PowHash::Sha256 => "sha256".to_string(),
+                PowHash::Argon2 => "argon2".to_string(),
+            },
+            clean,
+        };
+
+        self.current = Some(job.clone());
+        let _ = self.job_sender.send(job);
+    }
+
+    fn login(&mut self, address: PublicKey) -> Worker {
+        let extranonce = self.next_extranonce.to_le_bytes();
+
+        self.next_extranonce += 1;
+
+        Worker {
+            address,
+            extranonce,
+        }
+    }
+
+    // Counts a share, returning the block when it is one too
+    fn submit(&mut self, worker: &Worker, job: &str, nonce: &str) -> Result<Option<Block>, String> {
+        let id: u64 = job.parse().map_err(|_| "Invalid job id".to_string())?;
+        let ending = hex::decode(nonce)?;
+
+        if ending.len() != 32 - EXTRANONCE_SIZE {
+            return Err(format!("Nonces end with {} bytes", 32 - EXTRANONCE_SIZE));
+        }
+
+        let mut full_nonce = [0; 32];
+
+        full_nonce[..EXTRANONCE_SIZE].copy_from_slice(&worker.extranonce);
+        full_nonce[EXTRANONCE_SIZE..].copy_from_slice(&ending);
+
+        let (mut block, target) = self
+            .jobs
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| "Stale job".to_string())?;
+
+        block.header_mut().set_nonce(full_nonce);
+
+        let work = BigUint::from_bytes_le(&self.hasher.hash(&block.header().encoded()));
+
+        if work >= self.share_target(&target) {
+            return Err("The share doesn't meet the target".to_string());
+        }
+
+        if !self.seen.insert((id, full_nonce)) {
+            return Err("Duplicate share".to_string());
+        }
+
+        *self.round.entry(worker.address).or_insert(0) += 1;
+        *self.totals.entry(worker.address).or_insert(0) += 1;
+
+        Ok(Some(block).filter(|_| work < target))
+    }
+
+    // What each worker is paid out of `amount` for the shares of the round,
+    // with the shares it pays for. Those whose part would be dust are left
+    // for the next round, and the shares of the pool itself are dropped.
+    fn payouts(&mut self, amount: u64, pool: &PublicKey) -> Vec<(Output, u64)> {
+        let shares: u64 = self.round.values().sum();
+        let paid = amount as u128 * (100 - self.settings.fee_percent.min(100)) as u128 / 100;
+        let dust_threshold = self.network.dust_threshold;
+
+        self.round.remove(pool);
+        self.round
+            .iter()
+            .filter_map(|(worker, count)| {
+                let part = (paid * *count as u128 / shares.max(1) as u128) as u64;
+
+                if part == 0 || part < dust_threshold {
+                    return None;
+                }
+
+                let output = Output {
+                    destination: *worker,
+                    amount: part,
+                };
+
+                Some((output, *count))
+            })
+            .collect()
+    }
+
+    // Takes `shares` of `worker` out of the round once a payout for them
+    // was accepted, leaving any that came in since
+    fn paid(&mut self, worker: &PublicKey, shares: u64) {
+        if let Some(count) = self.round.get_mut(worker) {
+            *count = count.saturating_sub(shares);
+
+            if *count == 0 {
+                self.round.remove(worker);
+            }
+        }
+    }
+}
+
+// Pays `outputs` from `secret_key` at `rate`, `None` without any output
+fn payout(secret_key: &SecretKey, outputs: &[Output], rate: u64) -> Option<Transaction> {
+    TransactionDetails::to_many(keys::public_key(secret_key), outputs, 0).map(|details| {
+        let fee = details.fee_for_rate(rate);
+        let details = details.with_fee(fee);
+        let signature = transaction::sign(&details, secret_key);
+
+        Transaction::new(details, signature)
+    })
+}
+
+// Payouts of `outputs`, with as many of them in each as fit in
+// `MAX_TRANSACTION_SIZE`
+fn payouts_within_size(secret_key: &SecretKey, outputs: &[Output], rate: u64) -> Vec<Transaction> {
+    let mut transactions = Vec::new();
+    let mut start = 0;
+
+    while start < outputs.len() {
+        let mut end = start + 1;
+
+        while end < outputs.len()
+            && payout(secret_key, &outputs[start..=end], rate)
+                .is_some_and(|payout| payout.size() <= blockchain::MAX_TRANSACTION_SIZE)
+        {
+            end += 1;
+        }
+
+        transactions.extend(payout(secret_key, &outputs[start..end], rate));
+        start = end;
+    }
+
+    transactions
+}
+
+// Serves workers on the port of the settings, and keeps them busy with
+// blocks on top of our tip
+pub async fn serve(
+    node: Arc<Mutex<Node>>,
+    settings: PoolSettings,
+    network: NetworkProfile,
+) -> Result<(), String> {
+    let port = settings.port;
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .map_err(|issue| format!("Could not listen for pool workers: {}", issue))?;
+    let pool = Arc::new(Mutex::new(Pool::new(settings, network)));
+
+    println!("POOL LISTENING ON PORT {}", port);
+
+    {
+        let node = node.clone();
+        let pool = pool.clone();
+        let events = node.lock().await.subscribe();
+
+        tokio::spawn(async move {
+            job_loop(node, pool, events).await;
+        });
+    }
+
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(issue) => {
+                println!("COULD NOT ACCEPT POOL WORKER: {}", issue);
+
+                continue;
+            }
+        };
+
+        let node = node.clone();
+        let pool = pool.clone();
+
+        tokio::spawn(async move {
+            worker_loop(node, pool, stream, address).await;
+        });
+    }
+}
+
+// New work whenever the tip moves, and every `JOB_INTERVAL` for the
+// transactions that came in since
+async fn job_loop(
+    node: Arc<Mutex<Node>>,
+    pool: Arc<Mutex<Pool>>,
+    mut events: broadcast::Receiver<ChainEvent>,
+) {
+    let mut interval = tokio::time::interval(JOB_INTERVAL);
+
+    loop {
+        let clean = tokio::select! {
+            event = events::next(&mut events) => match event {
+                Some(ChainEvent::Connected { .. }) => true,
+                // Only when there is no work yet
+                Some(ChainEvent::MempoolAdded { .. }) if pool.lock().await.current.is_none() => false,
+                Some(_) => continue,
+                None => return,
+            },
+            _ = interval.tick() => false,
+        };
+
+        let template = {
+            let mut node = node.lock().await;
+
+            if !node.is_synced() {
+                continue;
+            }
+
+            node.block_template().zip(node.next_target())
+        };
+
+        let mut pool = pool.lock().await;
+
+        match template {
+            Some((block, target)) => pool.add_job(block, target, clean),
+            // Nothing left to mine on the new tip
+            None if clean => {
+                pool.jobs.clear();
+                pool.current = None;
+            }
+            None => {}
+        }
+    }
+}
+
+async fn worker_loop(
+    node: Arc<Mutex<Node>>,
+    pool: Arc<Mutex<Pool>>,
+    stream: TcpStream,
+    address: SocketAddr,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut jobs = pool.lock().await.job_sender.subscribe();
+    let mut worker = None;
+
+    println!("POOL WORKER CONNECTED FROM {}", address);
+
+    loop {
+        let message = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    let logged_in = worker.is_some();
+                    let reply = handle(&node, &pool, &mut worker, &line).await;
+                    let mut message = serde_json::to_string(&reply).unwrap();
+
+                    // Workers start on the current job right after logging
+                    // in
+                    if !logged_in && worker.is_some() {
+                        if let Some(job) = pool.lock().await.current.clone() {
+                            message.push('\n');
+                            message.push_str(&notification(&job));
+                        }
+                    }
+
+                    message
+                }
+                _ => break,
+            },
+            job = jobs.recv() => match (job, &worker) {
+                (Ok(job), Some(_)) => notification(&job),
+                (Err(broadcast::error::RecvError::Closed), _) => break,
+                _ => continue,
+            },
+        };
+
+        if writer
+            .write_all(format!("{}\n", message).as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    println!("POOL WORKER FROM {} DISCONNECTED", address);
+}
+
+fn notification(job: &Job) -> String {
+    serde_json::to_string(&Notification {
+        id: serde_json::Value::Null,
+        method: "job",
+        params: job,
+    })
+    .unwrap()
+}
+
+async fn handle(
+    node: &Arc<Mutex<Node>>,
+    pool: &Arc<Mutex<Pool>>,
+    worker: &mut Option<Worker>,
+    line: &str,
+) -> Reply {
+    let call: Call = match serde_json::from_str(line) {
+        Ok(call) => call,
+        Err(issue) => {
+            return Reply {
+                id: serde_json::Value::Null,
+                result: serde_json::Value::Null,
+                error: Some(format!("Invalid call: {}", issue)),
+            }
+        }
+    };
+
+    let result = match (call.method.as_str(), &call.params[..], &*worker) {
+        ("login", [encoded], _) => match address::decode(encoded) {
+            Ok(address) => {
+                let logged_in = pool.lock().await.login(address);
+                let extranonce = hex::encode(&logged_in.extranonce);
+
+                *worker = Some(logged_in);
+
+                Ok(serde_json::json!({ "extranonce": extranonce }))
+            }
+            Err(issue) => Err(issue),
+        },
+        ("submit", [job, nonce], Some(worker)) => {
+            let submitted = pool.lock().await.submit(worker, job, nonce);
+
+            match submitted {
+                Ok(Some(block)) => {
+                    block_found(node, pool, block).await;
+
+                    Ok(serde_json::Value::Bool(true))
+                }
+                Ok(None) => Ok(serde_json::Value::Bool(true)),
+                Err(issue) => Err(issue),
+            }
+        }
+        ("submit", _, None) => Err("Log in first".to_string()),
+        _ => Err(format!("Unknown method {}", call.method)),
+    };
+
+    match result {
+        Ok(result) => Reply {
+            id: call.id,
+            result,
+            error: None,
+        },
+        Err(issue) => Reply {
+            id: call.id,
+            result: serde_json::Value::Null,
+            error: Some(issue),
+        },
+    }
+}
+
+// Adds the block to the chain and pays the workers its reward and fees
+async fn block_found(node: &Arc<Mutex<Node>>, pool: &Arc<Mutex<Pool>>, block: Block) {
+    let hash = block.hash();
+    let fees: u64 = block.transactions().iter().map(Transaction::fee).sum();
+
+    if let Err(reason) = blockchain::block_received(node.clone(), block.clone()).await {
+        println!(
+            "POOL BLOCK {} REJECTED: {}",
+            blockchain::HashFmt(hash),
+            reason
+        );
+
+        return;
+    }
+
+    blockchain::relay_block(node.clone(), block, None);
+
+    let (transactions, shares) = {
+        let node = node.lock().await;
+        let mut pool = pool.lock().await;
+        // The shares are kept, and paid with the next block
+        let secret_key = match node.secret_key() {
+            Ok(secret_key) => secret_key.clone(),
+            Err(issue) => {
+                println!("POOL COULD NOT PAY ITS WORKERS: {}", issue);
+
+                return;
+            }
+        };
+        let source = keys::public_key(&secret_key);
+        let height = node.block_height(&hash).unwrap_or(0);
+        let reward = pool.network.emission.reward(height) + fees;
+        let payouts = pool.payouts(reward, &source);
+        let outputs: Vec<Output> = payouts.iter().map(|(output, _)| *output).collect();
+        let shares: HashMap<PublicKey, u64> = payouts
+            .iter()
+            .map(|(output, shares)| (output.destination, *shares))
+            .collect();
+
+        println!(
+            "POOL FOUND BLOCK {} AT HEIGHT {}, PAYING {} WORKERS",
+            blockchain::HashFmt(hash),
+            height,
+            outputs.len()
+        );
+
+        for (worker, shares) in &pool.totals {
+            println!("{:>8} SHARES FROM {}", shares, address::encode(worker));
+        }
+
+        let rate = node.estimate_fee(CONFIRMATION_TARGET);
+
+        (payouts_within_size(&secret_key, &outputs, rate), shares)
+    };
+
+    // The shares of a rejected payout stay in the round, and are paid with
+    // the next block
+    for payout in transactions {
+        let outputs = payout.outputs();
+
+        match blockchain::transaction_received(node.clone(), payout, None, None).await {
+            Ok(_) => {
+                let mut pool = pool.lock().await;
+
+                for output in outputs {
+                    if let Some(shares) = shares.get(&output.destination) {
+                        pool.paid(&output.destination, *shares);
+                    }
+                }
+            }
+            Err(reason) => println!("POOL COULD NOT PAY ITS WORKERS: {}", reason),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::{mine, node};
+    use crate::keyfile;
+
+    const NETWORK: &str = "[emission]\ninitial_reward = 100000";
+
+    fn pool(workers: u8) -> Arc<Mutex<Pool>> {
+        let settings = PoolSettings {
+            port: 0,
+            share_difficulty: 1,
+            fee_percent: 1,
+        };
+        let mut pool = Pool::new(settings, toml::from_str(NETWORK).unwrap());
+
+        for worker in 1..=workers {
+            pool.round.insert([worker; 32], 2);
+        }
+
+        Arc::new(Mutex::new(pool))
+    }
+
+    #[tokio::test]
+    async fn rounds_of_many_workers_are_paid_in_several_transactions() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, NETWORK);
+        let pool = pool(50);
+        let block = mine(
+            &node,
+            &secret_key,
+            [0; 32],
+            blockchain::timestamp() - 100,
+            Vec::new(),
+        )
+        .await;
+
+        block_found(&node, &pool, block).await;
+
+        let node = node.lock().await;
+        let mempool = node.mempool();
+
+        // 22 outputs fit in a transaction
+        assert_eq!(mempool.len(), 3);
+        assert!(mempool
+            .entries()
+            .all(|(_, entry)| entry.transaction().size() <= blockchain::MAX_TRANSACTION_SIZE));
+
+        for worker in 1..=50 {
+            let (_, credits) = mempool.pending(&[worker; 32]);
+
+            assert_eq!(credits.iter().map(|(_, amount)| amount).sum::<u64>(), 1980);
+        }
+
+        assert!(pool.lock().await.round.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_payouts_leave_the_shares_in_the_round() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, NETWORK);
+        let pool = pool(3);
+        // The reward goes to someone else, so the pool can't pay
+        let block = mine(
+            &node,
+            &keyfile::generate(),
+            [0; 32],
+            blockchain::timestamp() - 100,
+            Vec::new(),
+        )
+        .await;
+
+        block_found(&node, &pool, block).await;
+
+        assert!(node.lock().await.mempool().is_empty());
+        assert_eq!(pool.lock().await.round.values().sum::<u64>(), 6);
+    }
+
+    #[test]
+    fn shares_that_came_in_since_a_payout_stay() {
+        let mut pool = Pool::new(
+            PoolSettings {
+                port: 0,
+                share_difficulty: 1,
+                fee_percent: 1,
+            },
+            toml::from_str(NETWORK).unwrap(),
+        );
+
+        pool.round.insert([1; 32], 2);
+
+        let payouts = pool.payouts(1000, &[9; 32]);
+
+        assert_eq!(payouts.len(), 1);
+        assert_eq!(pool.round[&[1; 32]], 2);
+
+        *pool.round.get_mut(&[1; 32]).unwrap() += 1;
+        pool.paid(&[1; 32], payouts[0].1);
+
+        assert_eq!(pool.round[&[1; 32]], 1);
+    }
+}
@@ -0,0 +1,105 @@
+use fcoin_core::Hash;
+use sha2::digest::generic_array::GenericArray;
+use sha2::{Digest, Sha256};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+
+// Argon2 settings, fixed for everyone mining on the network
+const ARGON2_MEMORY_KIB: u32 = 4 * 1024;
+const ARGON2_ITERATIONS: u32 = 1;
+const ARGON2_SALT: &[u8] = b"fcoin proof of work";
+// What SHA-256 starts from, and how much it hashes at a time
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+const SHA256_BLOCK_SIZE: usize = 64;
+
+// The hash block headers must bring below the target. Blocks are still
+// identified by their SHA-256 hash whatever this is.
+pub trait PowHasher: Send + Sync {
+    fn hash(&self, header: &[u8]) -> Hash;
+}
+
+pub struct Sha256Hasher;
+
+impl PowHasher for Sha256Hasher {
+    fn hash(&self, header: &[u8]) -> Hash {
+        Sha256::digest(header).into()
+    }
+}
+
+// Memory-hard, so specialized hardware gets less of an edge over CPUs
+pub struct Argon2Hasher {
+    argon2: Argon2<'static>,
+}
+
+impl Argon2Hasher {
+    pub fn new() -> Self {
+        let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, 1, Some(32)).unwrap();
+
+        Argon2Hasher {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        }
+    }
+}
+
+impl Default for Argon2Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PowHasher for Argon2Hasher {
+    fn hash(&self, header: &[u8]) -> Hash {
+        let mut hash = [0; 32];
+
+        self.argon2
+            .hash_password_into(header, ARGON2_SALT, &mut hash)
+            .unwrap();
+
+        hash
+    }
+}
+
+// The state SHA-256 is in after the whole 64-byte blocks of `data`, and
+// the rest of it. Workers hashing many headers that only differ after those
+// blocks start from there instead of hashing them again.
+pub fn sha256_midstate(data: &[u8]) -> ([u32; 8], &[u8]) {
+    let whole = data.len() - data.len() % SHA256_BLOCK_SIZE;
+    let mut state = SHA256_INITIAL_STATE;
+
+    compress(&mut state, &data[..whole]);
+
+    (state, &data[whole..])
+}
+
+// Finishes hashing `length` bytes, from the state after all but `rest`
+pub fn sha256_from_midstate(mut state: [u32; 8], rest: &[u8], length: usize) -> Hash {
+    let mut padded = rest.to_vec();
+
+    padded.push(0x80);
+    padded.resize(
+        (padded.len() + 8).div_ceil(SHA256_BLOCK_SIZE) * SHA256_BLOCK_SIZE - 8,
+        0,
+    );
+    padded.extend_from_slice(&(length as u64 * 8).to_be_bytes());
+
+    compress(&mut state, &padded);
+
+    let mut hash = [0; 32];
+
+    for (bytes, word) in hash.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+
+    hash
+}
+
+fn compress(state: &mut [u32; 8], blocks: &[u8]) {
+    let blocks: Vec<_> = blocks
+        .chunks(SHA256_BLOCK_SIZE)
+        .map(GenericArray::clone_from_slice)
+        .collect();
+
+    sha2::compress256(state, &blocks);
+}
@@ -0,0 +1,215 @@
+use super::framing::{
+    Connection, ReadConnection, StreamReader, StreamWriter, WriteConnection, LANES,
+};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::rustls;
+use quinn::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use quinn::rustls::crypto::CryptoProvider;
+use quinn::rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, SubjectPublicKeyInfoDer,
+    UnixTime,
+};
+use quinn::rustls::server::AlwaysResolvesServerRawPublicKeys;
+use quinn::rustls::sign::CertifiedKey;
+use quinn::rustls::{DigitallySignedStruct, SignatureScheme};
+use quinn::{Endpoint, Incoming, TransportConfig};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+
+// Sent in the TLS handshake, so only fcoin nodes talk to each other
+const ALPN: &[u8] = b"fcoin";
+// Peers can go quiet for longer than QUIC waits before dropping an idle
+// connection
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+// Peers are not authenticated, like over TCP, QUIC just needs TLS. Each run
+// uses a new key, sent as a raw public key rather than in a certificate.
+#[derive(Debug)]
+struct AnyServerKey(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyServerKey {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::General("QUIC needs TLS 1.3".to_string()))
+    }
+
+    // The key still has to have signed the handshake, or the connection
+    // wouldn't be encrypted to whoever has it
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature_with_raw_key(
+            message,
+            &SubjectPublicKeyInfoDer::from(cert.as_ref()),
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut transport = TransportConfig::default();
+
+    transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+
+    Arc::new(transport)
+}
+
+fn server_config(provider: Arc<CryptoProvider>) -> Result<quinn::ServerConfig, String> {
+    let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+        .map_err(|_| "Could not generate the QUIC key".to_string())?;
+    let key = provider
+        .key_provider
+        .load_private_key(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            pkcs8.as_ref().to_vec(),
+        )))
+        .map_err(|issue| issue.to_string())?;
+    let public_key = key
+        .public_key()
+        .ok_or("The QUIC key has no public key")?
+        .as_ref()
+        .to_vec();
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|issue| issue.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(AlwaysResolvesServerRawPublicKeys::new(Arc::new(
+            CertifiedKey::new(vec![CertificateDer::from(public_key)], key),
+        ))));
+
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(crypto).map_err(|issue| issue.to_string())?;
+    let mut config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+
+    config.transport_config(transport_config());
+
+    Ok(config)
+}
+
+fn client_config(provider: Arc<CryptoProvider>) -> Result<quinn::ClientConfig, String> {
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|issue| issue.to_string())?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyServerKey(provider)))
+        .with_no_client_auth();
+
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicClientConfig::try_from(crypto).map_err(|issue| issue.to_string())?;
+    let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+
+    config.transport_config(transport_config());
+
+    Ok(config)
+}
+
+// Accepts connections on UDP `port` when there is one, and makes them in
+// any case
+pub fn endpoint(port: Option<u16>) -> Result<Endpoint, String> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut endpoint = match port {
+        Some(port) => Endpoint::server(
+            server_config(provider.clone())?,
+            SocketAddr::from(([0, 0, 0, 0], port)),
+        ),
+        None => Endpoint::client(SocketAddr::from(([0, 0, 0, 0], 0))),
+    }
+    .map_err(|issue| format!("Could not open the QUIC endpoint: {}", issue))?;
+
+    endpoint.set_default_client_config(client_config(provider)?);
+
+    Ok(endpoint)
+}
+
+// Opens a stream for each `Lane`. Streams only show up on the other side
+// once something is sent on them, so each starts with its lane number.
+pub async fn connect(
+    endpoint: &Endpoint,
+    address: SocketAddr,
+) -> Result<(WriteConnection, ReadConnection), String> {
+    let connection = endpoint
+        .connect(address, "fcoin")
+        .map_err(|issue| issue.to_string())?
+        .await
+        .map_err(|issue| issue.to_string())?;
+    let mut streams: Vec<(StreamWriter, StreamReader)> = Vec::new();
+
+    for lane in 0..LANES.len() {
+        let (mut send, receive) = connection
+            .open_bi()
+            .await
+            .map_err(|issue| issue.to_string())?;
+
+        send.write_all(&[lane as u8])
+            .await
+            .map_err(|issue| issue.to_string())?;
+
+        streams.push((Box::new(send), Box::new(receive)));
+    }
+
+    Ok(Connection::from_streams(streams, Some(address)))
+}
+
+// Waits for the peer to open the stream of each `Lane`
+pub async fn accept(
+    incoming: Incoming,
+) -> Result<(SocketAddr, WriteConnection, ReadConnection), String> {
+    let connection = incoming.await.map_err(|issue| issue.to_string())?;
+    let address = connection.remote_address();
+    let mut streams: Vec<Option<(StreamWriter, StreamReader)>> =
+        LANES.iter().map(|_| None).collect();
+
+    for _ in LANES {
+        let (send, mut receive) = connection
+            .accept_bi()
+            .await
+            .map_err(|issue| issue.to_string())?;
+        let lane = receive.read_u8().await.map_err(|issue| issue.to_string())? as usize;
+
+        match streams.get_mut(lane) {
+            Some(stream @ None) => *stream = Some((Box::new(send), Box::new(receive))),
+            _ => return Err(format!("Unexpected stream for lane {}", lane)),
+        }
+    }
+
+    let (writter, reader) =
+        Connection::from_streams(streams.into_iter().flatten().collect(), Some(address));
+
+    Ok((address, writter, reader))
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+use tokio::sync::{Mutex, Semaphore};
+
+// Past this many IPs, those whose bucket filled back up are forgotten
+const MAX_TRACKED_IPS: usize = 10_000;
+
+// The `[rest_limits]` table of the configuration file, which keeps a public
+// REST API from being flooded. Each IP gets `requests_per_minute`, and can
+// make up to `burst` requests at once after being idle.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RestLimits {
+    pub requests_per_minute: u64,
+    pub burst: u64,
+    // Larger request bodies are refused before being read
+    pub max_body_bytes: usize,
+    // Requests being answered at once, from everyone
+    pub max_concurrent_requests: usize,
+    // Reverse proxies in front of the node, whose requests are limited by
+    // the client address they add to X-Forwarded-For instead. Requests on
+    // the Unix socket always come through a proxy.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for RestLimits {
+    fn default() -> Self {
+        RestLimits {
+            requests_per_minute: 300,
+            burst: 60,
+            max_body_bytes: 64 * 1024,
+            max_concurrent_requests: 128,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+// Who a request is from, as the limits saw it, for handlers that need it too
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Requests refused, by why, for /metrics
+#[derive(Default)]
+pub struct Rejections {
+    pub rate: AtomicU64,
+    pub concurrency: AtomicU64,
+    pub size: AtomicU64,
+}
+
+pub struct Limiter {
+    limits: RestLimits,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    in_flight: Arc<Semaphore>,
+    rejections: Rejections,
+}
+
+impl Limiter {
+    pub fn new(limits: RestLimits) -> Self {
+        Limiter {
+            in_flight: Arc::new(Semaphore::new(limits.max_concurrent_requests)),
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+            rejections: Rejections::default(),
+        }
+    }
+
+    pub fn rejections(&self) -> &Rejections {
+        &self.rejections
+    }
+
+    // Takes a token of the bucket of `ip`, or says how many seconds until
+    // there is one
+    async fn take(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        let per_second = self.limits.requests_per_minute as f64 / 60.0;
+        let burst = self.limits.burst.max(1) as f64;
+        let mut buckets = self.buckets.lock().await;
+
+        if buckets.len() >= MAX_TRACKED_IPS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second
+                    < burst
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * per_second)
+            .min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            Ok(())
+        } else if per_second > 0.0 {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        } else {
+            Err(60)
+        }
+    }
+
+    // The address the request came from, unless it came through our
+    // proxies, in which case the last address they forwarded for that isn't
+    // one of them
+    fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+
+        if peer.is_some_and(|peer| !self.limits.trusted_proxies.contains(&peer)) {
+            return peer;
+        }
+
+        for forwarded in forwarded_for(request.headers()).into_iter().rev() {
+            match forwarded {
+                Some(ip) if self.limits.trusted_proxies.contains(&ip) => {}
+                Some(ip) => return Some(ip),
+                None => break,
+            }
+        }
+
+        peer
+    }
+}
+
+// Layered over the whole REST API. Requests without a client address, as
+// in tests or from a proxy that didn't say, are only held to the size and
+// concurrency limits.
+pub async fn limit(
+    State(limiter): State<Arc<Limiter>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+
+    if length.is_some_and(|length| length > limiter.limits.max_body_bytes) {
+        limiter.rejections.size.fetch_add(1, Ordering::Relaxed);
+
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+    }
+
+    if let Some(ip) = limiter.client_ip(&request) {
+        request.extensions_mut().insert(ClientIp(ip));
+
+        if let Err(wait) = limiter.take(ip, Instant::now()).await {
+            limiter.rejections.rate.fetch_add(1, Ordering::Relaxed);
+
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, wait.to_string())],
+                "Too many requests, slow down",
+            )
+                .into_response();
+        }
+    }
+
+    let _permit = match limiter.in_flight.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            limiter
+                .rejections
+                .concurrency
+                .fetch_add(1, Ordering::Relaxed);
+
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, "1".to_string())],
+                "Too many requests at once, try again",
+            )
+                .into_response();
+        }
+    };
+
+    next.run(request).await
+}
+
+// Each proxy appends the address it got the request from, so only the last
+// ones, added by our own proxies, can be trusted. None for unreadable ones.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .flat_map(|header| header.to_str().unwrap_or("?").split(','))
+        .map(|address| address.trim().parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::time::Duration;
+
+    fn limiter(requests_per_minute: u64, burst: u64, trusted_proxies: &[&str]) -> Limiter {
+        Limiter::new(RestLimits {
+            requests_per_minute,
+            burst,
+            trusted_proxies: trusted_proxies
+                .iter()
+                .map(|proxy| proxy.parse().unwrap())
+                .collect(),
+            ..RestLimits::default()
+        })
+    }
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    // From `peer`, or the Unix socket without one, through proxies that
+    // added `forwarded` as X-Forwarded-For headers
+    fn request(peer: Option<&str>, forwarded: &[&str]) -> Request {
+        let mut request = forwarded
+            .iter()
+            .fold(Request::builder(), |builder, header| {
+                builder.header("x-forwarded-for", *header)
+            })
+            .body(Body::empty())
+            .unwrap();
+
+        if let Some(peer) = peer {
+            let address = SocketAddr::new(ip(peer), 4000);
+
+            request.extensions_mut().insert(ConnectInfo(address));
+        }
+
+        request
+    }
+
+    #[tokio::test]
+    async fn buckets_fill_back_up_over_time() {
+        let limiter = limiter(60, 2, &[]);
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        let client = ip("10.0.0.1");
+
+        assert_eq!(limiter.take(client, at(0.0)).await, Ok(()));
+        assert_eq!(limiter.take(client, at(0.0)).await, Ok(()));
+        assert_eq!(limiter.take(client, at(0.0)).await, Err(1));
+        assert_eq!(limiter.take(ip("10.0.0.2"), at(0.0)).await, Ok(()));
+
+        assert_eq!(limiter.take(client, at(0.5)).await, Err(1));
+        assert_eq!(limiter.take(client, at(1.0)).await, Ok(()));
+
+        // Never past the burst
+        assert_eq!(limiter.take(client, at(100.0)).await, Ok(()));
+        assert_eq!(limiter.take(client, at(100.0)).await, Ok(()));
+        assert_eq!(limiter.take(client, at(100.0)).await, Err(1));
+    }
+
+    #[tokio::test]
+    async fn refusals_say_when_to_retry() {
+        let slow = limiter(6, 1, &[]);
+        let start = Instant::now();
+        let client = ip("10.0.0.1");
+
+        assert_eq!(slow.take(client, start).await, Ok(()));
+        assert_eq!(slow.take(client, start).await, Err(10));
+        assert_eq!(
+            slow.take(client, start + Duration::from_secs(4)).await,
+            Err(6)
+        );
+
+        let closed = limiter(0, 1, &[]);
+
+        assert_eq!(closed.take(client, start).await, Ok(()));
+        assert_eq!(closed.take(client, start).await, Err(60));
+    }
+
+    #[tokio::test]
+    async fn full_buckets_are_forgotten_past_the_tracked_ips() {
+        let limiter = limiter(60, 100, &[]);
+        let start = Instant::now();
+        let busy = ip("192.168.0.1");
+
+        for _ in 0..100 {
+            limiter.take(busy, start).await.unwrap();
+        }
+
+        for index in 1..MAX_TRACKED_IPS {
+            let client = IpAddr::from([10, 0, (index / 256) as u8, (index % 256) as u8]);
+
+            limiter.take(client, start).await.unwrap();
+        }
+
+        // Nobody's bucket is full yet
+        limiter.take(ip("10.1.0.0"), start).await.unwrap();
+        assert_eq!(limiter.buckets.lock().await.len(), MAX_TRACKED_IPS + 1);
+
+        // Then only the busy one isn't, and forgetting it would let it start
+        // over with a full bucket
+        let later = start + Duration::from_secs(2);
+
+        limiter.take(ip("10.2.0.0"), later).await.unwrap();
+
+        let buckets = limiter.buckets.lock().await;
+
+        assert_eq!(buckets.len(), 2);
+        assert!(buckets.contains_key(&busy));
+        drop(buckets);
+        assert_eq!(limiter.take(busy, later).await, Ok(()));
+        assert_eq!(limiter.take(busy, later).await, Ok(()));
+        assert_eq!(limiter.take(busy, later).await, Err(1));
+    }
+
+    #[test]
+    fn clients_are_who_connected_unless_it_is_a_trusted_proxy() {
+        let limiter = limiter(60, 2, &["10.0.0.1", "10.0.0.2"]);
+
+        // Anyone can claim to forward for someone else
+        assert_eq!(
+            limiter.client_ip(&request(Some("1.1.1.1"), &["2.2.2.2"])),
+            Some(ip("1.1.1.1"))
+        );
+        assert_eq!(
+            limiter.client_ip(&request(Some("10.0.0.1"), &["2.2.2.2"])),
+            Some(ip("2.2.2.2"))
+        );
+        // A proxy that didn't say who for counts as the client
+        assert_eq!(
+            limiter.client_ip(&request(Some("10.0.0.1"), &[])),
+            Some(ip("10.0.0.1"))
+        );
+        // The Unix socket only has what the proxy says
+        assert_eq!(
+            limiter.client_ip(&request(None, &["2.2.2.2"])),
+            Some(ip("2.2.2.2"))
+        );
+        assert_eq!(limiter.client_ip(&request(None, &[])), None);
+    }
+
+    #[test]
+    fn forwarded_chains_are_only_trusted_from_our_proxies() {
+        let limiter = limiter(60, 2, &["10.0.0.1", "10.0.0.2"]);
+
+        // The client made up what comes before its address
+        assert_eq!(
+            limiter.client_ip(&request(Some("10.0.0.1"), &["9.9.9.9, 2.2.2.2"])),
+            Some(ip("2.2.2.2"))
+        );
+        assert_eq!(
+            limiter.client_ip(&request(
+                Some("10.0.0.1"),
+                &["10.0.0.2, 9.9.9.9", "2.2.2.2, 10.0.0.2"]
+            )),
+            Some(ip("2.2.2.2"))
+        );
+        // With only our proxies in the chain, the last one is the client
+        assert_eq!(
+            limiter.client_ip(&request(Some("10.0.0.1"), &["10.0.0.2"])),
+            Some(ip("10.0.0.1"))
+        );
+        // Past what can't be read nothing can be trusted
+        assert_eq!(
+            limiter.client_ip(&request(Some("10.0.0.1"), &["2.2.2.2, nonsense"])),
+            Some(ip("10.0.0.1"))
+        );
+    }
+}
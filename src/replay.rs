@@ -0,0 +1,241 @@
+use super::blockchain::{self, HashFmt, Node};
+use super::framing::{Frame, Payload};
+use super::rpc::Request;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::Mutex;
+
+const RECORDING_EXTENSION: &str = "frames";
+
+// A frame as it was received, bincode encoded one after the other in the
+// file of its peer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recorded {
+    // Orders the frames of all the peers
+    pub sequence: u64,
+    // Unix time in milliseconds
+    pub time: u128,
+    // `None` for the blocks we mined
+    pub peer: Option<SocketAddr>,
+    pub payload: Payload,
+}
+
+// Writes every frame received to `dir`, for `fcoin replay`. Each record is
+// written as it comes, so a recording is complete up to a crash.
+pub struct Recorder {
+    dir: PathBuf,
+    next_sequence: AtomicU64,
+    files: std::sync::Mutex<HashMap<Option<SocketAddr>, File>>,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|issue| format!("Could not create {}: {}", dir.display(), issue))?;
+
+        Ok(Recorder {
+            dir: dir.to_path_buf(),
+            next_sequence: AtomicU64::new(0),
+            files: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn record(&self, peer: Option<SocketAddr>, payload: &Payload) {
+        let mut files = self.files.lock().unwrap();
+        // Taken with the files locked, so they are in order in each file
+        let record = Recorded {
+            sequence: self.next_sequence.fetch_add(1, Ordering::Relaxed),
+            time: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            peer,
+            payload: payload.clone(),
+        };
+
+        let file = match files.get_mut(&peer) {
+            Some(file) => file,
+            None => {
+                // Colons don't go in file names everywhere
+                let name = match peer {
+                    Some(peer) => peer.to_string().replace([':', '[', ']'], "_"),
+                    None => "local".to_string(),
+                };
+                let path = self.dir.join(format!("{}.{}", name, RECORDING_EXTENSION));
+
+                match OpenOptions::new().create(true).append(true).open(&path) {
+                    Ok(file) => files.entry(peer).or_insert(file),
+                    Err(issue) => {
+                        println!("COULD NOT RECORD TO {}: {}", path.display(), issue);
+
+                        return;
+                    }
+                }
+            }
+        };
+
+        if let Err(issue) = file.write_all(&bincode::serialize(&record).unwrap()) {
+            println!("COULD NOT RECORD FRAME: {}", issue);
+        }
+    }
+}
+
+// The frames recorded in `dir`, from all the peers, in the order they were
+// received
+pub fn load(dir: &Path) -> Result<Vec<Recorded>, String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|issue| format!("Could not read {}: {}", dir.display(), issue))?;
+    let mut records = Vec::new();
+
+    for entry in entries {
+        let path = entry.map_err(|issue| issue.to_string())?.path();
+
+        if path.extension().and_then(|extension| extension.to_str()) != Some(RECORDING_EXTENSION) {
+            continue;
+        }
+
+        let file = File::open(&path)
+            .map_err(|issue| format!("Could not read {}: {}", path.display(), issue))?;
+        let mut reader = BufReader::new(file);
+
+        // The last record can be cut short by a crash
+        while let Ok(record) = bincode::deserialize_from::<_, Recorded>(&mut reader) {
+            records.push(record);
+        }
+    }
+
+    records.sort_by_key(|record| record.sequence);
+
+    Ok(records)
+}
+
+// Feeds the recorded frames that change the chain or the mempool to `node`,
+// in order, printing what it made of each. Frames that only ask for
+// something are skipped, as there is nobody to answer.
+pub async fn replay(node: Arc<Mutex<Node>>, records: Vec<Recorded>) {
+    // Like `peer_loop`, from the headers each peer sent
+    let mut peer_heights: HashMap<SocketAddr, u64> = HashMap::new();
+
+    for record in records {
+        let from = match record.peer {
+            Some(peer) => peer.to_string(),
+            None => "us".to_string(),
+        };
+        let frame = match record.payload.decode() {
+            Ok(frame) => frame,
+            Err(issue) => {
+                println!(
+                    "{} FROM {} DOES NOT DECODE: {}",
+                    record.sequence, from, issue
+                );
+
+                continue;
+            }
+        };
+
+        match frame {
+            Frame::Block(block) => {
+                let hash = block.hash();
+                let result = blockchain::block_received(node.clone(), block).await;
+
+                show(record.sequence, &from, "BLOCK", HashFmt(hash), result);
+            }
+            Frame::Blocks(blocks) => {
+                for (hash, result) in blockchain::blocks_received(node.clone(), blocks).await {
+                    show(record.sequence, &from, "BLOCK", HashFmt(hash), result);
+                }
+            }
+            Frame::Transaction(transaction) => {
+                let hash = transaction.hash();
+                let result = blockchain::transaction_received(
+                    node.clone(),
+                    transaction,
+                    record.peer,
+                    Some(record.payload),
+                )
+                .await;
+
+                show(record.sequence, &from, "TRANSACTION", HashFmt(hash), result);
+            }
+            Frame::Request(Request::BroadcastRawTransaction(raw)) => {
+                match fcoin_core::raw::decode_signed(&raw) {
+                    Ok(transaction) => {
+                        let hash = transaction.hash();
+                        let result = blockchain::transaction_received(
+                            node.clone(),
+                            transaction,
+                            record.peer,
+                            None,
+                        )
+                        .await;
+
+                        show(record.sequence, &from, "TRANSACTION", HashFmt(hash), result);
+                    }
+                    Err(issue) => println!("{} FROM {}: {}", record.sequence, from, issue),
+                }
+            }
+            Frame::Checkpoint(checkpoint) => {
+                let result = node
+                    .lock()
+                    .await
+                    .checkpoint_received(checkpoint, record.peer)
+                    .await;
+
+                if let Err(issue) = result {
+                    println!(
+                        "{} CHECKPOINT FROM {} REJECTED: {}",
+                        record.sequence, from, issue
+                    );
+                }
+            }
+            Frame::Headers(headers) => {
+                if let Some(peer) = record.peer {
+                    let mut node = node.lock().await;
+                    let height = match headers.first() {
+                        Some(first) if first.previous_hash() == &[0; 32] => headers.len() as u64,
+                        _ => {
+                            *peer_heights.get(&peer).unwrap_or(&node.height())
+                                + headers.len() as u64
+                        }
+                    };
+
+                    peer_heights.insert(peer, height);
+                    node.set_peer_height(peer, height);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let node = node.lock().await;
+
+    println!(
+        "REPLAYED. TIP IS {} AT HEIGHT {}",
+        HashFmt(node.tip_hash()),
+        node.height()
+    );
+}
+
+fn show(
+    sequence: u64,
+    from: &str,
+    kind: &str,
+    hash: HashFmt,
+    result: Result<(), blockchain::RejectReason>,
+) {
+    match result {
+        Ok(()) => println!("{} {} {} FROM {} ACCEPTED", sequence, kind, hash, from),
+        Err(reason) => println!(
+            "{} {} {} FROM {} REJECTED: {}",
+            sequence, kind, hash, from, reason
+        ),
+    }
+}
@@ -0,0 +1,716 @@
+use super::address_index::{AddressEntry, AddressIndex};
+use super::blockchain::{Block, HashFmt, Node, PublicKey, Transaction};
+use super::faucet::{self, Faucet};
+use super::graphql::{self, ChainSchema};
+use super::ratelimit::{self, Limiter, RestLimits};
+use async_graphql::http::GraphiQLSource;
+use fcoin_core::{address, hex, name};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::{header, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware;
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, MethodRouter};
+use axum::{Json, Router};
+use rust_embed::RustEmbed;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use tower_http::cors::{Any, CorsLayer};
+
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::Mutex;
+
+// Most entries a single page can have
+const MAX_PAGE_SIZE: usize = 100;
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+#[derive(Serialize, ToSchema)]
+struct AddressInfo {
+    address: String,
+    balance: u64,
+    transactions: u64,
+    first_seen: Option<u64>,
+    last_seen: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressPage {
+    total: usize,
+    offset: usize,
+    addresses: Vec<AddressInfo>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TransactionInfo {
+    hash: String,
+    block_hash: String,
+    height: Option<u64>,
+    confirmations: u64,
+    source: String,
+    // Of the first output
+    destination: String,
+    // Of all the outputs
+    amount: u64,
+    fee: u64,
+    outputs: Vec<TransactionOutput>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TransactionOutput {
+    destination: String,
+    amount: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlockSummary {
+    hash: String,
+    height: Option<u64>,
+    time: u64,
+    producer: String,
+    transactions: usize,
+    size: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlockPage {
+    total: u64,
+    offset: usize,
+    blocks: Vec<BlockSummary>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlockInfo {
+    hash: String,
+    height: Option<u64>,
+    time: u64,
+    producer: String,
+    size: u64,
+    previous_hash: String,
+    confirmations: u64,
+    transactions: Vec<BlockTransaction>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlockTransaction {
+    hash: String,
+    source: String,
+    // Of the first output
+    destination: String,
+    // Of all the outputs
+    amount: u64,
+    fee: u64,
+    outputs: Vec<TransactionOutput>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AssetInfo {
+    asset: String,
+    issuer: String,
+    supply: u64,
+    issued_at: u64,
+    holders: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TokenHolder {
+    address: String,
+    amount: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HolderPage {
+    total: usize,
+    offset: usize,
+    holders: Vec<TokenHolder>,
+}
+
+// The single page explorer, from the explorer directory
+#[derive(RustEmbed)]
+#[folder = "explorer/"]
+struct ExplorerFiles;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct Page {
+    /// Entries to skip
+    #[serde(default)]
+    offset: usize,
+    /// Entries to return, 20 by default and 100 at most
+    #[serde(default = "default_page_size")]
+    limit: usize,
+}
+
+fn default_page_size() -> usize {
+    DEFAULT_PAGE_SIZE
+}
+
+type Error = (StatusCode, String);
+
+#[derive(Clone)]
+struct ApiState {
+    node: Arc<Mutex<Node>>,
+    addresses: Arc<Mutex<AddressIndex>>,
+    schema: ChainSchema,
+    limiter: Arc<Limiter>,
+}
+
+// Describes every route, served at /api/spec.json
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "fcoin explorer API",
+        description = "Read-only chain data, as JSON"
+    ),
+    paths(
+        address_requested,
+        asset_requested,
+        asset_holders_requested,
+        blocks_requested,
+        block_requested,
+        rich_list_requested,
+        transaction_requested
+    )
+)]
+pub struct ApiDoc;
+
+// Every route of the API, each of which `ApiDoc` has to describe
+fn routes() -> Vec<(&'static str, MethodRouter<ApiState>)> {
+    vec![
+        ("/addresses/:address", get(address_requested)),
+        ("/assets/:asset", get(asset_requested)),
+        ("/assets/:asset/holders", get(asset_holders_requested)),
+        ("/blocks", get(blocks_requested)),
+        ("/blocks/:hash", get(block_requested)),
+        ("/richlist", get(rich_list_requested)),
+        ("/transactions/:hash", get(transaction_requested)),
+    ]
+}
+
+// Paths of the routes, written like in OpenAPI (/blocks/{hash})
+pub fn route_paths() -> Vec<String> {
+    routes()
+        .into_iter()
+        .map(|(path, _)| {
+            path.split('/')
+                .map(|part| match part.strip_prefix(':') {
+                    Some(parameter) => format!("{{{}}}", parameter),
+                    None => part.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .collect()
+}
+
+// The API, its OpenAPI document and a Swagger UI page for it at /api/docs.
+// The same data can be queried with GraphQL at /graphql, where browsers get
+// a GraphiQL page. With `explorer` a block explorer built on the API is
+// served at /, and with a `faucet` test coins are given away at /faucet.
+// All of it is held to `limits`.
+pub fn router(
+    node: Arc<Mutex<Node>>,
+    addresses: Arc<Mutex<AddressIndex>>,
+    explorer: bool,
+    faucet: Option<Faucet>,
+    limits: RestLimits,
+) -> Router {
+    let schema = graphql::schema(node.clone(), addresses.clone());
+    let max_body_bytes = limits.max_body_bytes;
+    let limiter = Arc::new(Limiter::new(limits));
+    let faucet = faucet.map(|faucet| faucet::router(node.clone(), faucet));
+
+    let mut router = routes()
+        .into_iter()
+        .fold(Router::new(), |router, (path, handler)| {
+            router.route(path, handler)
+        })
+        .route("/graphql", get(graphiql_requested).post(graphql_requested))
+        .route("/metrics", get(metrics_requested))
+        .merge(SwaggerUi::new("/api/docs").url("/api/spec.json", ApiDoc::openapi()))
+        .with_state(ApiState {
+            node,
+            addresses,
+            schema,
+            limiter: limiter.clone(),
+        });
+
+    if let Some(faucet) = faucet {
+        router = router.merge(faucet);
+    }
+
+    if explorer {
+        router = router.fallback(explorer_file_requested);
+    }
+
+    // Bodies without a length are cut at the limit as they are read
+    router
+        .layer(middleware::from_fn_with_state(limiter, ratelimit::limit))
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+}
+
+// Where and how `serve` serves the API, from the configuration file
+pub struct ServeSettings {
+    pub port: Option<u16>,
+    // For a reverse proxy on the same machine
+    pub socket: Option<PathBuf>,
+    pub explorer: bool,
+    pub limits: RestLimits,
+    // Web pages from these origins can call the API, "*" for any
+    pub cors_origins: Vec<String>,
+}
+
+// Read-only HTTP API for explorers, answering in JSON, on the port and the
+// Unix socket of `settings`
+pub async fn serve(
+    node: Arc<Mutex<Node>>,
+    addresses: Arc<Mutex<AddressIndex>>,
+    settings: ServeSettings,
+    faucet: Option<Faucet>,
+) -> Result<(), String> {
+    let cors = cors(&settings.cors_origins)?;
+    let router = router(node, addresses, settings.explorer, faucet, settings.limits).layer(cors);
+
+    let listener = match settings.port {
+        Some(port) => {
+            let listener = TcpListener::bind(format!("localhost:{}", port))
+                .await
+                .map_err(|issue| {
+                    format!("Could not bind the REST API to port {}: {}", port, issue)
+                })?;
+
+            println!("REST API LISTENING ON PORT {}", port);
+
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let listener = match (listener, settings.socket) {
+        (Some(listener), Some(path)) => {
+            let router = router.clone();
+
+            tokio::spawn(async move {
+                if let Err(issue) = serve_socket(router, &path).await {
+                    println!("{}", issue);
+                }
+            });
+
+            listener
+        }
+        (Some(listener), None) => listener,
+        (None, Some(path)) => return serve_socket(router, &path).await,
+        (None, None) => return Ok(()),
+    };
+
+    // Requests are limited by IP
+    axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .map_err(|issue| format!("REST API stopped: {}", issue))
+}
+
+// Only local processes can reach the socket, so its requests are treated as
+// coming through a trusted proxy. A socket left by a previous run is
+// replaced.
+async fn serve_socket(router: Router, path: &std::path::Path) -> Result<(), String> {
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path).map_err(|issue| {
+        format!(
+            "Could not bind the REST API to {}: {}",
+            path.display(),
+            issue
+        )
+    })?;
+
+    println!("REST API LISTENING ON {}", path.display());
+
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .map_err(|issue| format!("REST API socket stopped: {}", issue))?;
+        let service = TowerToHyperService::new(router.clone());
+
+        tokio::spawn(async move {
+            if let Err(issue) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                println!("REST API SOCKET CONNECTION FAILED: {}", issue);
+            }
+        });
+    }
+}
+
+// Browser wallets on other origins only read the answers with these
+fn cors(origins: &[String]) -> Result<CorsLayer, String> {
+    let cors = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE]);
+
+    if origins.iter().any(|origin| origin == "*") {
+        return Ok(cors.allow_origin(Any));
+    }
+
+    let origins = origins
+        .iter()
+        .map(|origin| {
+            HeaderValue::from_str(origin).map_err(|_| format!("Invalid CORS origin {:?}", origin))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(cors.allow_origin(origins))
+}
+
+fn block_summary(node: &Node, block: &Block) -> BlockSummary {
+    let hash = block.hash();
+
+    BlockSummary {
+        hash: HashFmt(hash).to_string(),
+        height: node.block_height(&hash),
+        time: block.header().time(),
+        producer: address::encode(block.header().node_public_key()),
+        transactions: block.transactions().len(),
+        size: block.size(),
+    }
+}
+
+fn address_info(public_key: &PublicKey, entry: &AddressEntry) -> AddressInfo {
+    AddressInfo {
+        address: address::encode(public_key),
+        balance: entry.balance(),
+        transactions: entry.transactions(),
+        first_seen: entry.first_seen(),
+        last_seen: entry.last_seen(),
+    }
+}
+
+/// Balance and activity of an address
+#[utoipa::path(
+    get,
+    path = "/addresses/{address}",
+    params(("address" = String, Path, description = "fcoin address")),
+    responses(
+        (status = 200, body = AddressInfo),
+        (status = 400, description = "Invalid address"),
+        (status = 404, description = "Never seen in the chain")
+    )
+)]
+async fn address_requested(
+    State(state): State<ApiState>,
+    Path(encoded): Path<String>,
+) -> Result<Json<AddressInfo>, Error> {
+    let public_key = address::decode(&encoded).map_err(|issue| (StatusCode::BAD_REQUEST, issue))?;
+
+    match state.addresses.lock().await.get(&public_key) {
+        Some(entry) => Ok(Json(address_info(&public_key, entry))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("{} has never been seen in the chain", encoded),
+        )),
+    }
+}
+
+fn valid_asset(asset: &str) -> Result<(), Error> {
+    if name::is_valid(asset) {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            format!("{} is not a valid asset name", asset),
+        ))
+    }
+}
+
+/// Issuer and supply of a token
+#[utoipa::path(
+    get,
+    path = "/assets/{asset}",
+    params(("asset" = String, Path, description = "Asset name")),
+    responses(
+        (status = 200, body = AssetInfo),
+        (status = 400, description = "Invalid asset name"),
+        (status = 404, description = "Never issued")
+    )
+)]
+async fn asset_requested(
+    State(state): State<ApiState>,
+    Path(asset): Path<String>,
+) -> Result<Json<AssetInfo>, Error> {
+    valid_asset(&asset)?;
+
+    let view = state.node.lock().await.chain_views().load();
+
+    match view.asset(&asset) {
+        Some(record) => Ok(Json(AssetInfo {
+            holders: view.token_holders(&asset).len(),
+            asset,
+            issuer: address::encode(&record.issuer),
+            supply: record.supply,
+            issued_at: record.issued_at,
+        })),
+        None => Err((StatusCode::NOT_FOUND, format!("{} was never issued", asset))),
+    }
+}
+
+/// Holders of a token, most tokens first
+#[utoipa::path(
+    get,
+    path = "/assets/{asset}/holders",
+    params(("asset" = String, Path, description = "Asset name"), Page),
+    responses(
+        (status = 200, body = HolderPage),
+        (status = 400, description = "Invalid asset name")
+    )
+)]
+async fn asset_holders_requested(
+    State(state): State<ApiState>,
+    Path(asset): Path<String>,
+    Query(page): Query<Page>,
+) -> Result<Json<HolderPage>, Error> {
+    valid_asset(&asset)?;
+
+    let holders = state
+        .node
+        .lock()
+        .await
+        .chain_views()
+        .load()
+        .token_holders(&asset);
+
+    Ok(Json(HolderPage {
+        total: holders.len(),
+        offset: page.offset,
+        holders: holders
+            .into_iter()
+            .skip(page.offset)
+            .take(page.limit.min(MAX_PAGE_SIZE))
+            .map(|(holder, amount)| TokenHolder {
+                address: address::encode(&holder),
+                amount,
+            })
+            .collect(),
+    }))
+}
+
+/// Addresses sorted by balance, richest first
+#[utoipa::path(get, path = "/richlist", params(Page), responses((status = 200, body = AddressPage)))]
+async fn rich_list_requested(
+    State(state): State<ApiState>,
+    Query(page): Query<Page>,
+) -> Json<AddressPage> {
+    let addresses = state.addresses.lock().await;
+
+    Json(AddressPage {
+        total: addresses.len(),
+        offset: page.offset,
+        addresses: addresses
+            .richest(page.offset, page.limit.min(MAX_PAGE_SIZE))
+            .into_iter()
+            .map(|(public_key, entry)| address_info(&public_key, entry))
+            .collect(),
+    })
+}
+
+/// Blocks of the main chain, newest first
+#[utoipa::path(get, path = "/blocks", params(Page), responses((status = 200, body = BlockPage)))]
+async fn blocks_requested(
+    State(state): State<ApiState>,
+    Query(page): Query<Page>,
+) -> Json<BlockPage> {
+    let node = state.node.lock().await;
+    let mut blocks = Vec::new();
+    let mut hash = node.tip_hash();
+
+    while let Some(block) = node.block(&hash) {
+        if blocks.len() == page.offset + page.limit.min(MAX_PAGE_SIZE) {
+            break;
+        }
+
+        blocks.push(block_summary(&node, block));
+        hash = *block.header().previous_hash();
+    }
+
+    Json(BlockPage {
+        total: node.height(),
+        offset: page.offset,
+        blocks: blocks.into_iter().skip(page.offset).collect(),
+    })
+}
+
+/// A block and its transactions
+#[utoipa::path(
+    get,
+    path = "/blocks/{hash}",
+    params(("hash" = String, Path, description = "Block hash, in hex")),
+    responses(
+        (status = 200, body = BlockInfo),
+        (status = 400, description = "Invalid hash"),
+        (status = 404, description = "Unknown block")
+    )
+)]
+async fn block_requested(
+    State(state): State<ApiState>,
+    Path(encoded): Path<String>,
+) -> Result<Json<BlockInfo>, Error> {
+    let hash =
+        hex::decode_array::<32>(&encoded).map_err(|issue| (StatusCode::BAD_REQUEST, issue))?;
+    let node = state.node.lock().await;
+
+    match node.block(&hash) {
+        Some(block) => Ok(Json(BlockInfo {
+            hash: encoded,
+            height: node.block_height(&hash),
+            time: block.header().time(),
+            producer: address::encode(block.header().node_public_key()),
+            size: block.size(),
+            previous_hash: HashFmt(*block.header().previous_hash()).to_string(),
+            confirmations: node.confirmations(&hash),
+            transactions: block
+                .transactions()
+                .iter()
+                .map(|transaction| BlockTransaction {
+                    hash: HashFmt(transaction.hash()).to_string(),
+                    source: address::encode(transaction.source()),
+                    destination: address::encode(transaction.destination()),
+                    amount: transaction.amount(),
+                    fee: transaction.fee(),
+                    outputs: transaction_outputs(transaction),
+                })
+                .collect(),
+        })),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("Block {} not found", encoded),
+        )),
+    }
+}
+
+async fn graphql_requested(
+    State(state): State<ApiState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(state.schema.execute(request).await)
+}
+
+// Mining and block propagation figures, in the Prometheus text format
+async fn metrics_requested(State(state): State<ApiState>) -> String {
+    let info = state.node.lock().await.mining_info();
+    let mut metrics = format!(
+        "fcoin_hashrate {}\n\
+         fcoin_blocks_mined_total {}\n\
+         fcoin_blocks_orphaned {}\n\
+         fcoin_orphan_rate {}\n",
+        info.hashrate, info.blocks_mined, info.blocks_orphaned, info.orphan_rate
+    );
+
+    for peer in &info.propagation {
+        metrics.push_str(&format!(
+            "fcoin_peer_blocks_total{{peer=\"{0}\"}} {1}\n\
+             fcoin_block_propagation_average_ms{{peer=\"{0}\"}} {2}\n\
+             fcoin_block_propagation_max_ms{{peer=\"{0}\"}} {3}\n",
+            peer.address, peer.blocks, peer.average_ms, peer.max_ms
+        ));
+    }
+
+    let rejections = state.limiter.rejections();
+
+    for (reason, count) in [
+        ("rate", &rejections.rate),
+        ("concurrency", &rejections.concurrency),
+        ("size", &rejections.size),
+    ] {
+        metrics.push_str(&format!(
+            "fcoin_rest_rejected_total{{reason=\"{}\"}} {}\n",
+            reason,
+            count.load(Ordering::Relaxed)
+        ));
+    }
+
+    metrics
+}
+
+async fn graphiql_requested() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+async fn explorer_file_requested(uri: Uri) -> Response {
+    let path = match uri.path().trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    };
+
+    let content_type = match path.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        _ => "application/octet-stream",
+    };
+
+    match ExplorerFiles::get(path) {
+        Some(file) => ([(header::CONTENT_TYPE, content_type)], file.data).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("{} not found", uri.path())).into_response(),
+    }
+}
+
+/// Where a transaction was mined
+#[utoipa::path(
+    get,
+    path = "/transactions/{hash}",
+    params(("hash" = String, Path, description = "Transaction hash, in hex")),
+    responses(
+        (status = 200, body = TransactionInfo),
+        (status = 400, description = "Invalid hash"),
+        (status = 404, description = "Not mined in the main chain"),
+        (status = 501, description = "The node doesn't index transactions (txindex)")
+    )
+)]
+async fn transaction_requested(
+    State(state): State<ApiState>,
+    Path(encoded): Path<String>,
+) -> Result<Json<TransactionInfo>, Error> {
+    let hash =
+        hex::decode_array::<32>(&encoded).map_err(|issue| (StatusCode::BAD_REQUEST, issue))?;
+    let node = state.node.lock().await;
+
+    match node.transaction(&hash) {
+        Ok(Some((transaction, location))) => Ok(Json(TransactionInfo {
+            hash: encoded,
+            block_hash: HashFmt(location.block_hash).to_string(),
+            height: node.block_height(&location.block_hash),
+            confirmations: node.confirmations(&location.block_hash),
+            source: address::encode(transaction.source()),
+            destination: address::encode(transaction.destination()),
+            amount: transaction.amount(),
+            fee: transaction.fee(),
+            outputs: transaction_outputs(transaction),
+        })),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Transaction {} not found", encoded),
+        )),
+        Err(issue) => Err((StatusCode::NOT_IMPLEMENTED, issue)),
+    }
+}
+
+fn transaction_outputs(transaction: &Transaction) -> Vec<TransactionOutput> {
+    transaction
+        .outputs()
+        .iter()
+        .map(|output| TransactionOutput {
+            destination: address::encode(&output.destination),
+            amount: output.amount,
+        })
+        .collect()
+}
@@ -0,0 +1,1122 @@
+use super::address_index::{AddressIndex, HistoryEntry};
+use super::alerts::Alert;
+use super::blockchain::{
+    self, Block, BlockHeader, Hash, HashFmt, Node, Output, PublicKey, RejectReason, Transaction,
+    TransactionDetails, TransactionStatus,
+};
+use super::deployment::DeploymentInfo;
+use super::framing::{Frame, Traffic, TrafficStats, PROTOCOL_VERSION};
+use super::keystore::{self, Passphrase};
+use super::memory::MemoryInfo;
+use super::merkle::MerkleProof;
+use super::miner::MiningInfo;
+use super::network::PowHash;
+use super::policy;
+use super::pow;
+use super::stats::ChainStats;
+use super::verify::ChainVerification;
+use super::view::{AssetRecord, ChainView, ChainViews, NameRecord, Supply};
+use super::wallets::{self, WalletInfo};
+use fcoin_core::{hex, raw};
+use num::BigUint;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::Mutex;
+
+// Calls wallets and tools make on a node, sent as `Frame::Request` and
+// answered with a `Frame::Response` on the same connection
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    CreateRawTransaction {
+        source: PublicKey,
+        // Paid by the same signature and fee
+        outputs: Vec<Output>,
+        fee: u64,
+        expires_at: Option<u64>,
+    },
+    // Signs with the node's own key, or that of a loaded wallet, within the
+    // spending policy of the node. Beyond it, `approval` has to be the hex
+    // signature of one of its approvers on `transaction`, as a message.
+    SignRawTransaction {
+        transaction: String,
+        wallet: Option<String>,
+        approval: Option<String>,
+    },
+    BroadcastRawTransaction(String),
+    // Fee rate (per 1000 bytes) to be confirmed within that many blocks
+    EstimateFee(u32),
+    GetChainStats,
+    // Both need the transaction index enabled
+    GetTransaction(Hash),
+    GetTransactionProof(Hash),
+    GetNodeInfo,
+    GetMempoolInfo,
+    // Every transaction waiting to be mined, highest fee rate first
+    GetRawMempool,
+    // Works without the transaction index, but is then slower for
+    // transactions deep in the chain
+    GetTransactionStatus(Hash),
+    // As of our tip, not counting what is in the mempool
+    GetBalance(PublicKey),
+    GetNetTotals,
+    GetPeerInfo,
+    // Changes to the balance of an address in blocks from `from_height` on
+    GetAddressHistory {
+        address: PublicKey,
+        from_height: u64,
+    },
+    // The latest alerts the node raised, oldest first
+    GetAlerts,
+    // How much memory the mempool and caches take, against their budgets
+    GetMemoryInfo,
+    // Checks the main chain again at that level, see `verify.rs`. Holds
+    // the node for as long as it takes.
+    VerifyChain(u8),
+    // Who holds a name, without the `name/` prefix
+    ResolveName(String),
+    // Tokens of an asset an address holds, as of our tip
+    GetAssetBalance {
+        asset: String,
+        address: PublicKey,
+    },
+    // Who issued an asset, and how many tokens
+    GetAsset(String),
+    // Coins issued, burned and left in circulation, as of our tip
+    GetSupply,
+    // Where each deployment of the network stands, for the next block
+    GetDeploymentInfo,
+    // Work for miners outside the node, see `fcoin-miner`. Only on proof of
+    // work networks.
+    GetBlockTemplate,
+    // A block mined on a template
+    SubmitBlock(Box<Block>),
+    // How hard the miner of the node works
+    GetMiningInfo,
+    // Header work for external (GPU, FPGA, ...) workers, only with SHA-256
+    // proof of work
+    GetWork,
+    // A nonce found for work, which the node puts in the block and checks
+    SubmitWork {
+        id: u64,
+        nonce: [u8; 32],
+    },
+    // Decrypts the key of the node, or of a loaded wallet, when its key
+    // file is encrypted, for `timeout` seconds. Signing fails with
+    // `ErrorCode::WalletLocked` while it is locked.
+    WalletUnlock {
+        passphrase: Passphrase,
+        timeout: u64,
+        wallet: Option<String>,
+    },
+    // Wipes the decrypted key before the timeout
+    WalletLock {
+        wallet: Option<String>,
+    },
+    // Named wallets, see `wallets.rs`. A new one is loaded right away, and
+    // encrypted with the passphrase when there is one.
+    CreateWallet {
+        name: String,
+        passphrase: Option<Passphrase>,
+    },
+    LoadWallet(String),
+    // Its key is wiped from memory, its key file kept
+    UnloadWallet(String),
+    // Those loaded
+    ListWallets,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Response {
+    RawTransaction(String),
+    TransactionHash(Hash),
+    // Answer to broadcasting a transaction the node already had, mined or
+    // not, which is not sent again
+    KnownTransaction {
+        hash: Hash,
+        status: TransactionStatus,
+    },
+    FeeRate(u64),
+    ChainStats(ChainStats),
+    Transaction {
+        transaction: Box<Transaction>,
+        block_hash: Hash,
+        height: u64,
+        confirmations: u64,
+    },
+    // With the header of the block, whose Merkle root the proof leads to
+    TransactionProof {
+        header: Box<BlockHeader>,
+        proof: MerkleProof,
+    },
+    NodeInfo(NodeInfo),
+    MempoolInfo(MempoolInfo),
+    RawMempool(Vec<MempoolTransaction>),
+    TransactionStatus(TransactionStatus),
+    Balance(i128),
+    NetTotals(Traffic),
+    PeerInfo(Vec<PeerInfo>),
+    AddressHistory {
+        // Before the first of the entries
+        opening_balance: i128,
+        entries: Vec<HistoryEntry>,
+    },
+    Alerts(Vec<Alert>),
+    MemoryInfo(MemoryInfo),
+    ChainVerification(ChainVerification),
+    Name {
+        // `None` when nobody holds it
+        record: Option<NameRecord>,
+        // Least fee to register or renew it
+        fee: u64,
+    },
+    AssetBalance(u64),
+    // `None` when it was never issued
+    Asset(Option<AssetRecord>),
+    Supply(Supply),
+    DeploymentInfo(Vec<DeploymentInfo>),
+    // `None` when there is nothing to mine
+    BlockTemplate(Option<Box<BlockTemplate>>),
+    // The submitted block is now part of our chain
+    BlockAccepted(Hash),
+    MiningInfo(MiningInfo),
+    // `None` when there is nothing to mine
+    Work(Option<Box<Work>>),
+    WalletUnlocked,
+    WalletLocked,
+    // A wallet just created or loaded
+    Wallet(WalletInfo),
+    WalletUnloaded,
+    Wallets(Vec<WalletInfo>),
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    // Mined by changing its nonce
+    pub block: Block,
+    pub height: u64,
+    // Little-endian, the proof of work hash of the header has to be below it
+    pub target: Vec<u8>,
+    pub pow_hash: PowHash,
+}
+
+// The proof of work hash is the SHA-256 hash of the header, whose first
+// bytes are already hashed into `midstate`. The rest of the header is
+// `before_nonce`, the nonce and `after_nonce`, `length` bytes in all.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Work {
+    pub id: u64,
+    pub midstate: [u32; 8],
+    pub before_nonce: Vec<u8>,
+    pub after_nonce: Vec<u8>,
+    pub length: u64,
+    // Little-endian, the hash has to be below it
+    pub target: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    // Of our main chain
+    pub height: u64,
+    // Blocks up to it can't be reorganized away anymore
+    pub finalized_height: u64,
+    // Of the best chain our peers told us about
+    pub best_known_height: u64,
+    // Whether we are close enough to the best chain to mine and accept
+    // transactions
+    pub synced: bool,
+    pub peers: usize,
+    pub mempool_transactions: usize,
+    pub protocol_version: u32,
+    // Least fee of a transaction carrying data, for each byte of it
+    pub data_byte_fee: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolInfo {
+    pub transactions: usize,
+    // Sum of the sizes of the transactions, in bytes
+    pub size: u64,
+    pub fees: u64,
+    // Per 1000 bytes, 0 when the mempool is empty
+    pub min_fee_rate: u64,
+    pub max_fee_rate: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolTransaction {
+    pub hash: Hash,
+    pub source: PublicKey,
+    pub fee: u64,
+    pub fee_rate: u64,
+    pub size: u64,
+    // Seconds since the node first saw it
+    pub age: u64,
+    // Unconfirmed transactions it depends on, and that depend on it
+    pub ancestors: usize,
+    pub descendants: usize,
+    // Of it and its ancestors together, which blocks are built by
+    pub package_fee_rate: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub address: SocketAddr,
+    pub protocol_version: u32,
+    // Of the chain they told us about, if they did
+    pub height: Option<u64>,
+    // Once the peer answered a ping
+    pub round_trip_ms: Option<u64>,
+    pub traffic: TrafficStats,
+}
+
+// Lets callers tell failures apart without parsing the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorCode {
+    // Malformed or wrongly signed raw transactions, unknown keys, ...
+    InvalidRequest,
+    InsufficientFunds,
+    NotFound,
+    // The node was not configured to answer this request
+    Unsupported,
+    // The node did not accept the transaction into its mempool
+    Rejected(RejectReason),
+    // The key of the node, or of the wallet, is encrypted and not unlocked
+    WalletLocked,
+    // Signing would break the spending policy, and wasn't approved
+    PolicyViolation,
+    // The listener the request came on doesn't allow its methods
+    NotAllowed,
+}
+
+// What a listener lets its clients call, so a public one can't reach the
+// wallet nor run the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MethodClass {
+    // The chain, the mempool, and building and broadcasting transactions
+    Read,
+    // Signing, and managing the wallets
+    Wallet,
+    // Peers, mining, verifying and memory
+    Admin,
+}
+
+// The `[rpc]` table of the configuration file. Only reading is allowed where
+// anyone can reach the node unless more is, everything on localhost.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RpcSettings {
+    // A listener on localhost only, for the wallets and tools of the
+    // operator, which `fcoin` commands then call instead of `port`
+    pub port: Option<u16>,
+    // Allowed on that listener
+    pub methods: Vec<MethodClass>,
+    // Allowed on the peer port and QUIC connections, which anyone reaches.
+    // Clients on the peer port from localhost get `methods`.
+    pub peer_methods: Vec<MethodClass>,
+    // Allowed on the REST API. Its routes only read, but the faucet pays
+    // from the key of the node, so needs `wallet`.
+    pub rest_methods: Vec<MethodClass>,
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        RpcSettings {
+            port: None,
+            methods: vec![MethodClass::Read, MethodClass::Wallet, MethodClass::Admin],
+            peer_methods: vec![MethodClass::Read],
+            rest_methods: vec![MethodClass::Read],
+        }
+    }
+}
+
+impl Request {
+    // Listed one by one, so new requests have to pick a class
+    pub fn class(&self) -> MethodClass {
+        match self {
+            Request::CreateRawTransaction { .. }
+            | Request::BroadcastRawTransaction(_)
+            | Request::EstimateFee(_)
+            | Request::GetChainStats
+            | Request::GetTransaction(_)
+            | Request::GetTransactionProof(_)
+            | Request::GetNodeInfo
+            | Request::GetMempoolInfo
+            | Request::GetRawMempool
+            | Request::GetTransactionStatus(_)
+            | Request::GetBalance(_)
+            | Request::GetAddressHistory { .. }
+            | Request::GetAlerts
+            | Request::ResolveName(_)
+            | Request::GetAssetBalance { .. }
+            | Request::GetAsset(_)
+            | Request::GetSupply
+            | Request::GetDeploymentInfo => MethodClass::Read,
+            Request::SignRawTransaction { .. }
+            | Request::WalletUnlock { .. }
+            | Request::WalletLock { .. }
+            | Request::CreateWallet { .. }
+            | Request::LoadWallet(_)
+            | Request::UnloadWallet(_)
+            | Request::ListWallets => MethodClass::Wallet,
+            Request::GetNetTotals
+            | Request::GetPeerInfo
+            | Request::GetMemoryInfo
+            | Request::VerifyChain(_)
+            | Request::GetBlockTemplate
+            | Request::SubmitBlock(_)
+            | Request::GetMiningInfo
+            | Request::GetWork
+            | Request::SubmitWork { .. } => MethodClass::Admin,
+        }
+    }
+
+    // Refused by nodes run without a wallet. Templates and work are for
+    // blocks paying the node.
+    fn needs_wallet(&self) -> bool {
+        matches!(
+            self,
+            Request::SignRawTransaction { .. }
+                | Request::WalletUnlock { .. }
+                | Request::WalletLock { .. }
+                | Request::CreateWallet { .. }
+                | Request::LoadWallet(_)
+                | Request::UnloadWallet(_)
+                | Request::ListWallets
+                | Request::GetBlockTemplate
+                | Request::GetWork
+                | Request::SubmitWork { .. }
+        )
+    }
+}
+
+impl Response {
+    fn error(code: ErrorCode, message: String) -> Response {
+        Response::Error { code, message }
+    }
+}
+
+// Why `request` isn't answered on a listener allowing `methods`, if it isn't
+fn refusal(request: &Request, methods: &[MethodClass], has_wallet: bool) -> Option<Response> {
+    if !methods.contains(&request.class()) {
+        Some(Response::error(
+            ErrorCode::NotAllowed,
+            format!(
+                "{:?} methods are not allowed on this listener",
+                request.class()
+            ),
+        ))
+    } else if request.needs_wallet() && !has_wallet {
+        Some(Response::error(
+            ErrorCode::Unsupported,
+            "The node runs without a wallet".to_string(),
+        ))
+    } else {
+        None
+    }
+}
+
+fn create_raw_transaction(
+    view: &ChainView,
+    source: PublicKey,
+    outputs: &[Output],
+    fee: u64,
+    expires_at: Option<u64>,
+) -> Response {
+    let details = match TransactionDetails::to_many(source, outputs, fee) {
+        Some(details) => details.with_expiry(expires_at),
+        None => return Response::error(ErrorCode::InvalidRequest, "No outputs".to_string()),
+    };
+    let amount = match details.total_amount() {
+        Some(amount) => amount,
+        None => {
+            return Response::error(
+                ErrorCode::InvalidRequest,
+                "The outputs add up to more coins than can exist".to_string(),
+            )
+        }
+    };
+
+    match view.balance(&source) {
+        balance if balance >= amount as i128 + fee as i128 => {
+            Response::RawTransaction(raw::encode_unsigned(&details))
+        }
+        balance => Response::error(
+            ErrorCode::InsufficientFunds,
+            format!(
+                "Not enough funds: has ${}, trying to transfer ${} with a ${} fee",
+                balance, amount, fee
+            ),
+        ),
+    }
+}
+
+async fn sign_raw_transaction(
+    node: Arc<Mutex<Node>>,
+    transaction: String,
+    wallet: Option<String>,
+    approval: Option<String>,
+) -> Response {
+    let details = match raw::decode_unsigned(&transaction) {
+        Ok(details) => details,
+        Err(issue) => return Response::error(ErrorCode::InvalidRequest, issue),
+    };
+    let approval = match approval.map(|approval| hex::decode_array::<64>(&approval)) {
+        Some(Ok(approval)) => Some(approval),
+        Some(Err(issue)) => return Response::error(ErrorCode::InvalidRequest, issue),
+        None => None,
+    };
+    let now = blockchain::timestamp();
+    let mut node = node.lock().await;
+    let policy = node.policy().clone();
+    let keystore = match node.wallet_mut(wallet.as_deref()) {
+        Ok(keystore) => keystore,
+        Err(issue) => return Response::error(ErrorCode::NotFound, issue),
+    };
+
+    if let Err(issue) = policy.check(&details, keystore.spending_mut().spent_since(now)) {
+        match approval {
+            Some(approval) if policy.is_approved(&transaction, &approval) => {
+                println!(
+                    "{} SIGNING BEYOND THE POLICY, APPROVED: {}",
+                    wallet_name(&wallet),
+                    issue
+                );
+            }
+            _ => return Response::error(ErrorCode::PolicyViolation, issue),
+        }
+    }
+
+    let signed = match keystore.secret_key() {
+        Ok(secret_key) => raw::sign_raw_transaction(&transaction, secret_key),
+        Err(issue) => return Response::error(ErrorCode::WalletLocked, issue),
+    };
+
+    match signed {
+        Ok(signed) => {
+            keystore.spending_mut().record(now, policy::spent(&details));
+
+            Response::RawTransaction(signed)
+        }
+        Err(issue) => Response::error(ErrorCode::InvalidRequest, issue),
+    }
+}
+
+async fn wallet_unlock(
+    node: Arc<Mutex<Node>>,
+    passphrase: Passphrase,
+    timeout: u64,
+    wallet: Option<String>,
+) -> Response {
+    let encrypted = match node.lock().await.wallet(wallet.as_deref()) {
+        Ok(keystore) => match keystore.encrypted_key() {
+            Some(encrypted) => encrypted.to_string(),
+            None => {
+                return Response::error(
+                    ErrorCode::Unsupported,
+                    "The wallet is not encrypted".to_string(),
+                )
+            }
+        },
+        Err(issue) => return Response::error(ErrorCode::NotFound, issue),
+    };
+    // Without the node lock, as it takes a while
+    let secret_key = match keystore::decrypt(encrypted, passphrase).await {
+        Ok(secret_key) => secret_key,
+        Err(issue) => return Response::error(ErrorCode::InvalidRequest, issue),
+    };
+    let timeout = Duration::from_secs(timeout);
+    let unlocked = node
+        .lock()
+        .await
+        .wallet_mut(wallet.as_deref())
+        .and_then(|keystore| keystore.unlock(secret_key, timeout));
+
+    if let Err(issue) = unlocked {
+        return Response::error(ErrorCode::InvalidRequest, issue);
+    }
+
+    println!(
+        "{} UNLOCKED FOR {} SECONDS",
+        wallet_name(&wallet),
+        timeout.as_secs()
+    );
+
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+
+        // Unless it was unloaded since
+        let relocked = match node.lock().await.wallet_mut(wallet.as_deref()) {
+            Ok(keystore) => keystore.relock_if_expired(),
+            Err(_) => false,
+        };
+
+        if relocked {
+            println!("{} LOCKED AGAIN", wallet_name(&wallet));
+        }
+    });
+
+    Response::WalletUnlocked
+}
+
+fn wallet_lock(node: &mut Node, wallet: Option<String>) -> Response {
+    let keystore = match node.wallet_mut(wallet.as_deref()) {
+        Ok(keystore) => keystore,
+        Err(issue) => return Response::error(ErrorCode::NotFound, issue),
+    };
+
+    match keystore.lock() {
+        Ok(()) => {
+            println!("{} LOCKED", wallet_name(&wallet));
+
+            Response::WalletLocked
+        }
+        Err(issue) => Response::error(ErrorCode::Unsupported, issue),
+    }
+}
+
+// For the logs
+fn wallet_name(wallet: &Option<String>) -> String {
+    match wallet {
+        Some(name) => format!("WALLET {}", name),
+        None => "WALLET".to_string(),
+    }
+}
+
+async fn create_wallet(
+    node: Arc<Mutex<Node>>,
+    name: String,
+    passphrase: Option<Passphrase>,
+) -> Response {
+    let directory = node.lock().await.wallets().directory().to_path_buf();
+    let created = {
+        let name = name.clone();
+
+        // Encrypting the key takes a while
+        tokio::task::spawn_blocking(move || {
+            wallets::create(
+                &directory,
+                &name,
+                passphrase.as_ref().map(Passphrase::as_str),
+            )
+        })
+        .await
+        .map_err(|issue| issue.to_string())
+        .and_then(|created| created)
+    };
+
+    if let Err(issue) = created {
+        return Response::error(ErrorCode::InvalidRequest, issue);
+    }
+
+    println!("WALLET {} CREATED", name);
+
+    load_wallet(&mut *node.lock().await, &name)
+}
+
+fn load_wallet(node: &mut Node, name: &str) -> Response {
+    match node.wallets_mut().load(name) {
+        Ok(info) => {
+            println!("WALLET {} LOADED", name);
+
+            Response::Wallet(info)
+        }
+        Err(issue) => Response::error(ErrorCode::InvalidRequest, issue),
+    }
+}
+
+fn unload_wallet(node: &mut Node, name: &str) -> Response {
+    match node.wallets_mut().unload(name) {
+        Ok(()) => {
+            println!("WALLET {} UNLOADED", name);
+
+            Response::WalletUnloaded
+        }
+        Err(issue) => Response::error(ErrorCode::NotFound, issue),
+    }
+}
+
+async fn broadcast_raw_transaction(
+    node: Arc<Mutex<Node>>,
+    transaction: String,
+    origin: SocketAddr,
+) -> Response {
+    match raw::decode_signed(&transaction) {
+        Ok(transaction) => {
+            let hash = transaction.hash();
+
+            match blockchain::transaction_received(node.clone(), transaction, Some(origin), None)
+                .await
+            {
+                Ok(()) => Response::TransactionHash(hash),
+                // Submitting again, after a timeout for example, is fine.
+                // The id doesn't cover the signature, so a payment can't
+                // be made twice by signing it again either.
+                Err(RejectReason::Duplicate) => Response::KnownTransaction {
+                    hash,
+                    status: node.lock().await.transaction_status(&hash),
+                },
+                Err(reason) => Response::error(
+                    ErrorCode::Rejected(reason),
+                    format!("Transaction {} rejected: {}", HashFmt(hash), reason),
+                ),
+            }
+        }
+        Err(issue) => Response::error(ErrorCode::InvalidRequest, issue),
+    }
+}
+
+fn node_info(node: &Node) -> NodeInfo {
+    NodeInfo {
+        height: node.height(),
+        finalized_height: node.finalized_height(),
+        best_known_height: node.best_known_height(),
+        synced: node.is_synced(),
+        peers: node.peer_count(),
+        mempool_transactions: node.mempool_len(),
+        protocol_version: PROTOCOL_VERSION,
+        data_byte_fee: node.data_byte_fee(),
+    }
+}
+
+fn mempool_info(node: &Node) -> MempoolInfo {
+    let mempool = node.mempool();
+    let rates = mempool.fee_rates();
+
+    MempoolInfo {
+        transactions: mempool.len(),
+        size: mempool
+            .entries()
+            .map(|(_, entry)| entry.transaction().size())
+            .sum(),
+        fees: mempool
+            .entries()
+            .map(|(_, entry)| entry.transaction().fee())
+            .sum(),
+        min_fee_rate: rates.last().copied().unwrap_or(0),
+        max_fee_rate: rates.first().copied().unwrap_or(0),
+    }
+}
+
+fn raw_mempool(node: &Node) -> Vec<MempoolTransaction> {
+    let now = blockchain::timestamp();
+
+    let mut transactions: Vec<MempoolTransaction> = node
+        .mempool()
+        .entries()
+        .map(|(hash, entry)| {
+            let transaction = entry.transaction();
+
+            MempoolTransaction {
+                hash: *hash,
+                source: *transaction.source(),
+                fee: transaction.fee(),
+                fee_rate: transaction.fee_rate(),
+                size: transaction.size(),
+                age: now.saturating_sub(entry.received()),
+                ancestors: node.mempool().ancestors(hash).len(),
+                descendants: node.mempool().descendants(hash).len(),
+                package_fee_rate: node.mempool().package_fee_rate(hash),
+            }
+        })
+        .collect();
+
+    // Ties go to whoever has been waiting longer
+    transactions.sort_by(|a, b| b.fee_rate.cmp(&a.fee_rate).then(b.age.cmp(&a.age)));
+
+    transactions
+}
+
+fn get_transaction(node: &Node, hash: Hash) -> Response {
+    match node.transaction(&hash) {
+        Ok(Some((transaction, location))) => Response::Transaction {
+            transaction: Box::new(transaction.clone()),
+            block_hash: location.block_hash,
+            height: node.block_height(&location.block_hash).unwrap_or(0),
+            confirmations: node.confirmations(&location.block_hash),
+        },
+        Ok(None) => transaction_not_found(hash),
+        Err(issue) => Response::error(ErrorCode::Unsupported, issue),
+    }
+}
+
+fn get_transaction_proof(node: &Node, hash: Hash) -> Response {
+    match node.transaction(&hash) {
+        Ok(Some((_, location))) => match node.block(&location.block_hash).and_then(|block| {
+            block
+                .merkle_proof(location.index)
+                .map(|proof| (block.header().clone(), proof))
+        }) {
+            Some((header, proof)) => Response::TransactionProof {
+                header: Box::new(header),
+                proof,
+            },
+            None => transaction_not_found(hash),
+        },
+        Ok(None) => transaction_not_found(hash),
+        Err(issue) => Response::error(ErrorCode::Unsupported, issue),
+    }
+}
+
+// What blocks mined now must meet, or why none should be
+fn mining_target(node: &Node) -> Result<BigUint, Response> {
+    // Anything mined before we catch up would be on a stale tip
+    if !node.is_synced() {
+        return Err(Response::error(
+            ErrorCode::Rejected(RejectReason::NotSynced),
+            "The node is not synced".to_string(),
+        ));
+    }
+
+    node.next_target().ok_or_else(|| {
+        Response::error(
+            ErrorCode::Unsupported,
+            "Only proof of work networks can be mined".to_string(),
+        )
+    })
+}
+
+fn block_template(node: &mut Node) -> Response {
+    let target = match mining_target(node) {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+
+    Response::BlockTemplate(node.block_template().map(|block| {
+        Box::new(BlockTemplate {
+            block,
+            height: node.height() + 1,
+            target: target.to_bytes_le(),
+            pow_hash: node.pow_hash(),
+        })
+    }))
+}
+
+fn get_work(node: &mut Node) -> Response {
+    let target = match mining_target(node) {
+        Ok(target) => target,
+        Err(response) => return response,
+    };
+
+    if node.pow_hash() != PowHash::Sha256 {
+        return Response::error(
+            ErrorCode::Unsupported,
+            "Only SHA-256 proof of work has midstates".to_string(),
+        );
+    }
+
+    let block = match node.block_template() {
+        Some(block) => block,
+        None => return Response::Work(None),
+    };
+    let (before, after) = block.header().encoded_around_nonce();
+    let (midstate, before_nonce) = pow::sha256_midstate(&before);
+
+    Response::Work(Some(Box::new(Work {
+        id: node.add_work(block),
+        midstate,
+        before_nonce: before_nonce.to_vec(),
+        length: (before.len() + 32 + after.len()) as u64,
+        after_nonce: after,
+        target: target.to_bytes_le(),
+    })))
+}
+
+// Final validation is left to `block_received`, as for any other block
+async fn submit_work(node: Arc<Mutex<Node>>, id: u64, nonce: [u8; 32]) -> Response {
+    let mut block = match node.lock().await.work(id).cloned() {
+        Some(block) => block,
+        None => {
+            return Response::error(
+                ErrorCode::NotFound,
+                format!("Work {} is unknown or stale", id),
+            )
+        }
+    };
+
+    block.header_mut().set_nonce(nonce);
+
+    submit_block(node, block).await
+}
+
+async fn submit_block(node: Arc<Mutex<Node>>, block: Block) -> Response {
+    let hash = block.hash();
+
+    match blockchain::block_received(node.clone(), block.clone()).await {
+        Ok(()) => {
+            blockchain::relay_block(node, block, None);
+
+            Response::BlockAccepted(hash)
+        }
+        Err(reason) => Response::error(ErrorCode::Rejected(reason), reason.to_string()),
+    }
+}
+
+fn peer_info(node: &Node) -> Vec<PeerInfo> {
+    node.peer_traffic()
+        .into_iter()
+        .map(|(address, protocol_version, height, traffic)| PeerInfo {
+            address,
+            protocol_version,
+            height,
+            round_trip_ms: node
+                .round_trip(&address)
+                .map(|round_trip| round_trip.as_millis() as u64),
+            traffic,
+        })
+        .collect()
+}
+
+fn transaction_not_found(hash: Hash) -> Response {
+    Response::error(
+        ErrorCode::NotFound,
+        format!("Transaction {} not found", HashFmt(hash)),
+    )
+}
+
+fn address_history(addresses: &AddressIndex, address: &PublicKey, from_height: u64) -> Response {
+    let history = addresses
+        .get(address)
+        .map(|entry| entry.history())
+        .unwrap_or_default();
+    let start = history.partition_point(|entry| entry.height < from_height);
+
+    Response::AddressHistory {
+        opening_balance: history[..start].iter().map(HistoryEntry::change).sum(),
+        entries: history[start..].to_vec(),
+    }
+}
+
+// Balances are read from `views`, which doesn't wait for blocks being
+// processed
+pub async fn request_received(
+    node: Arc<Mutex<Node>>,
+    addresses: Arc<Mutex<AddressIndex>>,
+    views: &ChainViews,
+    address: SocketAddr,
+    request: Request,
+    methods: &[MethodClass],
+) {
+    println!("RPC {:?} FROM {}", request, address);
+
+    let has_wallet = node.lock().await.has_wallet();
+
+    if let Some(response) = refusal(&request, methods, has_wallet) {
+        node.lock()
+            .await
+            .send(&address, Frame::Response(response))
+            .await;
+
+        return;
+    }
+
+    let response = match request {
+        Request::CreateRawTransaction {
+            source,
+            outputs,
+            fee,
+            expires_at,
+        } => create_raw_transaction(&views.load(), source, &outputs, fee, expires_at),
+        Request::SignRawTransaction {
+            transaction,
+            wallet,
+            approval,
+        } => sign_raw_transaction(node.clone(), transaction, wallet, approval).await,
+        Request::BroadcastRawTransaction(transaction) => {
+            broadcast_raw_transaction(node.clone(), transaction, address).await
+        }
+        Request::EstimateFee(target_blocks) => {
+            Response::FeeRate(node.lock().await.estimate_fee(target_blocks))
+        }
+        Request::GetChainStats => Response::ChainStats(node.lock().await.chain_stats()),
+        Request::GetNodeInfo => Response::NodeInfo(node_info(&*node.lock().await)),
+        Request::GetTransaction(hash) => get_transaction(&*node.lock().await, hash),
+        Request::GetTransactionProof(hash) => get_transaction_proof(&*node.lock().await, hash),
+        Request::GetMempoolInfo => Response::MempoolInfo(mempool_info(&*node.lock().await)),
+        Request::GetRawMempool => Response::RawMempool(raw_mempool(&*node.lock().await)),
+        Request::GetTransactionStatus(hash) => {
+            Response::TransactionStatus(node.lock().await.transaction_status(&hash))
+        }
+        Request::GetNetTotals => Response::NetTotals(node.lock().await.net_totals()),
+        Request::GetPeerInfo => Response::PeerInfo(peer_info(&*node.lock().await)),
+        Request::GetBalance(id) => Response::Balance(views.load().balance(&id)),
+        Request::GetAddressHistory {
+            address: id,
+            from_height,
+        } => address_history(&*addresses.lock().await, &id, from_height),
+        Request::GetAlerts => Response::Alerts(node.lock().await.alerts().recent()),
+        Request::GetMemoryInfo => Response::MemoryInfo(node.lock().await.memory_info()),
+        Request::VerifyChain(level) => {
+            Response::ChainVerification(node.lock().await.verify_chain(level))
+        }
+        Request::ResolveName(name) => {
+            let view = views.load();
+
+            Response::Name {
+                record: view
+                    .name(&name)
+                    .filter(|record| record.expires_at >= view.height),
+                fee: node.lock().await.name_fee(),
+            }
+        }
+        Request::GetAssetBalance { asset, address } => {
+            Response::AssetBalance(views.load().token_balance(&asset, &address))
+        }
+        Request::GetAsset(asset) => Response::Asset(views.load().asset(&asset)),
+        Request::GetSupply => Response::Supply(views.load().supply()),
+        Request::GetDeploymentInfo => Response::DeploymentInfo(node.lock().await.deployment_info()),
+        Request::GetBlockTemplate => block_template(&mut *node.lock().await),
+        Request::SubmitBlock(block) => submit_block(node.clone(), *block).await,
+        Request::GetMiningInfo => Response::MiningInfo(node.lock().await.mining_info()),
+        Request::GetWork => get_work(&mut *node.lock().await),
+        Request::SubmitWork { id, nonce } => submit_work(node.clone(), id, nonce).await,
+        Request::WalletUnlock {
+            passphrase,
+            timeout,
+            wallet,
+        } => wallet_unlock(node.clone(), passphrase, timeout, wallet).await,
+        Request::WalletLock { wallet } => wallet_lock(&mut *node.lock().await, wallet),
+        Request::CreateWallet { name, passphrase } => {
+            create_wallet(node.clone(), name, passphrase).await
+        }
+        Request::LoadWallet(name) => load_wallet(&mut *node.lock().await, &name),
+        Request::UnloadWallet(name) => unload_wallet(&mut *node.lock().await, &name),
+        Request::ListWallets => Response::Wallets(node.lock().await.wallets().list()),
+    };
+
+    node.lock()
+        .await
+        .send(&address, Frame::Response(response))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::{branch, mine, node, payment};
+    use crate::keyfile;
+
+    fn code(response: Option<Response>) -> Option<ErrorCode> {
+        match response {
+            Some(Response::Error { code, .. }) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn peers_can_only_read_by_default() {
+        let settings = RpcSettings::default();
+        let lock = Request::WalletLock { wallet: None };
+
+        assert_eq!(
+            code(refusal(&lock, &settings.peer_methods, true)),
+            Some(ErrorCode::NotAllowed)
+        );
+        assert_eq!(
+            code(refusal(&Request::GetPeerInfo, &settings.rest_methods, true)),
+            Some(ErrorCode::NotAllowed)
+        );
+        assert!(refusal(&Request::GetChainStats, &settings.peer_methods, true).is_none());
+        assert!(refusal(&lock, &settings.methods, true).is_none());
+    }
+
+    #[test]
+    fn wallet_requests_need_a_wallet() {
+        let methods = RpcSettings::default().methods;
+
+        assert_eq!(
+            code(refusal(&Request::ListWallets, &methods, false)),
+            Some(ErrorCode::Unsupported)
+        );
+        assert!(refusal(&Request::GetNodeInfo, &methods, false).is_none());
+    }
+
+    #[tokio::test]
+    async fn rejected_transactions_answer_their_reason() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "[emission]\ninitial_reward = 1000");
+        let origin = "127.0.0.1:7125".parse().unwrap();
+        branch(
+            &node,
+            &secret_key,
+            [0; 32],
+            blockchain::timestamp() - 100,
+            10,
+            1,
+        )
+        .await;
+
+        let overdrawn = raw::encode_signed(&payment(&secret_key, [7; 32], 1000, 1));
+        let response = broadcast_raw_transaction(node.clone(), overdrawn, origin).await;
+        assert_eq!(
+            code(Some(response)),
+            Some(ErrorCode::Rejected(RejectReason::InsufficientFunds))
+        );
+
+        let paid = payment(&secret_key, [7; 32], 10, 1);
+        let raw = raw::encode_signed(&paid);
+
+        match broadcast_raw_transaction(node.clone(), raw.clone(), origin).await {
+            Response::TransactionHash(hash) => assert_eq!(hash, paid.hash()),
+            response => panic!("Unexpected {:?}", response),
+        }
+
+        // Sending it again is fine
+        match broadcast_raw_transaction(node.clone(), raw, origin).await {
+            Response::KnownTransaction { status, .. } => {
+                assert_eq!(status, blockchain::TransactionStatus::InMempool)
+            }
+            response => panic!("Unexpected {:?}", response),
+        }
+
+        let garbage = broadcast_raw_transaction(node.clone(), "zz".to_string(), origin).await;
+        assert_eq!(code(Some(garbage)), Some(ErrorCode::InvalidRequest));
+    }
+
+    #[tokio::test]
+    async fn rejected_blocks_answer_their_reason() {
+        let secret_key = keyfile::generate();
+        let node = node(&secret_key, "");
+        let orphan = mine(
+            &node,
+            &secret_key,
+            [9; 32],
+            blockchain::timestamp(),
+            Vec::new(),
+        )
+        .await;
+
+        assert_eq!(
+            code(Some(submit_block(node, orphan).await)),
+            Some(ErrorCode::Rejected(RejectReason::UnknownParent))
+        );
+    }
+
+    #[test]
+    fn raw_transactions_need_outputs_and_funds() {
+        let view = ChainView::default();
+        let outputs = [Output {
+            destination: [7; 32],
+            amount: 10,
+        }];
+
+        assert_eq!(
+            code(Some(create_raw_transaction(
+                &view, [1; 32], &outputs, 1, None
+            ))),
+            Some(ErrorCode::InsufficientFunds)
+        );
+        assert_eq!(
+            code(Some(create_raw_transaction(&view, [1; 32], &[], 1, None))),
+            Some(ErrorCode::InvalidRequest)
+        );
+    }
+}
@@ -1,27 +1,941 @@
-#[macro_use]
-extern crate serde_big_array;
-big_array! { BigArray; }
+use clap::{Args, Parser, Subcommand};
+use fcoin::blockchain::{Hash, HashFmt, TransactionStatus};
+use fcoin::client;
+use fcoin::contacts::{self, Contacts};
+use fcoin::rpc::{Request, Response};
+use fcoin::signer::{self, HidSigner, KeySigner, TransactionSigner};
+use fcoin_core::asset::AssetAction;
+use fcoin_core::hex;
+use fcoin_core::transaction::{self, Output, TransactionDetails};
+use fcoin_core::uri::PaymentRequest;
+use fcoin_core::{address, name, raw, PublicKey};
+use std::time::{Duration, Instant, SystemTime};
 
-mod blockchain;
-mod framing;
+const KEY_FILE_PATH: &str = "fcoin.key";
+// Kept by `fcoin-wallet contact`
+const CONTACTS_PATH: &str = "contacts.json";
+const NODE_ADDRESS: &str = "localhost:7123";
+// Fee estimates aim to be confirmed within this many blocks by default
+const CONFIRMATION_TARGET: u32 = 6;
+// How often the node is asked about a transaction we wait for
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const WAIT_TIMEOUT: u64 = 10 * 60;
 
-use framing::{Connection, Frame};
-use tokio::net::TcpStream;
+// Transactions can be sent in one go, or created on an online machine,
+// signed on an offline one holding the keys and broadcast back online.
+#[derive(Parser)]
+#[command(name = "send-fcoin", about = "Sends fcoin")]
+struct Arguments {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct Payment {
+    /// Address, @label of a contact, registered name/<name> or fcoin:
+    /// payment URI. Repeated as address:amount to pay several recipients
+    /// with one transaction
+    #[arg(long, required = true)]
+    to: Vec<String>,
+    /// With a single --to
+    #[arg(long)]
+    amount: Option<u64>,
+    /// Pays tokens of this asset instead of coins
+    #[arg(long)]
+    asset: Option<String>,
+    /// Defaults to the fee estimated by the node
+    #[arg(long)]
+    fee: Option<u64>,
+    /// How many blocks we are willing to wait for when estimating the fee
+    #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+    confirm_within: u32,
+    /// Last block height the transaction can be mined at
+    #[arg(long)]
+    expires_at: Option<u64>,
+    #[arg(long, default_value = NODE_ADDRESS)]
+    node: String,
+    #[arg(long, default_value = CONTACTS_PATH)]
+    contacts_file: String,
+}
+
+#[derive(Args)]
+struct Wait {
+    /// Waits until the transaction has this many confirmations
+    #[arg(long)]
+    wait: Option<u64>,
+    /// Seconds to wait for the confirmations before giving up
+    #[arg(long, default_value_t = WAIT_TIMEOUT)]
+    timeout: u64,
+}
+
+#[derive(Args)]
+struct Signer {
+    #[arg(long, default_value = KEY_FILE_PATH)]
+    key_file: String,
+    /// Signs on a hardware wallet at this hidraw device (e.g. /dev/hidraw0)
+    /// instead of with the key file
+    #[arg(long)]
+    device: Option<String>,
+}
+
+impl Signer {
+    fn open(&self) -> Result<Box<dyn TransactionSigner>, String> {
+        match &self.device {
+            Some(device) => Ok(Box::new(HidSigner::new(device))),
+            None => Ok(Box::new(KeySigner::load(&self.key_file)?)),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Creates, signs and broadcasts a transaction
+    Send {
+        #[command(flatten)]
+        payment: Payment,
+        #[command(flatten)]
+        signer: Signer,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Creates an unsigned transaction, checking the funds with the node
+    Create {
+        #[arg(long)]
+        from: String,
+        #[command(flatten)]
+        payment: Payment,
+        #[arg(long)]
+        output: String,
+    },
+    /// Signs an unsigned transaction, needs no network access
+    Sign {
+        input: String,
+        #[command(flatten)]
+        signer: Signer,
+        #[arg(long)]
+        output: String,
+    },
+    /// Sends a signed transaction to the node
+    Broadcast {
+        input: String,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Pays every address,amount,memo row of a CSV file, one transaction
+    /// per row
+    Batch {
+        input: String,
+        /// Fee of each transaction, defaults to the fee estimated by the node
+        #[arg(long)]
+        fee: Option<u64>,
+        /// How many blocks we are willing to wait for when estimating the fee
+        #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+        confirm_within: u32,
+        #[command(flatten)]
+        signer: Signer,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+    },
+    /// Registers a name for our address, or renews it, so we can be paid
+    /// at name/<name>
+    Register {
+        name: String,
+        /// Defaults to the fee estimated by the node, and is at least the
+        /// name fee of the network
+        #[arg(long)]
+        fee: Option<u64>,
+        /// How many blocks we are willing to wait for when estimating the fee
+        #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+        confirm_within: u32,
+        #[command(flatten)]
+        signer: Signer,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Issues a new asset, all of its tokens going to our address
+    Issue {
+        asset: String,
+        /// Tokens there will ever be
+        #[arg(long)]
+        supply: u64,
+        /// Defaults to the fee estimated by the node
+        #[arg(long)]
+        fee: Option<u64>,
+        /// How many blocks we are willing to wait for when estimating the fee
+        #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+        confirm_within: u32,
+        #[command(flatten)]
+        signer: Signer,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Shows who issued an asset and how many tokens, and how many of them
+    /// an address holds
+    Asset {
+        asset: String,
+        #[arg(long)]
+        address: Option<String>,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+    },
+    /// Destroys coins for good, by paying them to the burn address
+    Burn {
+        #[arg(long)]
+        amount: u64,
+        /// Up to 80 bytes of text to carry along
+        #[arg(long)]
+        data: Option<String>,
+        /// Defaults to the fee estimated by the node
+        #[arg(long)]
+        fee: Option<u64>,
+        /// How many blocks we are willing to wait for when estimating the fee
+        #[arg(long, default_value_t = CONFIRMATION_TARGET)]
+        confirm_within: u32,
+        #[command(flatten)]
+        signer: Signer,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+        #[command(flatten)]
+        wait: Wait,
+    },
+    /// Shows whether a transaction was mined, is waiting or was rejected
+    Status {
+        hash: String,
+        #[arg(long, default_value = NODE_ADDRESS)]
+        node: String,
+    },
+}
 
 #[tokio::main]
 async fn main() {
+    let result = match Arguments::parse().command {
+        Command::Send {
+            payment,
+            signer,
+            wait,
+        } => send(&payment, &signer, &wait).await,
+        Command::Create {
+            from,
+            payment,
+            output,
+        } => create(&from, &payment, &output).await,
+        Command::Sign {
+            input,
+            signer,
+            output,
+        } => sign(&input, &signer, &output),
+        Command::Broadcast { input, node, wait } => broadcast(&input, &node, &wait).await,
+        Command::Batch {
+            input,
+            fee,
+            confirm_within,
+            signer,
+            node,
+        } => batch(&input, fee, confirm_within, &signer, &node).await,
+        Command::Register {
+            name,
+            fee,
+            confirm_within,
+            signer,
+            node,
+            wait,
+        } => register(&name, fee, confirm_within, &signer, &node, &wait).await,
+        Command::Issue {
+            asset,
+            supply,
+            fee,
+            confirm_within,
+            signer,
+            node,
+            wait,
+        } => {
+            let action = AssetAction::Issue { asset, supply };
+
+            issue(action, fee, confirm_within, &signer, &node, &wait).await
+        }
+        Command::Asset {
+            asset,
+            address,
+            node,
+        } => show_asset(&asset, address.as_deref(), &node).await,
+        Command::Burn {
+            amount,
+            data,
+            fee,
+            confirm_within,
+            signer,
+            node,
+            wait,
+        } => {
+            let data = data.map(String::into_bytes);
+
+            burn(amount, data, fee, confirm_within, &signer, &node, &wait).await
+        }
+        Command::Status { hash, node } => status(&hash, &node).await,
+    };
+
+    if let Err(issue) = result {
+        println!("{}", issue);
+        std::process::exit(1);
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+// A plain address, a contact like `@alice`, or a name registered on chain
+// like `name/alice`
+async fn resolve_address(node: &str, contacts: &Contacts, text: &str) -> Result<PublicKey, String> {
+    if let Some(label) = contacts::parse(text) {
+        return contacts
+            .get(label)
+            .copied()
+            .ok_or_else(|| format!("No contact {}", text));
+    }
+
+    let Some(name) = name::parse(text) else {
+        return address::decode(text);
+    };
+
+    match client::resolve_name(node, name).await?.0 {
+        Some(record) => Ok(record.owner),
+        None => Err(format!("{} is not registered", text)),
+    }
+}
+
+// `--to` takes either an address or an `fcoin:` payment URI, whose amount is
+// used when none is given in the command line
+async fn resolve_payment(
+    node: &str,
+    contacts: &Contacts,
+    to: &str,
+    amount: Option<u64>,
+) -> Result<(PublicKey, u64), String> {
+    if !to.starts_with("fcoin:") {
+        return Ok((
+            resolve_address(node, contacts, to).await?,
+            amount.ok_or_else(|| "No amount given".to_string())?,
+        ));
+    }
+
+    let request = PaymentRequest::parse(to)?;
+
+    if request.is_expired(timestamp()) {
+        return Err("The payment request has expired".to_string());
+    }
+
+    if let Some(memo) = &request.memo {
+        println!("Payment request memo: {}", memo);
+    }
+
+    match (request.amount, amount) {
+        (Some(requested), Some(amount)) if requested != amount => Err(format!(
+            "The payment request asks for ${}, not ${}",
+            requested, amount
+        )),
+        (Some(amount), _) | (None, Some(amount)) => Ok((request.address, amount)),
+        (None, None) => Err("No amount given".to_string()),
+    }
+}
+
+// Each `--to`, which can also be `address:amount`
+async fn resolve_outputs(payment: &Payment) -> Result<Vec<Output>, String> {
+    if payment.to.len() > 1 && payment.amount.is_some() {
+        return Err("--amount only goes with a single --to, use --to address:amount".to_string());
+    }
+
+    let contacts = Contacts::load(&payment.contacts_file)?;
+    let mut outputs = Vec::new();
+
+    for to in &payment.to {
+        let (destination, amount) = match to.split_once(':') {
+            Some((address, amount)) if !to.starts_with("fcoin:") => (
+                resolve_address(&payment.node, &contacts, address).await?,
+                amount
+                    .parse()
+                    .map_err(|_| format!("Invalid amount in {}", to))?,
+            ),
+            _ => resolve_payment(&payment.node, &contacts, to, payment.amount).await?,
+        };
+
+        outputs.push(Output {
+            destination,
+            amount,
+        });
+    }
+
+    Ok(outputs)
+}
+
+// Builds the unsigned transaction, asking the node for a fee estimate when
+// none was given. Paying tokens, it pays no coins.
+async fn unsigned_transaction(source: PublicKey, payment: &Payment) -> Result<String, String> {
+    let outputs = resolve_outputs(payment).await?;
+    let action = payment.asset.as_ref().map(|asset| AssetAction::Transfer {
+        asset: asset.clone(),
+        outputs: outputs.clone(),
+    });
+
+    if let Some(action) = &action {
+        let held = client::asset_balance(&payment.node, action.asset(), source).await?;
+
+        if action.spent().is_none_or(|spent| spent > held) {
+            return Err(format!("Only {} {} to pay with", held, action.asset()));
+        }
+    }
+
+    let details = |fee| match &action {
+        Some(action) => Ok(TransactionDetails::with_tokens(source, action.clone(), fee)),
+        None => TransactionDetails::to_many(source, &outputs, fee)
+            .ok_or_else(|| "No recipient given".to_string()),
+    };
+
+    let fee = match payment.fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(&payment.node, payment.confirm_within).await?;
+            let fee = details(0)?.fee_for_rate(rate);
+
+            println!("Paying an estimated fee of ${} (${} per kB)", fee, rate);
+
+            fee
+        }
+    };
+
+    Ok(raw::encode_unsigned(
+        &details(fee)?.with_expiry(payment.expires_at),
+    ))
+}
+
+async fn send(payment: &Payment, signer: &Signer, wait: &Wait) -> Result<(), String> {
     println!("Sending fcoin...");
 
-    let stream = TcpStream::connect("localhost:7123").await.unwrap();
-    let (mut writter, _) = Connection::new(stream);
+    let signer = signer.open()?;
+
+    let unsigned = unsigned_transaction(signer.public_key()?, payment).await?;
+    let signed = raw::encode_signed(&signer::sign_transaction(
+        &*signer,
+        raw::decode_unsigned(&unsigned)?,
+    )?);
+
+    let (hash, known) = client::broadcast_raw_transaction(&payment.node, &signed).await?;
+
+    print_broadcast(hash, known);
+
+    wait_for_confirmations(&payment.node, hash, wait).await
+}
+
+// The node builds the transaction so it can check the source has the funds.
+// It only builds coin payments, tokens being checked while building here.
+async fn create(from: &str, payment: &Payment, output: &str) -> Result<(), String> {
+    let source = address::decode(from)?;
+    let unsigned = unsigned_transaction(source, payment).await?;
+    let details = raw::decode_unsigned(&unsigned)?;
+
+    if details.asset().is_some() {
+        write_file(output, &unsigned)?;
+
+        println!("Unsigned transaction written to {}", output);
+
+        return Ok(());
+    }
+
+    let request = Request::CreateRawTransaction {
+        source,
+        outputs: details.outputs(),
+        fee: details.fee(),
+        expires_at: details.expires_at(),
+    };
+
+    match client::call(&payment.node, request).await? {
+        Response::RawTransaction(unsigned) => {
+            write_file(output, &unsigned)?;
+
+            println!("Unsigned transaction written to {}", output);
+
+            Ok(())
+        }
+        _ => Err("Unexpected response from the node".to_string()),
+    }
+}
+
+fn sign(input: &str, signer: &Signer, output: &str) -> Result<(), String> {
+    let details = raw::decode_unsigned(&read_file(input)?)?;
+
+    println!(
+        "Signing transfer ${} (fee ${}) from {}",
+        details.amount(),
+        details.fee(),
+        address::encode(details.source())
+    );
+
+    for output in details.outputs() {
+        println!(
+            "  ${} to {}",
+            output.amount,
+            address::encode(&output.destination)
+        );
+    }
+
+    if let Some(name) = details.name() {
+        println!("  registering {}{}", name::NAME_PREFIX, name);
+    }
+
+    if let Some(action) = details.asset() {
+        println!("  {}", action);
+
+        if let AssetAction::Transfer { asset, outputs } = action {
+            for output in outputs {
+                println!(
+                    "    {} {} to {}",
+                    output.amount,
+                    asset,
+                    address::encode(&output.destination)
+                );
+            }
+        }
+    }
+
+    if let Some(data) = details.data() {
+        println!("  carrying {}", hex::encode(data));
+    }
+
+    if let Some(height) = details.expires_at() {
+        println!("Expires after block {}", height);
+    }
+
+    let signed = raw::encode_signed(&signer::sign_transaction(&*signer.open()?, details)?);
+
+    write_file(output, &signed)?;
+
+    println!("Signed transaction written to {}", output);
+
+    Ok(())
+}
+
+// Fails early when someone else holds the name, which the node would reject
+async fn register(
+    name: &str,
+    fee: Option<u64>,
+    confirm_within: u32,
+    signer: &Signer,
+    node: &str,
+    wait: &Wait,
+) -> Result<(), String> {
+    let name = name::parse(name).unwrap_or(name);
+
+    if !name::is_valid(name) {
+        return Err(format!(
+            "Names are {} to {} lowercase letters, digits and dashes, not starting or ending with a dash",
+            name::MIN_LENGTH,
+            name::MAX_LENGTH
+        ));
+    }
+
+    let signer = signer.open()?;
+    let source = signer.public_key()?;
+    let (record, name_fee) = client::resolve_name(node, name).await?;
+
+    if let Some(record) = record.filter(|record| record.owner != source) {
+        return Err(format!(
+            "{}{} is registered to {} until block {}",
+            name::NAME_PREFIX,
+            name,
+            address::encode(&record.owner),
+            record.expires_at
+        ));
+    }
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+
+            TransactionDetails::register(source, name, 0).fee_for_rate(rate)
+        }
+    };
+    let fee = fee.max(name_fee);
+    let signed = raw::encode_signed(&signer::sign_transaction(
+        &*signer,
+        TransactionDetails::register(source, name, fee),
+    )?);
 
-    // TODO: Fetch this information from command line
-    let details = blockchain::TransactionDetails::new([1; 128], [2; 128], 5);
-    let signature = blockchain::sign(&details);
+    println!("Registering {}{} for ${}", name::NAME_PREFIX, name, fee);
+
+    let (hash, known) = client::broadcast_raw_transaction(node, &signed).await?;
+
+    print_broadcast(hash, known);
+
+    wait_for_confirmations(node, hash, wait).await
+}
+
+// Fails early when the asset was already issued, which the node would reject
+async fn issue(
+    action: AssetAction,
+    fee: Option<u64>,
+    confirm_within: u32,
+    signer: &Signer,
+    node: &str,
+    wait: &Wait,
+) -> Result<(), String> {
+    let signer = signer.open()?;
+    let source = signer.public_key()?;
+
+    if !action.is_valid(&source) {
+        return Err(format!(
+            "Assets are named like names are, {} to {} lowercase letters, digits and dashes, and have some supply",
+            name::MIN_LENGTH,
+            name::MAX_LENGTH
+        ));
+    }
+
+    if let Some(record) = client::asset(node, action.asset()).await? {
+        return Err(format!(
+            "{} was already issued by {}",
+            action.asset(),
+            address::encode(&record.issuer)
+        ));
+    }
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+
+            TransactionDetails::with_tokens(source, action.clone(), 0).fee_for_rate(rate)
+        }
+    };
+
+    println!("{} for ${}", action, fee);
+
+    let signed = raw::encode_signed(&signer::sign_transaction(
+        &*signer,
+        TransactionDetails::with_tokens(source, action, fee),
+    )?);
+    let (hash, known) = client::broadcast_raw_transaction(node, &signed).await?;
+
+    print_broadcast(hash, known);
+
+    wait_for_confirmations(node, hash, wait).await
+}
+
+async fn burn(
+    amount: u64,
+    data: Option<Vec<u8>>,
+    fee: Option<u64>,
+    confirm_within: u32,
+    signer: &Signer,
+    node: &str,
+    wait: &Wait,
+) -> Result<(), String> {
+    if data
+        .as_ref()
+        .is_some_and(|data| data.len() > transaction::MAX_DATA_SIZE)
+    {
+        return Err(format!(
+            "Transactions carry up to {} bytes of data",
+            transaction::MAX_DATA_SIZE
+        ));
+    }
+
+    let signer = signer.open()?;
+    let source = signer.public_key()?;
+    let details = |fee| {
+        TransactionDetails::new(source, transaction::burn_address(), amount, fee)
+            .with_data(data.clone())
+    };
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+            let data_byte_fee = client::node_info(node).await?.data_byte_fee;
+            let bytes = data.as_ref().map_or(0, Vec::len) as u64;
+
+            details(0).fee_for_rate(rate).max(data_byte_fee * bytes)
+        }
+    };
+
+    println!("Burning ${} for a ${} fee", amount, fee);
+
+    let signed = raw::encode_signed(&signer::sign_transaction(&*signer, details(fee))?);
+    let (hash, known) = client::broadcast_raw_transaction(node, &signed).await?;
+
+    print_broadcast(hash, known);
+
+    wait_for_confirmations(node, hash, wait).await
+}
+
+async fn show_asset(asset: &str, address: Option<&str>, node: &str) -> Result<(), String> {
+    let record = client::asset(node, asset)
+        .await?
+        .ok_or_else(|| format!("{} was never issued", asset))?;
+
+    println!(
+        "{} {} issued by {} at height {}",
+        record.supply,
+        asset,
+        address::encode(&record.issuer),
+        record.issued_at
+    );
+
+    if let Some(encoded) = address {
+        let balance = client::asset_balance(node, asset, address::decode(encoded)?).await?;
+
+        println!("{} holds {} {}", encoded, balance, asset);
+    }
+
+    Ok(())
+}
+
+async fn broadcast(input: &str, node: &str, wait: &Wait) -> Result<(), String> {
+    let (hash, known) = client::broadcast_raw_transaction(node, &read_file(input)?).await?;
+
+    print_broadcast(hash, known);
+
+    wait_for_confirmations(node, hash, wait).await
+}
+
+fn print_broadcast(hash: Hash, known: Option<TransactionStatus>) {
+    match known {
+        Some(status) => println!(
+            "Transaction {} was already submitted, it is {}",
+            HashFmt(hash),
+            status
+        ),
+        None => println!("Sent transaction {}", HashFmt(hash)),
+    }
+}
+
+// A row of a batch payment file
+struct BatchPayment {
+    line: usize,
+    destination: PublicKey,
+    amount: u64,
+    memo: Option<String>,
+}
+
+// Rows are `address,amount,memo`, the memo being optional and free to have
+// commas. Blank lines, `#` comments and a header row are skipped.
+fn parse_batch(content: &str) -> Result<Vec<BatchPayment>, String> {
+    let mut payments = Vec::new();
+    let mut issues = Vec::new();
+
+    for (index, row) in content.lines().enumerate() {
+        let line = index + 1;
+        let row = row.trim();
+
+        if row.is_empty() || row.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = row.splitn(3, ',').map(str::trim);
+        let address = fields.next().unwrap_or("");
+
+        if payments.is_empty() && issues.is_empty() && address.eq_ignore_ascii_case("address") {
+            continue;
+        }
+
+        let destination = address::decode(address);
+        let amount = fields.next().unwrap_or("").parse::<u64>();
+        let memo = fields
+            .next()
+            .filter(|memo| !memo.is_empty())
+            .map(str::to_string);
+
+        match (destination, amount) {
+            (Ok(destination), Ok(amount)) => payments.push(BatchPayment {
+                line,
+                destination,
+                amount,
+                memo,
+            }),
+            (Err(issue), _) => issues.push(format!("Line {}: {}", line, issue)),
+            (_, Err(_)) => issues.push(format!("Line {}: invalid amount", line)),
+        }
+    }
+
+    if !issues.is_empty() {
+        return Err(issues.join("\n"));
+    }
+
+    if payments.is_empty() {
+        return Err("No payments to send".to_string());
+    }
+
+    Ok(payments)
+}
+
+// Nothing is sent unless every row is valid. After that each payment is
+// sent on its own, so some may go through while others are rejected.
+async fn batch(
+    input: &str,
+    fee: Option<u64>,
+    confirm_within: u32,
+    signer: &Signer,
+    node: &str,
+) -> Result<(), String> {
+    let payments = parse_batch(&read_file(input)?)?;
+    let signer = signer.open()?;
+    let source = signer.public_key()?;
+
+    let rate = match fee {
+        Some(_) => 0,
+        None => client::estimate_fee(node, confirm_within).await?,
+    };
+
+    let mut sent = 0;
+    let mut known = 0;
+    let mut total = 0;
+
+    for payment in &payments {
+        let fee = fee.unwrap_or_else(|| {
+            TransactionDetails::new(source, payment.destination, payment.amount, 0)
+                .fee_for_rate(rate)
+        });
+        let details = TransactionDetails::new(source, payment.destination, payment.amount, fee);
+        let memo = match &payment.memo {
+            Some(memo) => format!(" ({})", memo),
+            None => String::new(),
+        };
+
+        let result = match signer::sign_transaction(&*signer, details) {
+            Ok(signed) => {
+                client::broadcast_raw_transaction(node, &raw::encode_signed(&signed)).await
+            }
+            Err(issue) => Err(issue),
+        };
+
+        match result {
+            Ok((hash, Some(status))) => {
+                println!(
+                    "Line {}: ${} to {}{} was already submitted in {}, it is {}",
+                    payment.line,
+                    payment.amount,
+                    address::encode(&payment.destination),
+                    memo,
+                    HashFmt(hash),
+                    status
+                );
+
+                known += 1;
+            }
+            Ok((hash, None)) => {
+                println!(
+                    "Line {}: sent ${} (fee ${}) to {}{} in {}",
+                    payment.line,
+                    payment.amount,
+                    fee,
+                    address::encode(&payment.destination),
+                    memo,
+                    HashFmt(hash)
+                );
+
+                sent += 1;
+                total += payment.amount + fee;
+            }
+            Err(issue) => println!(
+                "Line {}: could not send ${} to {}{}: {}",
+                payment.line,
+                payment.amount,
+                address::encode(&payment.destination),
+                memo,
+                issue
+            ),
+        }
+    }
+
+    println!(
+        "Sent {} of {} payments, ${} including fees",
+        sent,
+        payments.len(),
+        total
+    );
+
+    if known > 0 {
+        println!("{} payments had already been submitted", known);
+    }
+
+    if sent + known < payments.len() {
+        return Err("Some payments were not sent".to_string());
+    }
+
+    Ok(())
+}
+
+async fn status(hash: &str, node: &str) -> Result<(), String> {
+    let hash = hex::decode_array::<32>(hash)?;
+
+    println!(
+        "Transaction {} is {}",
+        HashFmt(hash),
+        client::transaction_status(node, hash).await?
+    );
+
+    Ok(())
+}
+
+// Polls the node until the transaction is buried deep enough, printing
+// every change of its status on the way
+async fn wait_for_confirmations(node: &str, hash: Hash, wait: &Wait) -> Result<(), String> {
+    let confirmations = match wait.wait {
+        Some(confirmations) => confirmations,
+        None => return Ok(()),
+    };
+    let deadline = Instant::now() + Duration::from_secs(wait.timeout);
+    let mut last = None;
+
+    loop {
+        let status = client::transaction_status(node, hash).await?;
+
+        if last != Some(status) {
+            println!("Transaction {} is {}", HashFmt(hash), status);
+
+            last = Some(status);
+        }
+
+        match status {
+            TransactionStatus::Confirmed {
+                confirmations: current,
+                ..
+            } if current >= confirmations => return Ok(()),
+            TransactionStatus::Rejected(reason) => {
+                return Err(format!(
+                    "Transaction {} rejected: {}",
+                    HashFmt(hash),
+                    reason
+                ))
+            }
+            _ => {}
+        }
+
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "Gave up waiting for {} confirmations of {}",
+                confirmations,
+                HashFmt(hash)
+            ));
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|issue| format!("Could not read {}: {}", path, issue))
+}
 
-    writter
-        .write(Frame::Transaction(blockchain::Transaction::new(
-            details, signature,
-        )))
-        .await;
+fn write_file(path: &str, content: &str) -> Result<(), String> {
+    std::fs::write(path, format!("{}\n", content))
+        .map_err(|issue| format!("Could not write {}: {}", path, issue))
 }
@@ -0,0 +1,213 @@
+use super::keyfile;
+use super::keystore::LockedKey;
+use fcoin_core::encoding::Encode;
+use fcoin_core::transaction::{self, Transaction, TransactionDetails};
+use fcoin_core::{keys, PublicKey, SecretKey, Signature};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+// Ledger style APDUs understood by the fcoin app on signing devices
+const APDU_CLASS: u8 = 0xe0;
+const INS_GET_PUBLIC_KEY: u8 = 0x02;
+const INS_SIGN: u8 = 0x04;
+const STATUS_OK: u16 = 0x9000;
+const STATUS_DENIED: u16 = 0x6985;
+
+// APDUs travel in 64 byte HID reports, each starting with the channel, a
+// tag and the sequence number of the report
+const HID_REPORT_SIZE: usize = 64;
+const HID_CHANNEL: u16 = 0x0101;
+const HID_TAG_APDU: u8 = 0x05;
+
+// Whatever holds the secret key of a wallet. The wallet only ever sees the
+// public key and the signatures.
+pub trait TransactionSigner {
+    fn public_key(&self) -> Result<PublicKey, String>;
+
+    // Signs the encoding of `details`, which covers everything but the
+    // signature itself
+    fn sign(&self, details: &TransactionDetails) -> Result<Signature, String>;
+}
+
+// Signs with a key read from a key file, kept locked in memory
+pub struct KeySigner {
+    secret_key: LockedKey,
+}
+
+impl KeySigner {
+    pub fn new(secret_key: SecretKey) -> Self {
+        KeySigner {
+            secret_key: LockedKey::new(secret_key),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        Ok(KeySigner::new(keyfile::load(path)?))
+    }
+}
+
+impl TransactionSigner for KeySigner {
+    fn public_key(&self) -> Result<PublicKey, String> {
+        Ok(keys::public_key(self.secret_key.secret_key()))
+    }
+
+    fn sign(&self, details: &TransactionDetails) -> Result<Signature, String> {
+        Ok(transaction::sign(details, self.secret_key.secret_key()))
+    }
+}
+
+// Delegates to an external device running the fcoin app, talked to over a
+// Linux hidraw node such as /dev/hidraw0. The user confirms every
+// transaction on the device.
+pub struct HidSigner {
+    path: String,
+}
+
+impl HidSigner {
+    pub fn new(path: &str) -> Self {
+        HidSigner {
+            path: path.to_string(),
+        }
+    }
+
+    fn open(&self) -> Result<File, String> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .map_err(|issue| format!("Could not open the device {}: {}", self.path, issue))
+    }
+
+    // Sends an APDU and returns the data of the response
+    fn exchange(&self, instruction: u8, data: &[u8]) -> Result<Vec<u8>, String> {
+        if data.len() > u8::MAX as usize {
+            return Err("Too much data for the device".to_string());
+        }
+
+        let mut apdu = vec![APDU_CLASS, instruction, 0, 0, data.len() as u8];
+        apdu.extend_from_slice(data);
+
+        let mut device = self.open()?;
+
+        for report in hid_reports(&apdu) {
+            device
+                .write_all(&report)
+                .map_err(|issue| format!("Could not write to the device: {}", issue))?;
+        }
+
+        let mut response = read_response(&mut device)?;
+
+        if response.len() < 2 {
+            return Err("Truncated response from the device".to_string());
+        }
+
+        let status_bytes = response.split_off(response.len() - 2);
+
+        match u16::from_be_bytes([status_bytes[0], status_bytes[1]]) {
+            STATUS_OK => Ok(response),
+            STATUS_DENIED => Err("Refused on the device".to_string()),
+            status => Err(format!("The device failed with status {:04x}", status)),
+        }
+    }
+}
+
+impl TransactionSigner for HidSigner {
+    fn public_key(&self) -> Result<PublicKey, String> {
+        let response = self.exchange(INS_GET_PUBLIC_KEY, &[])?;
+
+        response
+            .as_slice()
+            .try_into()
+            .map_err(|_| "The device sent an invalid public key".to_string())
+    }
+
+    fn sign(&self, details: &TransactionDetails) -> Result<Signature, String> {
+        println!("Confirm the transaction on the device...");
+
+        let response = self.exchange(INS_SIGN, &details.encoded())?;
+
+        response
+            .as_slice()
+            .try_into()
+            .map_err(|_| "The device sent an invalid signature".to_string())
+    }
+}
+
+// The signature is checked, as a faulty device shouldn't get a transaction
+// rejected by the network
+pub fn sign_transaction(
+    signer: &dyn TransactionSigner,
+    details: TransactionDetails,
+) -> Result<Transaction, String> {
+    if details.source() != &signer.public_key()? {
+        return Err("The key does not match the source of the transaction".to_string());
+    }
+
+    let transaction = Transaction::new(details.clone(), signer.sign(&details)?);
+
+    if !transaction.has_valid_signature() {
+        return Err("The signer produced an invalid signature".to_string());
+    }
+
+    Ok(transaction)
+}
+
+// Splits an APDU in HID reports. The first one also has the length of the
+// APDU. Each is preceded by the report id hidraw expects, 0.
+fn hid_reports(apdu: &[u8]) -> Vec<Vec<u8>> {
+    let mut data = (apdu.len() as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(apdu);
+
+    data.chunks(HID_REPORT_SIZE - 5)
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut report = vec![0];
+            report.extend_from_slice(&HID_CHANNEL.to_be_bytes());
+            report.push(HID_TAG_APDU);
+            report.extend_from_slice(&(sequence as u16).to_be_bytes());
+            report.extend_from_slice(chunk);
+            report.resize(1 + HID_REPORT_SIZE, 0);
+
+            report
+        })
+        .collect()
+}
+
+fn read_response(device: &mut File) -> Result<Vec<u8>, String> {
+    let mut response = Vec::new();
+    let mut length = None;
+    let mut sequence = 0u16;
+
+    loop {
+        let mut report = [0; HID_REPORT_SIZE];
+
+        device
+            .read_exact(&mut report)
+            .map_err(|issue| format!("Could not read from the device: {}", issue))?;
+
+        if report[0..2] != HID_CHANNEL.to_be_bytes()
+            || report[2] != HID_TAG_APDU
+            || report[3..5] != sequence.to_be_bytes()
+        {
+            return Err("Unexpected report from the device".to_string());
+        }
+
+        let mut chunk = &report[5..];
+
+        if length.is_none() {
+            length = Some(u16::from_be_bytes([chunk[0], chunk[1]]) as usize);
+            chunk = &chunk[2..];
+        }
+
+        let expected = length.unwrap();
+
+        response.extend_from_slice(&chunk[..chunk.len().min(expected - response.len())]);
+
+        if response.len() == expected {
+            return Ok(response);
+        }
+
+        sequence += 1;
+    }
+}
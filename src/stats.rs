@@ -0,0 +1,116 @@
+use super::blockchain::{Block, Hash, PublicKey};
+use super::network::Emission;
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+// How many of the latest blocks the average block interval is taken over
+pub const BLOCK_INTERVAL_WINDOW: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainStats {
+    pub coins_issued: u64,
+    pub blocks: u64,
+    // Unique public keys that mined, sent or received coins
+    pub addresses: u64,
+    // In seconds, `None` until there are at least two blocks
+    pub average_block_interval: Option<u64>,
+    // Block hashes (read as little-endian numbers) must be below this
+    pub target: Hash,
+}
+
+// Updated as blocks are added to the main chain, so the stats never need
+// going through all of the blocks again
+pub struct ChainStatsIndex {
+    coins_issued: u64,
+    blocks: u64,
+    // How many times each address appears in the main chain
+    addresses: HashMap<PublicKey, u64>,
+    // Times of the latest `BLOCK_INTERVAL_WINDOW` + 1 blocks
+    recent_times: VecDeque<u64>,
+    emission: Emission,
+}
+
+impl ChainStatsIndex {
+    pub fn new(emission: Emission) -> Self {
+        ChainStatsIndex {
+            coins_issued: 0,
+            blocks: 0,
+            addresses: HashMap::new(),
+            recent_times: VecDeque::new(),
+            emission,
+        }
+    }
+
+    // `block` must extend the chain the index has seen so far
+    pub fn apply(&mut self, block: &Block) {
+        self.blocks += 1;
+        self.coins_issued += self.emission.reward(self.blocks);
+
+        for address in block_addresses(block) {
+            *self.addresses.entry(address).or_insert(0) += 1;
+        }
+
+        self.recent_times.push_back(block.header().time());
+
+        if self.recent_times.len() > BLOCK_INTERVAL_WINDOW + 1 {
+            self.recent_times.pop_front();
+        }
+    }
+
+    // Undoes `apply`, `block` must be the current tip. The interval is a
+    // bit off until enough blocks come after it again.
+    pub fn revert(&mut self, block: &Block) {
+        self.coins_issued -= self.emission.reward(self.blocks);
+        self.blocks -= 1;
+
+        for address in block_addresses(block) {
+            if let Some(count) = self.addresses.get_mut(&address) {
+                *count -= 1;
+
+                if *count == 0 {
+                    self.addresses.remove(&address);
+                }
+            }
+        }
+
+        self.recent_times.pop_back();
+    }
+
+    pub fn stats(&self, target: Hash) -> ChainStats {
+        let average_block_interval = match (self.recent_times.front(), self.recent_times.back()) {
+            (Some(first), Some(last)) if self.recent_times.len() > 1 => {
+                Some(last.saturating_sub(*first) / (self.recent_times.len() as u64 - 1))
+            }
+            _ => None,
+        };
+
+        ChainStats {
+            coins_issued: self.coins_issued,
+            blocks: self.blocks,
+            addresses: self.addresses.len() as u64,
+            average_block_interval,
+            target,
+        }
+    }
+}
+
+impl Default for ChainStatsIndex {
+    fn default() -> Self {
+        Self::new(Emission::default())
+    }
+}
+
+// Everyone that mined, sent or received coins in `block`
+fn block_addresses(block: &Block) -> impl Iterator<Item = PublicKey> + '_ {
+    std::iter::once(*block.header().node_public_key()).chain(block.transactions().iter().flat_map(
+        |transaction| {
+            std::iter::once(*transaction.source()).chain(
+                transaction
+                    .outputs()
+                    .into_iter()
+                    .map(|output| output.destination),
+            )
+        },
+    ))
+}
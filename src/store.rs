@@ -0,0 +1,187 @@
+use super::blockchain::{Block, Hash};
+use super::compact_filter::CompactFilter;
+use super::network::Emission;
+use num::BigUint;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// How many of the latest blocks the median time past is taken over
+pub const MEDIAN_TIME_BLOCKS: usize = 11;
+
+// Where a transaction was mined
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TransactionLocation {
+    pub block_hash: Hash,
+    // Position of the transaction in the block
+    pub index: usize,
+}
+
+// Everything the node knows about the chain, indexed by block hash
+pub struct ChainStore {
+    blocks: HashMap<Hash, Block>,
+    // Computed once when a block is accepted and served to light clients
+    filters: HashMap<Hash, CompactFilter>,
+    // Number of blocks from genesis up to and including each block
+    heights: HashMap<Hash, u64>,
+    // Work of the blocks from genesis up to and including each block, see
+    // `Consensus::work`
+    works: HashMap<Hash, BigUint>,
+    // The main chain transactions by their hash, which is how blocks are
+    // checked not to mine one again. Others can only look them up with
+    // `txindex`.
+    transactions: HashMap<Hash, TransactionLocation>,
+    serves_transactions: bool,
+    // Hash of the main chain block at each height, from the first block
+    main_chain: Vec<Hash>,
+    emission: Emission,
+}
+
+impl ChainStore {
+    pub fn new(emission: Emission) -> Self {
+        ChainStore {
+            blocks: HashMap::new(),
+            filters: HashMap::new(),
+            heights: HashMap::new(),
+            works: HashMap::new(),
+            transactions: HashMap::new(),
+            serves_transactions: false,
+            main_chain: Vec::new(),
+            emission,
+        }
+    }
+
+    // Also lets where each transaction is be looked up by hash, for the
+    // APIs
+    pub fn with_transaction_index(emission: Emission) -> Self {
+        ChainStore {
+            serves_transactions: true,
+            ..Self::new(emission)
+        }
+    }
+
+    pub fn has_transaction_index(&self) -> bool {
+        self.serves_transactions
+    }
+
+    // Where the main chain has the transaction
+    pub fn transaction_location(&self, hash: &Hash) -> Option<TransactionLocation> {
+        self.transactions.get(hash).copied()
+    }
+
+    // Hash of the block of the main chain at `height`
+    pub fn main_chain_hash(&self, height: u64) -> Option<Hash> {
+        self.main_chain
+            .get((height as usize).checked_sub(1)?)
+            .copied()
+    }
+
+    // The empty chain before the first block is part of every chain
+    pub fn is_on_main_chain(&self, hash: &Hash) -> bool {
+        match self.height(hash) {
+            Some(0) => true,
+            Some(height) => self.main_chain_hash(height) == Some(*hash),
+            None => false,
+        }
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&Block> {
+        self.blocks.get(hash)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn filter(&self, hash: &Hash) -> Option<&CompactFilter> {
+        self.filters.get(hash)
+    }
+
+    // The first block is at height 1, an empty chain at 0
+    pub fn height(&self, hash: &Hash) -> Option<u64> {
+        if hash == &[0; 32] {
+            Some(0)
+        } else {
+            self.heights.get(hash).copied()
+        }
+    }
+
+    // Coins created for whoever produced the block, on top of the fees
+    pub fn reward(&self, hash: &Hash) -> u64 {
+        self.emission.reward(self.height(hash).unwrap_or(0))
+    }
+
+    // What it took to produce the chain up to the block, nothing for an
+    // empty chain
+    pub fn chain_work(&self, hash: &Hash) -> BigUint {
+        self.works.get(hash).cloned().unwrap_or_default()
+    }
+
+    // The median time of the block and those before it, up to
+    // `MEDIAN_TIME_BLOCKS`, which no block can hold back or push ahead on its
+    // own. 0 for an empty chain.
+    pub fn median_time_past(&self, hash: &Hash) -> u64 {
+        let mut times = Vec::new();
+        let mut current = hash;
+
+        while times.len() < MEDIAN_TIME_BLOCKS {
+            match self.blocks.get(current) {
+                Some(block) => {
+                    times.push(block.header().time());
+                    current = block.header().previous_hash();
+                }
+                None => break,
+            }
+        }
+
+        times.sort_unstable();
+        times.get(times.len() / 2).copied().unwrap_or(0)
+    }
+
+    // `work` is what the block itself adds
+    pub fn insert(&mut self, hash: Hash, block: Block, work: BigUint) {
+        let height = self.height(block.header().previous_hash()).unwrap_or(0) + 1;
+        let chain_work = self.chain_work(block.header().previous_hash()) + work;
+
+        self.heights.insert(hash, height);
+        self.works.insert(hash, chain_work);
+
+        self.filters.insert(hash, block.compact_filter());
+        self.blocks.insert(hash, block);
+    }
+
+    // Called as the block at `hash` joins the main chain, on top of it
+    pub fn index_transactions(&mut self, hash: &Hash) {
+        if let Some(block) = self.blocks.get(hash) {
+            for (index, transaction) in block.transactions().iter().enumerate() {
+                // Validation keeps transactions from being mined twice in
+                // the same chain, the first location is the one that counts
+                self.transactions
+                    .entry(transaction.hash())
+                    .or_insert(TransactionLocation {
+                        block_hash: *hash,
+                        index,
+                    });
+            }
+
+            self.main_chain.push(*hash);
+        }
+    }
+
+    // Called as the block at `hash` leaves the main chain, from its tip
+    pub fn unindex_transactions(&mut self, hash: &Hash) {
+        if let Some(block) = self.blocks.get(hash) {
+            for transaction in block.transactions() {
+                self.transactions.remove(&transaction.hash());
+            }
+
+            self.main_chain.pop();
+        }
+    }
+}
+
+impl Default for ChainStore {
+    fn default() -> Self {
+        Self::new(Emission::default())
+    }
+}
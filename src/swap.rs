@@ -0,0 +1,137 @@
+use super::address_index::HistoryKind;
+use super::blockchain::{Hash, HashFmt, PublicKey, Transaction, TransactionDetails};
+use super::client;
+use fcoin_core::contract::{Contract, Unlock};
+use fcoin_core::{keys, raw, transaction, SecretKey};
+
+// Atomic swaps between two fcoin networks. The initiator locks coins on
+// their network for the participant, under the hash of a secret only they
+// know. The participant locks coins on the other network for the initiator
+// under the same hash, with an earlier timeout. Redeeming the participant's
+// contract shows the secret, which then redeems the initiator's. Either can
+// take their coins back once their contract times out unredeemed.
+
+// A hash time lock for `recipient`, refundable to `refund` after
+// `locktime` more blocks
+pub async fn hash_time_lock(
+    node: &str,
+    recipient: PublicKey,
+    hash_lock: Hash,
+    locktime: u64,
+    refund: PublicKey,
+) -> Result<Contract, String> {
+    let height = client::node_info(node).await?.height;
+
+    Ok(Contract::HashTimeLock {
+        recipient,
+        hash_lock,
+        timeout: height + locktime,
+        refund,
+    })
+}
+
+// Pays `amount` to `contract`, returning the transaction
+pub async fn lock(
+    node: &str,
+    secret_key: &SecretKey,
+    contract: &Contract,
+    amount: u64,
+    fee: Option<u64>,
+    confirm_within: u32,
+) -> Result<Hash, String> {
+    let source = keys::public_key(secret_key);
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+
+            TransactionDetails::new(source, contract.address(), amount, 0).fee_for_rate(rate)
+        }
+    };
+    let details = TransactionDetails::new(source, contract.address(), amount, fee);
+    let signature = transaction::sign(&details, secret_key);
+    let transaction = Transaction::new(details, signature);
+
+    let (hash, _) =
+        client::broadcast_raw_transaction(node, &raw::encode_signed(&transaction)).await?;
+
+    Ok(hash)
+}
+
+// Moves everything in `contract` to us, redeeming with `preimage` or
+// refunding without it. Returns the amount and the transaction.
+pub async fn unlock(
+    node: &str,
+    secret_key: &SecretKey,
+    contract: Contract,
+    preimage: Option<Hash>,
+    fee: Option<u64>,
+    confirm_within: u32,
+) -> Result<(u64, Hash), String> {
+    let source = contract.address();
+    let destination = keys::public_key(secret_key);
+    let balance = client::balance(node, source).await?;
+    let unlock = Unlock {
+        preimage,
+        ..Unlock::new(contract)
+    };
+    let signed = |amount, fee| {
+        let details = TransactionDetails::new(source, destination, amount, fee);
+        let signature = transaction::sign(&details, secret_key);
+
+        Transaction::new(details, signature).with_unlock(unlock.clone())
+    };
+
+    if !signed(0, 0).has_valid_signature() {
+        return Err(match preimage {
+            Some(_) => "The contract isn't for us, or the secret is wrong".to_string(),
+            None => "The contract doesn't refund to us".to_string(),
+        });
+    }
+
+    let fee = match fee {
+        Some(fee) => fee,
+        None => {
+            let rate = client::estimate_fee(node, confirm_within).await?;
+
+            (signed(0, 0).size() * rate).div_ceil(1000)
+        }
+    };
+
+    if balance <= fee as i128 {
+        return Err(format!(
+            "Nothing to take: the contract has ${} and the fee is ${}",
+            balance, fee
+        ));
+    }
+
+    let amount = (balance - fee as i128) as u64;
+    let (hash, _) =
+        client::broadcast_raw_transaction(node, &raw::encode_signed(&signed(amount, fee))).await?;
+
+    Ok((amount, hash))
+}
+
+// The secret shown by the transaction that redeemed `contract`. The node
+// needs the transaction index to find it.
+pub async fn find_secret(node: &str, contract: &Contract) -> Result<Hash, String> {
+    let (_, entries) = client::address_history(node, contract.address(), 0).await?;
+
+    for entry in entries {
+        if let HistoryKind::Sent { .. } = entry.kind {
+            let (transaction, _, _) = client::transaction(node, entry.hash).await?;
+
+            if let Some(preimage) = transaction.unlock().and_then(|unlock| unlock.preimage) {
+                return Ok(preimage);
+            }
+
+            return Err(format!(
+                "The contract was refunded in {}, not redeemed",
+                HashFmt(entry.hash)
+            ));
+        }
+    }
+
+    Err("The contract wasn't redeemed yet".to_string())
+}
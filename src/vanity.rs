@@ -0,0 +1,115 @@
+use super::keyfile;
+use fcoin_core::address::{self, CHECKSUM_LENGTH};
+use fcoin_core::{keys, SecretKey};
+use num::{BigUint, ToPrimitive};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+// Version byte of addresses, which fixes how they start
+const ADDRESS_VERSION: u8 = 0x23;
+// How often progress is reported
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+// Keys each thread tries between checks of whether another one found it
+const BATCH_SIZE: u64 = 1000;
+
+// Odds of a random address starting with `prefix`, or an error when none
+// ever can. Base58 digits sort like the ASCII characters that stand for
+// them, so with every address having the same length, the ones starting
+// with a prefix are a contiguous range.
+pub fn prefix_probability(prefix: &str) -> Result<f64, String> {
+    if let Some(invalid) = prefix.chars().find(|c| !BASE58_ALPHABET.contains(*c)) {
+        return Err(format!("{} is not used in addresses", invalid));
+    }
+
+    let lowest = address_bound(0x00);
+    let highest = address_bound(0xff);
+    let length = lowest.len();
+
+    if prefix.len() > length {
+        return Err(format!("Addresses only have {} characters", length));
+    }
+
+    let from = number(&format!("{:1<width$}", prefix, width = length))?.max(number(&lowest)?);
+    let to = number(&format!("{:z<width$}", prefix, width = length))?.min(number(&highest)?);
+
+    if from > to {
+        return Err(format!(
+            "Addresses always start between {} and {}",
+            &lowest[..prefix.len()],
+            &highest[..prefix.len()]
+        ));
+    }
+
+    let matching = (to - from + 1u32).to_f64().unwrap_or(f64::MAX);
+    let total = (number(&highest)? - number(&lowest)? + 1u32)
+        .to_f64()
+        .unwrap_or(f64::MAX);
+
+    Ok(matching / total)
+}
+
+// Encoding of the lowest or highest address, every byte after the version
+// being `byte`
+fn address_bound(byte: u8) -> String {
+    let mut payload = vec![ADDRESS_VERSION];
+    payload.extend_from_slice(&[byte; 32 + CHECKSUM_LENGTH]);
+
+    bs58::encode(payload).into_string()
+}
+
+fn number(encoded: &str) -> Result<BigUint, String> {
+    bs58::decode(encoded)
+        .into_vec()
+        .map(|bytes| BigUint::from_bytes_be(&bytes))
+        .map_err(|issue| issue.to_string())
+}
+
+// Generates keys on `threads` threads until one has an address starting
+// with `prefix`. `progress` is called every second with how many keys were
+// tried so far.
+pub fn search(prefix: &str, threads: usize, mut progress: impl FnMut(u64)) -> SecretKey {
+    let found = Arc::new(AtomicBool::new(false));
+    let attempts = Arc::new(AtomicU64::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..threads.max(1) {
+        let prefix = prefix.to_string();
+        let found = found.clone();
+        let attempts = attempts.clone();
+        let tx = tx.clone();
+
+        thread::spawn(move || {
+            while !found.load(Ordering::Relaxed) {
+                for _ in 0..BATCH_SIZE {
+                    let secret_key = keyfile::generate();
+
+                    if address::encode(&keys::public_key(&secret_key)).starts_with(&prefix) {
+                        found.store(true, Ordering::Relaxed);
+                        let _ = tx.send(secret_key);
+
+                        return;
+                    }
+                }
+
+                attempts.fetch_add(BATCH_SIZE, Ordering::Relaxed);
+            }
+        });
+    }
+
+    let mut last_report = Instant::now();
+
+    loop {
+        match rx.recv_timeout(PROGRESS_INTERVAL) {
+            Ok(secret_key) => return secret_key,
+            Err(_) => {
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    progress(attempts.load(Ordering::Relaxed));
+                    last_report = Instant::now();
+                }
+            }
+        }
+    }
+}
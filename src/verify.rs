@@ -0,0 +1,33 @@
+use super::blockchain::Hash;
+
+use serde::{Deserialize, Serialize};
+
+// How thoroughly `Node::verify_chain` checks the main chain. Each level
+// also checks what the ones below do.
+//
+// The blocks link up, from the genesis block to the tip, and are stored
+// under their hash and height
+pub const VERIFY_LINKAGE: u8 = 0;
+// Their proof of work or stake signature, and everything else that only
+// needs the block itself
+pub const VERIFY_SEALS: u8 = 1;
+// Their transactions, run again against balances rebuilt from the genesis
+// block, which must end up as the node has them
+pub const VERIFY_TRANSACTIONS: u8 = 2;
+
+// Answer to `VerifyChain`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainVerification {
+    pub level: u8,
+    // Blocks of the main chain that passed, from the genesis block up
+    pub blocks: u64,
+    // The first block that didn't, `None` when the whole chain passed
+    pub violation: Option<ChainViolation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainViolation {
+    pub height: u64,
+    pub hash: Hash,
+    pub issue: String,
+}
@@ -0,0 +1,282 @@
+use super::blockchain::{Block, Hash, PublicKey};
+use arc_swap::ArcSwap;
+use fcoin_core::asset::AssetAction;
+use fcoin_core::transaction::burn_address;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+// How many of the latest views are kept, so disconnecting blocks doesn't
+// need going through the chain again
+const VIEW_HISTORY: usize = 100;
+
+// The chain as of a tip, for what only reads it (the RPC, REST and GraphQL
+// APIs) without waiting for the node lock. Views never change once
+// published, so what is read from one stays consistent as the chain moves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChainView {
+    pub tip_hash: Hash,
+    pub height: u64,
+    // Of the tip, 0 before the first block
+    pub time: u64,
+    // Shares what didn't change with the views before it
+    balances: im::HashMap<PublicKey, i128>,
+    // Coins created by every block
+    issued: u64,
+    // What the balances add up to, those of the burn address included
+    held: i128,
+    // Every name ever registered, expired or not
+    names: im::HashMap<String, NameRecord>,
+    assets: im::HashMap<String, AssetRecord>,
+    // Tokens held, by asset and holder
+    tokens: im::HashMap<(String, PublicKey), u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameRecord {
+    pub owner: PublicKey,
+    // Last height it is registered at, unless renewed
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Supply {
+    // Created by every block
+    pub issued: u64,
+    // Paid to the burn address
+    pub burned: u64,
+    // Taken from slashed producers
+    pub slashed: u64,
+    // What is left to spend
+    pub circulating: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetRecord {
+    pub issuer: PublicKey,
+    pub supply: u64,
+    // Height of the block that issued it
+    pub issued_at: u64,
+}
+
+impl ChainView {
+    // Same as `Node::balance`, without going through the chain
+    pub fn balance(&self, id: &PublicKey) -> i128 {
+        self.balances.get(id).copied().unwrap_or(0)
+    }
+
+    pub fn supply(&self) -> Supply {
+        let burned = self.balance(&burn_address()).max(0) as u64;
+        let held = self.held.max(0) as u64;
+
+        Supply {
+            issued: self.issued,
+            burned,
+            slashed: self.issued.saturating_sub(held),
+            circulating: held.saturating_sub(burned),
+        }
+    }
+
+    // The latest registration of `name`, which may have expired
+    pub fn name(&self, name: &str) -> Option<NameRecord> {
+        self.names.get(name).copied()
+    }
+
+    pub fn asset(&self, asset: &str) -> Option<AssetRecord> {
+        self.assets.get(asset).copied()
+    }
+
+    // Tokens of `asset` held by `id`
+    pub fn token_balance(&self, asset: &str, id: &PublicKey) -> u64 {
+        self.tokens
+            .get(&(asset.to_string(), *id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Everyone holding tokens of `asset`, with how many, the most first
+    pub fn token_holders(&self, asset: &str) -> Vec<(PublicKey, u64)> {
+        let mut holders: Vec<_> = self
+            .tokens
+            .iter()
+            .filter(|((held, _), amount)| held == asset && *amount > &0)
+            .map(|((_, holder), amount)| (*holder, *amount))
+            .collect();
+
+        holders.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        holders
+    }
+
+    // The view once `block`, which created `reward` coins, is the tip.
+    // Names it registers last `name_lifetime` blocks.
+    pub fn connected(
+        &self,
+        hash: Hash,
+        block: &Block,
+        reward: u64,
+        name_lifetime: u64,
+    ) -> ChainView {
+        let mut balances = self.balances.clone();
+        let mut names = self.names.clone();
+        let mut assets = self.assets.clone();
+        let mut tokens = self.tokens.clone();
+        let slashed = |id: &PublicKey| {
+            block
+                .slashings()
+                .iter()
+                .any(|evidence| evidence.offender() == id)
+        };
+        let mut held = self.held;
+        let mut add = |id: &PublicKey, change: i128| {
+            if !slashed(id) {
+                *balances.entry(*id).or_insert(0) += change;
+                held += change;
+            }
+        };
+        let mut fees = 0;
+
+        for transaction in block.transactions() {
+            add(
+                transaction.source(),
+                -((transaction.amount() + transaction.fee()) as i128),
+            );
+            for output in transaction.outputs() {
+                add(&output.destination, output.amount as i128);
+            }
+
+            fees += transaction.fee() as i128;
+
+            if let Some(name) = transaction.name() {
+                names.insert(
+                    name.to_string(),
+                    NameRecord {
+                        owner: *transaction.source(),
+                        expires_at: self.height + 1 + name_lifetime,
+                    },
+                );
+            }
+
+            if let Some(action) = transaction.asset() {
+                let asset = action.asset().to_string();
+
+                if let AssetAction::Issue { supply, .. } = action {
+                    assets.insert(
+                        asset.clone(),
+                        AssetRecord {
+                            issuer: *transaction.source(),
+                            supply: *supply,
+                            issued_at: self.height + 1,
+                        },
+                    );
+                }
+
+                let held = tokens
+                    .entry((asset.clone(), *transaction.source()))
+                    .or_insert(0);
+
+                *held = held.saturating_sub(action.spent().unwrap_or(0));
+
+                for output in action.credits(transaction.source()) {
+                    *tokens
+                        .entry((asset.clone(), output.destination))
+                        .or_insert(0) += output.amount;
+                }
+            }
+        }
+
+        add(block.header().node_public_key(), reward as i128 + fees);
+
+        // Nothing is left to slashed producers
+        for evidence in block.slashings() {
+            held -= balances.remove(evidence.offender()).unwrap_or(0);
+        }
+
+        ChainView {
+            tip_hash: hash,
+            height: self.height + 1,
+            time: block.header().time(),
+            balances,
+            issued: self.issued + reward,
+            held,
+            names,
+            assets,
+            tokens,
+        }
+    }
+}
+
+// Where readers take the latest view from, cloned for each of them
+#[derive(Clone, Default)]
+pub struct ChainViews {
+    current: Arc<ArcSwap<ChainView>>,
+}
+
+impl ChainViews {
+    pub fn load(&self) -> Arc<ChainView> {
+        self.current.load_full()
+    }
+}
+
+// Kept by the node, which publishes a new view each time its tip changes
+pub struct ViewPublisher {
+    views: ChainViews,
+    // The latest views, the current one last
+    history: VecDeque<Arc<ChainView>>,
+    // Of the network
+    name_lifetime: u64,
+}
+
+impl ViewPublisher {
+    pub fn new(name_lifetime: u64) -> Self {
+        ViewPublisher {
+            views: ChainViews::default(),
+            history: VecDeque::new(),
+            name_lifetime,
+        }
+    }
+
+    pub fn views(&self) -> ChainViews {
+        self.views.clone()
+    }
+
+    pub fn connect(&mut self, hash: Hash, block: &Block, reward: u64) {
+        let view = Arc::new(
+            self.views
+                .load()
+                .connected(hash, block, reward, self.name_lifetime),
+        );
+
+        if self.history.len() == VIEW_HISTORY {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(view.clone());
+        self.views.current.store(view);
+    }
+
+    // Goes back to the view of the parent of the tip. Returns false when it
+    // is no longer kept, and the chain has to be connected again with
+    // `rebuild`.
+    pub fn disconnect(&mut self) -> bool {
+        self.history.pop_back();
+
+        match self.history.back() {
+            Some(view) => {
+                self.views.current.store(view.clone());
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    // From the genesis block, each block with its hash and reward
+    pub fn rebuild<'a>(&mut self, blocks: impl Iterator<Item = (Hash, &'a Block, u64)>) {
+        self.views.current.store(Arc::new(ChainView::default()));
+
+        for (hash, block, reward) in blocks {
+            self.connect(hash, block, reward);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use super::keyfile;
+use super::keystore::Keystore;
+use fcoin_core::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Where named wallets keep their key files, one `<name>.key` each
+pub const WALLETS_DIRECTORY: &str = "wallets";
+// Long enough for any sensible name, short enough for a file name
+const MAX_NAME_LENGTH: usize = 64;
+
+// See `Request::ListWallets`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletInfo {
+    pub name: String,
+    pub address: PublicKey,
+    pub encrypted: bool,
+    pub locked: bool,
+}
+
+// Wallets the node holds besides its own key. Each has its own key file,
+// and once loaded is locked and unlocked on its own, like the key of the
+// node is.
+pub struct Wallets {
+    directory: PathBuf,
+    loaded: BTreeMap<String, Keystore>,
+}
+
+impl Default for Wallets {
+    fn default() -> Self {
+        Wallets::new(WALLETS_DIRECTORY)
+    }
+}
+
+impl Wallets {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Wallets {
+            directory: directory.into(),
+            loaded: BTreeMap::new(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    // Encrypted wallets start locked
+    pub fn load(&mut self, name: &str) -> Result<WalletInfo, String> {
+        let path = path(&self.directory, name)?;
+
+        if self.loaded.contains_key(name) {
+            return Err(format!("Wallet {} is already loaded", name));
+        }
+
+        if !Path::new(&path).exists() {
+            return Err(format!("There is no wallet {}", name));
+        }
+
+        let keystore = keyfile::load_keystore(&path)?;
+        let info = info(name, &keystore);
+
+        self.loaded.insert(name.to_string(), keystore);
+
+        Ok(info)
+    }
+
+    // Its key is wiped from memory, the key file stays
+    pub fn unload(&mut self, name: &str) -> Result<(), String> {
+        self.loaded
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_loaded(name))
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Keystore, String> {
+        self.loaded.get(name).ok_or_else(|| not_loaded(name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Result<&mut Keystore, String> {
+        self.loaded.get_mut(name).ok_or_else(|| not_loaded(name))
+    }
+
+    // Those loaded, by name
+    pub fn list(&self) -> Vec<WalletInfo> {
+        self.loaded
+            .iter()
+            .map(|(name, keystore)| info(name, keystore))
+            .collect()
+    }
+}
+
+// Generates the key of a new wallet in `directory`, encrypted right away when
+// there is a passphrase. Encrypting takes a while, so the node isn't held
+// for it, and loads the wallet after.
+pub fn create(directory: &Path, name: &str, passphrase: Option<&str>) -> Result<(), String> {
+    let path = path(directory, name)?;
+
+    if passphrase == Some("") {
+        return Err("The passphrase can't be empty".to_string());
+    }
+
+    if Path::new(&path).exists() {
+        return Err(format!("Wallet {} already exists", name));
+    }
+
+    std::fs::create_dir_all(directory)
+        .map_err(|issue| format!("Could not create {}: {}", directory.display(), issue))?;
+
+    let secret_key = keyfile::generate();
+
+    match passphrase {
+        Some(passphrase) => keyfile::save_encrypted(&path, &secret_key, passphrase),
+        None => keyfile::save(&path, &secret_key),
+    }
+}
+
+// Names end up in paths, so only plain ones are allowed
+fn path(directory: &Path, name: &str) -> Result<String, String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !valid {
+        return Err(format!(
+            "Invalid wallet name {:?}: use up to {} letters, digits, - and _",
+            name, MAX_NAME_LENGTH
+        ));
+    }
+
+    Ok(directory
+        .join(format!("{}.key", name))
+        .to_string_lossy()
+        .to_string())
+}
+
+fn info(name: &str, keystore: &Keystore) -> WalletInfo {
+    WalletInfo {
+        name: name.to_string(),
+        address: *keystore.public_key(),
+        encrypted: keystore.encrypted_key().is_some(),
+        locked: keystore.is_locked(),
+    }
+}
+
+fn not_loaded(name: &str) -> String {
+    format!("Wallet {} is not loaded", name)
+}
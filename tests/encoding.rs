@@ -0,0 +1,49 @@
+// Golden vectors for the consensus encoding of blocks. If any of these
+// change, so do the block hashes, which forks the network.
+use fcoin::blockchain::BlockHeader;
+use fcoin_core::encoding::Encode;
+use fcoin_core::hex;
+
+#[test]
+fn block_header() {
+    let header = BlockHeader::new(1_600_000_000, [1; 32], [2; 32], [3; 32], [4; 32]);
+
+    assert_eq!(
+        hex::encode(&header.encoded()),
+        format!(
+            "{}{}{}{}{}",
+            "00105e5f00000000",
+            "0101010101010101010101010101010101010101010101010101010101010101",
+            "0202020202020202020202020202020202020202020202020202020202020202",
+            "0303030303030303030303030303030303030303030303030303030303030303",
+            "0404040404040404040404040404040404040404040404040404040404040404"
+        )
+    );
+    assert_eq!(
+        hex::encode(&header.hash()),
+        "d5154d05daafc728d2f5c58b0444c14f73289b644b83d5185ab5d10e8438d43c"
+    );
+}
+
+#[test]
+fn block_header_signaling() {
+    let header =
+        BlockHeader::new(1_600_000_000, [1; 32], [2; 32], [3; 32], [4; 32]).with_version(5);
+
+    assert_eq!(
+        hex::encode(&header.encoded()),
+        format!(
+            "{}{}{}{}{}{}",
+            "00105e5f00000000",
+            "0101010101010101010101010101010101010101010101010101010101010101",
+            "0202020202020202020202020202020202020202020202020202020202020202",
+            "0303030303030303030303030303030303030303030303030303030303030303",
+            "0404040404040404040404040404040404040404040404040404040404040404",
+            "05000000"
+        )
+    );
+    assert_eq!(
+        hex::encode(&header.hash()),
+        "c9f1c3bda38bafffa05e8458743ca167434aab42695ce40b20a95cea5c200922"
+    );
+}
@@ -0,0 +1,141 @@
+// Rule changes scheduled in the `[network.forks]` table only apply from
+// their height on: blocks right before it follow the old rules, and blocks
+// from it the new ones
+use fcoin::address_book::AddressBook;
+use fcoin::blockchain::{
+    self, Block, BlockHeader, Hash, Node, NodeSettings, RejectReason, Transaction,
+    TransactionDetails,
+};
+use fcoin::framing::RelayPolicy;
+use fcoin::keyfile;
+use fcoin::memory::MemorySettings;
+use fcoin::miner::MiningSettings;
+use fcoin::network::{Fork, Forks, NetworkProfile};
+use fcoin::policy::SpendingPolicy;
+use fcoin::store::ChainStore;
+use fcoin_core::{keys, transaction, SecretKey};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// The data fee applies from the third block
+const PROFILE: &str = r#"
+data_byte_fee = 1
+
+[forks]
+data-fee = 3
+
+[emission]
+initial_reward = 1000
+"#;
+
+fn network() -> NetworkProfile {
+    toml::from_str(PROFILE).unwrap()
+}
+
+fn node(secret_key: SecretKey) -> Arc<Mutex<Node>> {
+    Arc::new(Mutex::new(Node::new(
+        secret_key,
+        NodeSettings {
+            mempool_ttl: 60,
+            transaction_index: false,
+            sync_tolerance: 6,
+            network: network(),
+            daily_upload_cap: None,
+            relay: RelayPolicy::Full,
+            listen_port: None,
+            memory: MemorySettings::default(),
+            signal: Vec::new(),
+            mining: MiningSettings::default(),
+            trusted_peers: Vec::new(),
+            policy: SpendingPolicy::default(),
+        },
+        AddressBook::default(),
+    )))
+}
+
+// Targets only move at the end of a retarget window, so the first blocks
+// only need to meet the easiest one
+fn mine(secret_key: &SecretKey, parent: Hash, time: u64, transactions: Vec<Transaction>) -> Block {
+    let header = BlockHeader::new(time, keys::public_key(secret_key), parent, [0; 32], [0; 32]);
+    let mut block = Block::new(header, transactions);
+    let consensus = network().consensus();
+
+    while !consensus.seal(&mut block, &ChainStore::default(), secret_key) {}
+
+    block
+}
+
+// Carries 32 bytes of data, paying `fee` for them
+fn anchor(secret_key: &SecretKey, data: u8, fee: u64) -> Transaction {
+    let details = TransactionDetails::anchor(keys::public_key(secret_key), vec![data; 32], fee);
+    let signature = transaction::sign(&details, secret_key);
+
+    Transaction::new(details, signature)
+}
+
+#[test]
+fn forks_apply_from_their_height() {
+    let network = network();
+
+    assert!(!network.is_active(Fork::DataFee, 2));
+    assert!(network.is_active(Fork::DataFee, 3));
+    assert!(NetworkProfile::default().is_active(Fork::DataFee, 1));
+}
+
+#[test]
+fn scheduling_a_fork_changes_the_network() {
+    assert_ne!(network().id(), {
+        let mut unscheduled = network();
+
+        unscheduled.forks = Forks::default();
+        unscheduled.id()
+    });
+}
+
+#[tokio::test]
+async fn blocks_straddling_a_fork() {
+    let secret_key = keyfile::generate();
+    let node = node(secret_key.clone());
+    // Each block after the median time of those before it
+    let start = blockchain::timestamp();
+
+    let first = mine(&secret_key, [0; 32], start, Vec::new());
+    blockchain::block_received(node.clone(), first.clone())
+        .await
+        .unwrap();
+
+    // Right before the fork, data is free
+    let second = mine(
+        &secret_key,
+        first.hash(),
+        start + 1,
+        vec![anchor(&secret_key, 1, 0)],
+    );
+    blockchain::block_received(node.clone(), second.clone())
+        .await
+        .unwrap();
+
+    // From the fork on, it isn't
+    let unpaid = mine(
+        &secret_key,
+        second.hash(),
+        start + 2,
+        vec![anchor(&secret_key, 2, 0)],
+    );
+    assert_eq!(
+        blockchain::block_received(node.clone(), unpaid).await,
+        Err(RejectReason::DataFeeTooLow)
+    );
+
+    let paid = mine(
+        &secret_key,
+        second.hash(),
+        start + 2,
+        vec![anchor(&secret_key, 2, 32)],
+    );
+    blockchain::block_received(node.clone(), paid.clone())
+        .await
+        .unwrap();
+
+    assert_eq!(node.lock().await.height(), 3);
+}
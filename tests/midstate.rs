@@ -0,0 +1,37 @@
+// External workers finish the proof of work hash of a header from the
+// midstate they are given, which has to come out the same as hashing it
+// whole
+use fcoin::blockchain::BlockHeader;
+use fcoin::pow::{self, PowHasher, Sha256Hasher};
+use fcoin_core::encoding::Encode;
+
+#[test]
+fn hashing_from_the_midstate() {
+    for version in [0, 1 << 3] {
+        let header = BlockHeader::new(1_600_000_000, [1; 32], [2; 32], [3; 32], [4; 32])
+            .with_version(version);
+        let (before, after) = header.encoded_around_nonce();
+        let (midstate, rest) = pow::sha256_midstate(&before);
+        let mut tail = rest.to_vec();
+
+        tail.extend_from_slice(&[4; 32]);
+        tail.extend_from_slice(&after);
+
+        assert_eq!(
+            pow::sha256_from_midstate(midstate, &tail, before.len() + 32 + after.len()),
+            Sha256Hasher.hash(&header.encoded())
+        );
+    }
+}
+
+#[test]
+fn hashing_whole_blocks_from_the_midstate() {
+    let data = [7; 128];
+    let (midstate, rest) = pow::sha256_midstate(&data);
+
+    assert!(rest.is_empty());
+    assert_eq!(
+        pow::sha256_from_midstate(midstate, rest, data.len()),
+        Sha256Hasher.hash(&data)
+    );
+}
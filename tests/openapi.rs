@@ -0,0 +1,108 @@
+// The OpenAPI document has to describe exactly the routes of the REST API,
+// with the statuses they actually answer with
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use fcoin::address_book::AddressBook;
+use fcoin::address_index::AddressIndex;
+use fcoin::blockchain::{Node, NodeSettings};
+use fcoin::framing::RelayPolicy;
+use fcoin::keyfile;
+use fcoin::memory::MemorySettings;
+use fcoin::miner::MiningSettings;
+use fcoin::network::NetworkProfile;
+use fcoin::policy::SpendingPolicy;
+use fcoin::ratelimit::RestLimits;
+use fcoin::rest::{self, ApiDoc};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+use utoipa::OpenApi;
+
+const ADDRESS: &str = "2BK9bXAxmT1EEvAyUdBGcTQRSUWhWifSVFZ7SFPU1uS8C4ZM4ax";
+
+fn router() -> Router {
+    let network = NetworkProfile::default();
+    let addresses = AddressIndex::new(network.emission);
+    let node = Node::new(
+        keyfile::generate(),
+        NodeSettings {
+            mempool_ttl: 60,
+            transaction_index: false,
+            sync_tolerance: 6,
+            network,
+            daily_upload_cap: None,
+            relay: RelayPolicy::Full,
+            listen_port: None,
+            memory: MemorySettings::default(),
+            signal: Vec::new(),
+            mining: MiningSettings::default(),
+            trusted_peers: Vec::new(),
+            policy: SpendingPolicy::default(),
+        },
+        AddressBook::default(),
+    );
+
+    rest::router(
+        Arc::new(Mutex::new(node)),
+        Arc::new(Mutex::new(addresses)),
+        false,
+        None,
+        RestLimits::default(),
+    )
+}
+
+async fn get(uri: &str) -> (StatusCode, String) {
+    let response = router()
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[test]
+fn spec_describes_every_route() {
+    let mut documented: Vec<String> = ApiDoc::openapi().paths.paths.keys().cloned().collect();
+    let mut routes = rest::route_paths();
+
+    documented.sort();
+    routes.sort();
+
+    assert_eq!(documented, routes);
+}
+
+#[tokio::test]
+async fn routes_answer_as_documented() {
+    for (path, item) in ApiDoc::openapi().paths.paths {
+        let operation = item.get.expect("the API only has GET routes");
+        let uri = path
+            .replace("{address}", ADDRESS)
+            .replace("{hash}", &"00".repeat(32))
+            .replace("{asset}", "gold");
+
+        let (status, _) = get(&uri).await;
+
+        assert!(
+            operation.responses.responses.contains_key(status.as_str()),
+            "{} answered {}, which is not documented",
+            uri,
+            status
+        );
+    }
+}
+
+#[tokio::test]
+async fn spec_and_docs_are_served() {
+    let (status, spec) = get("/api/spec.json").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(spec, ApiDoc::openapi().to_json().unwrap());
+
+    let (status, docs) = get("/api/docs/").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(docs.contains("swagger"));
+}